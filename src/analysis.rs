@@ -0,0 +1,842 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::ASTNode;
+use crate::ForCond;
+use crate::Op;
+use crate::ParseError;
+use crate::Parser;
+use crate::Pattern;
+use crate::PatternFields;
+use crate::TypeField;
+use crate::Value;
+use crate::VarType;
+use crate::VariantArgs;
+use crate::VariantDef;
+use crate::VariantShape;
+
+/// Methods every list has, offered after a `.`.
+pub const LIST_METHODS: [&str; 23] = [
+    "push", "pop", "map", "filter", "reduce", "find", "find_index", "any", "all",
+    "for_each", "sort", "reverse", "slice", "concat", "join", "contains",
+    "index_of", "insert", "remove", "len", "flat_map", "zip", "enumerate",
+];
+
+/// Methods every string has, offered after a `.` on a string.
+pub const STRING_METHODS: [&str; 12] = [
+    "len", "split", "trim", "upper", "lower", "starts_with", "ends_with",
+    "contains", "replace", "find", "substring", "chars",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DefKind {
+    Variable,
+    Function,
+    Param,
+    Struct,
+    Enum,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub name: String,
+    pub kind: DefKind,
+    /// Byte range of the defining identifier.
+    pub span: Range<usize>,
+    /// Inferred type, `Any` when unknown.
+    pub typ: String,
+    scope: usize,
+    /// Declared with `let` or `const`, which shadow outer names on purpose.
+    pub(crate) declared: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub name: String,
+    pub span: Range<usize>,
+    /// Index into `Analysis::definitions`, none for undefined names.
+    pub def: Option<usize>,
+    scope: usize,
+}
+
+#[derive(Debug)]
+struct Scope {
+    parent: Option<usize>,
+    span: Range<usize>,
+}
+
+/// Static view of a script for editor tooling. Names are resolved the
+/// way the vm stores them: only functions open a scope, and the first
+/// assignment to a name inside a scope defines it.
+#[derive(Debug)]
+pub struct Analysis {
+    pub error: Option<ParseError>,
+    pub definitions: Vec<Definition>,
+    pub references: Vec<Reference>,
+    scopes: Vec<Scope>,
+    /// Identifiers seen before a parse error, used for completion.
+    names: Vec<String>,
+}
+
+impl Analysis {
+    pub fn new(input: &str) -> Analysis {
+        let mut parser = Parser::new(input);
+        let ast = parser.try_parse();
+
+        let mut walker = Walker {
+            idents: parser.ident_spans(),
+            next: 0,
+            funs: parser.fun_spans(),
+            next_fun: 0,
+            scopes: vec![Scope { parent: None, span: 0..input.len() }],
+            curr: 0,
+            defs: vec![],
+            refs: vec![],
+            structs: HashMap::new(),
+        };
+
+        let error = match ast {
+            Ok(nodes) => {
+                for node in &nodes {
+                    walker.visit(node);
+                }
+                walker.resolve_late();
+                None
+            },
+            Err(err) => Some(err),
+        };
+
+        let mut names = parser.ident_spans().iter()
+            .map(|(n, _)| n.clone())
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+
+        Analysis {
+            error,
+            definitions: walker.defs,
+            references: walker.refs,
+            scopes: walker.scopes,
+            names,
+        }
+    }
+
+    /// Functions assigned to identifiers, struct and enum definitions.
+    pub fn symbols(&self) -> impl Iterator<Item = &Definition> {
+        self.definitions.iter().filter(|d| matches!(d.kind, DefKind::Function | DefKind::Struct | DefKind::Enum))
+    }
+
+    /// Definition of the identifier at `offset`, which is either a
+    /// definition itself or a resolved reference.
+    pub fn definition_at(&self, offset: usize) -> Option<&Definition> {
+        let contains = |span: &Range<usize>| span.start <= offset && offset <= span.end;
+
+        if let Some(def) = self.definitions.iter().find(|d| contains(&d.span)) {
+            return Some(def);
+        }
+
+        let r = self.references.iter().find(|r| contains(&r.span))?;
+        self.definitions.get(r.def?)
+    }
+
+    /// Hover text for the identifier at `offset` with its span.
+    pub fn hover_at(&self, offset: usize) -> Option<(String, Range<usize>)> {
+        let contains = |span: &Range<usize>| span.start <= offset && offset <= span.end;
+
+        let span = self.definitions.iter().map(|d| &d.span)
+            .chain(self.references.iter().map(|r| &r.span))
+            .find(|s| contains(s))?
+            .clone();
+        let def = self.definition_at(offset)?;
+
+        let text = match def.kind {
+            DefKind::Struct | DefKind::Enum => def.typ.clone(),
+            _ => format!("{}: {}", def.name, def.typ),
+        };
+
+        Some((text, span))
+    }
+
+    /// Completion candidates at `offset`: methods after a `.`, string
+    /// methods when the receiver is a string and list methods otherwise,
+    /// or else the names visible from the enclosing scope.
+    pub fn completions_at(&self, input: &str, offset: usize) -> Vec<Completion> {
+        let offset = offset.min(input.len());
+        let before = &input[..offset];
+        let word = before.trim_end_matches(|c: char| c.is_ascii_alphanumeric() || c == '_');
+
+        if let Some(receiver) = word.strip_suffix('.') {
+            let name = receiver.rsplit(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).next().unwrap_or("");
+            let is_str = receiver.ends_with('"') || self.definitions.iter()
+                .any(|d| d.name == name && d.typ == "String");
+            let methods: &[&str] = if is_str { &STRING_METHODS } else { &LIST_METHODS };
+            return methods.iter().map(|m| Completion {
+                label: m.to_string(),
+                kind: CompletionKind::Method,
+                detail: None,
+            }).collect();
+        }
+
+        if self.error.is_some() {
+            return self.names.iter().map(|n| Completion {
+                label: n.clone(),
+                kind: CompletionKind::Variable,
+                detail: None,
+            }).collect();
+        }
+
+        let mut scope = self.scope_at(offset);
+        let mut seen = vec![];
+        let mut items = vec![];
+        let mut inner = true;
+
+        loop {
+            for def in self.definitions.iter().filter(|d| d.scope == scope) {
+                // Later assignments of the enclosing scope may still run
+                // before a function is called, its own ones can not.
+                if inner && def.span.start >= offset {
+                    continue;
+                }
+                if seen.contains(&def.name) {
+                    continue;
+                }
+                seen.push(def.name.clone());
+                items.push(Completion {
+                    label: def.name.clone(),
+                    kind: match def.kind {
+                        DefKind::Function => CompletionKind::Function,
+                        DefKind::Struct | DefKind::Enum => CompletionKind::Struct,
+                        _ => CompletionKind::Variable,
+                    },
+                    detail: Some(def.typ.clone()),
+                });
+            }
+
+            match self.scopes[scope].parent {
+                Some(p) => scope = p,
+                None => break,
+            }
+            inner = false;
+        }
+
+        items
+    }
+
+    /// Definition of the same name in a scope enclosing the one of `def`.
+    pub(crate) fn outer_definition(&self, def: &Definition) -> Option<&Definition> {
+        let mut scope = self.scopes[def.scope].parent;
+
+        while let Some(s) = scope {
+            let found = self.definitions.iter().find(|d| d.scope == s && d.name == def.name);
+            if found.is_some() {
+                return found;
+            }
+            scope = self.scopes[s].parent;
+        }
+
+        None
+    }
+
+    fn scope_at(&self, offset: usize) -> usize {
+        // Scopes are created in source order, so the last match is the innermost
+        self.scopes.iter()
+            .rposition(|s| s.span.start <= offset && offset <= s.span.end)
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompletionKind {
+    Method,
+    Function,
+    Variable,
+    Struct,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    pub detail: Option<String>,
+}
+
+struct Walker<'a> {
+    idents: &'a [(String, Range<usize>)],
+    next: usize,
+    funs: &'a [Range<usize>],
+    next_fun: usize,
+    scopes: Vec<Scope>,
+    curr: usize,
+    defs: Vec<Definition>,
+    refs: Vec<Reference>,
+    structs: HashMap<String, Vec<TypeField>>,
+}
+
+impl<'a> Walker<'a> {
+    /// Span of the next occurrence of `name`. The parser records
+    /// identifiers in the order this walker visits them.
+    fn span(&mut self, name: &str) -> Range<usize> {
+        match self.idents[self.next..].iter().position(|(n, _)| n == name) {
+            Some(i) => {
+                self.next += i + 1;
+                self.idents[self.next - 1].1.clone()
+            },
+            None => 0..0,
+        }
+    }
+
+    fn lookup(&self, name: &str, scope: usize, before: usize) -> Option<usize> {
+        let mut scope = Some(scope);
+        let mut inner = true;
+
+        while let Some(s) = scope {
+            let found = self.defs.iter()
+                .position(|d| d.scope == s && d.name == name && (!inner || d.span.start <= before));
+            if found.is_some() {
+                return found;
+            }
+            scope = self.scopes[s].parent;
+            inner = false;
+        }
+
+        None
+    }
+
+    fn define(&mut self, name: &str, kind: DefKind, span: Range<usize>, typ: String) {
+        self.defs.push(Definition {
+            name: name.to_string(),
+            kind,
+            span,
+            typ,
+            scope: self.curr,
+            declared: false,
+        });
+    }
+
+    fn reference(&mut self, name: &str, span: Range<usize>) -> Option<usize> {
+        let def = self.lookup(name, self.curr, span.start);
+        self.refs.push(Reference {
+            name: name.to_string(),
+            span,
+            def,
+            scope: self.curr,
+        });
+        def
+    }
+
+    /// Resolves references to names assigned after the function using
+    /// them, which are in scope by the time it is called.
+    fn resolve_late(&mut self) {
+        for i in 0..self.refs.len() {
+            if self.refs[i].def.is_none() {
+                let r = &self.refs[i];
+                self.refs[i].def = self.lookup(&r.name, r.scope, usize::MAX);
+            }
+        }
+    }
+
+    fn ident_type(&self, def: Option<usize>) -> String {
+        match def {
+            Some(d) => self.defs[d].typ.clone(),
+            None => "Any".to_string(),
+        }
+    }
+
+    fn type_ref(&mut self, typ: &VarType) {
+        match typ {
+            VarType::Ident(typ) => {
+                let span = self.span(typ);
+                self.reference(typ, span);
+            },
+            VarType::List(item) | VarType::Optional(item) => self.type_ref(item),
+            _ => {},
+        }
+    }
+
+    /// Visits a match pattern, defining the names it binds.
+    fn pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Bind(name) => {
+                let span = self.span(name);
+                self.define(name, DefKind::Variable, span, "Any".to_string());
+                if let Some(def) = self.defs.last_mut() {
+                    def.declared = true;
+                }
+            },
+            Pattern::Variant { enum_name, name, fields } => {
+                let span = self.span(enum_name);
+                self.reference(enum_name, span);
+                self.span(name);
+                match fields {
+                    PatternFields::Unit => {},
+                    PatternFields::Tuple(items) => {
+                        for item in items {
+                            self.pattern(item);
+                        }
+                    },
+                    PatternFields::Struct(fields) => {
+                        for (field, p) in fields {
+                            // A shorthand field is the name it binds
+                            match p {
+                                Pattern::Bind(name) if name == field => {},
+                                _ => {
+                                    self.span(field);
+                                },
+                            }
+                            self.pattern(p);
+                        }
+                    },
+                }
+            },
+            Pattern::Wildcard | Pattern::Lit(_) => {},
+        }
+    }
+
+    fn visit_all(&mut self, nodes: &[ASTNode]) {
+        for node in nodes {
+            self.visit(node);
+        }
+    }
+
+    /// Visits a node and returns its inferred type.
+    fn visit(&mut self, node: &ASTNode) -> String {
+        match node {
+            ASTNode::Lit(v) => match v {
+                Value::Int(_) => "Int",
+                Value::Float(_) => "Float",
+                Value::Str(_) => "String",
+                Value::Bool(_) => "Bool",
+                _ => "Any",
+            }.to_string(),
+            ASTNode::Ident(name) => {
+                let span = self.span(name);
+                let def = self.reference(name, span);
+                self.ident_type(def)
+            },
+            ASTNode::Assign(a) => {
+                let name = match &*a.left {
+                    ASTNode::Ident(name) => name,
+                    target => {
+                        self.visit(target);
+                        return self.visit(&a.right);
+                    },
+                };
+                let span = self.span(name);
+                let typ = self.visit(&a.right);
+
+                // `+=` and friends update a name, they never define one
+                let exists = self.defs.iter().any(|d| d.scope == self.curr && &d.name == name);
+                if a.op.is_some() || exists {
+                    self.reference(name, span);
+                } else {
+                    let kind = match &*a.right {
+                        ASTNode::Fun(_) => DefKind::Function,
+                        _ => DefKind::Variable,
+                    };
+                    self.define(name, kind, span, typ.clone());
+                }
+                typ
+            },
+            ASTNode::Decl(d) => {
+                let span = self.span(&d.name);
+                let typ = self.visit(&d.value);
+                let kind = match &*d.value {
+                    ASTNode::Fun(_) => DefKind::Function,
+                    _ => DefKind::Variable,
+                };
+                self.define(&d.name, kind, span, typ.clone());
+                if let Some(def) = self.defs.last_mut() {
+                    def.declared = true;
+                }
+                typ
+            },
+            ASTNode::Array(a) => {
+                for item in &a.items {
+                    self.visit(item);
+                }
+                "List".to_string()
+            },
+            ASTNode::ObjIns(obj) => {
+                if let Some(name) = &obj.name {
+                    let span = self.span(name);
+                    self.reference(name, span);
+                }
+                for prop in &obj.props {
+                    if let ASTNode::Spread(v) = &*prop.value {
+                        self.visit(v);
+                        continue;
+                    }
+                    self.span(&prop.name);
+                    self.visit(&prop.value);
+                }
+                obj.name.clone().unwrap_or_else(|| "Object".to_string())
+            },
+            ASTNode::Fun(f) => {
+                let span = match self.funs.get(self.next_fun) {
+                    Some(s) => s.clone(),
+                    None => 0..0,
+                };
+                self.next_fun += 1;
+
+                self.scopes.push(Scope { parent: Some(self.curr), span });
+                let parent = self.curr;
+                self.curr = self.scopes.len() - 1;
+
+                for param in &f.params {
+                    let span = self.span(&param.name);
+                    self.define(&param.name, DefKind::Param, span, "Any".to_string());
+                }
+                self.visit_all(&f.body);
+
+                self.curr = parent;
+
+                let params = f.params.iter().map(|p| p.name.as_str()).collect::<Vec<_>>();
+                format!("fn({})", params.join(", "))
+            },
+            ASTNode::Call(c) => {
+                self.visit(&c.callee);
+                self.visit_all(&c.args);
+
+                match &*c.callee {
+                    ASTNode::ProbAccess(p) if p.property == "map" => "List",
+                    _ => "Any",
+                }.to_string()
+            },
+            ASTNode::ProbAccess(p) => {
+                let object = self.visit(&p.object);
+                self.span(&p.property);
+
+                let field = self.structs.get(&object)
+                    .and_then(|fields| fields.iter().find(|f| f.name == p.property));
+                match field {
+                    Some(f) => var_type_name(&f.typ),
+                    None => "Any".to_string(),
+                }
+            },
+            ASTNode::Index(i) => {
+                let object = self.visit(&i.object);
+                self.visit(&i.index);
+                match object.as_str() {
+                    "String" => "String",
+                    _ => "Any",
+                }.to_string()
+            },
+            ASTNode::Neg(v) => match self.visit(v).as_str() {
+                t @ ("Int" | "Float") => t.to_string(),
+                _ => "Any".to_string(),
+            },
+            ASTNode::BinOp(b) => {
+                let left = self.visit(&b.left);
+                let right = self.visit(&b.right);
+
+                match (&b.op, left.as_str(), right.as_str()) {
+                    (Op::Eq | Op::Lt | Op::Gt, _, _) => "Bool",
+                    (Op::Coalesce, l, r) if l == r => l,
+                    (Op::Coalesce, _, _) => "Any",
+                    (Op::Plus, "String", _) | (Op::Plus, _, "String") => "String",
+                    (Op::Mul, "String", "Int") | (Op::Mul, "Int", "String") => "String",
+                    (_, "Int", "Int") => "Int",
+                    (_, "Int" | "Float", "Int" | "Float") => "Float",
+                    _ => "Any",
+                }.to_string()
+            },
+            ASTNode::Ret(r) => {
+                if let Some(v) = &*r.value {
+                    self.visit(v);
+                }
+                "Any".to_string()
+            },
+            ASTNode::If(i) => {
+                self.visit(&i.cond);
+                self.visit_all(&i.body);
+                if let Some(els) = &i.els {
+                    self.visit_all(els);
+                }
+                "Any".to_string()
+            },
+            ASTNode::For(f) => {
+                let (idents, it) = match &f.cond {
+                    ForCond::FromIt { ident, it } => (std::slice::from_ref(ident), Some(it)),
+                    ForCond::Unpack { idents, it } => (idents.as_slice(), Some(it)),
+                    ForCond::None => (&[][..], None),
+                };
+                let spans = idents.iter().map(|i| self.span(i)).collect::<Vec<_>>();
+                if let Some(it) = it {
+                    self.visit(it);
+                }
+                for (ident, span) in idents.iter().zip(spans) {
+                    if !self.defs.iter().any(|d| d.scope == self.curr && &d.name == ident) {
+                        self.define(ident, DefKind::Variable, span, "Any".to_string());
+                    }
+                }
+                self.visit_all(&f.body);
+                "Any".to_string()
+            },
+            ASTNode::Test(t) => {
+                self.visit_all(&t.body);
+                "Any".to_string()
+            },
+            ASTNode::Import(i) => {
+                let name = &i.path[i.path.len() - 1];
+                let span = self.span(name);
+                self.define(name, DefKind::Variable, span, "module".to_string());
+                "Any".to_string()
+            },
+            ASTNode::Use(u) => {
+                let span = self.span(&u.path[0]);
+                self.reference(&u.path[0], span);
+                let name = &u.path[u.path.len() - 1];
+                let span = self.span(name);
+                self.define(name, DefKind::Variable, span, "Any".to_string());
+                "Any".to_string()
+            },
+            ASTNode::Module(m) => {
+                let span = self.span(&m.name);
+                self.define(&m.name, DefKind::Variable, span, "module".to_string());
+                self.visit_all(&m.body);
+                "Any".to_string()
+            },
+            ASTNode::Export(node) => {
+                self.visit(node);
+
+                // Exported names are used by the importing scripts
+                let name = match &**node {
+                    ASTNode::Assign(a) => match &*a.left {
+                        ASTNode::Ident(name) => Some(name),
+                        _ => None,
+                    },
+                    ASTNode::Decl(d) => Some(&d.name),
+                    ASTNode::Module(m) => Some(&m.name),
+                    ASTNode::Import(i) => i.path.last(),
+                    ASTNode::StructDef(s) => Some(&s.name),
+                    ASTNode::EnumDef(e) => Some(&e.name),
+                    _ => None,
+                };
+                let def = name.and_then(|n| self.defs.iter().rposition(|d| d.scope == self.curr && &d.name == n));
+                if let Some(i) = def {
+                    let d = &self.defs[i];
+                    self.refs.push(Reference {
+                        name: d.name.clone(),
+                        span: d.span.clone(),
+                        def: Some(i),
+                        scope: self.curr,
+                    });
+                }
+                "Any".to_string()
+            },
+            ASTNode::StructDef(s) => {
+                let span = self.span(&s.name);
+                for field in &s.fields {
+                    self.span(&field.name);
+                    self.type_ref(&field.typ);
+                }
+
+                let fields = s.fields.iter()
+                    .map(|f| format!("{}: {}", f.name, var_type_name(&f.typ)))
+                    .collect::<Vec<_>>();
+                let typ = format!("struct {} {{ {} }}", s.name, fields.join(", "));
+                self.define(&s.name, DefKind::Struct, span, typ);
+                self.structs.insert(s.name.clone(), s.fields.clone());
+
+                "Any".to_string()
+            },
+            ASTNode::EnumDef(e) => {
+                let span = self.span(&e.name);
+                let mut variants = Vec::new();
+                for v in &e.variants {
+                    self.span(&v.name);
+                    match &v.shape {
+                        VariantShape::Unit => {},
+                        VariantShape::Tuple(types) => {
+                            for typ in types {
+                                self.type_ref(typ);
+                            }
+                        },
+                        VariantShape::Struct(fields) => {
+                            for field in fields {
+                                self.span(&field.name);
+                                self.type_ref(&field.typ);
+                            }
+                        },
+                    }
+                    variants.push(variant_signature(v));
+                }
+
+                let typ = format!("enum {} {{ {} }}", e.name, variants.join(", "));
+                self.define(&e.name, DefKind::Enum, span, typ);
+
+                "Any".to_string()
+            },
+            ASTNode::Variant(v) => {
+                let span = self.span(&v.enum_name);
+                self.reference(&v.enum_name, span);
+                self.span(&v.name);
+                match &v.args {
+                    VariantArgs::Unit => {},
+                    VariantArgs::Tuple(args) => self.visit_all(args),
+                    VariantArgs::Struct(props) => {
+                        for prop in props {
+                            self.span(&prop.name);
+                            self.visit(&prop.value);
+                        }
+                    },
+                }
+                v.enum_name.clone()
+            },
+            ASTNode::Match(m) => {
+                self.visit(&m.value);
+                for arm in &m.arms {
+                    self.pattern(&arm.pattern);
+                    self.visit_all(&arm.body);
+                }
+                "Any".to_string()
+            },
+            _ => "Any".to_string(),
+        }
+    }
+}
+
+/// `Name`, `Name(A, B)` or `Name { a: A }` for a variant of an enum.
+pub(crate) fn variant_signature(v: &VariantDef) -> String {
+    match &v.shape {
+        VariantShape::Unit => v.name.clone(),
+        VariantShape::Tuple(types) => {
+            let types = types.iter().map(var_type_name).collect::<Vec<_>>();
+            format!("{}({})", v.name, types.join(", "))
+        },
+        VariantShape::Struct(fields) => {
+            let fields = fields.iter()
+                .map(|f| format!("{}: {}", f.name, var_type_name(&f.typ)))
+                .collect::<Vec<_>>();
+            format!("{} {{ {} }}", v.name, fields.join(", "))
+        },
+    }
+}
+
+pub(crate) fn var_type_name(typ: &VarType) -> String {
+    match typ {
+        VarType::Int => "Int".to_string(),
+        VarType::Float => "Float".to_string(),
+        VarType::String => "String".to_string(),
+        VarType::Ident(name) => name.clone(),
+        VarType::List(item) => format!("[{}]", var_type_name(item)),
+        VarType::Optional(typ) => format!("{}?", var_type_name(typ)),
+        _ => "Any".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE: &str = "struct Point { x: Int, y: Float }
+scale = (p, n) => {
+    k = 2
+    x = p.x
+    return n * k
+}
+total = 1 + 2.5
+name = \"a\"
+p = Point { x: 1, y: 2.0 }
+px = p.x
+res = scale(p, total)";
+
+    fn at(code: &str, needle: &str, nth: usize) -> usize {
+        code.match_indices(needle).nth(nth).unwrap().0
+    }
+
+    #[test]
+    fn symbols_and_types() {
+        let a = Analysis::new(CODE);
+        assert!(a.error.is_none());
+
+        let symbols = a.symbols().map(|d| (d.name.as_str(), d.kind)).collect::<Vec<_>>();
+        assert_eq!(symbols, vec![("Point", DefKind::Struct), ("scale", DefKind::Function)]);
+
+        let hover = |needle, nth| a.hover_at(at(CODE, needle, nth)).unwrap().0;
+        assert_eq!(hover("scale", 1), "scale: fn(p, n)");
+        assert_eq!(hover("total", 0), "total: Float");
+        assert_eq!(hover("name", 0), "name: String");
+        assert_eq!(hover("k", 1), "k: Int");
+        assert_eq!(hover("n)", 0), "n: Any");
+        assert_eq!(hover("px", 0), "px: Int");
+        assert_eq!(hover("Point", 1), "struct Point { x: Int, y: Float }");
+    }
+
+    #[test]
+    fn definitions_follow_scopes() {
+        let a = Analysis::new(CODE);
+
+        // `p` inside the function is the parameter, outside the global
+        let inner = a.definition_at(at(CODE, "p.x", 0)).unwrap();
+        assert_eq!(inner.kind, DefKind::Param);
+        let outer = a.definition_at(at(CODE, "p.x", 1)).unwrap();
+        assert_eq!(outer.span.start, at(CODE, "p =", 0));
+
+        let def = a.definition_at(at(CODE, "k\n}", 0)).unwrap();
+        assert_eq!(def.span.start, at(CODE, "k =", 0));
+        assert!(a.definition_at(at(CODE, "return", 0)).is_none());
+    }
+
+    #[test]
+    fn completions() {
+        let a = Analysis::new(CODE);
+
+        let labels = |offset| a.completions_at(CODE, offset).into_iter()
+            .map(|c| c.label)
+            .collect::<Vec<_>>();
+
+        let inside = labels(at(CODE, "return", 0));
+        assert_eq!(&inside[..4], ["p", "n", "k", "x"]);
+        assert!(inside.contains(&"res".to_string()));
+
+        let outside = labels(at(CODE, "name", 0));
+        assert!(outside.contains(&"total".to_string()));
+        assert!(!outside.contains(&"k".to_string()));
+        assert!(!outside.contains(&"name".to_string()));
+
+        let code = "items = [1]\nitems.p";
+        let methods = Analysis::new(code).completions_at(code, code.len());
+        assert_eq!(methods.iter().map(|c| c.label.as_str()).collect::<Vec<_>>(), LIST_METHODS);
+
+        let code = "greeting = \"hi \" + name\nn = greeting.len()";
+        let methods = Analysis::new(code).completions_at(code, code.len() - 5);
+        assert_eq!(methods.iter().map(|c| c.label.as_str()).collect::<Vec<_>>(), STRING_METHODS);
+    }
+
+    #[test]
+    fn enums_and_patterns() {
+        let code = "enum Shape { Circle(Float), Rect { w: Int, h: Int }, Empty }
+s = Shape::Rect { w: 1, h: 2 }
+match s {
+    Shape::Circle(r) => print(r)
+    Shape::Rect { w, h: height } => print(w * height)
+    _ => print(0)
+}";
+        let a = Analysis::new(code);
+        assert!(a.error.is_none());
+
+        let symbols = a.symbols().map(|d| (d.name.as_str(), d.kind)).collect::<Vec<_>>();
+        assert_eq!(symbols, vec![("Shape", DefKind::Enum)]);
+
+        let hover = |needle, nth| a.hover_at(at(code, needle, nth)).unwrap().0;
+        assert_eq!(hover("Shape", 1), "enum Shape { Circle(Float), Rect { w: Int, h: Int }, Empty }");
+        assert_eq!(hover("s =", 0), "s: Shape");
+
+        let def = a.definition_at(at(code, "height)", 0)).unwrap();
+        assert_eq!(def.span.start, at(code, "height", 0));
+        let def = a.definition_at(at(code, "w *", 0)).unwrap();
+        assert_eq!(def.span.start, at(code, "w, h", 0));
+        let def = a.definition_at(at(code, "r)", 1)).unwrap();
+        assert_eq!(def.span.start, at(code, "r)", 0));
+    }
+
+    #[test]
+    fn parse_errors_keep_names() {
+        let code = "alpha = 1\nbeta = (alpha";
+        let a = Analysis::new(code);
+        let err = a.error.as_ref().unwrap();
+        assert_eq!(err.line, 2);
+        assert!(a.completions_at(code, code.len()).iter().any(|c| c.label == "alpha"));
+    }
+}
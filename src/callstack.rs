@@ -1,5 +1,9 @@
+use crate::list::ListCall;
 use crate::Ptr;
 use crate::StackValue;
+use crate::program::ProgramError;
+use crate::program::Reader;
+use crate::program::Writer;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum BuildIn {
@@ -8,6 +12,7 @@ pub enum BuildIn {
         inx: u32,
         blk: u32,
     },
+    List(Box<ListCall>),
     None
 }
 
@@ -116,4 +121,70 @@ impl Callstack {
     pub fn depth(&self) -> usize {
         self.stack.len()
     }
+
+    pub fn frames(&self) -> &[Call] {
+        &self.stack
+    }
+
+    pub(crate) fn write(&self, w: &mut Writer) -> Result<(), ProgramError> {
+        w.u32(self.stack.len() as u32);
+        for call in &self.stack {
+            w.u32(call.blk);
+            w.u32(call.pc);
+            w.u32(call.scope_id);
+            w.u32(call.values.len() as u32);
+            for v in &call.values {
+                w.stack_value(v)?;
+            }
+            match &call.buildin {
+                BuildIn::Map { ptr, inx, blk } => {
+                    w.u8(1);
+                    w.ptr(ptr);
+                    w.u32(*inx);
+                    w.u32(*blk);
+                },
+                BuildIn::List(call) => {
+                    w.u8(2);
+                    call.write(w)?;
+                },
+                BuildIn::None => w.u8(0)
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read(r: &mut Reader) -> Result<Self, ProgramError> {
+        let mut stack = vec![];
+        for _ in 0..r.len()? {
+            let blk = r.u32()?;
+            let pc = r.u32()?;
+            let scope_id = r.u32()?;
+            let mut values = vec![];
+            for _ in 0..r.len()? {
+                values.push(r.stack_value()?);
+            }
+            let buildin = match r.u8()? {
+                0 => BuildIn::None,
+                1 => BuildIn::Map {
+                    ptr: r.ptr()?,
+                    inx: r.u32()?,
+                    blk: r.u32()?
+                },
+                2 => BuildIn::List(Box::new(ListCall::read(r)?)),
+                t => return Err(ProgramError::Invalid(format!("unknown builtin tag {}", t)))
+            };
+            stack.push(Call {
+                blk,
+                pc,
+                scope_id,
+                values,
+                buildin
+            });
+        }
+
+        Ok(Self {
+            log: 0,
+            stack
+        })
+    }
 }
\ No newline at end of file
@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::Obj;
+use crate::ObjProp;
+use crate::RunResult;
+use crate::Value;
+use crate::Variant;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvertError {
+    msg: String
+}
+
+impl ConvertError {
+    pub fn new(msg: impl Into<String>) -> Self {
+        Self {
+            msg: msg.into()
+        }
+    }
+
+    pub fn expected(expected: &str, got: &Value) -> Self {
+        Self::new(format!("expected {} but got {}", expected, value_kind(got)))
+    }
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+pub fn value_kind(val: &Value) -> &'static str {
+    match val {
+        Value::Int(_) => "int",
+        Value::Float(_) => "float",
+        Value::Str(_) => "string",
+        Value::Bool(_) => "bool",
+        Value::List(_) => "list",
+        Value::Ptr(_) => "pointer",
+        Value::Fn(_) | Value::Native(_) => "function",
+        Value::Obj(_) => "object",
+        Value::Enum(_) => "enum",
+        Value::ListIter(_) => "iterator",
+        Value::UndefIdent(_) => "undefined",
+        Value::UndefCall { .. } => "undefined call",
+        Value::UserData(_) => "userdata",
+        Value::None => "none",
+    }
+}
+
+/// Conversion from a script value into a Rust type.
+pub trait FromValue: Sized {
+    fn from_value(val: Value) -> Result<Self, ConvertError>;
+}
+
+/// Conversion from a Rust type into a script value.
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+impl FromValue for Value {
+    fn from_value(val: Value) -> Result<Self, ConvertError> {
+        Ok(val)
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+macro_rules! impl_from_int {
+    ($($t:ty),*) => {
+        $(
+            impl FromValue for $t {
+                fn from_value(val: Value) -> Result<Self, ConvertError> {
+                    match val {
+                        Value::Int(i) => <$t>::try_from(i).map_err(|_| {
+                            ConvertError::new(format!("{} does not fit in {}", i, stringify!($t)))
+                        }),
+                        _ => Err(ConvertError::expected("int", &val))
+                    }
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_into_int {
+    ($($t:ty),*) => {
+        $(
+            impl IntoValue for $t {
+                fn into_value(self) -> Value {
+                    Value::Int(self as i64)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_try_into_int {
+    ($($t:ty),*) => {
+        $(
+            impl TryFrom<$t> for Value {
+                type Error = ConvertError;
+
+                fn try_from(v: $t) -> Result<Self, ConvertError> {
+                    match i64::try_from(v) {
+                        Ok(i) => Ok(Value::Int(i)),
+                        Err(_) => Err(ConvertError::new(format!("{} does not fit in int", v)))
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+impl_into_int!(i8, i16, i32, i64, isize, u8, u16, u32);
+// Values past `i64::MAX` do not fit in an int, so `u64` and `usize`
+// convert with `Value::try_from` and fail like serde does
+impl_try_into_int!(u64, usize);
+
+impl FromValue for f64 {
+    fn from_value(val: Value) -> Result<Self, ConvertError> {
+        match val {
+            Value::Float(f) => Ok(f),
+            Value::Int(i) => Ok(i as f64),
+            _ => Err(ConvertError::expected("float", &val))
+        }
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Float(self)
+    }
+}
+
+impl FromValue for f32 {
+    fn from_value(val: Value) -> Result<Self, ConvertError> {
+        f64::from_value(val).map(|f| f as f32)
+    }
+}
+
+impl IntoValue for f32 {
+    fn into_value(self) -> Value {
+        Value::Float(self as f64)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(val: Value) -> Result<Self, ConvertError> {
+        match val {
+            Value::Bool(b) => Ok(b),
+            _ => Err(ConvertError::expected("bool", &val))
+        }
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl FromValue for String {
+    fn from_value(val: Value) -> Result<Self, ConvertError> {
+        match val {
+            Value::Str(s) => Ok(s),
+            _ => Err(ConvertError::expected("string", &val))
+        }
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::Str(self)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::Str(self.to_string())
+    }
+}
+
+impl FromValue for () {
+    fn from_value(val: Value) -> Result<Self, ConvertError> {
+        match val {
+            Value::None => Ok(()),
+            _ => Err(ConvertError::expected("none", &val))
+        }
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::None
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(val: Value) -> Result<Self, ConvertError> {
+        match val {
+            Value::None => Ok(None),
+            _ => T::from_value(val).map(Some)
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        match self {
+            Some(v) => v.into_value(),
+            None => Value::None
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(val: Value) -> Result<Self, ConvertError> {
+        match val {
+            Value::List(list) => list.into_iter().map(T::from_value).collect(),
+            _ => Err(ConvertError::expected("list", &val))
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::List(self.into_iter().map(|v| v.into_value()).collect())
+    }
+}
+
+impl<T: FromValue> FromValue for HashMap<String, T> {
+    fn from_value(val: Value) -> Result<Self, ConvertError> {
+        match val {
+            Value::Obj(obj) => {
+                obj.props.into_iter().map(|prop| {
+                    let v = T::from_value(prop.value)?;
+                    Ok((prop.name, v))
+                }).collect()
+            },
+            _ => Err(ConvertError::expected("object", &val))
+        }
+    }
+}
+
+/// Host enums convert through `Variant`, matching on its name and
+/// reading the fields with `item` or `field`.
+impl FromValue for Variant {
+    fn from_value(val: Value) -> Result<Self, ConvertError> {
+        match val {
+            Value::Enum(v) => Ok(v),
+            _ => Err(ConvertError::expected("enum", &val))
+        }
+    }
+}
+
+impl IntoValue for Variant {
+    fn into_value(self) -> Value {
+        Value::Enum(self)
+    }
+}
+
+impl<T: IntoValue> IntoValue for HashMap<String, T> {
+    fn into_value(self) -> Value {
+        Value::Obj(
+            Obj {
+                name: None,
+                props: self.into_iter().map(|(name, value)| {
+                    ObjProp {
+                        name,
+                        value: value.into_value()
+                    }
+                }).collect()
+            }
+        )
+    }
+}
+
+macro_rules! impl_tuple {
+    ($len:expr, $($name:ident),+) => {
+        impl<$($name: FromValue),+> FromValue for ($($name,)+) {
+            fn from_value(val: Value) -> Result<Self, ConvertError> {
+                match val {
+                    Value::List(list) => {
+                        if list.len() != $len {
+                            return Err(ConvertError::new(
+                                format!("expected list of {} items but got {}", $len, list.len())
+                            ));
+                        }
+                        let mut items = list.into_iter();
+                        Ok(($($name::from_value(items.next().unwrap())?,)+))
+                    },
+                    _ => Err(ConvertError::expected("list", &val))
+                }
+            }
+        }
+
+        impl<$($name: IntoValue),+> IntoValue for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn into_value(self) -> Value {
+                let ($($name,)+) = self;
+                Value::List(vec![$($name.into_value()),+])
+            }
+        }
+    };
+}
+
+impl_tuple!(1, A);
+impl_tuple!(2, A, B);
+impl_tuple!(3, A, B, C);
+impl_tuple!(4, A, B, C, D);
+impl_tuple!(5, A, B, C, D, E);
+impl_tuple!(6, A, B, C, D, E, F);
+
+impl RunResult {
+    /// Converts the returned value of a finished run into a Rust type.
+    pub fn into_value<T: FromValue>(self) -> Result<T, ConvertError> {
+        match self {
+            RunResult::Value(v) => T::from_value(v),
+            RunResult::None => T::from_value(Value::None),
+            RunResult::Await { .. } => Err(ConvertError::new("script is awaiting a value")),
+            RunResult::Paused { .. } => Err(ConvertError::new("script is paused")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Vm;
+
+    #[test]
+    fn primitives_round_trip() {
+        assert_eq!(i32::from_value(5i32.into_value()), Ok(5));
+        assert!(u8::from_value(Value::Int(300)).is_err());
+        assert_eq!(Value::try_from(7u64), Ok(Value::Int(7)));
+        assert!(Value::try_from(u64::MAX).is_err());
+        assert!(Value::try_from(usize::MAX).is_err());
+        assert_eq!(f64::from_value(Value::Int(2)), Ok(2.0));
+        assert_eq!(bool::from_value(true.into_value()), Ok(true));
+        assert_eq!(String::from_value("abc".into_value()), Ok("abc".to_string()));
+    }
+
+    #[test]
+    fn option_from_none() {
+        assert_eq!(Option::<i64>::from_value(Value::None), Ok(None));
+        assert_eq!(Option::<i64>::from_value(Value::Int(1)), Ok(Some(1)));
+        assert_eq!(Some(1i64).into_value(), Value::Int(1));
+    }
+
+    #[test]
+    fn tuple_from_list() {
+        let val = (1i64, "a".to_string(), 2.5f64).into_value();
+        assert_eq!(val, Value::List(vec![
+            Value::Int(1),
+            Value::Str("a".to_string()),
+            Value::Float(2.5)
+        ]));
+        let t = <(i64, String, f64)>::from_value(val).unwrap();
+        assert_eq!(t, (1, "a".to_string(), 2.5));
+        assert!(<(i64, i64)>::from_value(Value::List(vec![Value::Int(1)])).is_err());
+    }
+
+    #[test]
+    fn script_result_to_vec() {
+        let mut vm = Vm::new();
+        let res = vm.run_code("return [1, 2, 3]");
+        let list: Vec<i64> = res.into_value().unwrap();
+        assert_eq!(list, vec![1, 2, 3]);
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Status {
+        Ok(i64),
+        Error { code: i64, message: String },
+        Pending,
+    }
+
+    impl FromValue for Status {
+        fn from_value(val: Value) -> Result<Self, ConvertError> {
+            let v = Variant::from_value(val)?;
+            let get = |field: Option<&Value>| field.cloned().ok_or_else(|| ConvertError::new(format!("{} is missing a field", v)));
+            match v.name.as_str() {
+                "Ok" => Ok(Status::Ok(i64::from_value(get(v.item(0))?)?)),
+                "Error" => Ok(Status::Error {
+                    code: i64::from_value(get(v.field("code"))?)?,
+                    message: String::from_value(get(v.field("message"))?)?,
+                }),
+                "Pending" => Ok(Status::Pending),
+                _ => Err(ConvertError::new(format!("unknown variant {}", v))),
+            }
+        }
+    }
+
+    impl IntoValue for Status {
+        fn into_value(self) -> Value {
+            match self {
+                Status::Ok(n) => Variant::tuple("Status", "Ok", vec![n.into_value()]),
+                Status::Error { code, message } => Variant::record("Status", "Error", vec![
+                    ObjProp { name: "code".to_string(), value: code.into_value() },
+                    ObjProp { name: "message".to_string(), value: message.into_value() },
+                ]),
+                Status::Pending => Variant::unit("Status", "Pending"),
+            }.into_value()
+        }
+    }
+
+    #[test]
+    fn host_enum_round_trip() {
+        let mut vm = Vm::new();
+        let res = vm.run_code(r#"
+        enum Status { Ok(int), Error { code: int, message: string }, Pending }
+        return [Status::Ok(1), Status::Error { code: 404, message: "gone" }, Status::Pending]"#);
+        let list: Vec<Status> = res.into_value().unwrap();
+        assert_eq!(list, vec![
+            Status::Ok(1),
+            Status::Error { code: 404, message: "gone".to_string() },
+            Status::Pending,
+        ]);
+
+        let val = Status::Error { code: 1, message: "m".to_string() }.into_value();
+        assert_eq!(val.to_string(), r#"Status::Error { code: 1, message: "m" }"#);
+        assert_eq!(Status::from_value(val), Ok(Status::Error { code: 1, message: "m".to_string() }));
+        assert!(Status::from_value(Value::Int(1)).is_err());
+    }
+
+    #[test]
+    fn script_result_to_map() {
+        let mut vm = Vm::new();
+        let res = vm.run_code(r#"
+        return {
+            a: [1, 2],
+            b: [3]
+        }"#);
+        let map: HashMap<String, Vec<i64>> = res.into_value().unwrap();
+        assert_eq!(map.get("a"), Some(&vec![1, 2]));
+        assert_eq!(map.get("b"), Some(&vec![3]));
+    }
+}
@@ -0,0 +1,760 @@
+use std::cmp::Reverse;
+use std::ops::Range;
+
+use crate::ASTNode;
+use crate::Array;
+use crate::Assign;
+use crate::BinOp;
+use crate::Call;
+use crate::Decl;
+use crate::EnumDef;
+use crate::For;
+use crate::ForCond;
+use crate::Fun;
+use crate::If;
+use crate::Import;
+use crate::Index;
+use crate::Match;
+use crate::MatchArm;
+use crate::ModuleDef;
+use crate::ObjIns;
+use crate::Op;
+use crate::Param;
+use crate::ParseError;
+use crate::Parser;
+use crate::Pattern;
+use crate::PatternFields;
+use crate::ProbAccess;
+use crate::Property;
+use crate::Ret;
+use crate::StructDef;
+use crate::TestDef;
+use crate::TypeField;
+use crate::Use;
+use crate::Value;
+use crate::VarType;
+use crate::VariantArgs;
+use crate::VariantDef;
+use crate::VariantIns;
+use crate::VariantShape;
+use crate::parsing::comment_spans;
+use crate::parsing::negate;
+use crate::parsing::parse_float;
+use crate::parsing::parse_int;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeKind {
+    Root,
+    Assign,
+    Decl,
+    ObjIns,
+    Array,
+    Fun,
+    Call,
+    ProbAccess,
+    Index,
+    Neg,
+    BinOp,
+    Ret,
+    If,
+    For,
+    Struct,
+    Enum,
+    Variant,
+    Match,
+    MatchArm,
+    Pattern,
+    Test,
+    Module,
+    Import,
+    Use,
+    Export,
+    Spread,
+    Ident,
+    Lit,
+    /// A parenthesized expression, which has no node of its own in the AST.
+    Paren,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    Ident,
+    Int,
+    Float,
+    Str,
+    Keyword,
+    Punct,
+    /// Holds the trivia after the last token.
+    Eof,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriviaKind {
+    Whitespace,
+    Newline,
+    Comment,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,
+}
+
+/// A token with the whitespace and comments around it. Trailing trivia
+/// runs to the end of the token's line, the line break and everything
+/// up to the next token lead that token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxToken {
+    pub kind: TokenKind,
+    pub text: String,
+    pub span: Range<usize>,
+    pub leading: Vec<Trivia>,
+    pub trailing: Vec<Trivia>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxNode {
+    pub kind: NodeKind,
+    pub children: Vec<SyntaxElement>,
+}
+
+/// Lossless syntax tree of a script. Every byte of the source is held by
+/// a token or its trivia, so `text` gives the input back unchanged, and
+/// `ast` reads the same nodes `Parser::parse` returns out of the tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTree {
+    pub root: SyntaxNode,
+}
+
+impl SyntaxTree {
+    pub fn parse(input: &str) -> Result<SyntaxTree, ParseError> {
+        let mut parser = Parser::new(input);
+        parser.try_parse()?;
+
+        let spans = parser.token_spans();
+        let mut tokens: Vec<SyntaxToken> = Vec::with_capacity(spans.len() + 1);
+        let mut pos = 0;
+
+        for (kind, span) in spans.iter().cloned().chain(std::iter::once((TokenKind::Eof, input.len()..input.len()))) {
+            let gap = trivia(&input[pos..span.start]);
+
+            // The previous token keeps what is left of its line
+            let split = match tokens.is_empty() {
+                true => 0,
+                false => gap.iter().position(|t| t.kind == TriviaKind::Newline).unwrap_or(gap.len()),
+            };
+            let mut leading = gap;
+            let trailing = leading.drain(..split).collect::<Vec<_>>();
+            if let Some(prev) = tokens.last_mut() {
+                prev.trailing = trailing;
+            }
+
+            tokens.push(SyntaxToken {
+                kind,
+                text: input[span.clone()].to_string(),
+                span: span.clone(),
+                leading,
+                trailing: vec![],
+            });
+            pos = span.end;
+        }
+
+        let eof = tokens.pop();
+
+        let mut nodes = parser.node_spans().iter().cloned().enumerate().collect::<Vec<_>>();
+        // Parents are recorded after their children, so a parent spanning
+        // the same tokens as its child is ordered first
+        nodes.sort_by_key(|(i, (_, r))| (r.start, Reverse(r.end), Reverse(*i)));
+        let nodes = nodes.into_iter().map(|(_, n)| n).collect::<Vec<_>>();
+
+        let count = tokens.len();
+        let mut tokens = tokens.into_iter();
+        let mut next = 0;
+        let mut root = build(NodeKind::Root, 0..count, &nodes, &mut next, &mut tokens);
+        root.children.extend(eof.map(SyntaxElement::Token));
+
+        Ok(SyntaxTree { root })
+    }
+
+    /// The source the tree was parsed from.
+    pub fn text(&self) -> String {
+        self.root.text()
+    }
+
+    /// The statements as AST nodes.
+    pub fn ast(&self) -> Vec<ASTNode> {
+        self.root.nodes().filter_map(|n| n.to_ast()).collect()
+    }
+}
+
+fn build(
+    kind: NodeKind,
+    range: Range<usize>,
+    nodes: &[(NodeKind, Range<usize>)],
+    next: &mut usize,
+    tokens: &mut impl Iterator<Item = SyntaxToken>,
+) -> SyntaxNode {
+    let mut children = vec![];
+    let mut i = range.start;
+
+    while i < range.end {
+        match nodes.get(*next) {
+            Some((k, r)) if r.start == i && r.end <= range.end => {
+                *next += 1;
+                children.push(SyntaxElement::Node(build(*k, r.clone(), nodes, next, tokens)));
+                i = r.end;
+            },
+            _ => {
+                if let Some(token) = tokens.next() {
+                    children.push(SyntaxElement::Token(token));
+                }
+                i += 1;
+            }
+        }
+    }
+
+    SyntaxNode { kind, children }
+}
+
+fn trivia(text: &str) -> Vec<Trivia> {
+    let mut out = vec![];
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        let (kind, len) = if rest.starts_with("//") || rest.starts_with("/*") {
+            (TriviaKind::Comment, comment_spans(rest)[0].end)
+        } else if rest.starts_with("\r\n") {
+            (TriviaKind::Newline, 2)
+        } else if rest.starts_with('\n') {
+            (TriviaKind::Newline, 1)
+        } else {
+            let len = ["\n", "\r\n", "/"].iter()
+                .filter_map(|p| rest.find(p))
+                .min()
+                .unwrap_or(rest.len());
+            (TriviaKind::Whitespace, len)
+        };
+
+        out.push(Trivia { kind, text: rest[..len].to_string() });
+        rest = &rest[len..];
+    }
+
+    out
+}
+
+impl SyntaxToken {
+    pub fn text_with_trivia(&self) -> String {
+        let mut out = String::new();
+        for t in &self.leading {
+            out.push_str(&t.text);
+        }
+        out.push_str(&self.text);
+        for t in &self.trailing {
+            out.push_str(&t.text);
+        }
+        out
+    }
+}
+
+impl SyntaxNode {
+    /// Source text of the node with the trivia of its tokens.
+    pub fn text(&self) -> String {
+        self.tokens().iter().map(|t| t.text_with_trivia()).collect()
+    }
+
+    /// All tokens under the node in source order.
+    pub fn tokens(&self) -> Vec<&SyntaxToken> {
+        let mut out = vec![];
+        for child in &self.children {
+            match child {
+                SyntaxElement::Token(t) => out.push(t),
+                SyntaxElement::Node(n) => out.extend(n.tokens()),
+            }
+        }
+        out
+    }
+
+    /// Direct child nodes.
+    pub fn nodes(&self) -> impl Iterator<Item = &SyntaxNode> {
+        self.children.iter().filter_map(|c| match c {
+            SyntaxElement::Node(n) => Some(n),
+            _ => None,
+        })
+    }
+
+    fn own_tokens(&self) -> Vec<&SyntaxToken> {
+        self.children.iter().filter_map(|c| match c {
+            SyntaxElement::Token(t) => Some(t),
+            _ => None,
+        }).collect()
+    }
+
+    fn asts(&self) -> Vec<ASTNode> {
+        self.nodes().filter_map(|n| n.to_ast()).collect()
+    }
+
+    fn first_ast(&self) -> Option<ASTNode> {
+        self.nodes().next().and_then(|n| n.to_ast())
+    }
+
+    /// The AST node this syntax node stands for. Parentheses give the
+    /// node they wrap, the root has none.
+    pub fn to_ast(&self) -> Option<ASTNode> {
+        let tokens = self.own_tokens();
+        let text = |i: usize| tokens.get(i).map(|t| t.text.clone()).unwrap_or_default();
+
+        let node = match self.kind {
+            NodeKind::Root => return None,
+            NodeKind::Paren => return self.first_ast(),
+            NodeKind::Ident => ASTNode::Ident(text(0)),
+            NodeKind::Lit => ASTNode::Lit(literal(tokens.first()?)?),
+            NodeKind::Assign => {
+                let op = match tokens.iter().find(|t| t.text.ends_with('='))?.text.as_str() {
+                    "+=" => Some(Op::Plus),
+                    "-=" => Some(Op::Minus),
+                    "*=" => Some(Op::Mul),
+                    "/=" => Some(Op::Div),
+                    _ => None,
+                };
+                // Plain names are a token of the assignment, other targets a node
+                let mut nodes = self.asts().into_iter();
+                let left = match tokens.first()?.kind {
+                    TokenKind::Ident => ASTNode::Ident(text(0)),
+                    _ => nodes.next()?,
+                };
+                ASTNode::Assign(Assign {
+                    left: Box::new(left),
+                    right: Box::new(nodes.next()?),
+                    op,
+                })
+            },
+            NodeKind::Decl => ASTNode::Decl(Decl {
+                name: text(1),
+                value: Box::new(self.first_ast()?),
+                constant: text(0) == "const",
+            }),
+            NodeKind::Array => ASTNode::Array(Array { items: self.asts() }),
+            NodeKind::ObjIns => {
+                let named = tokens.first().is_some_and(|t| t.kind == TokenKind::Ident)
+                    && tokens.get(1).is_some_and(|t| t.text == "{");
+
+                let mut props = vec![];
+                let mut prop = String::new();
+                for child in &self.children {
+                    match child {
+                        SyntaxElement::Token(t) if t.kind == TokenKind::Ident => prop = t.text.clone(),
+                        SyntaxElement::Node(n) if n.kind == NodeKind::Spread => props.push(Property {
+                            name: String::new(),
+                            value: Box::new(n.to_ast()?),
+                        }),
+                        SyntaxElement::Node(n) => props.push(Property {
+                            name: prop.clone(),
+                            value: Box::new(n.to_ast()?),
+                        }),
+                        _ => {}
+                    }
+                }
+
+                ASTNode::ObjIns(ObjIns {
+                    name: if named { Some(text(0)) } else { None },
+                    props,
+                })
+            },
+            NodeKind::Fun => ASTNode::Fun(Fun {
+                params: tokens.iter()
+                    .take_while(|t| t.text != "=>")
+                    .filter(|t| t.kind == TokenKind::Ident)
+                    .map(|t| Param { name: t.text.clone() })
+                    .collect(),
+                body: self.asts(),
+            }),
+            NodeKind::Call => {
+                let mut nodes = self.asts().into_iter();
+                ASTNode::Call(Call {
+                    callee: Box::new(nodes.next()?),
+                    args: nodes.collect(),
+                })
+            },
+            NodeKind::ProbAccess => ASTNode::ProbAccess(ProbAccess {
+                object: Box::new(self.first_ast()?),
+                property: tokens.last()?.text.clone(),
+                optional: tokens.iter().any(|t| t.text == "?."),
+            }),
+            NodeKind::Index => {
+                let mut nodes = self.asts().into_iter();
+                ASTNode::Index(Index {
+                    object: Box::new(nodes.next()?),
+                    index: Box::new(nodes.next()?),
+                })
+            },
+            NodeKind::Neg => negate(self.first_ast()?),
+            NodeKind::BinOp => {
+                let mut nodes = self.asts().into_iter();
+                let op = match text(0).as_str() {
+                    "+" => Op::Plus,
+                    "-" => Op::Minus,
+                    "*" => Op::Mul,
+                    "/" => Op::Div,
+                    "<" => Op::Lt,
+                    ">" => Op::Gt,
+                    "??" => Op::Coalesce,
+                    _ => Op::Eq,
+                };
+                ASTNode::BinOp(BinOp {
+                    left: Box::new(nodes.next()?),
+                    op,
+                    right: Box::new(nodes.next()?),
+                })
+            },
+            NodeKind::Ret => ASTNode::Ret(Ret { value: Box::new(self.first_ast()) }),
+            NodeKind::If => {
+                let mut cond = None;
+                let mut body = vec![];
+                let mut els = vec![];
+                let mut in_else = false;
+
+                for child in &self.children {
+                    match child {
+                        SyntaxElement::Token(t) if t.text == "else" => in_else = true,
+                        SyntaxElement::Node(n) if cond.is_none() => cond = n.to_ast(),
+                        SyntaxElement::Node(n) if in_else => els.extend(n.to_ast()),
+                        SyntaxElement::Node(n) => body.extend(n.to_ast()),
+                        _ => {}
+                    }
+                }
+
+                ASTNode::If(If {
+                    cond: Box::new(cond?),
+                    body,
+                    els: if els.is_empty() { None } else { Some(els) },
+                })
+            },
+            NodeKind::For => {
+                let mut nodes = self.asts();
+                let cond = match tokens.get(1) {
+                    Some(t) if t.kind == TokenKind::Ident => {
+                        if nodes.is_empty() {
+                            return None;
+                        }
+                        ForCond::FromIt { ident: t.text.clone(), it: Box::new(nodes.remove(0)) }
+                    },
+                    Some(t) if t.text == "[" => {
+                        if nodes.is_empty() {
+                            return None;
+                        }
+                        let idents = tokens[2..].iter()
+                            .take_while(|t| t.text != "]")
+                            .filter(|t| t.kind == TokenKind::Ident)
+                            .map(|t| t.text.clone())
+                            .collect();
+                        ForCond::Unpack { idents, it: Box::new(nodes.remove(0)) }
+                    },
+                    _ => ForCond::None,
+                };
+                ASTNode::For(For { cond, body: nodes })
+            },
+            NodeKind::Test => {
+                let name = tokens.get(1)?;
+                ASTNode::Test(TestDef {
+                    name: name.text[1..name.text.len() - 1].to_string(),
+                    body: self.asts(),
+                })
+            },
+            NodeKind::Module => ASTNode::Module(ModuleDef {
+                name: text(1),
+                body: self.asts(),
+            }),
+            NodeKind::Import => ASTNode::Import(Import { path: path(&tokens) }),
+            NodeKind::Use => ASTNode::Use(Use { path: path(&tokens) }),
+            NodeKind::Export => ASTNode::Export(Box::new(self.first_ast()?)),
+            NodeKind::Spread => ASTNode::Spread(Box::new(self.first_ast()?)),
+            NodeKind::Struct => ASTNode::StructDef(StructDef { name: text(1), fields: fields(&tokens[3..]) }),
+            NodeKind::Enum => {
+                let mut variants = vec![];
+                // The tokens between the braces
+                let mut rest = &tokens[3..tokens.len() - 1];
+                while let Some((t, after)) = rest.split_first() {
+                    rest = after;
+                    if t.kind != TokenKind::Ident {
+                        continue;
+                    }
+
+                    let shape = match rest.first().map(|t| t.text.as_str()) {
+                        Some(open @ ("(" | "{")) => {
+                            let close = if open == "(" { ")" } else { "}" };
+                            let end = rest.iter().position(|t| t.text == close)?;
+                            let inner = &rest[1..end];
+                            rest = &rest[end + 1..];
+                            match open {
+                                "(" => VariantShape::Tuple(types(inner)),
+                                _ => VariantShape::Struct(fields(inner)),
+                            }
+                        },
+                        _ => VariantShape::Unit,
+                    };
+                    variants.push(VariantDef { name: t.text.clone(), shape });
+                }
+                ASTNode::EnumDef(EnumDef { name: text(1), variants })
+            },
+            NodeKind::Variant => {
+                let args = match text(3).as_str() {
+                    "(" => VariantArgs::Tuple(self.asts()),
+                    "{" => {
+                        let mut props = vec![];
+                        let mut prop = String::new();
+                        for child in &self.children {
+                            match child {
+                                SyntaxElement::Token(t) if t.kind == TokenKind::Ident => prop = t.text.clone(),
+                                SyntaxElement::Node(n) => props.push(Property {
+                                    name: prop.clone(),
+                                    value: Box::new(n.to_ast()?),
+                                }),
+                                _ => {}
+                            }
+                        }
+                        VariantArgs::Struct(props)
+                    },
+                    _ => VariantArgs::Unit,
+                };
+                ASTNode::Variant(VariantIns { enum_name: text(0), name: text(2), args })
+            },
+            NodeKind::Match => {
+                let mut nodes = self.nodes();
+                let value = nodes.next()?.to_ast()?;
+                let arms = nodes.map(|arm| {
+                    let mut nodes = arm.nodes();
+                    let pattern = nodes.next()?.to_pattern()?;
+                    Some(MatchArm { pattern, body: nodes.filter_map(|n| n.to_ast()).collect() })
+                }).collect::<Option<Vec<_>>>()?;
+                ASTNode::Match(Match { value: Box::new(value), arms })
+            },
+            NodeKind::MatchArm | NodeKind::Pattern => return None,
+        };
+
+        Some(node)
+    }
+
+    /// The pattern of a `match` arm this node stands for.
+    fn to_pattern(&self) -> Option<Pattern> {
+        let tokens = self.own_tokens();
+        let first = tokens.first()?;
+
+        match tokens.get(1).map(|t| t.text.as_str()) {
+            _ if first.text == "-" => match literal(tokens.get(1)?)? {
+                Value::Int(i) => Some(Pattern::Lit(Value::Int(-i))),
+                Value::Float(f) => Some(Pattern::Lit(Value::Float(-f))),
+                _ => None,
+            },
+            Some("::") => {
+                let fields = match tokens.get(3).map(|t| t.text.as_str()) {
+                    Some("(") => PatternFields::Tuple(self.nodes().map(|n| n.to_pattern()).collect::<Option<_>>()?),
+                    Some("{") => {
+                        // A field followed by a colon takes the next pattern,
+                        // one without binds its name
+                        let mut fields = vec![];
+                        let mut field: Option<String> = None;
+                        for child in self.children.iter().skip(4) {
+                            match child {
+                                SyntaxElement::Token(t) if t.kind == TokenKind::Ident => {
+                                    if let Some(name) = field.replace(t.text.clone()) {
+                                        fields.push((name.clone(), Pattern::Bind(name)));
+                                    }
+                                },
+                                SyntaxElement::Token(t) if t.text == ":" => {},
+                                SyntaxElement::Token(_) => {
+                                    if let Some(name) = field.take() {
+                                        fields.push((name.clone(), Pattern::Bind(name)));
+                                    }
+                                },
+                                SyntaxElement::Node(n) => fields.push((field.take()?, n.to_pattern()?)),
+                            }
+                        }
+                        PatternFields::Struct(fields)
+                    },
+                    _ => PatternFields::Unit,
+                };
+                Some(Pattern::Variant { enum_name: first.text.clone(), name: tokens[2].text.clone(), fields })
+            },
+            _ if first.text == "_" => Some(Pattern::Wildcard),
+            _ if first.kind == TokenKind::Ident => Some(Pattern::Bind(first.text.clone())),
+            _ => Some(Pattern::Lit(literal(first)?)),
+        }
+    }
+}
+
+/// The value of a literal token.
+fn literal(t: &SyntaxToken) -> Option<Value> {
+    Some(match t.kind {
+        TokenKind::Str => Value::Str(t.text[1..t.text.len() - 1].to_string()),
+        TokenKind::Int => Value::Int(i64::try_from(parse_int(&t.text)?).ok()?),
+        TokenKind::Float => Value::Float(parse_float(&t.text)?),
+        _ if t.text == "none" => Value::None,
+        _ => Value::Bool(t.text == "true"),
+    })
+}
+
+/// The type the tokens start with and how many tokens it takes.
+fn var_type(tokens: &[&SyntaxToken]) -> Option<(VarType, usize)> {
+    let (typ, len) = match tokens.first()?.text.as_str() {
+        "[" => {
+            let (item, len) = var_type(&tokens[1..])?;
+            (VarType::List(Box::new(item)), len + 2)
+        },
+        "Int" => (VarType::Int, 1),
+        "Float" => (VarType::Float, 1),
+        "String" => (VarType::String, 1),
+        other => (VarType::Ident(other.to_string()), 1),
+    };
+    match tokens.get(len) {
+        Some(t) if t.text == "?" => Some((typ.optional(), len + 1)),
+        _ => Some((typ, len)),
+    }
+}
+
+/// The comma separated types among the tokens.
+fn types(tokens: &[&SyntaxToken]) -> Vec<VarType> {
+    let mut types = vec![];
+    let mut i = 0;
+    while i < tokens.len() {
+        match var_type(&tokens[i..]) {
+            Some((typ, len)) if tokens[i].text != "," => {
+                types.push(typ);
+                i += len;
+            },
+            _ => i += 1,
+        }
+    }
+    types
+}
+
+/// The `name: Type` and `name?: Type` fields among the tokens.
+fn fields(tokens: &[&SyntaxToken]) -> Vec<TypeField> {
+    let mut fields = vec![];
+    let mut i = 0;
+    while i < tokens.len() {
+        if tokens[i].text != ":" || i == 0 {
+            i += 1;
+            continue;
+        }
+        let optional = tokens[i - 1].text == "?";
+        let name = match optional {
+            true if i > 1 => &tokens[i - 2].text,
+            _ => &tokens[i - 1].text,
+        };
+        match var_type(&tokens[i + 1..]) {
+            Some((typ, len)) => {
+                let typ = if optional { typ.optional() } else { typ };
+                fields.push(TypeField { name: name.clone(), typ });
+                i += len + 1;
+            },
+            None => i += 1,
+        }
+    }
+    fields
+}
+
+/// The names of an `import` or `use` path, leaving out the keyword.
+fn path(tokens: &[&SyntaxToken]) -> Vec<String> {
+    tokens.iter()
+        .skip(1)
+        .filter(|t| t.kind == TokenKind::Ident)
+        .map(|t| t.text.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(node: &SyntaxNode) -> Vec<NodeKind> {
+        node.nodes().map(|n| n.kind).collect()
+    }
+
+    #[test]
+    fn reconstructs_source_byte_for_byte() {
+        for code in [
+            "",
+            "   \n// only a comment",
+            "a = 1",
+            "\t// header\r\na = (1 + 2) * 3   // trailing\r\n\r\n\nb = [1,2 ,3]\n",
+            "/* a /* b */\n*/ x = /**/ 1 /* c */\n/// doc\nf = y => y",
+            "f = x => {\n  return x  }\n\nres = [1].map((i, x) => {\n\treturn f(x) // inner\n})\n   ",
+            "if a == 1 { b = 2 } else if a == 2 {} else {\n c = 3\n}\nstruct P { x: Int\n y: P }",
+        ] {
+            let tree = SyntaxTree::parse(code).unwrap();
+            assert_eq!(tree.text(), code);
+        }
+    }
+
+    #[test]
+    fn ast_view_matches_parser() {
+        for code in [
+            "a = (1 + 2) * 3\nb = 1 - 2 - 3\nc = \"s\"\nd = 2.5\ne = true",
+            "res = [1, 2].map((inx, x) => {\n    return x * 2\n})",
+            "obj = Point { x: 1, y: { z: false } }\nv = obj.x\nf()(1)\ng.h(2)",
+            "if a == 1 { b = 2 } else if a == 2 { b = 3 } else { b = 4 }\nfor x in [1] { print(x) }\nfor { }",
+            "struct User { name: String, age: Int, friend: User }\nf = x => x\nreturn",
+            "v = std.math.pi\nstd.math.sqrt(2)",
+            "import lib.math\nuse math::double\nmod io {\n    export copy = x => x\n    export import lib.util\n}\nexport struct P { x: Int }\nexport enum E { A }",
+            "test \"adds\" {\n    assert_eq(f(1), 1)\n}",
+            "a = items[0]\nb = m.rows[i][-1]\nitems[0] = 2\no.x.y *= 3\nn -= 1\ns = \"abc\"[1]",
+            "let a = 1\nconst f = (x) => {\n    let y = x\n    return y\n}\nexport let b = a\nc = a\nd",
+            "a = 5-3\nb = -x * -(y - 1)\nc = -o.f(1)[0]\nd = [0xff, 0o17, 0b1010_1010, 1_000, 2.5e-3, 1e6, -.5]",
+            "enum S { A, B(Int, x), C { d: String } }\ns = S::C { d: \"x\" }\nmatch S::B(1, -2) {\n    S::B(a, -2) => { print(a) }\n    S::C { d, e: _ } => print(d)\n    \"s\" => print(1)\n    _ => { }\n}\nt = S::A",
+            "struct P { c?: [P], f: String? }\na = p?.b.c ?? none\nq?.f(1)\nf() + 1\nx\n-1\nmatch a {\n    none => 1\n}\nb = a ?? []\nc = a?.c ?? { d: [1] }",
+        ] {
+            let tree = SyntaxTree::parse(code).unwrap();
+            assert_eq!(tree.ast(), Parser::new(code).parse(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn trivia_attaches_to_tokens() {
+        let tree = SyntaxTree::parse("a = 1 // one\n// two\nb = 2\n").unwrap();
+        assert_eq!(kinds(&tree.root), vec![NodeKind::Assign, NodeKind::Assign]);
+
+        let tokens = tree.root.tokens();
+        let one = tokens.iter().find(|t| t.text == "1").unwrap();
+        assert_eq!(one.trailing, vec![
+            Trivia { kind: TriviaKind::Whitespace, text: " ".to_string() },
+            Trivia { kind: TriviaKind::Comment, text: "// one".to_string() },
+        ]);
+
+        let b = tokens.iter().find(|t| t.text == "b").unwrap();
+        assert_eq!(b.leading.iter().map(|t| t.kind).collect::<Vec<_>>(), vec![
+            TriviaKind::Newline,
+            TriviaKind::Comment,
+            TriviaKind::Newline,
+        ]);
+
+        let eof = tokens.last().unwrap();
+        assert_eq!(eof.kind, TokenKind::Eof);
+        assert_eq!(eof.text_with_trivia(), "\n");
+
+        let tree = SyntaxTree::parse("a = 1 /* x /* y */\n */\nb = 2").unwrap();
+        let tokens = tree.root.tokens();
+        let one = tokens.iter().find(|t| t.text == "1").unwrap();
+        assert_eq!(one.trailing[1], Trivia { kind: TriviaKind::Comment, text: "/* x /* y */\n */".to_string() });
+    }
+
+    #[test]
+    fn nodes_nest_like_the_grammar() {
+        let tree = SyntaxTree::parse("x = (a + b) * c").unwrap();
+        let assign = tree.root.nodes().next().unwrap();
+        let mul = assign.nodes().next().unwrap();
+        assert_eq!(mul.kind, NodeKind::BinOp);
+        assert_eq!(kinds(mul), vec![NodeKind::Paren, NodeKind::Ident]);
+        assert_eq!(mul.text(), "(a + b) * c");
+
+        let paren = mul.nodes().next().unwrap();
+        assert_eq!(kinds(paren), vec![NodeKind::BinOp]);
+        assert!(SyntaxTree::parse("x = (1").is_err());
+    }
+}
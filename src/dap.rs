@@ -0,0 +1,624 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+
+use serde_json::json;
+
+use crate::repl::panic_message;
+use crate::value_kind;
+use crate::FsLoader;
+use crate::Frame;
+use crate::RunResult;
+use crate::StdLib;
+use crate::Value;
+use crate::Vm;
+use crate::VariantFields;
+
+const THREAD_ID: i64 = 1;
+
+type AwaitHandler = Box<dyn FnMut(&Vm, Value) -> Result<Value, String>>;
+
+/// Reads one `Content-Length` framed message. Returns `None` at the end
+/// of the input.
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<serde_json::Value>> {
+    let mut len = None;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some(v) = line.strip_prefix("Content-Length:") {
+            len = v.trim().parse::<usize>().ok();
+        }
+    }
+
+    let len = match len {
+        Some(l) => l,
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header"))
+    };
+
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf)?;
+    serde_json::from_slice(&buf).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message(writer: &mut impl Write, msg: &serde_json::Value) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+enum VarRef {
+    Locals(u32),
+    Globals,
+    Value(Value),
+}
+
+/// Debug Adapter Protocol server debugging a single script.
+pub struct DapServer<R, W> {
+    reader: R,
+    writer: W,
+    seq: i64,
+    vm: Vm,
+    path: Option<String>,
+    entry: Option<u32>,
+    stop_on_entry: bool,
+    configured: bool,
+    started: bool,
+    stack_id: Option<usize>,
+    breakpoints: HashMap<String, Vec<(i64, u32)>>,
+    next_bp_id: i64,
+    frames: Vec<Frame>,
+    refs: Vec<VarRef>,
+    on_await: AwaitHandler,
+    done: bool,
+}
+
+impl<R: BufRead, W: Write> DapServer<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader,
+            writer,
+            seq: 0,
+            vm: Vm::new(),
+            path: None,
+            entry: None,
+            stop_on_entry: false,
+            configured: false,
+            started: false,
+            stack_id: None,
+            breakpoints: HashMap::new(),
+            next_bp_id: 1,
+            frames: vec![],
+            refs: vec![],
+            on_await: Box::new(|_, _| Ok(Value::None)),
+            done: false,
+        }
+    }
+
+    /// Answers awaited calls of the debugged script. Without a handler
+    /// every await returns none.
+    pub fn on_await<F>(&mut self, f: F)
+    where
+        F: FnMut(&Vm, Value) -> Result<Value, String> + 'static
+    {
+        self.on_await = Box::new(f);
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        while !self.done {
+            match read_message(&mut self.reader)? {
+                Some(msg) => self.handle(msg)?,
+                None => break
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, mut msg: serde_json::Value) -> io::Result<()> {
+        self.seq += 1;
+        msg["seq"] = json!(self.seq);
+        write_message(&mut self.writer, &msg)
+    }
+
+    fn respond(&mut self, req: &serde_json::Value, body: serde_json::Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "command": req["command"],
+            "success": true,
+            "body": body
+        }))
+    }
+
+    fn fail(&mut self, req: &serde_json::Value, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "command": req["command"],
+            "success": false,
+            "message": message
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: serde_json::Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "event",
+            "event": event,
+            "body": body
+        }))
+    }
+
+    fn output(&mut self, category: &str, text: &str) -> io::Result<()> {
+        self.event("output", json!({
+            "category": category,
+            "output": format!("{}\n", text)
+        }))
+    }
+
+    pub fn handle(&mut self, req: serde_json::Value) -> io::Result<()> {
+        if req["type"] != "request" {
+            return Ok(());
+        }
+
+        let args = req["arguments"].clone();
+        let command = req["command"].as_str().unwrap_or_default().to_string();
+
+        match command.as_str() {
+            "initialize" => {
+                self.respond(&req, json!({
+                    "supportsConfigurationDoneRequest": true
+                }))?;
+                self.event("initialized", json!({}))
+            },
+            "launch" => {
+                let path = match args["program"].as_str() {
+                    Some(p) => p.to_string(),
+                    None => return self.fail(&req, "launch requires a program")
+                };
+                let code = match fs::read_to_string(&path) {
+                    Ok(c) => c,
+                    Err(e) => return self.fail(&req, &format!("failed to read {}: {}", path, e))
+                };
+
+                // Imports resolve next to the launched script
+                let dir = Path::new(&path).parent().unwrap_or(Path::new("."));
+                self.vm.set_loader(FsLoader::new(dir));
+                self.vm.open_std(&StdLib::ALL);
+
+                let vm = &mut self.vm;
+                let entry = match panic::catch_unwind(AssertUnwindSafe(|| vm.compile_code(&code))) {
+                    Ok(blk) => blk,
+                    Err(e) => return self.fail(&req, &panic_message(e.as_ref()))
+                };
+
+                self.path = Some(path);
+                self.entry = Some(entry);
+                self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+                self.respond(&req, json!({}))?;
+
+                // Breakpoints set before launch can only be placed now
+                let actual = self.apply_breakpoints();
+                let path = self.path.clone().unwrap_or_default();
+                let bps = self.breakpoints.get(&path).cloned().unwrap_or_default();
+                for (id, line) in bps {
+                    if let Some(l) = actual.get(&(path.clone(), line)) {
+                        self.event("breakpoint", json!({
+                            "reason": "changed",
+                            "breakpoint": { "id": id, "verified": true, "line": l }
+                        }))?;
+                    }
+                }
+
+                self.start()
+            },
+            "setBreakpoints" => {
+                let path = args["source"]["path"].as_str().unwrap_or_default().to_string();
+                let lines = match args["breakpoints"].as_array() {
+                    Some(list) => list.iter().filter_map(|b| b["line"].as_u64()).collect(),
+                    None => vec![]
+                };
+                let lines = lines.into_iter().map(|l| {
+                    self.next_bp_id += 1;
+                    (self.next_bp_id - 1, l as u32)
+                }).collect::<Vec<_>>();
+                self.breakpoints.insert(path.clone(), lines);
+                let actual = self.apply_breakpoints();
+
+                let bps = self.breakpoints[&path].iter().map(|(id, line)| {
+                    match actual.get(&(path.clone(), *line)) {
+                        Some(l) => json!({ "id": id, "verified": true, "line": l }),
+                        None => json!({ "id": id, "verified": false, "line": line })
+                    }
+                }).collect::<Vec<_>>();
+
+                self.respond(&req, json!({ "breakpoints": bps }))
+            },
+            "configurationDone" => {
+                self.configured = true;
+                self.respond(&req, json!({}))?;
+                self.start()
+            },
+            "threads" => {
+                self.respond(&req, json!({
+                    "threads": [{ "id": THREAD_ID, "name": "main" }]
+                }))
+            },
+            "continue" | "next" | "stepIn" | "stepOut" => {
+                let stack_id = match self.stack_id {
+                    Some(s) => s,
+                    None => return self.fail(&req, "not paused")
+                };
+                self.respond(&req, json!({ "allThreadsContinued": true }))?;
+
+                let vm = &mut self.vm;
+                let res = panic::catch_unwind(AssertUnwindSafe(|| match command.as_str() {
+                    "next" => vm.step_over(stack_id),
+                    "stepIn" => vm.step_in(stack_id),
+                    "stepOut" => vm.step_out(stack_id),
+                    _ => vm.resume(stack_id)
+                }));
+                let reason = if command == "continue" { "breakpoint" } else { "step" };
+                self.drive(res, reason)
+            },
+            "stackTrace" => {
+                let path = self.path.clone().unwrap_or_default();
+                let frames = self.frames.iter().enumerate().map(|(i, f)| {
+                    let name = if i + 1 == self.frames.len() {
+                        "main".to_string()
+                    } else {
+                        format!("fn blk {}", f.blk)
+                    };
+                    json!({
+                        "id": i,
+                        "name": name,
+                        "line": f.line.unwrap_or(0),
+                        "column": 1,
+                        "source": { "path": path }
+                    })
+                }).collect::<Vec<_>>();
+
+                self.respond(&req, json!({
+                    "stackFrames": frames,
+                    "totalFrames": self.frames.len()
+                }))
+            },
+            "scopes" => {
+                let frame = match self.frames.get(args["frameId"].as_u64().unwrap_or(0) as usize) {
+                    Some(f) => f.scope_id,
+                    None => return self.fail(&req, "unknown frame")
+                };
+                let locals = self.add_ref(VarRef::Locals(frame));
+                let globals = self.add_ref(VarRef::Globals);
+
+                self.respond(&req, json!({
+                    "scopes": [
+                        { "name": "Locals", "variablesReference": locals, "expensive": false },
+                        { "name": "Globals", "variablesReference": globals, "expensive": false }
+                    ]
+                }))
+            },
+            "variables" => {
+                let vars = match self.variables(args["variablesReference"].as_u64().unwrap_or(0) as usize) {
+                    Some(v) => v,
+                    None => return self.fail(&req, "unknown variables reference")
+                };
+                let vars = vars.into_iter().map(|(name, val)| {
+                    let reference = match val {
+                        Value::List(_) | Value::Obj(_) => self.add_ref(VarRef::Value(val.clone())),
+                        Value::Enum(ref v) if v.fields != VariantFields::Unit => self.add_ref(VarRef::Value(val.clone())),
+                        _ => 0
+                    };
+                    json!({
+                        "name": name,
+                        "value": val.to_string(),
+                        "type": value_kind(&val),
+                        "variablesReference": reference
+                    })
+                }).collect::<Vec<_>>();
+
+                self.respond(&req, json!({ "variables": vars }))
+            },
+            "disconnect" => {
+                self.done = true;
+                self.respond(&req, json!({}))
+            },
+            _ => self.fail(&req, &format!("unsupported command {}", command))
+        }
+    }
+
+    /// Sets the vm breakpoints from the requested lines and returns the
+    /// line each request was placed on.
+    fn apply_breakpoints(&mut self) -> HashMap<(String, u32), u32> {
+        let mut actual = HashMap::new();
+        self.vm.clear_breakpoints();
+
+        let (path, source) = match (&self.path, self.entry.and_then(|b| self.vm.source_of(b))) {
+            (Some(p), Some(s)) => (p.clone(), s),
+            _ => return actual
+        };
+
+        if let Some(lines) = self.breakpoints.get(&path) {
+            for (_, line) in lines {
+                if let Some(l) = self.vm.set_line_breakpoint(source, *line) {
+                    actual.insert((path.clone(), *line), l);
+                }
+            }
+        }
+
+        actual
+    }
+
+    /// Runs the script once it is both launched and configured.
+    fn start(&mut self) -> io::Result<()> {
+        let entry = match self.entry {
+            Some(e) if self.configured && !self.started => e,
+            _ => return Ok(())
+        };
+        self.started = true;
+
+        let vm = &mut self.vm;
+        let stop_on_entry = self.stop_on_entry;
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            if stop_on_entry {
+                vm.debug_blk(entry)
+            } else {
+                vm.run_blk(entry, Value::None)
+            }
+        }));
+        let reason = if stop_on_entry { "entry" } else { "breakpoint" };
+        self.drive(res, reason)
+    }
+
+    fn drive(&mut self, res: std::thread::Result<RunResult>, reason: &str) -> io::Result<()> {
+        self.frames.clear();
+        self.refs.clear();
+        self.stack_id = None;
+
+        let mut res = res;
+
+        loop {
+            let run = match res {
+                Ok(r) => r,
+                Err(e) => {
+                    let msg = format!("error: {}", panic_message(e.as_ref()));
+                    self.output("stderr", &msg)?;
+                    return self.terminate(1);
+                }
+            };
+
+            match run {
+                RunResult::Paused { stack_id, .. } => {
+                    self.stack_id = Some(stack_id);
+                    self.frames = self.vm.frames(stack_id);
+                    return self.event("stopped", json!({
+                        "reason": reason,
+                        "threadId": THREAD_ID,
+                        "allThreadsStopped": true
+                    }));
+                },
+                RunResult::Await { stack_id, value } => {
+                    let reply = match (self.on_await)(&self.vm, value) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            self.output("stderr", &format!("error: {}", e))?;
+                            return self.terminate(1);
+                        }
+                    };
+                    let vm = &mut self.vm;
+                    res = panic::catch_unwind(AssertUnwindSafe(|| vm.cont(stack_id, reply)));
+                },
+                RunResult::Value(v) => {
+                    self.output("console", &v.to_string())?;
+                    return self.terminate(0);
+                },
+                RunResult::None => return self.terminate(0)
+            }
+        }
+    }
+
+    fn terminate(&mut self, code: i64) -> io::Result<()> {
+        self.event("exited", json!({ "exitCode": code }))?;
+        self.event("terminated", json!({}))
+    }
+
+    fn add_ref(&mut self, r: VarRef) -> usize {
+        self.refs.push(r);
+        self.refs.len()
+    }
+
+    fn variables(&mut self, reference: usize) -> Option<Vec<(String, Value)>> {
+        let vars = match self.refs.get(reference.checked_sub(1)?)? {
+            VarRef::Locals(scope_id) => {
+                let scope_id = *scope_id;
+                self.vm.variables(scope_id).into_iter().map(|v| (v.name, v.value)).collect()
+            },
+            VarRef::Globals => {
+                self.vm.global_variables().into_iter().map(|v| (v.name, v.value)).collect()
+            },
+            VarRef::Value(Value::List(list)) => {
+                list.iter().enumerate().map(|(i, v)| (i.to_string(), v.clone())).collect()
+            },
+            VarRef::Value(Value::Obj(obj)) => {
+                obj.props.iter().map(|p| (p.name.clone(), p.value.clone())).collect()
+            },
+            VarRef::Value(Value::Enum(v)) => match &v.fields {
+                VariantFields::Unit => vec![],
+                VariantFields::Tuple(items) => items.iter().enumerate().map(|(i, v)| (i.to_string(), v.clone())).collect(),
+                VariantFields::Struct(props) => props.iter().map(|p| (p.name.clone(), p.value.clone())).collect(),
+            },
+            VarRef::Value(_) => vec![]
+        };
+
+        Some(vars)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const CODE: &str = "double = (x) => {
+    y = x * 2
+    return y
+}
+person = Person { name: \"seppo\", tags: [1, 2] }
+b = double(3)
+return b";
+
+    struct Session {
+        server: DapServer<Cursor<Vec<u8>>, Vec<u8>>,
+        seq: i64,
+        read: usize,
+        launch: Vec<serde_json::Value>,
+    }
+
+    impl Session {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("dap_test_{}.fs", std::process::id()));
+            fs::write(&path, CODE).unwrap();
+
+            let mut s = Self {
+                server: DapServer::new(Cursor::new(vec![]), vec![]),
+                seq: 0,
+                read: 0,
+                launch: vec![],
+            };
+            s.request("initialize", json!({ "adapterID": "flexscript" }));
+            s.request("setBreakpoints", json!({
+                "source": { "path": path.to_str().unwrap() },
+                "breakpoints": [{ "line": 2 }]
+            }));
+            s.launch = s.request("launch", json!({ "program": path.to_str().unwrap() }));
+            s
+        }
+
+        /// Sends a request and returns every message the server wrote.
+        fn request(&mut self, command: &str, args: serde_json::Value) -> Vec<serde_json::Value> {
+            self.seq += 1;
+            self.server.handle(json!({
+                "seq": self.seq,
+                "type": "request",
+                "command": command,
+                "arguments": args
+            })).unwrap();
+
+            let mut out = Cursor::new(&self.server.writer[self.read..]);
+            let mut msgs = vec![];
+            while let Some(msg) = read_message(&mut out).unwrap() {
+                msgs.push(msg);
+            }
+            self.read = self.server.writer.len();
+            msgs
+        }
+
+        fn body(&mut self, command: &str, args: serde_json::Value) -> serde_json::Value {
+            let msgs = self.request(command, args);
+            assert_eq!(msgs[0]["success"], true, "{:?}", msgs[0]);
+            msgs[0]["body"].clone()
+        }
+    }
+
+    fn event<'a>(msgs: &'a [serde_json::Value], name: &str) -> &'a serde_json::Value {
+        msgs.iter().find(|m| m["event"] == name).unwrap_or_else(|| panic!("no {} event in {:?}", name, msgs))
+    }
+
+    #[test]
+    fn stop_at_breakpoint_and_inspect() {
+        let mut s = Session::new();
+        let msgs = s.request("configurationDone", json!({}));
+        assert_eq!(event(&msgs, "stopped")["body"]["reason"], "breakpoint");
+        assert_eq!(s.launch[1]["body"]["breakpoint"]["verified"], true);
+
+        let trace = s.body("stackTrace", json!({ "threadId": 1 }));
+        assert_eq!(trace["stackFrames"][0]["line"], 2);
+        assert_eq!(trace["stackFrames"][1]["line"], 6);
+        assert_eq!(trace["stackFrames"][1]["name"], "main");
+
+        let scopes = s.body("scopes", json!({ "frameId": 1 }));
+        let locals = scopes["scopes"][0]["variablesReference"].clone();
+        let vars = s.body("variables", json!({ "variablesReference": locals }));
+        let vars = vars["variables"].as_array().unwrap();
+        let person = vars.iter().find(|v| v["name"] == "person").unwrap();
+        assert_eq!(person["type"], "object");
+
+        let props = s.body("variables", json!({ "variablesReference": person["variablesReference"] }));
+        let props = props["variables"].as_array().unwrap();
+        let name = props.iter().find(|p| p["name"] == "name").unwrap();
+        assert_eq!(name["value"], "\"seppo\"");
+        let tags = props.iter().find(|p| p["name"] == "tags").unwrap()["variablesReference"].clone();
+        let tags = s.body("variables", json!({ "variablesReference": tags }));
+        assert_eq!(tags["variables"][1]["name"], "1");
+        assert_eq!(tags["variables"][1]["value"], "2");
+
+        let msgs = s.request("continue", json!({ "threadId": 1 }));
+        assert_eq!(event(&msgs, "output")["body"]["output"], "6\n");
+        event(&msgs, "terminated");
+    }
+
+    #[test]
+    fn step_through_function() {
+        let mut s = Session::new();
+        s.request("configurationDone", json!({}));
+
+        let msgs = s.request("next", json!({ "threadId": 1 }));
+        assert_eq!(event(&msgs, "stopped")["body"]["reason"], "step");
+        let trace = s.body("stackTrace", json!({ "threadId": 1 }));
+        assert_eq!(trace["stackFrames"][0]["line"], 3);
+
+        s.request("stepOut", json!({ "threadId": 1 }));
+        let trace = s.body("stackTrace", json!({ "threadId": 1 }));
+        assert_eq!(trace["totalFrames"], 1);
+        assert_eq!(trace["stackFrames"][0]["line"], 7);
+    }
+
+    #[test]
+    fn framed_session_over_streams() {
+        let path = std::env::temp_dir().join(format!("dap_framed_{}.fs", std::process::id()));
+        fs::write(&path, "a = await(fetch())\nreturn a").unwrap();
+
+        let mut input = vec![];
+        for (seq, (command, args)) in [
+            ("initialize", json!({})),
+            ("launch", json!({ "program": path.to_str().unwrap() })),
+            ("configurationDone", json!({})),
+            ("disconnect", json!({})),
+        ].into_iter().enumerate() {
+            write_message(&mut input, &json!({
+                "seq": seq + 1,
+                "type": "request",
+                "command": command,
+                "arguments": args
+            })).unwrap();
+        }
+
+        let mut server = DapServer::new(Cursor::new(input), vec![]);
+        server.on_await(|_, _| Ok(Value::Int(5)));
+        server.run().unwrap();
+
+        let mut out = Cursor::new(server.writer);
+        let mut msgs = vec![];
+        while let Some(msg) = read_message(&mut out).unwrap() {
+            msgs.push(msg);
+        }
+
+        assert_eq!(msgs[0]["command"], "initialize");
+        assert_eq!(event(&msgs, "output")["body"]["output"], "5\n");
+        assert_eq!(event(&msgs, "exited")["body"]["exitCode"], 0);
+        assert_eq!(msgs.last().unwrap()["command"], "disconnect");
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::HashSet;
+
+use crate::vm::source_name;
+use crate::vm_types::LineTable;
+use crate::RunResult;
+use crate::Value;
+use crate::Vm;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepMode {
+    /// Stop at the next statement, entering called functions.
+    In,
+    /// Stop at the next statement of the current function or its callers.
+    Over,
+    /// Stop at the next statement after the current function returns.
+    Out,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub blk: u32,
+    pub pc: u32,
+    pub line: Option<u32>,
+    pub scope_id: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub name: String,
+    pub value: Value,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Debugger {
+    breakpoints: HashSet<(u32, u32)>,
+    step: Option<(StepMode, usize)>,
+    skip: bool,
+}
+
+impl Debugger {
+    /// Called before each instruction. Pauses on statement starts only,
+    /// or on every instruction of blocks compiled without line tables.
+    pub(crate) fn should_pause(&mut self, line_tables: &[LineTable], blk: u32, pc: u32, depth: usize) -> bool {
+        if self.skip {
+            self.skip = false;
+            return false;
+        }
+
+        if self.breakpoints.is_empty() && self.step.is_none() {
+            return false;
+        }
+
+        let pause = if self.breakpoints.contains(&(blk, pc)) {
+            true
+        } else {
+            let is_stmt = match line_tables.get(blk as usize) {
+                Some(t) if !t.entries.is_empty() => t.entries.iter().any(|(start, _)| *start == pc),
+                _ => true
+            };
+
+            match self.step {
+                Some((StepMode::In, _)) => is_stmt,
+                Some((StepMode::Over, d)) => is_stmt && depth <= d,
+                Some((StepMode::Out, d)) => is_stmt && depth < d,
+                None => false
+            }
+        };
+
+        if pause {
+            self.step = None;
+        }
+
+        pause
+    }
+}
+
+impl Vm {
+    pub fn set_breakpoint(&mut self, blk: u32, pc: u32) {
+        self.debugger.breakpoints.insert((blk, pc));
+    }
+
+    pub fn clear_breakpoint(&mut self, blk: u32, pc: u32) {
+        self.debugger.breakpoints.remove(&(blk, pc));
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.debugger.breakpoints.clear();
+    }
+
+    /// Sets a breakpoint on every statement starting on `line` of the given
+    /// source. When the line has no statement the next line with one is
+    /// used. Returns the line the breakpoint ended up on.
+    pub fn set_line_breakpoint(&mut self, source: u32, line: u32) -> Option<u32> {
+        let tables = self.line_tables.iter()
+            .enumerate()
+            .filter(|(_, t)| t.source == Some(source));
+
+        let actual = tables.clone()
+            .flat_map(|(_, t)| t.entries.iter().map(|(_, l)| *l))
+            .filter(|l| *l >= line)
+            .min()?;
+
+        for (blk, t) in tables {
+            for (pc, l) in &t.entries {
+                if *l == actual {
+                    self.debugger.breakpoints.insert((blk as u32, *pc));
+                }
+            }
+        }
+
+        Some(actual)
+    }
+
+    /// Index of the source a block was compiled from, for line breakpoints.
+    pub fn source_of(&self, blk: u32) -> Option<u32> {
+        self.line_tables.get(blk as usize)?.source
+    }
+
+    pub fn line_at(&self, blk: u32, pc: u32) -> Option<u32> {
+        self.line_tables.get(blk as usize)?.line_at(pc)
+    }
+
+    /// Pauses before the first statement of the block instead of running it.
+    pub fn debug_blk(&mut self, blk: u32) -> RunResult {
+        self.debugger.step = Some((StepMode::In, 0));
+        self.run_blk(blk, Value::None)
+    }
+
+    /// Continues a paused script until the next breakpoint.
+    pub fn resume(&mut self, stack_id: usize) -> RunResult {
+        self.debugger.step = None;
+        self.debugger.skip = true;
+        self.run_stack(stack_id)
+    }
+
+    pub fn step(&mut self, stack_id: usize, mode: StepMode) -> RunResult {
+        let depth = match self.callstacks.get(stack_id) {
+            Some(s) => s.depth(),
+            None => return RunResult::None
+        };
+        self.debugger.step = Some((mode, depth));
+        self.debugger.skip = true;
+        self.run_stack(stack_id)
+    }
+
+    pub fn step_in(&mut self, stack_id: usize) -> RunResult {
+        self.step(stack_id, StepMode::In)
+    }
+
+    pub fn step_over(&mut self, stack_id: usize) -> RunResult {
+        self.step(stack_id, StepMode::Over)
+    }
+
+    pub fn step_out(&mut self, stack_id: usize) -> RunResult {
+        self.step(stack_id, StepMode::Out)
+    }
+
+    /// Frames of a callstack, innermost first.
+    pub fn frames(&self, stack_id: usize) -> Vec<Frame> {
+        let calls = match self.callstacks.get(stack_id) {
+            Some(s) => s.frames(),
+            None => return vec![]
+        };
+
+        calls.iter().rev().enumerate().map(|(i, call)| {
+            // Callers already moved past the call instruction
+            let pc = if i == 0 { call.pc } else { call.pc.saturating_sub(1) };
+            Frame {
+                blk: call.blk,
+                pc: call.pc,
+                line: self.line_at(call.blk, pc),
+                scope_id: call.scope_id
+            }
+        }).collect()
+    }
+
+    /// Named variables visible from a scope, innermost declaration first
+    /// when a name is shadowed. Globals are listed by `global_variables`,
+    /// names the compiler makes up are left out and names declared in a
+    /// block are listed while it runs.
+    pub fn variables(&mut self, scope_id: u32) -> Vec<Variable> {
+        let mut seen = HashSet::new();
+        let mut vars = vec![];
+        let mut curr = Some(scope_id);
+
+        while let Some(id) = curr {
+            if id == self.globals {
+                break;
+            }
+            vars.extend(self.scope_variables(id, &mut seen));
+            curr = self.scope.get_parent_scope(id);
+        }
+
+        vars
+    }
+
+    pub fn global_variables(&mut self) -> Vec<Variable> {
+        self.scope_variables(self.globals, &mut HashSet::new())
+    }
+
+    fn scope_variables(&mut self, scope_id: u32, seen: &mut HashSet<u32>) -> Vec<Variable> {
+        let mut named = self.scope.named_vars(scope_id);
+        named.retain(|(id, _)| seen.insert(*id));
+
+        let lets = match self.running_at(scope_id) {
+            Some((blk, pc)) => self.line_tables.get(blk as usize).into_iter()
+                .flat_map(|t| &t.lets)
+                .filter(|(_, start, end)| (*start..*end).contains(&pc))
+                .map(|(id, start, _)| (*id, *start))
+                .collect(),
+            None => vec![],
+        };
+
+        let mut vars = named.into_iter().filter_map(|(id, val)| {
+            let idt = self.id_idt_map.get(&id).cloned().unwrap_or_else(|| id.to_string());
+            let name = source_name(&idt)?.to_string();
+            // Names of a block which is not running are out of scope
+            let start = match lets.iter().find(|(i, _)| *i == id) {
+                Some((_, start)) => Some(*start),
+                None if name != idt => return None,
+                None => None,
+            };
+            Some((start, Variable { name, value: self.scope.copy_value(val) }))
+        }).collect::<Vec<_>>();
+        // Inner blocks start later and shadow the names around them
+        vars.sort_by(|(a_start, a), (b_start, b)| a.name.cmp(&b.name).then(b_start.cmp(a_start)));
+        vars.into_iter().map(|(_, v)| v).collect()
+    }
+
+    /// The block a scope runs and the pc it is at, when it is running.
+    fn running_at(&self, scope_id: u32) -> Option<(u32, u32)> {
+        self.callstacks.iter().find_map(|s| {
+            s.frames().iter().rev().enumerate()
+                .find(|(_, call)| call.scope_id == scope_id)
+                .map(|(i, call)| {
+                    // Callers already moved past the call instruction
+                    let pc = if i == 0 { call.pc } else { call.pc.saturating_sub(1) };
+                    (call.blk, pc)
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE: &str = "double = (x) => {
+    y = x * 2
+    return y
+}
+a = 1
+b = double(a)
+c = [a, b]
+return c";
+
+    fn paused(res: RunResult) -> (usize, u32, u32) {
+        match res {
+            RunResult::Paused { stack_id, blk, pc } => (stack_id, blk, pc),
+            _ => panic!("Expected pause but got {:?}", res)
+        }
+    }
+
+    fn line(vm: &Vm, res: RunResult) -> (usize, u32) {
+        let (stack_id, blk, pc) = paused(res);
+        (stack_id, vm.line_at(blk, pc).unwrap())
+    }
+
+    #[test]
+    fn line_breakpoint_and_variables() {
+        let mut vm = Vm::new();
+        let blk = vm.compile_code(CODE);
+        let source = vm.source_of(blk).unwrap();
+        assert_eq!(vm.set_line_breakpoint(source, 3), Some(3));
+
+        let res = vm.run_blk(blk, Value::None);
+        let (stack_id, line) = line(&vm, res);
+        assert_eq!(line, 3);
+
+        let frames = vm.frames(stack_id);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].line, Some(3));
+        assert_eq!(frames[1].line, Some(6));
+
+        let vars = vm.variables(frames[0].scope_id);
+        assert_eq!(vars[0], Variable { name: "x".to_string(), value: Value::Int(1) });
+        assert_eq!(vars[1], Variable { name: "y".to_string(), value: Value::Int(2) });
+        assert!(vars.iter().any(|v| v.name == "a"));
+
+        vm.clear_breakpoints();
+        assert_eq!(vm.resume(stack_id), RunResult::Value(Value::List(vec![
+            Value::Int(1),
+            Value::Int(2),
+        ])));
+    }
+
+    #[test]
+    fn step_over_in_and_out() {
+        let mut vm = Vm::new();
+        let blk = vm.compile_code(CODE);
+
+        let res = vm.debug_blk(blk);
+        let (stack_id, l) = line(&vm, res);
+        assert_eq!(l, 1);
+        let res = vm.step_over(stack_id);
+        assert_eq!(line(&vm, res).1, 5);
+        let res = vm.step_over(stack_id);
+        assert_eq!(line(&vm, res).1, 6);
+        let res = vm.step_in(stack_id);
+        assert_eq!(line(&vm, res).1, 2);
+        let res = vm.step_out(stack_id);
+        assert_eq!(line(&vm, res).1, 7);
+
+        let vars = vm.variables(vm.frames(stack_id)[0].scope_id);
+        assert_eq!(vars.iter().find(|v| v.name == "b").unwrap().value, Value::Int(2));
+
+        let res = vm.step_over(stack_id);
+        assert_eq!(line(&vm, res).1, 8);
+        assert!(matches!(vm.step_over(stack_id), RunResult::Value(_)));
+    }
+
+    #[test]
+    fn pc_breakpoint_and_globals() {
+        let mut vm = Vm::new();
+        vm.set_global("limit", Value::Int(3));
+        let blk = vm.compile_code("a = limit\nreturn a");
+        vm.set_breakpoint(blk, 2);
+
+        let (stack_id, _, pc) = paused(vm.run_blk(blk, Value::None));
+        assert_eq!(pc, 2);
+        assert_eq!(vm.global_variables(), vec![Variable { name: "limit".to_string(), value: Value::Int(3) }]);
+
+        let vars = vm.variables(vm.frames(stack_id)[0].scope_id);
+        assert_eq!(vars, vec![Variable { name: "a".to_string(), value: Value::Int(3) }]);
+        assert_eq!(vm.resume(stack_id), RunResult::Value(Value::Int(3)));
+    }
+
+    #[test]
+    fn hides_compiler_names() {
+        let code = "r = []\nif true {\n    let x = 1\n    r.push(x)\n}\nfor i in [1] {\n    r.push(i)\n}\nmatch 1 {\n    _ => r.push(2)\n}\nreturn r";
+        let mut vm = Vm::new();
+        let blk = vm.compile_code(code);
+        let source = vm.source_of(blk).unwrap();
+        vm.set_line_breakpoint(source, 12);
+
+        let (stack_id, _, _) = paused(vm.run_blk(blk, Value::None));
+        let vars = vm.variables(vm.frames(stack_id)[0].scope_id);
+        assert_eq!(vars.iter().map(|v| v.name.as_str()).collect::<Vec<_>>(), vec!["i", "r"]);
+    }
+
+    #[test]
+    fn shows_block_names_while_the_block_runs() {
+        let code = "x = 5\nr = []\nif true {\n    let x = 1\n    r.push(x)\n}\nreturn r";
+        let mut vm = Vm::new();
+        let blk = vm.compile_code(code);
+        let source = vm.source_of(blk).unwrap();
+        vm.set_line_breakpoint(source, 5);
+
+        let (stack_id, _, _) = paused(vm.run_blk(blk, Value::None));
+        let vars = vm.variables(vm.frames(stack_id)[0].scope_id);
+        let vars = vars.into_iter().map(|v| (v.name, v.value)).collect::<Vec<_>>();
+        assert_eq!(vars, vec![
+            ("r".to_string(), Value::List(vec![])),
+            ("x".to_string(), Value::Int(1)),
+            ("x".to_string(), Value::Int(5)),
+        ]);
+    }
+
+    #[test]
+    fn optional_call_adds_no_variables() {
+        let mut vm = Vm::new();
+        let blk = vm.compile_code("o = { f: (a, b) => { return a - b } }\nr = o?.f(5, 2)\nreturn r");
+        let source = vm.source_of(blk).unwrap();
+        vm.set_line_breakpoint(source, 3);
+
+        let (stack_id, _, _) = paused(vm.run_blk(blk, Value::None));
+        let vars = vm.variables(vm.frames(stack_id)[0].scope_id);
+        assert_eq!(vars.iter().map(|v| v.name.as_str()).collect::<Vec<_>>(), vec!["o", "r"]);
+        assert_eq!(vars[1].value, Value::Int(3));
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::vm::prop_name;
+use crate::vm_types::ByteCode;
+use crate::Vm;
+
+fn jump_target(c: &ByteCode) -> Option<u32> {
+    match c {
+        ByteCode::Jump(t) => Some(*t),
+        ByteCode::JumpIfFalse(t) => Some(*t),
+        ByteCode::JumpIfNone(t) => Some(*t),
+        ByteCode::JumpIfSome(t) => Some(*t),
+        _ => None
+    }
+}
+
+fn op_name(c: &ByteCode) -> String {
+    let s = format!("{:?}", c);
+    match s.split_once('(') {
+        Some((name, _)) => name.to_string(),
+        None => s
+    }
+}
+
+impl Vm {
+    /// Human readable listing of a code block and every function block
+    /// it creates. Identifiers and constants are resolved, jump targets
+    /// are shown as labels and source lines are interleaved when the
+    /// block was compiled from source.
+    pub fn disassemble(&self, blk: u32) -> String {
+        let mut out = String::new();
+        let mut visited = HashSet::new();
+        let mut queue = vec![blk];
+
+        while let Some(blk) = queue.pop() {
+            if !visited.insert(blk) {
+                continue;
+            }
+
+            if !out.is_empty() {
+                out.push('\n');
+            }
+
+            let nested = self.disassemble_block(&mut out, blk);
+            queue.extend(nested.into_iter().rev());
+        }
+
+        out
+    }
+
+    fn disassemble_block(&self, out: &mut String, blk: u32) -> Vec<u32> {
+        let code = match self.code_blocks.get(blk as usize) {
+            Some(c) => c,
+            None => {
+                let _ = writeln!(out, "blk {}: <missing>", blk);
+                return vec![];
+            }
+        };
+
+        let mut labels = BTreeMap::new();
+        for c in code {
+            if let Some(t) = jump_target(c) {
+                labels.insert(t, 0);
+            }
+        }
+        for (i, label) in labels.values_mut().enumerate() {
+            *label = i;
+        }
+
+        let lines = self.line_tables.get(blk as usize);
+        let source = lines
+            .and_then(|l| l.source)
+            .and_then(|s| self.sources.get(s as usize));
+
+        let _ = writeln!(out, "blk {}:", blk);
+
+        let mut nested = vec![];
+        let mut last_line = None;
+
+        for (pc, c) in code.iter().enumerate() {
+            let pc = pc as u32;
+
+            if let (Some(lines), Some(source)) = (lines, source) {
+                match lines.line_at(pc) {
+                    Some(line) if last_line != Some(line) => {
+                        let text = source.lines().nth(line as usize - 1).unwrap_or("");
+                        let _ = writeln!(out, "{:>6} | {}", line, text.trim_end());
+                        last_line = Some(line);
+                    },
+                    _ => {}
+                }
+            }
+
+            let label = match labels.get(&pc) {
+                Some(l) => format!("L{}:", l),
+                None => String::new()
+            };
+
+            let operand = match c {
+                ByteCode::Load(i) |
+                ByteCode::LoadCallee(i) |
+                ByteCode::Store(i) |
+                ByteCode::Var(i) |
+                ByteCode::AccessProp(i) |
+                ByteCode::StoreProp(i) => {
+                    format!("{} ; {}", i, prop_name(&self.id_idt_map, *i))
+                },
+                ByteCode::LoadConst(i) |
+                ByteCode::Variant(i) |
+                ByteCode::Matches(i) => {
+                    match self.constants.get(*i as usize) {
+                        Some(v) => format!("{} ; {}", i, v),
+                        None => format!("{} ; <missing>", i)
+                    }
+                },
+                ByteCode::Jump(t) |
+                ByteCode::JumpIfFalse(t) |
+                ByteCode::JumpIfNone(t) |
+                ByteCode::JumpIfSome(t) => {
+                    format!("L{}", labels[t])
+                },
+                ByteCode::Fun(i) => {
+                    nested.push(*i);
+                    format!("blk {}", i)
+                },
+                _ => {
+                    let s = format!("{:?}", c);
+                    match s.split_once('(') {
+                        Some((_, arg)) => arg.trim_end_matches(')').to_string(),
+                        None => String::new()
+                    }
+                }
+            };
+
+            let ins = format!("{:<6} {:05}  {:<12} {}", label, pc, op_name(c), operand);
+            let _ = writeln!(out, "{}", ins.trim_end());
+        }
+
+        if let Some(l) = labels.get(&(code.len() as u32)) {
+            let _ = writeln!(out, "{:<6} {:05}  <end>", format!("L{}:", l), code.len());
+        }
+
+        nested
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Vm;
+
+    #[test]
+    fn resolves_names_and_constants() {
+        let mut vm = Vm::new();
+        let blk = vm.compile_code(r#"name = "seppo""#);
+        let out = vm.disassemble(blk);
+        assert!(out.contains("LoadConst    0 ; \"seppo\""));
+        assert!(out.contains("Store        30 ; name"));
+        assert!(out.contains("     1 | name = \"seppo\""));
+    }
+
+    #[test]
+    fn jump_targets_as_labels() {
+        let mut vm = Vm::new();
+        let blk = vm.compile_code("for a in [1, 2] {\n    b = a\n}");
+        let out = vm.disassemble(blk);
+        assert!(out.contains("L0:    00004  Next"));
+        assert!(out.contains("JumpIfFalse  L1"));
+        assert!(out.contains("Jump         L0"));
+        assert!(out.contains("L1:    00011  <end>"));
+        assert!(out.contains("     2 |     b = a"));
+    }
+
+    #[test]
+    fn includes_nested_function_blocks() {
+        let mut vm = Vm::new();
+        let blk = vm.compile_code("f = (x) => {\n    return x\n}\nreturn f(1)");
+        let out = vm.disassemble(blk);
+        assert!(out.contains("Fun          blk 0"));
+        assert!(out.contains("blk 0:"));
+        assert!(out.contains("     2 |     return x"));
+        assert!(out.contains("     4 | return f(1)"));
+    }
+}
@@ -0,0 +1,338 @@
+use crate::analysis::var_type_name;
+use crate::analysis::variant_signature;
+use crate::cst::SyntaxNode;
+use crate::cst::SyntaxTree;
+use crate::parsing::DocComment;
+use crate::ASTNode;
+use crate::Assign;
+use crate::Decl;
+use crate::ParseError;
+use crate::Parser;
+use crate::TypeField;
+use crate::VariantDef;
+
+/// Output format of `module_docs`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DocFormat {
+    Markdown,
+    Html,
+}
+
+enum ItemKind {
+    Fun(Vec<String>),
+    Struct(Vec<TypeField>),
+    Enum(Vec<VariantDef>),
+}
+
+/// A function, struct or enum of the module, the name qualified by the inline
+/// modules it is exported from.
+struct Item {
+    name: String,
+    kind: ItemKind,
+    doc: Option<String>,
+}
+
+impl Item {
+    /// `name(a, b)` for a function, the name for a struct or enum.
+    fn title(&self) -> String {
+        match &self.kind {
+            ItemKind::Fun(params) => format!("{}({})", self.name, params.join(", ")),
+            ItemKind::Struct(_) | ItemKind::Enum(_) => self.name.clone(),
+        }
+    }
+}
+
+/// API docs of the script in `input`, titled `name`. They list the
+/// exported functions with their parameters, the structs with their
+/// fields and the enums with their variants, each with the `///` comment above it. Exports of exported inline
+/// modules are listed under `module.name`.
+pub fn module_docs(name: &str, input: &str, format: DocFormat) -> Result<String, ParseError> {
+    let mut parser = Parser::new(input);
+    parser.try_parse()?;
+    let tree = SyntaxTree::parse(input)?;
+
+    let mut items = vec![];
+    collect(&tree.root, "", parser.doc_comments(), &mut items);
+
+    Ok(match format {
+        DocFormat::Markdown => markdown(name, &items),
+        DocFormat::Html => html(name, &items),
+    })
+}
+
+fn collect(node: &SyntaxNode, prefix: &str, docs: &[DocComment], items: &mut Vec<Item>) {
+    for stmt in node.nodes() {
+        let start = stmt.tokens().first().map(|t| t.span.start);
+        let doc = docs.iter().find(|d| Some(d.span.start) == start).map(|d| d.text.clone());
+
+        match stmt.to_ast() {
+            Some(ASTNode::Export(item)) => match *item {
+                ASTNode::Assign(Assign { left, right, .. }) => {
+                    if let (ASTNode::Ident(name), ASTNode::Fun(fun)) = (*left, *right) {
+                        let params = fun.params.into_iter().map(|p| p.name).collect();
+                        items.push(Item { name: format!("{}{}", prefix, name), kind: ItemKind::Fun(params), doc });
+                    }
+                },
+                ASTNode::Decl(Decl { name, value, .. }) => {
+                    if let ASTNode::Fun(fun) = *value {
+                        let params = fun.params.into_iter().map(|p| p.name).collect();
+                        items.push(Item { name: format!("{}{}", prefix, name), kind: ItemKind::Fun(params), doc });
+                    }
+                },
+                ASTNode::Module(def) => {
+                    if let Some(module) = stmt.nodes().next() {
+                        collect(module, &format!("{}{}.", prefix, def.name), docs, items);
+                    }
+                },
+                ASTNode::StructDef(def) => {
+                    items.push(Item { name: format!("{}{}", prefix, def.name), kind: ItemKind::Struct(def.fields), doc });
+                },
+                ASTNode::EnumDef(def) => {
+                    items.push(Item { name: format!("{}{}", prefix, def.name), kind: ItemKind::Enum(def.variants), doc });
+                },
+                _ => {},
+            },
+            Some(ASTNode::StructDef(def)) => {
+                items.push(Item { name: format!("{}{}", prefix, def.name), kind: ItemKind::Struct(def.fields), doc });
+            },
+            Some(ASTNode::EnumDef(def)) => {
+                items.push(Item { name: format!("{}{}", prefix, def.name), kind: ItemKind::Enum(def.variants), doc });
+            },
+            _ => {},
+        }
+    }
+}
+
+fn funs(items: &[Item]) -> impl Iterator<Item = &Item> {
+    items.iter().filter(|i| matches!(i.kind, ItemKind::Fun(_)))
+}
+
+fn structs(items: &[Item]) -> impl Iterator<Item = &Item> {
+    items.iter().filter(|i| matches!(i.kind, ItemKind::Struct(_)))
+}
+
+fn enums(items: &[Item]) -> impl Iterator<Item = &Item> {
+    items.iter().filter(|i| matches!(i.kind, ItemKind::Enum(_)))
+}
+
+fn sections(items: &[Item]) -> [(&str, Vec<&Item>); 3] {
+    [("Functions", funs(items).collect()), ("Structs", structs(items).collect()), ("Enums", enums(items).collect())]
+}
+
+fn markdown(name: &str, items: &[Item]) -> String {
+    let mut out = format!("# {}\n", name);
+
+    for (heading, list) in sections(items) {
+        if list.is_empty() {
+            continue;
+        }
+
+        out.push_str(&format!("\n## {}\n", heading));
+        for item in list {
+            out.push_str(&format!("\n### `{}`\n", item.title()));
+            if let Some(doc) = &item.doc {
+                out.push_str(&format!("\n{}\n", doc));
+            }
+            if let ItemKind::Struct(fields) = &item.kind {
+                out.push_str("\n| Field | Type |\n| --- | --- |\n");
+                for f in fields {
+                    out.push_str(&format!("| `{}` | `{}` |\n", f.name, var_type_name(&f.typ)));
+                }
+            }
+            if let ItemKind::Enum(variants) = &item.kind {
+                out.push('\n');
+                for v in variants {
+                    out.push_str(&format!("- `{}`\n", variant_signature(v)));
+                }
+            }
+        }
+    }
+
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn html(name: &str, items: &[Item]) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n</head>\n<body>\n<h1>{0}</h1>\n",
+        escape(name),
+    );
+
+    for (heading, list) in sections(items) {
+        if list.is_empty() {
+            continue;
+        }
+
+        out.push_str(&format!("<h2>{}</h2>\n", heading));
+        for item in list {
+            out.push_str(&format!("<h3 id=\"{}\"><code>{}</code></h3>\n", escape(&item.name), escape(&item.title())));
+            // Blank lines split the doc comment into paragraphs
+            for para in item.doc.iter().flat_map(|d| d.split("\n\n")) {
+                out.push_str(&format!("<p>{}</p>\n", escape(para.trim())));
+            }
+            if let ItemKind::Struct(fields) = &item.kind {
+                out.push_str("<table>\n<tr><th>Field</th><th>Type</th></tr>\n");
+                for f in fields {
+                    out.push_str(&format!(
+                        "<tr><td><code>{}</code></td><td><code>{}</code></td></tr>\n",
+                        escape(&f.name),
+                        escape(&var_type_name(&f.typ)),
+                    ));
+                }
+                out.push_str("</table>\n");
+            }
+            if let ItemKind::Enum(variants) = &item.kind {
+                out.push_str("<ul>\n");
+                for v in variants {
+                    out.push_str(&format!("<li><code>{}</code></li>\n", escape(&variant_signature(v))));
+                }
+                out.push_str("</ul>\n");
+            }
+        }
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE: &str = "/// A point on the map.
+struct Point { x: Int, y: Float, label: String }
+
+/// Doubles `x`.
+///
+/// Works for ints and floats.
+export double = (x) => {
+    return x * 2
+}
+
+/// Not exported, so not listed.
+helper = (a) => {
+    return a
+}
+
+// A plain comment is no doc.
+export add = (a, b) => a + b
+
+/// Halves `x`.
+export const half = (x) => {
+    return x / 2
+}
+
+export mod geo {
+    /// Moves `p` by `d`.
+    export shift = (p, d) => p
+}
+
+/// How a request ended.
+enum Status { Ok(Point), Error { code: Int, message: String }, Pending }
+";
+
+    #[test]
+    fn markdown_docs() {
+        let docs = module_docs("math", CODE, DocFormat::Markdown).unwrap();
+        assert_eq!(docs, "# math
+
+## Functions
+
+### `double(x)`
+
+Doubles `x`.
+
+Works for ints and floats.
+
+### `add(a, b)`
+
+### `half(x)`
+
+Halves `x`.
+
+### `geo.shift(p, d)`
+
+Moves `p` by `d`.
+
+## Structs
+
+### `Point`
+
+A point on the map.
+
+| Field | Type |
+| --- | --- |
+| `x` | `Int` |
+| `y` | `Float` |
+| `label` | `String` |
+
+## Enums
+
+### `Status`
+
+How a request ended.
+
+- `Ok(Point)`
+- `Error { code: Int, message: String }`
+- `Pending`
+");
+    }
+
+    #[test]
+    fn html_docs() {
+        let docs = module_docs("a<b", "/// Sums <a> & b.\nexport add = (a, b) => a + b", DocFormat::Html).unwrap();
+        assert_eq!(docs, "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>a&lt;b</title>
+</head>
+<body>
+<h1>a&lt;b</h1>
+<h2>Functions</h2>
+<h3 id=\"add\"><code>add(a, b)</code></h3>
+<p>Sums &lt;a&gt; &amp; b.</p>
+</body>
+</html>
+");
+
+        let docs = module_docs("m", CODE, DocFormat::Html).unwrap();
+        assert!(docs.contains("<p>Doubles `x`.</p>\n<p>Works for ints and floats.</p>\n"));
+        assert!(docs.contains("<tr><td><code>y</code></td><td><code>Float</code></td></tr>\n"));
+        assert!(docs.contains("<ul>\n<li><code>Ok(Point)</code></li>\n"));
+    }
+
+    #[test]
+    fn exported_types() {
+        let code = "/// A point.\nexport struct Point { x: Int }\n/// A shape.\nexport enum Shape { Dot(Point) }";
+        let docs = module_docs("geo", code, DocFormat::Markdown).unwrap();
+        assert_eq!(docs, "# geo
+
+## Structs
+
+### `Point`
+
+A point.
+
+| Field | Type |
+| --- | --- |
+| `x` | `Int` |
+
+## Enums
+
+### `Shape`
+
+A shape.
+
+- `Dot(Point)`
+");
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(module_docs("m", "/* open", DocFormat::Markdown).is_err());
+    }
+}
@@ -0,0 +1,843 @@
+use std::ops::Range;
+
+use crate::analysis::var_type_name;
+use crate::ASTNode;
+use crate::ForCond;
+use crate::Op;
+use crate::ParseError;
+use crate::Parser;
+use crate::Pattern;
+use crate::PatternFields;
+use crate::Value;
+use crate::VariantArgs;
+use crate::VariantShape;
+use crate::TokenKind;
+use crate::parsing::parse_float;
+use crate::parsing::parse_int;
+
+const INDENT: &str = "    ";
+
+/// Lists, objects and call arguments longer than this are split into one
+/// item per line.
+const MAX_INLINE: usize = 60;
+
+/// Formats a script into canonical FlexScript. Formatting keeps the
+/// syntax tree unchanged and formatting the output again returns it as is.
+pub fn format_code(input: &str) -> Result<String, ParseError> {
+    let mut parser = Parser::new(input);
+    let ast = parser.try_parse()?;
+
+    let numbers = parser.token_spans().into_iter()
+        .filter(|(kind, _)| matches!(kind, TokenKind::Int | TokenKind::Float))
+        .map(|(_, span)| &input[span])
+        .collect();
+
+    let mut printer = Printer {
+        input,
+        stmts: parser.stmt_spans(),
+        blocks: parser.block_spans(),
+        comments: parser.comments(),
+        numbers,
+        state: State::default(),
+    };
+
+    Ok(printer.block(&ast, 0, Some(input.len())))
+}
+
+/// True when the script is already formatted, for checks in CI.
+pub fn is_formatted(input: &str) -> Result<bool, ParseError> {
+    Ok(format_code(input)? == input)
+}
+
+/// Where an item is printed, which decides if a function can be written
+/// with an expression body.
+#[derive(Clone, Copy, PartialEq)]
+enum Pos {
+    /// Followed by `,` or a closing bracket.
+    Delimited,
+    /// Ends a statement, `safe` when the next statement does not start
+    /// with `(` which would turn the body into a call.
+    Tail { safe: bool },
+}
+
+/// Positions in the expression grammar. Terms are the left side of `+`,
+/// `-`, `==`, `<` and `>`, factors the operands of `*` and `/`, and objects the
+/// left side of a property access or call.
+#[derive(Clone, Copy, PartialEq)]
+enum Ctx {
+    Expr,
+    Term,
+    Factor,
+    Object,
+}
+
+#[derive(Clone, Copy, Default)]
+struct State {
+    stmt: usize,
+    block: usize,
+    comment: usize,
+    number: usize,
+    last_end: usize,
+}
+
+struct Printer<'a> {
+    input: &'a str,
+    stmts: &'a [Range<usize>],
+    blocks: &'a [Range<usize>],
+    comments: Vec<Range<usize>>,
+    /// Number literals as written, so `0xff` and `1_000` keep their form.
+    numbers: Vec<&'a str>,
+    state: State,
+}
+
+impl<'a> Printer<'a> {
+    fn next_stmt(&mut self) -> Range<usize> {
+        let span = self.stmts.get(self.state.stmt).cloned().unwrap_or(0..0);
+        self.state.stmt += 1;
+        span
+    }
+
+    /// Whether the next body was written in braces, which the parser
+    /// recorded as a block opening before the body's first statement.
+    fn next_is_block(&self) -> bool {
+        let block = match self.blocks.get(self.state.block) {
+            Some(b) => b,
+            None => return false,
+        };
+        match self.stmts.get(self.state.stmt) {
+            Some(stmt) => block.start < stmt.start,
+            None => true,
+        }
+    }
+
+    /// Prints a literal, numbers in the form they were written in.
+    fn literal(&mut self, v: &Value) -> String {
+        let text = match v {
+            Value::Int(_) | Value::Float(_) => self.numbers.get(self.state.number).copied(),
+            _ => None,
+        };
+        let same = match (v, text) {
+            (Value::Int(i), Some(t)) => parse_int(t) == Some(i.unsigned_abs()),
+            (Value::Float(f), Some(t)) => parse_float(t) == Some(f.abs()),
+            _ => false,
+        };
+        if !same {
+            return literal(v);
+        }
+
+        self.state.number += 1;
+        let negative = matches!(v, Value::Int(i) if *i < 0) || matches!(v, Value::Float(f) if f.is_sign_negative());
+        format!("{}{}", if negative { "-" } else { "" }, text.unwrap_or_default())
+    }
+
+    fn comment(&self, i: usize) -> &'a str {
+        self.input[self.comments[i].clone()].trim_end()
+    }
+
+    fn blank_before(&self, pos: usize) -> bool {
+        self.state.last_end < pos && self.input[self.state.last_end..pos].matches('\n').count() > 1
+    }
+
+    /// Prints comments starting before `pos` on lines of their own.
+    fn comments_before(&mut self, pos: usize, indent: usize, out: &mut String, first: &mut bool) {
+        while let Some(c) = self.comments.get(self.state.comment).cloned() {
+            if c.start >= pos {
+                break;
+            }
+            if !*first && self.blank_before(c.start) {
+                out.push('\n');
+            }
+            *first = false;
+
+            out.push_str(&INDENT.repeat(indent));
+            out.push_str(self.comment(self.state.comment));
+            out.push('\n');
+            self.state.comment += 1;
+            self.state.last_end = c.end;
+        }
+    }
+
+    /// Prints statements one per line. Comments left before `end` are
+    /// printed after the last statement.
+    fn block(&mut self, nodes: &[ASTNode], indent: usize, end: Option<usize>) -> String {
+        let mut out = String::new();
+        let mut first = true;
+
+        for (i, node) in nodes.iter().enumerate() {
+            let span = self.next_stmt();
+            self.comments_before(span.start, indent, &mut out, &mut first);
+            if !first && self.blank_before(span.start) {
+                out.push('\n');
+            }
+            first = false;
+
+            let safe = nodes.get(i + 1).is_none_or(|next| !starts_with_paren(next));
+            let text = self.item(node, indent, Pos::Tail { safe });
+            out.push_str(&INDENT.repeat(indent));
+            out.push_str(&text);
+            self.state.last_end = self.state.last_end.max(span.end);
+
+            // A comment on the line the statement ends on stays there
+            if let Some(c) = self.comments.get(self.state.comment).cloned() {
+                if c.start >= span.end && !self.input[span.end..c.start].contains('\n') {
+                    out.push(' ');
+                    out.push_str(self.comment(self.state.comment));
+                    self.state.comment += 1;
+                    self.state.last_end = c.end;
+                }
+            }
+            out.push('\n');
+        }
+
+        if let Some(end) = end {
+            self.comments_before(end, indent, &mut out, &mut first);
+        }
+
+        out
+    }
+
+    /// Prints a `{ ... }` body the parser recorded as a block.
+    fn braced(&mut self, nodes: &[ASTNode], indent: usize) -> String {
+        let end = self.blocks.get(self.state.block).map(|b| b.end.saturating_sub(1));
+        self.state.block += 1;
+
+        let body = self.block(nodes, indent + 1, end);
+        match body.is_empty() {
+            true => "{}".to_string(),
+            false => format!("{{\n{}{}}}", body, INDENT.repeat(indent)),
+        }
+    }
+
+    fn item(&mut self, node: &ASTNode, indent: usize, pos: Pos) -> String {
+        match node {
+            ASTNode::Assign(a) => {
+                let left = self.item(&a.left, indent, pos);
+                let op = match a.op {
+                    Some(Op::Plus) => "+=",
+                    Some(Op::Minus) => "-=",
+                    Some(Op::Mul) => "*=",
+                    Some(Op::Div) => "/=",
+                    _ => "=",
+                };
+                format!("{} {} {}", left, op, self.item(&a.right, indent, pos))
+            },
+            ASTNode::Decl(d) => {
+                let keyword = if d.constant { "const" } else { "let" };
+                format!("{} {} = {}", keyword, d.name, self.item(&d.value, indent, pos))
+            },
+            ASTNode::Spread(v) => format!("...{}", self.item(v, indent, pos)),
+            ASTNode::Ret(r) => match &*r.value {
+                Some(v) => format!("return {}", self.item(v, indent, pos)),
+                None => "return".to_string(),
+            },
+            ASTNode::If(i) => {
+                let mut out = format!("if {} ", self.expr(&i.cond, indent, Ctx::Expr));
+                out.push_str(&self.braced(&i.body, indent));
+
+                if let Some(els) = &i.els {
+                    match els.as_slice() {
+                        [n @ ASTNode::If(_)] if !self.next_is_block() => {
+                            self.next_stmt();
+                            out.push_str(" else ");
+                            out.push_str(&self.item(n, indent, pos));
+                        },
+                        _ => {
+                            out.push_str(" else ");
+                            out.push_str(&self.braced(els, indent));
+                        }
+                    }
+                }
+                out
+            },
+            ASTNode::For(f) => {
+                let head = match &f.cond {
+                    ForCond::FromIt { ident, it } => {
+                        let it_text = self.for_it(it, indent);
+                        format!("for {} in {} ", ident, it_text)
+                    },
+                    ForCond::Unpack { idents, it } => {
+                        let it_text = self.for_it(it, indent);
+                        format!("for [{}] in {} ", idents.join(", "), it_text)
+                    },
+                    ForCond::None => "for ".to_string(),
+                };
+                format!("{}{}", head, self.braced(&f.body, indent))
+            },
+            ASTNode::Test(t) => format!("test \"{}\" {}", t.name, self.braced(&t.body, indent)),
+            ASTNode::Module(m) => format!("mod {} {}", m.name, self.braced(&m.body, indent)),
+            ASTNode::Import(i) => format!("import {}", i.path.join(".")),
+            ASTNode::Use(u) => format!("use {}", u.path.join("::")),
+            ASTNode::Export(node) => format!("export {}", self.item(node, indent, pos)),
+            ASTNode::StructDef(s) => {
+                if s.fields.is_empty() {
+                    return format!("struct {} {{}}", s.name);
+                }
+                let mut out = format!("struct {} {{\n", s.name);
+                for field in &s.fields {
+                    out.push_str(&format!("{}{}: {},\n", INDENT.repeat(indent + 1), field.name, var_type_name(&field.typ)));
+                }
+                out.push_str(&INDENT.repeat(indent));
+                out.push('}');
+                out
+            },
+            ASTNode::EnumDef(e) => {
+                if e.variants.is_empty() {
+                    return format!("enum {} {{}}", e.name);
+                }
+                let mut out = format!("enum {} {{\n", e.name);
+                for v in &e.variants {
+                    let fields = match &v.shape {
+                        VariantShape::Unit => String::new(),
+                        VariantShape::Tuple(types) => {
+                            format!("({})", types.iter().map(var_type_name).collect::<Vec<_>>().join(", "))
+                        },
+                        VariantShape::Struct(fields) => {
+                            let fields = fields.iter()
+                                .map(|f| format!("{}: {}", f.name, var_type_name(&f.typ)))
+                                .collect::<Vec<_>>();
+                            format!(" {{ {} }}", fields.join(", "))
+                        },
+                    };
+                    out.push_str(&format!("{}{}{},\n", INDENT.repeat(indent + 1), v.name, fields));
+                }
+                out.push_str(&INDENT.repeat(indent));
+                out.push('}');
+                out
+            },
+            ASTNode::Variant(v) => {
+                let path = format!("{}::{}", v.enum_name, v.name);
+                match &v.args {
+                    VariantArgs::Unit => path,
+                    VariantArgs::Tuple(args) => {
+                        let args = args.iter().map(|a| (None, a)).collect::<Vec<_>>();
+                        format!("{}{}", path, self.seq("(", ")", &args, indent, true))
+                    },
+                    VariantArgs::Struct(props) => {
+                        let props = props.iter()
+                            .map(|p| (Some(p.name.as_str()), &*p.value))
+                            .collect::<Vec<_>>();
+                        format!("{} {}", path, self.seq("{", "}", &props, indent, false))
+                    },
+                }
+            },
+            ASTNode::Match(m) => {
+                let mut out = format!("match {} {{\n", self.expr(&m.value, indent, Ctx::Expr));
+                let mut first = true;
+                for arm in &m.arms {
+                    // Comments above an arm are printed before its pattern
+                    let braced = arm.body.is_empty() || self.next_is_block();
+                    let start = match braced {
+                        true => self.blocks.get(self.state.block).map(|b| b.start),
+                        false => self.stmts.get(self.state.stmt).map(|s| s.start),
+                    };
+                    if let Some(start) = start {
+                        self.comments_before(start, indent + 1, &mut out, &mut first);
+                    }
+                    first = false;
+
+                    let pattern = self.pattern(&arm.pattern);
+                    let body = match braced {
+                        true => self.braced(&arm.body, indent + 1),
+                        false => {
+                            let span = self.next_stmt();
+                            let body = self.item(&arm.body[0], indent + 1, Pos::Tail { safe: true });
+                            self.state.last_end = self.state.last_end.max(span.end);
+                            body
+                        },
+                    };
+                    out.push_str(&format!("{}{} => {}\n", INDENT.repeat(indent + 1), pattern, body));
+                }
+                out.push_str(&INDENT.repeat(indent));
+                out.push('}');
+                out
+            },
+            ASTNode::Fun(f) => self.fun(f, indent, pos),
+            ASTNode::Array(a) => {
+                let items = a.items.iter().map(|i| (None, i)).collect::<Vec<_>>();
+                self.seq("[", "]", &items, indent, false)
+            },
+            ASTNode::ObjIns(obj) => {
+                let props = obj.props.iter()
+                    .map(|p| match &*p.value {
+                        ASTNode::Spread(_) => (None, &*p.value),
+                        _ => (Some(p.name.as_str()), &*p.value),
+                    })
+                    .collect::<Vec<_>>();
+                let body = self.seq("{", "}", &props, indent, false);
+                match &obj.name {
+                    Some(name) => format!("{} {}", name, body),
+                    None => body,
+                }
+            },
+            _ => self.expr(node, indent, Ctx::Expr),
+        }
+    }
+
+    fn fun(&mut self, f: &crate::Fun, indent: usize, pos: Pos) -> String {
+        let params = f.params.iter().map(|p| p.name.as_str()).collect::<Vec<_>>();
+        let head = format!("({}) =>", params.join(", "));
+
+        let short = match (f.body.as_slice(), pos) {
+            ([ASTNode::Ident(_)], Pos::Tail { .. }) => false,
+            ([body], Pos::Delimited) | ([body], Pos::Tail { safe: true }) => matches!(body,
+                ASTNode::Ident(_) | ASTNode::Lit(_) | ASTNode::BinOp(_) | ASTNode::Call(_)
+                | ASTNode::ProbAccess(_) | ASTNode::Index(_) | ASTNode::Array(_)
+            ),
+            _ => false,
+        };
+
+        let braced = f.body.is_empty() || self.next_is_block();
+
+        if short {
+            let saved = self.state;
+            let mut clean = true;
+
+            if braced {
+                let block = self.blocks[self.state.block].clone();
+                clean = !self.comments.iter().any(|c| block.start < c.start && c.start < block.end);
+                self.state.block += 1;
+            }
+
+            self.next_stmt();
+            let body = self.item(&f.body[0], indent, pos);
+            if clean && !body.contains('\n') {
+                return format!("{} {}", head, body);
+            }
+            self.state = saved;
+        }
+
+        let body = match braced {
+            true => self.braced(&f.body, indent),
+            false => {
+                let body = self.block(&f.body, indent + 1, None);
+                format!("{{\n{}{}}}", body, INDENT.repeat(indent))
+            }
+        };
+        format!("{} {}", head, body)
+    }
+
+    /// Prints comma separated items on one line when they are short,
+    /// otherwise one per line with a trailing comma. With `hug_last`
+    /// a multi line last item stays on the opening line, as callbacks do.
+    fn seq(&mut self, open: &str, close: &str, items: &[(Option<&str>, &ASTNode)], indent: usize, hug_last: bool) -> String {
+        if items.is_empty() {
+            return format!("{}{}", open, close);
+        }
+
+        let saved = self.state;
+        let parts = items.iter()
+            .map(|(name, node)| self.seq_item(*name, node, indent))
+            .collect::<Vec<_>>();
+        let joined = parts.join(", ");
+        let pad = if open == "{" { " " } else { "" };
+
+        let multi = parts.iter().filter(|p| p.contains('\n')).count();
+        let hugged = hug_last && multi == 1 && parts.last().is_some_and(|p| p.contains('\n'));
+        let first_line = joined.lines().next().unwrap_or_default();
+
+        if (multi == 0 && joined.len() <= MAX_INLINE) || (hugged && first_line.len() <= MAX_INLINE) {
+            return format!("{}{}{}{}{}", open, pad, joined, pad, close);
+        }
+
+        self.state = saved;
+        let mut out = format!("{}\n", open);
+        for (name, node) in items {
+            out.push_str(&INDENT.repeat(indent + 1));
+            out.push_str(&self.seq_item(*name, node, indent + 1));
+            out.push_str(",\n");
+        }
+        out.push_str(&INDENT.repeat(indent));
+        out.push_str(close);
+        out
+    }
+
+    fn for_it(&mut self, it: &ASTNode, indent: usize) -> String {
+        match it {
+            ASTNode::Ident(_) | ASTNode::Array(_) => self.item(it, indent, Pos::Delimited),
+            _ => {
+                // The parser reads a leading identifier as the whole iterable
+                let text = self.expr(it, indent, Ctx::Expr);
+                match text.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+                    true => format!("({})", text),
+                    false => text,
+                }
+            }
+        }
+    }
+
+    fn pattern(&mut self, p: &Pattern) -> String {
+        match p {
+            Pattern::Wildcard => "_".to_string(),
+            Pattern::Bind(name) => name.clone(),
+            Pattern::Lit(v) => self.literal(v),
+            Pattern::Variant { enum_name, name, fields } => {
+                let path = format!("{}::{}", enum_name, name);
+                match fields {
+                    PatternFields::Unit => path,
+                    PatternFields::Tuple(items) => {
+                        let items = items.iter().map(|i| self.pattern(i)).collect::<Vec<_>>();
+                        format!("{}({})", path, items.join(", "))
+                    },
+                    PatternFields::Struct(fields) => {
+                        let fields = fields.iter().map(|(field, p)| match p {
+                            Pattern::Bind(name) if name == field => field.clone(),
+                            p => format!("{}: {}", field, self.pattern(p)),
+                        }).collect::<Vec<_>>();
+                        match fields.is_empty() {
+                            true => format!("{} {{}}", path),
+                            false => format!("{} {{ {} }}", path, fields.join(", ")),
+                        }
+                    },
+                }
+            },
+        }
+    }
+
+    fn seq_item(&mut self, name: Option<&str>, node: &ASTNode, indent: usize) -> String {
+        let value = self.item(node, indent, Pos::Delimited);
+        match name {
+            Some(name) => format!("{}: {}", name, value),
+            None => value,
+        }
+    }
+
+    fn expr(&mut self, node: &ASTNode, indent: usize, ctx: Ctx) -> String {
+        if needs_parens(node, ctx) {
+            return format!("({})", self.expr(node, indent, Ctx::Expr));
+        }
+
+        match node {
+            ASTNode::Ident(name) => name.clone(),
+            ASTNode::Lit(v) => self.literal(v),
+            ASTNode::BinOp(b) => {
+                let (op, side) = match b.op {
+                    Op::Plus => ("+", Ctx::Term),
+                    Op::Minus => ("-", Ctx::Term),
+                    Op::Eq => ("==", Ctx::Term),
+                    Op::Lt => ("<", Ctx::Term),
+                    Op::Gt => (">", Ctx::Term),
+                    Op::Coalesce => ("??", Ctx::Term),
+                    Op::Mul => ("*", Ctx::Factor),
+                    Op::Div => ("/", Ctx::Factor),
+                };
+                let right_ctx = if side == Ctx::Term { Ctx::Expr } else { Ctx::Factor };
+
+                let left = self.expr(&b.left, indent, side);
+                format!("{} {} {}", left, op, self.expr(&b.right, indent, right_ctx))
+            },
+            ASTNode::Call(c) => {
+                let callee = self.expr(&c.callee, indent, Ctx::Object);
+                let args = c.args.iter().map(|a| (None, a)).collect::<Vec<_>>();
+                format!("{}{}", callee, self.seq("(", ")", &args, indent, true))
+            },
+            ASTNode::ProbAccess(p) => {
+                let dot = if p.optional { "?." } else { "." };
+                format!("{}{}{}", self.expr(&p.object, indent, Ctx::Object), dot, p.property)
+            },
+            ASTNode::Index(i) => {
+                let object = self.expr(&i.object, indent, Ctx::Object);
+                format!("{}[{}]", object, self.item(&i.index, indent, Pos::Delimited))
+            },
+            ASTNode::Neg(v) => {
+                // Lists are not read as the operand of a minus without parens
+                let ctx = if matches!(**v, ASTNode::Array(_)) { Ctx::Factor } else { Ctx::Object };
+                format!("-{}", self.expr(v, indent, ctx))
+            },
+            _ => self.item(node, indent, Pos::Delimited),
+        }
+    }
+}
+
+/// Whether a node has to be wrapped in parentheses to be read back the
+/// same in the given position.
+fn needs_parens(node: &ASTNode, ctx: Ctx) -> bool {
+    match (ctx, node) {
+        (Ctx::Object, ASTNode::Lit(Value::Int(i))) if *i < 0 => true,
+        (Ctx::Object, ASTNode::Lit(Value::Float(f))) if f.is_sign_negative() => true,
+        (_, ASTNode::Ident(_) | ASTNode::Lit(_) | ASTNode::Variant(_)) => false,
+        (_, ASTNode::Call(_) | ASTNode::ProbAccess(_) | ASTNode::Index(_)) => false,
+        (Ctx::Object, ASTNode::Neg(_)) => true,
+        (_, ASTNode::Neg(_)) => false,
+        (Ctx::Expr, _) => false,
+        (Ctx::Term, ASTNode::BinOp(b)) => !matches!(b.op, Op::Mul | Op::Div),
+        (Ctx::Term, _) => true,
+        (Ctx::Factor, _) => true,
+        (Ctx::Object, ASTNode::Array(_)) => false,
+        (Ctx::Object, ASTNode::BinOp(_)) => true,
+        (Ctx::Object, _) => true,
+    }
+}
+
+fn starts_with_paren(node: &ASTNode) -> bool {
+    match node {
+        ASTNode::Fun(_) => true,
+        ASTNode::Call(c) => needs_parens(&c.callee, Ctx::Object) || starts_with_paren(&c.callee),
+        ASTNode::ProbAccess(p) => needs_parens(&p.object, Ctx::Object) || starts_with_paren(&p.object),
+        ASTNode::Index(i) => needs_parens(&i.object, Ctx::Object) || starts_with_paren(&i.object),
+        ASTNode::BinOp(b) => {
+            let ctx = if matches!(b.op, Op::Mul | Op::Div) { Ctx::Factor } else { Ctx::Term };
+            needs_parens(&b.left, ctx) || starts_with_paren(&b.left)
+        },
+        _ => false,
+    }
+}
+
+fn literal(v: &Value) -> String {
+    match v {
+        Value::Str(s) => format!("\"{}\"", s),
+        Value::Float(f) => {
+            let s = f.to_string();
+            match s.contains('.') {
+                true => s,
+                false => format!("{}.0", s),
+            }
+        },
+        Value::Int(i) => i.to_string(),
+        Value::Bool(b) => b.to_string(),
+        _ => "none".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(code: &str) -> String {
+        let formatted = format_code(code).unwrap();
+        assert_eq!(Parser::new(&formatted).parse(), Parser::new(code).parse(), "{}", formatted);
+        assert_eq!(format_code(&formatted).unwrap(), formatted);
+        formatted
+    }
+
+    #[test]
+    fn canonical_functions_and_objects() {
+        let code = r#"
+double = p => p * 2
+inc = (p) => { p + 1 }
+ten = () => 10
+point = Point { x: 1
+y: 2, }
+res = [1, 2].map((inx, x) => {
+return double(x)
+})
+"#;
+
+        assert_eq!(round_trip(code), r#"double = (p) => p * 2
+inc = (p) => p + 1
+ten = () => 10
+point = Point { x: 1, y: 2 }
+res = [1, 2].map((inx, x) => {
+    return double(x)
+})
+"#);
+    }
+
+    #[test]
+    fn keeps_comments_and_blank_lines() {
+        let code = "// header
+a = 1   // one
+
+
+// about b
+b = (x) => {
+  // inside
+  return x // trailing
+  // last
+}
+// end
+";
+
+        assert_eq!(round_trip(code), "// header
+a = 1 // one
+
+// about b
+b = (x) => {
+    // inside
+    return x // trailing
+    // last
+}
+// end
+");
+    }
+
+    #[test]
+    fn block_and_doc_comments() {
+        let code = "/* header
+   /* nested */ */
+/// Adds one.
+f = (x) => {
+  return x + 1 /* after */
+}
+";
+
+        assert_eq!(round_trip(code), "/* header
+   /* nested */ */
+/// Adds one.
+f = (x) => {
+    return x + 1 /* after */
+}
+");
+    }
+
+    #[test]
+    fn parentheses_and_control_flow() {
+        let code = r#"
+a = (1 + 2) * 3
+b = (a * 2)(1)
+c = (f(1)) + 2
+d = 1 - (2 - 3)
+e = (a + 1) < "b" * 2
+if a == 9 { x = 1 } else if a == 8 { x = 2 } else { x = 3 }
+for i in (a.items) { print(i) }
+for { }
+struct User { name: String, age: Int }
+"#;
+
+        assert_eq!(round_trip(code), r#"a = (1 + 2) * 3
+b = (a * 2)(1)
+c = f(1) + 2
+d = 1 - 2 - 3
+e = (a + 1) < "b" * 2
+if a == 9 {
+    x = 1
+} else if a == 8 {
+    x = 2
+} else {
+    x = 3
+}
+for i in (a.items) {
+    print(i)
+}
+for {}
+struct User {
+    name: String,
+    age: Int,
+}
+"#);
+    }
+
+    #[test]
+    fn modules_and_imports() {
+        let code = r#"import  lib.math
+use math::double
+mod io { prefix = 1
+export copy = x => { return x + prefix } }
+"#;
+
+        assert_eq!(round_trip(code), r#"import lib.math
+use math::double
+mod io {
+    prefix = 1
+    export copy = (x) => {
+        return x + prefix
+    }
+}
+"#);
+    }
+
+    #[test]
+    fn declarations() {
+        let code = "let  a=1\nconst f = x => { let b = x\nreturn b }\nexport const pi = 3.14\n";
+
+        assert_eq!(round_trip(code), "let a = 1
+const f = (x) => {
+    let b = x
+    return b
+}
+export const pi = 3.14
+");
+    }
+
+    #[test]
+    fn spread_and_unpack() {
+        let code = "moved = {...base,x: 1}\nfor [k,v] in moved { print(k) }\n";
+
+        assert_eq!(round_trip(code), "moved = { ...base, x: 1 }
+for [k, v] in moved {
+    print(k)
+}
+");
+    }
+
+    #[test]
+    fn index_and_assign_targets() {
+        let code = "items[0]=m.rows[ i ][-1]\no.count+=1\nn /= 2\nv = (f())[0]\n";
+
+        assert_eq!(round_trip(code), "items[0] = m.rows[i][-1]\no.count += 1\nn /= 2\nv = f()[0]\n");
+    }
+
+    #[test]
+    fn minus_and_number_literals() {
+        let code = "a = 5-3\nb = -x*-(y-1)\nc = -o.f(1)[0]\nd = [0xFF, 1_000, 2.5e-3, -0b11, -.5]\ne = (-1).abs()\n";
+
+        assert_eq!(round_trip(code), "a = 5 - 3
+b = -x * -(y - 1)
+c = -o.f(1)[0]
+d = [0xFF, 1_000, 2.5e-3, -0b11, -.5]
+e = (-1).abs()
+");
+    }
+
+    #[test]
+    fn enums_and_match() {
+        let code = "enum Status { Ok(Human), Error { code: int\nmessage: string }\nPending }\ns = Status::Error{code:-1,message:\"m\"}\nmatch s {\n// ok\nStatus::Ok(h)=>print(h)\nStatus::Error{code:-1,message:m}=>{ print(m) }\n_ => print(1)\n}\n";
+
+        assert_eq!(round_trip(code), "enum Status {
+    Ok(Human),
+    Error { code: int, message: string },
+    Pending,
+}
+s = Status::Error { code: -1, message: \"m\" }
+match s {
+    // ok
+    Status::Ok(h) => print(h)
+    Status::Error { code: -1, message: m } => {
+        print(m)
+    }
+    _ => print(1)
+}
+");
+    }
+
+    #[test]
+    fn optional_values() {
+        let code = "struct Person { name: string\nchildren?: [Person], color: string? }\nc = p?.color??\"none\"\nn = p?.children?.len()\nk = p?.children??[]\nmatch c {\nnone => print(none)\n}\n";
+
+        assert_eq!(round_trip(code), "struct Person {
+    name: string,
+    children: [Person]?,
+    color: string?,
+}
+c = p?.color ?? \"none\"
+n = p?.children?.len()
+k = p?.children ?? []
+match c {
+    none => print(none)
+}
+");
+    }
+
+    #[test]
+    fn long_lists_split() {
+        let code = r#"people = [{ id: 1, name: "mikko", email: "mikko@example.com" }, { id: 2, name: "john" }]"#;
+
+        assert_eq!(round_trip(code), r#"people = [
+    { id: 1, name: "mikko", email: "mikko@example.com" },
+    { id: 2, name: "john" },
+]
+"#);
+    }
+
+    #[test]
+    fn round_trips_examples() {
+        for file in ["examples/math_expr.fs", "examples/lit.fs"] {
+            let code = std::fs::read_to_string(file).unwrap();
+            round_trip(&code);
+        }
+
+        let code = "f = x => {\n    return [x, 2.0, \"s\", true]\n}\n(f)(1)\ng = (a) => a\n";
+        round_trip(code);
+        assert!(!is_formatted(code).unwrap());
+        assert!(is_formatted(&format_code(code).unwrap()).unwrap());
+    }
+}
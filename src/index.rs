@@ -0,0 +1,212 @@
+use crate::scope::ScopeManager;
+use crate::value_kind;
+use crate::Ptr;
+use crate::StackValue;
+use crate::Value;
+use crate::Variant;
+use crate::VariantFields;
+
+/// Position `index` in a sequence of `len` items, negative from the end.
+fn position(index: &Value, len: usize) -> Result<usize, String> {
+    let i = match index {
+        Value::Int(i) => *i,
+        v => return Err(format!("index must be an int but got {}", value_kind(v))),
+    };
+
+    let pos = if i < 0 { len as i64 + i } else { i };
+    if pos < 0 || pos >= len as i64 {
+        return Err(format!("index {} is out of range for length {}", i, len));
+    }
+    Ok(pos as usize)
+}
+
+fn key(index: &Value) -> Result<&str, String> {
+    match index {
+        Value::Str(s) => Ok(s),
+        v => Err(format!("object key must be a string but got {}", value_kind(v))),
+    }
+}
+
+/// Places a value read out of the container at `ptr` on the stack. A list
+/// or object held inline is first moved into a slot of the container's
+/// scope, so changes made through the returned pointer stick.
+pub(crate) fn member(scope: &mut ScopeManager, ptr: &Ptr, val: &Value) -> (StackValue, Option<Value>) {
+    match val {
+        Value::List(_) | Value::Obj(_) | Value::Enum(_) => {
+            let slot = scope.store_unamed(ptr.scope_id, val.clone());
+            (StackValue::Ptr(slot.clone()), Some(Value::Ptr(slot)))
+        },
+        v => (StackValue::from(v), None),
+    }
+}
+
+/// The field of an enum value at `index`, a position for a tuple variant
+/// and a name for a struct variant.
+fn variant_field<'a>(v: &'a mut Variant, index: &Value) -> Result<&'a mut Value, String> {
+    let path = format!("{}::{}", v.enum_name, v.name);
+    match &mut v.fields {
+        VariantFields::Tuple(items) => {
+            let i = position(index, items.len())?;
+            Ok(&mut items[i])
+        },
+        VariantFields::Struct(props) => {
+            let k = key(index)?;
+            props.get_mut(k).ok_or_else(|| format!("{} has no field {}", path, k))
+        },
+        VariantFields::Unit => Err(format!("{} has no fields", path)),
+    }
+}
+
+/// Reads `target[index]` from a list, string, object or enum value. `index` has its
+/// pointer resolved.
+pub(crate) fn get(scope: &mut ScopeManager, target: &StackValue, index: &Value) -> Result<StackValue, String> {
+    let ptr = match target {
+        StackValue::Str(s) => {
+            let len = s.chars().count();
+            let i = position(index, len)?;
+            return Ok(StackValue::Str(s.chars().nth(i).unwrap().to_string()));
+        },
+        StackValue::Ptr(ptr) => ptr,
+        v => return Err(format!("can not index {}", value_kind(&Value::from(v.clone())))),
+    };
+
+    let val = match scope.lookup(ptr) {
+        Some(Value::List(items)) => {
+            let i = position(index, items.len())?;
+            items[i].clone()
+        },
+        Some(Value::Obj(obj)) => obj.props.get(key(index)?).cloned().unwrap_or_default(),
+        Some(Value::Enum(v)) => variant_field(v, index)?.clone(),
+        Some(v) => return Err(format!("can not index {}", value_kind(v))),
+        None => return Err("value not found".to_string()),
+    };
+
+    let (val, slot) = member(scope, ptr, &val);
+    match (slot, scope.lookup(ptr)) {
+        (Some(slot), Some(Value::Enum(v))) => *variant_field(v, index)? = slot,
+        (Some(slot), _) => set(scope, target, index, slot)?,
+        _ => {}
+    }
+    Ok(val)
+}
+
+/// Writes `target[index] = value` into a list or object. A list is not
+/// grown, writing past its end is an error.
+pub(crate) fn set(scope: &mut ScopeManager, target: &StackValue, index: &Value, value: Value) -> Result<(), String> {
+    let ptr = match target {
+        StackValue::Ptr(ptr) => ptr,
+        StackValue::Str(_) => return Err("strings can not be changed, build a new one instead".to_string()),
+        v => return Err(format!("can not index {}", value_kind(&Value::from(v.clone())))),
+    };
+
+    match scope.lookup(ptr) {
+        Some(Value::List(items)) => {
+            let i = position(index, items.len())?;
+            items[i] = value;
+        },
+        Some(Value::Obj(obj)) => obj.props.set(key(index)?.to_string(), value),
+        Some(Value::Enum(_)) => return Err("fields of an enum value can not be changed".to_string()),
+        Some(v) => return Err(format!("can not index {}", value_kind(v))),
+        None => return Err("value not found".to_string()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::ints;
+    use crate::test_util::run;
+    use crate::test_util::run_err;
+    use crate::test_util::str;
+    use crate::Value;
+
+    #[test]
+    fn reads() {
+        assert_eq!(run("items = [1, 2, 3]\nreturn items[0]"), Value::Int(1));
+        assert_eq!(run("items = [1, 2, 3]\nreturn items[-1]"), Value::Int(3));
+        assert_eq!(run("s = \"héllo\"\nreturn s[1]"), str("é"));
+        assert_eq!(run("return \"abc\"[-1]"), str("c"));
+        assert_eq!(run("o = { a: 1 }\nk = \"a\"\nreturn o[k]"), Value::Int(1));
+        assert_eq!(run("o = { a: 1 }\nreturn o[\"b\"]"), Value::None);
+        assert_eq!(run("m = { rows: [[1, 2], [3, 4]] }\nreturn m.rows[1][0]"), Value::Int(3));
+        assert_eq!(run("return [5, 6][1]"), Value::Int(6));
+    }
+
+    #[test]
+    fn assigns() {
+        assert_eq!(run("items = [1, 2, 3]\nitems[0] = 9\nitems[-1] = 7\nreturn items"), ints(&[9, 2, 7]));
+        assert_eq!(run("o = { a: 1 }\no.a = 2\no.b = 3\nreturn [o.a, o.b, o.keys()]"), Value::List(vec![
+            Value::Int(2),
+            Value::Int(3),
+            Value::List(vec![str("a"), str("b")]),
+        ]));
+        assert_eq!(run("o = {}\no[\"k\"] = [1]\no.k[0] = 2\nreturn o.k"), ints(&[2]));
+        assert_eq!(run("o = { inner: { n: 1 } }\no.inner.n = 5\nreturn o.inner.n"), Value::Int(5));
+    }
+
+    #[test]
+    fn compound_assigns() {
+        let code = "n = 10
+n += 5
+n -= 3
+n *= 2
+n /= 4
+o = { count: 1, items: [1, 2] }
+o.count += 1
+o.items[1] *= 10
+s = \"a\"
+s += \"b\"
+return [n, o.count, o.items, s]";
+        assert_eq!(run(code), Value::List(vec![
+            Value::Int(6),
+            Value::Int(2),
+            ints(&[1, 20]),
+            str("ab"),
+        ]));
+    }
+
+    #[test]
+    fn changes_reach_shared_values() {
+        let code = "items = []
+items.push({ n: 1 })
+first = items[0]
+first.n = 2
+f = o => {
+    o.n += 1
+}
+f(items[0])
+return items[0].n";
+        assert_eq!(run(code), Value::Int(3));
+
+        let code = "rows = [[1], [2]]
+for row in rows {
+    row[0] += 1
+}
+return rows";
+        assert_eq!(run(code), Value::List(vec![ints(&[2]), ints(&[3])]));
+    }
+
+    #[test]
+    fn index_evaluates_once() {
+        let code = "items = [1, 2]
+calls = []
+next = () => {
+    calls.push(1)
+    return 1
+}
+items[next()] += 5
+return [items, calls.len()]";
+        assert_eq!(run(code), Value::List(vec![ints(&[1, 7]), Value::Int(1)]));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(run_err("items = [1]\nreturn items[3]"), "index 3 is out of range for length 1");
+        assert_eq!(run_err("items = [1]\nitems[-2] = 1"), "index -2 is out of range for length 1");
+        assert_eq!(run_err("items = [1]\nreturn items[\"a\"]"), "index must be an int but got string");
+        assert_eq!(run_err("o = {}\nreturn o[1]"), "object key must be a string but got int");
+        assert_eq!(run_err("s = \"ab\"\ns[0] = \"c\""), "strings can not be changed, build a new one instead");
+        assert_eq!(run_err("n = 1\nreturn n[0]"), "can not index int");
+        assert_eq!(run_err("n = 1\nn.x = 2"), "can not set field x on int");
+    }
+}
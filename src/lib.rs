@@ -5,7 +5,83 @@ mod types;
 mod scope;
 mod callstack;
 mod vm_tests;
+#[cfg(test)]
+mod test_util;
+mod convert;
+mod value_serde;
+mod userdata;
+mod repl;
+mod disasm;
+mod program;
+mod snapshot;
+mod debug;
+mod dap;
+mod analysis;
+mod lsp;
+mod fsfmt;
+mod cst;
+mod lint;
+mod testing;
+mod module;
+mod stdlib;
+mod list;
+mod string;
+mod object;
+mod index;
+mod doc;
 
 pub use types::*;
 pub use parsing::*;
-pub use vm::*;
\ No newline at end of file
+pub use vm::*;
+pub use convert::*;
+pub use value_serde::from_value;
+pub use value_serde::to_value;
+pub use userdata::UserData;
+pub use userdata::UserDataMethods;
+pub use userdata::UserDataRef;
+pub use repl::Repl;
+pub use repl::ReplOutput;
+pub use repl::pretty;
+pub use program::ProgramError;
+pub use program::PROGRAM_MAGIC;
+pub use program::PROGRAM_VERSION;
+pub use snapshot::SNAPSHOT_MAGIC;
+pub use snapshot::SNAPSHOT_VERSION;
+pub use debug::Frame;
+pub use debug::StepMode;
+pub use debug::Variable;
+pub use dap::DapServer;
+pub use analysis::Analysis;
+pub use analysis::Completion;
+pub use analysis::CompletionKind;
+pub use analysis::DefKind;
+pub use analysis::Definition;
+pub use analysis::Reference;
+pub use lsp::LspServer;
+pub use doc::module_docs;
+pub use doc::DocFormat;
+pub use fsfmt::format_code;
+pub use fsfmt::is_formatted;
+pub use cst::NodeKind;
+pub use cst::SyntaxElement;
+pub use cst::SyntaxNode;
+pub use cst::SyntaxToken;
+pub use cst::SyntaxTree;
+pub use cst::TokenKind;
+pub use cst::Trivia;
+pub use cst::TriviaKind;
+pub use lint::lint;
+pub use lint::ConfigError;
+pub use lint::Lint;
+pub use lint::LintConfig;
+pub use lint::Rule;
+pub use lint::Severity;
+pub use testing::run_tests;
+pub use testing::Outcome;
+pub use testing::TestResult;
+pub use testing::TEST_FUNCTIONS;
+pub use module::FsLoader;
+pub use module::MemoryLoader;
+pub use module::ModuleLoader;
+pub use stdlib::NativeFn;
+pub use stdlib::StdLib;
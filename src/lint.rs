@@ -0,0 +1,553 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+use serde_json::json;
+
+use crate::col_of;
+use crate::line_of;
+use crate::Analysis;
+use crate::DefKind;
+use crate::NodeKind;
+use crate::ParseError;
+use crate::SyntaxElement;
+use crate::SyntaxNode;
+use crate::SyntaxToken;
+use crate::SyntaxTree;
+use crate::TokenKind;
+use crate::TEST_FUNCTIONS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    UnusedVariable,
+    UnusedParam,
+    Shadowing,
+    Unreachable,
+    EndlessLoop,
+    IncompatibleComparison,
+    UndefinedCall,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 7] = [
+        Rule::UnusedVariable,
+        Rule::UnusedParam,
+        Rule::Shadowing,
+        Rule::Unreachable,
+        Rule::EndlessLoop,
+        Rule::IncompatibleComparison,
+        Rule::UndefinedCall,
+    ];
+
+    /// Name used in config files and reports.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::UnusedVariable => "unused-variable",
+            Rule::UnusedParam => "unused-param",
+            Rule::Shadowing => "shadowing",
+            Rule::Unreachable => "unreachable",
+            Rule::EndlessLoop => "endless-loop",
+            Rule::IncompatibleComparison => "incompatible-comparison",
+            Rule::UndefinedCall => "undefined-call",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Rule> {
+        Rule::ALL.iter().find(|r| r.name() == name).copied()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Off,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Off => "off",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Severity> {
+        match name {
+            "off" => Some(Severity::Off),
+            "warning" => Some(Severity::Warning),
+            "error" => Some(Severity::Error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub msg: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid lint config: {}", self.msg)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Severity of every rule and the host functions scripts may call
+/// without awaiting them. All rules warn by default.
+#[derive(Debug, Clone)]
+pub struct LintConfig {
+    rules: HashMap<Rule, Severity>,
+    pub host_functions: Vec<String>,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        Self {
+            rules: Rule::ALL.iter().map(|r| (*r, Severity::Warning)).collect(),
+            host_functions: vec![],
+        }
+    }
+}
+
+impl LintConfig {
+    pub fn set(&mut self, rule: Rule, severity: Severity) -> &mut Self {
+        self.rules.insert(rule, severity);
+        self
+    }
+
+    pub fn severity(&self, rule: Rule) -> Severity {
+        self.rules.get(&rule).copied().unwrap_or(Severity::Warning)
+    }
+
+    pub fn host_function(&mut self, name: &str) -> &mut Self {
+        self.host_functions.push(name.to_string());
+        self
+    }
+
+    /// Reads a config like
+    /// `{ "rules": { "shadowing": "error", "unused-param": "off" }, "host_functions": ["fetch"] }`.
+    /// Rules left out keep their default.
+    pub fn from_json(text: &str) -> Result<LintConfig, ConfigError> {
+        let err = |msg: String| ConfigError { msg };
+        let json: serde_json::Value = serde_json::from_str(text).map_err(|e| err(e.to_string()))?;
+        let mut config = LintConfig::default();
+
+        if let Some(rules) = json.get("rules") {
+            let rules = rules.as_object().ok_or_else(|| err("rules must be an object".to_string()))?;
+            for (name, severity) in rules {
+                let rule = Rule::from_name(name).ok_or_else(|| err(format!("unknown rule {}", name)))?;
+                let severity = severity.as_str()
+                    .and_then(Severity::from_name)
+                    .ok_or_else(|| err(format!("{} must be off, warning or error", name)))?;
+                config.set(rule, severity);
+            }
+        }
+
+        if let Some(names) = json.get("host_functions") {
+            let names = names.as_array().ok_or_else(|| err("host_functions must be a list".to_string()))?;
+            for name in names {
+                let name = name.as_str().ok_or_else(|| err("host_functions must hold names".to_string()))?;
+                config.host_function(name);
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub rule: Rule,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Range<usize>,
+    /// One based line and column (in characters) of the span start.
+    pub line: u32,
+    pub col: u32,
+}
+
+impl Lint {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "rule": self.rule.name(),
+            "severity": self.severity.name(),
+            "message": self.message,
+            "line": self.line,
+            "col": self.col,
+            "start": self.span.start,
+            "end": self.span.end,
+        })
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}[{}]: {}", self.line, self.col, self.severity.name(), self.rule.name(), self.message)
+    }
+}
+
+/// Checks a script against the enabled rules and returns the findings
+/// in source order. Names starting with `_` are never reported unused.
+pub fn lint(input: &str, config: &LintConfig) -> Result<Vec<Lint>, ParseError> {
+    let tree = SyntaxTree::parse(input)?;
+    let analysis = Analysis::new(input);
+
+    let mut linter = Linter {
+        input,
+        config,
+        analysis: &analysis,
+        awaited: vec![],
+        lints: vec![],
+    };
+    linter.check_names();
+    linter.walk(&tree.root);
+
+    let mut lints = linter.lints;
+    lints.sort_by_key(|l| (l.span.start, l.span.end));
+    Ok(lints)
+}
+
+struct Linter<'a> {
+    input: &'a str,
+    config: &'a LintConfig,
+    analysis: &'a Analysis,
+    /// Callees passed straight to `await`, which are host functions.
+    awaited: Vec<Range<usize>>,
+    lints: Vec<Lint>,
+}
+
+impl<'a> Linter<'a> {
+    fn report(&mut self, rule: Rule, span: Range<usize>, message: String) {
+        let severity = self.config.severity(rule);
+        if severity == Severity::Off {
+            return;
+        }
+
+        self.lints.push(Lint {
+            rule,
+            severity,
+            message,
+            line: line_of(self.input, span.start),
+            col: col_of(self.input, span.start),
+            span,
+        });
+    }
+
+    fn check_names(&mut self) {
+        let analysis = self.analysis;
+
+        for (i, def) in analysis.definitions.iter().enumerate() {
+            if matches!(def.kind, DefKind::Struct | DefKind::Enum) {
+                continue;
+            }
+
+            let used = analysis.references.iter().any(|r| r.def == Some(i));
+            if !used && !def.name.starts_with('_') {
+                match def.kind {
+                    DefKind::Param => self.report(Rule::UnusedParam, def.span.clone(), format!("parameter {} is never used", def.name)),
+                    _ => self.report(Rule::UnusedVariable, def.span.clone(), format!("{} is assigned but never used", def.name)),
+                }
+            }
+
+            if def.kind == DefKind::Param || def.declared {
+                continue;
+            }
+            if let Some(outer) = analysis.outer_definition(def) {
+                let line = line_of(self.input, outer.span.start);
+                self.report(Rule::Shadowing, def.span.clone(), format!("{} shadows the definition on line {}", def.name, line));
+            }
+        }
+    }
+
+    fn walk(&mut self, node: &SyntaxNode) {
+        match node.kind {
+            NodeKind::Root | NodeKind::Fun | NodeKind::If | NodeKind::For | NodeKind::Test | NodeKind::Module => self.check_unreachable(node),
+            _ => {}
+        }
+
+        match node.kind {
+            NodeKind::For => self.check_loop(node),
+            NodeKind::BinOp => self.check_comparison(node),
+            NodeKind::Call => self.check_call(node),
+            _ => {}
+        }
+
+        for child in node.nodes() {
+            self.walk(child);
+        }
+    }
+
+    /// Reports the first statement after a `return` in each block.
+    /// The branches of an `if` are separate blocks.
+    fn check_unreachable(&mut self, node: &SyntaxNode) {
+        let mut returned = false;
+        let mut reported = false;
+
+        for child in &node.children {
+            match child {
+                SyntaxElement::Token(t) if t.text == "else" => {
+                    returned = false;
+                    reported = false;
+                },
+                SyntaxElement::Node(n) if returned && !reported => {
+                    self.report(Rule::Unreachable, span(n), "unreachable code after return".to_string());
+                    reported = true;
+                },
+                SyntaxElement::Node(n) if n.kind == NodeKind::Ret => returned = true,
+                _ => {}
+            }
+        }
+    }
+
+    fn check_loop(&mut self, node: &SyntaxNode) {
+        let tokens = own_tokens(node);
+        let iterates = tokens.get(1).is_some_and(|t| t.kind == TokenKind::Ident);
+
+        if !iterates && !node.nodes().any(returns) {
+            let keyword = tokens[0].span.clone();
+            self.report(Rule::EndlessLoop, keyword, "loop has no condition and never returns".to_string());
+        }
+    }
+
+    fn check_comparison(&mut self, node: &SyntaxNode) {
+        if !own_tokens(node).iter().any(|t| t.text == "==") {
+            return;
+        }
+
+        let mut sides = node.nodes().map(literal_type);
+        let (left, right) = match (sides.next().flatten(), sides.next().flatten()) {
+            (Some(l), Some(r)) => (l, r),
+            _ => return,
+        };
+
+        let numbers = ["Int", "Float"];
+        if left != right && !(numbers.contains(&left) && numbers.contains(&right)) {
+            // Only none compares with any value, other mixed types fail
+            let outcome = if left == "None" || right == "None" { "is always false" } else { "fails at runtime" };
+            self.report(
+                Rule::IncompatibleComparison,
+                span(node),
+                format!("comparing {} with {} {}", left, right, outcome),
+            );
+        }
+    }
+
+    fn check_call(&mut self, node: &SyntaxNode) {
+        let mut nodes = node.nodes();
+        let callee = match nodes.next() {
+            Some(c) if c.kind == NodeKind::Ident => c,
+            _ => return,
+        };
+        let name = own_tokens(callee)[0].text.as_str();
+        let callee_span = span(callee);
+
+        if name == "await" {
+            for arg in nodes.map(unparen) {
+                if arg.kind != NodeKind::Call {
+                    continue;
+                }
+                if let Some(c) = arg.nodes().next() {
+                    self.awaited.push(span(c));
+                }
+            }
+            return;
+        }
+
+        if self.awaited.contains(&callee_span)
+            || TEST_FUNCTIONS.contains(&name)
+            || self.config.host_functions.iter().any(|h| h == name)
+        {
+            return;
+        }
+
+        let defined = self.analysis.references.iter()
+            .find(|r| r.span == callee_span)
+            .is_none_or(|r| r.def.is_some());
+        if !defined {
+            self.report(
+                Rule::UndefinedCall,
+                callee_span,
+                format!("call to undefined function {}", name),
+            );
+        }
+    }
+}
+
+fn own_tokens(node: &SyntaxNode) -> Vec<&SyntaxToken> {
+    node.children.iter().filter_map(|c| match c {
+        SyntaxElement::Token(t) => Some(t),
+        _ => None,
+    }).collect()
+}
+
+/// Byte range of a node without surrounding trivia.
+fn span(node: &SyntaxNode) -> Range<usize> {
+    let tokens = node.tokens();
+    match (tokens.first(), tokens.last()) {
+        (Some(first), Some(last)) => first.span.start..last.span.end,
+        _ => 0..0,
+    }
+}
+
+fn unparen(node: &SyntaxNode) -> &SyntaxNode {
+    match node.kind {
+        NodeKind::Paren => node.nodes().next().map(unparen).unwrap_or(node),
+        _ => node,
+    }
+}
+
+/// Whether a statement contains a `return` of the enclosing function.
+fn returns(node: &SyntaxNode) -> bool {
+    match node.kind {
+        NodeKind::Ret => true,
+        NodeKind::Fun => false,
+        _ => node.nodes().any(returns),
+    }
+}
+
+fn literal_type(node: &SyntaxNode) -> Option<&'static str> {
+    let node = unparen(node);
+
+    match node.kind {
+        NodeKind::Lit => Some(match own_tokens(node).first()?.kind {
+            TokenKind::Int => "Int",
+            TokenKind::Float => "Float",
+            TokenKind::Str => "String",
+            _ if own_tokens(node)[0].text == "none" => "None",
+            _ => "Bool",
+        }),
+        NodeKind::Neg => node.nodes().next()
+            .and_then(literal_type)
+            .filter(|t| matches!(*t, "Int" | "Float")),
+        NodeKind::Array => Some("List"),
+        NodeKind::ObjIns => Some("Object"),
+        NodeKind::Fun => Some("Function"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(code: &str, config: &LintConfig) -> Vec<(Rule, u32, String)> {
+        lint(code, config).unwrap().into_iter()
+            .map(|l| (l.rule, l.line, l.message))
+            .collect()
+    }
+
+    #[test]
+    fn unused_and_shadowed_names() {
+        let code = "total = 1
+unused = 2
+add = (a, b, _c) => {
+    total = a
+    return total
+}
+res = add(total, 2)
+print(res)";
+
+        assert_eq!(rules(code, &LintConfig::default()), vec![
+            (Rule::UnusedVariable, 2, "unused is assigned but never used".to_string()),
+            (Rule::UnusedParam, 3, "parameter b is never used".to_string()),
+            (Rule::Shadowing, 4, "total shadows the definition on line 1".to_string()),
+            (Rule::UndefinedCall, 8, "call to undefined function print".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn declarations_shadow_on_purpose() {
+        let code = "let total = 1
+add = (a) => {
+    let total = a
+    return total
+}
+print(add(total))";
+
+        assert_eq!(rules(code, &LintConfig::default()), vec![
+            (Rule::UndefinedCall, 6, "call to undefined function print".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn unreachable_code_and_endless_loops() {
+        let code = "f = () => {
+    if true {
+        return 1
+        x = 2
+    } else {
+        return 2
+    }
+    for {
+        g = () => { return 3 }
+        g()
+    }
+    for {
+        return 4
+    }
+}
+f()";
+
+        let found = rules(code, &LintConfig::default()).into_iter()
+            .map(|(rule, line, _)| (rule, line))
+            .collect::<Vec<_>>();
+        assert_eq!(found, vec![
+            (Rule::UnusedVariable, 4),
+            (Rule::Unreachable, 4),
+            (Rule::EndlessLoop, 8),
+        ]);
+    }
+
+    #[test]
+    fn comparisons_and_host_calls() {
+        let code = "a = -1 == \"1\"
+b = (2) == 2.0
+c = true == \"yes\"
+f = none == 1
+d = await(fetch(a + b))
+e = await(other(log(c)))
+notify(d, e)";
+
+        let mut config = LintConfig::default();
+        config.host_function("notify").set(Rule::UnusedVariable, Severity::Off);
+
+        assert_eq!(rules(code, &config), vec![
+            (Rule::IncompatibleComparison, 1, "comparing Int with String fails at runtime".to_string()),
+            (Rule::IncompatibleComparison, 3, "comparing Bool with String fails at runtime".to_string()),
+            (Rule::IncompatibleComparison, 4, "comparing None with Int is always false".to_string()),
+            (Rule::UndefinedCall, 6, "call to undefined function log".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn config_and_json_output() {
+        let config = LintConfig::from_json(r#"{
+            "rules": { "unused-variable": "error", "undefined-call": "off" },
+            "host_functions": ["fetch"]
+        }"#).unwrap();
+        assert_eq!(config.severity(Rule::UnusedVariable), Severity::Error);
+        assert_eq!(config.severity(Rule::Shadowing), Severity::Warning);
+        assert_eq!(config.host_functions, vec!["fetch".to_string()]);
+
+        let lints = lint("x = missing()", &config).unwrap();
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].to_string(), "1:1: error[unused-variable]: x is assigned but never used");
+        assert_eq!(lints[0].to_json(), json!({
+            "rule": "unused-variable",
+            "severity": "error",
+            "message": "x is assigned but never used",
+            "line": 1,
+            "col": 1,
+            "start": 0,
+            "end": 1,
+        }));
+
+        assert!(LintConfig::from_json(r#"{ "rules": { "nope": "off" } }"#).is_err());
+        assert!(LintConfig::from_json(r#"{ "rules": { "shadowing": "loud" } }"#).is_err());
+        assert!(lint("x = (", &config).is_err());
+    }
+}
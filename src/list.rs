@@ -0,0 +1,672 @@
+use std::cmp::Ordering;
+
+use crate::program::ProgramError;
+use crate::program::Reader;
+use crate::program::Writer;
+use crate::scope::ScopeManager;
+use crate::stdlib::text;
+use crate::value_kind;
+use crate::vm::truthy;
+use crate::Ptr;
+use crate::StackValue;
+use crate::Value;
+
+/// List methods which call a script function for the items.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListMethod {
+    Filter,
+    Reduce,
+    Find,
+    FindIndex,
+    Any,
+    All,
+    ForEach,
+    Sort,
+    FlatMap,
+}
+
+impl ListMethod {
+    const ALL: [ListMethod; 9] = [
+        ListMethod::Filter,
+        ListMethod::Reduce,
+        ListMethod::Find,
+        ListMethod::FindIndex,
+        ListMethod::Any,
+        ListMethod::All,
+        ListMethod::ForEach,
+        ListMethod::Sort,
+        ListMethod::FlatMap,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ListMethod::Filter => "filter",
+            ListMethod::Reduce => "reduce",
+            ListMethod::Find => "find",
+            ListMethod::FindIndex => "find_index",
+            ListMethod::Any => "any",
+            ListMethod::All => "all",
+            ListMethod::ForEach => "for_each",
+            ListMethod::Sort => "sort",
+            ListMethod::FlatMap => "flat_map",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ListMethod> {
+        ListMethod::ALL.into_iter().find(|m| m.name() == name)
+    }
+}
+
+/// State of a merge sort which asks the script to compare two items at a
+/// time. `perm` holds indices into the list, which is only reordered once
+/// every comparison is done.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortState {
+    perm: Vec<u32>,
+    merged: Vec<u32>,
+    width: u32,
+    lo: u32,
+    i: u32,
+    j: u32,
+}
+
+impl SortState {
+    fn new(len: usize) -> Self {
+        Self {
+            perm: (0..len as u32).collect(),
+            merged: vec![],
+            width: 1,
+            lo: 0,
+            i: 0,
+            j: 1,
+        }
+    }
+
+    fn bounds(&self) -> (u32, u32) {
+        let n = self.perm.len() as u32;
+        ((self.lo + self.width).min(n), (self.lo + 2 * self.width).min(n))
+    }
+
+    /// The next pair to compare, or none when the list is sorted.
+    fn next(&mut self) -> Option<(u32, u32)> {
+        let n = self.perm.len() as u32;
+        loop {
+            if self.width >= n {
+                return None;
+            }
+
+            let (mid, hi) = self.bounds();
+            if self.i < mid && self.j < hi {
+                return Some((self.perm[self.i as usize], self.perm[self.j as usize]));
+            }
+
+            // One run is used up, the rest of the other is in order
+            self.merged.extend_from_slice(&self.perm[self.i as usize..mid as usize]);
+            self.merged.extend_from_slice(&self.perm[self.j as usize..hi as usize]);
+            let start = self.lo as usize;
+            self.perm.splice(start..hi as usize, self.merged.drain(..));
+
+            self.lo = hi;
+            if self.lo >= n {
+                self.width *= 2;
+                self.lo = 0;
+            }
+            self.i = self.lo;
+            self.j = self.bounds().0;
+        }
+    }
+
+    /// Takes the left item when it does not sort after the right one,
+    /// which keeps equal items in order.
+    fn feed(&mut self, left_first: bool) {
+        match left_first {
+            true => {
+                self.merged.push(self.perm[self.i as usize]);
+                self.i += 1;
+            },
+            false => {
+                self.merged.push(self.perm[self.j as usize]);
+                self.j += 1;
+            }
+        }
+    }
+
+    fn write(&self, w: &mut Writer) {
+        for list in [&self.perm, &self.merged] {
+            w.u32(list.len() as u32);
+            for i in list {
+                w.u32(*i);
+            }
+        }
+        w.u32(self.width);
+        w.u32(self.lo);
+        w.u32(self.i);
+        w.u32(self.j);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, ProgramError> {
+        let mut lists = vec![];
+        for _ in 0..2 {
+            let mut list = vec![];
+            for _ in 0..r.len()? {
+                list.push(r.u32()?);
+            }
+            lists.push(list);
+        }
+        let merged = lists.pop().unwrap();
+        let perm = lists.pop().unwrap();
+        Ok(Self {
+            perm,
+            merged,
+            width: r.u32()?,
+            lo: r.u32()?,
+            i: r.u32()?,
+            j: r.u32()?,
+        })
+    }
+}
+
+/// A list method in progress. It is kept on the calling frame like
+/// `BuildIn::Map` and calls `blk` once per step, so the callback can
+/// await and the call survives snapshots.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListCall {
+    pub method: ListMethod,
+    pub ptr: Ptr,
+    pub blk: u32,
+    pub inx: u32,
+    /// Result so far: the kept items of `filter` and `flat_map`, the
+    /// accumulator of `reduce` and the answer of the searches.
+    pub acc: Value,
+    pub done: bool,
+    pub sort: Option<SortState>,
+    /// The items at call time. A callback which pushes or pops does not
+    /// change what is visited.
+    pub items: Vec<Value>,
+}
+
+pub enum Step {
+    /// Call the callback with these arguments.
+    Call(Vec<Value>),
+    /// The method is done with this result, none for the list itself.
+    Done(Option<Value>),
+}
+
+impl ListCall {
+    /// The next callback to run, or the result once the items are used up.
+    /// A finished sort leaves the sorted list in `items`.
+    pub fn next(&mut self) -> Step {
+        let items = &mut self.items;
+        if let Some(sort) = &mut self.sort {
+            return match sort.next() {
+                Some((a, b)) => Step::Call(vec![items[a as usize].clone(), items[b as usize].clone()]),
+                None => {
+                    let sorted = sort.perm.iter().map(|i| items[*i as usize].clone()).collect();
+                    *items = sorted;
+                    Step::Done(None)
+                }
+            };
+        }
+
+        let item = match items.get(self.inx as usize) {
+            Some(item) if !self.done => item.clone(),
+            _ => return Step::Done(Some(self.acc.clone())),
+        };
+
+        match self.method {
+            ListMethod::Reduce => Step::Call(vec![self.acc.clone(), item]),
+            _ => Step::Call(vec![Value::Int(self.inx as i64), item]),
+        }
+    }
+
+    /// Takes the value returned by the callback. `resolved` is the same
+    /// value with pointers followed.
+    pub fn feed(&mut self, result: Value, resolved: Value) -> Result<(), String> {
+        if let Some(sort) = &mut self.sort {
+            let order = match resolved {
+                Value::Int(i) => i as f64,
+                Value::Float(f) => f,
+                v => return Err(format!("sort comparator returned {}, expected a number", value_kind(&v)))
+            };
+            sort.feed(order <= 0.0);
+            return Ok(());
+        }
+
+        let item = self.items.get(self.inx as usize).cloned().unwrap_or_default();
+        let found = match self.method {
+            ListMethod::Filter | ListMethod::Find | ListMethod::FindIndex | ListMethod::Any | ListMethod::All => truthy(&resolved)?,
+            _ => false,
+        };
+        match (self.method, &mut self.acc) {
+            (ListMethod::Filter, Value::List(kept)) if found => kept.push(item),
+            (ListMethod::FlatMap, Value::List(out)) => match resolved {
+                Value::List(list) => out.extend(list),
+                _ => out.push(result),
+            },
+            (ListMethod::Reduce, acc) => *acc = result,
+            (ListMethod::Find, acc) if found => {
+                *acc = item;
+                self.done = true;
+            },
+            (ListMethod::FindIndex, acc) if found => {
+                *acc = Value::Int(self.inx as i64);
+                self.done = true;
+            },
+            (ListMethod::Any, acc) if found => {
+                *acc = Value::Bool(true);
+                self.done = true;
+            },
+            (ListMethod::All, acc) if !found => {
+                *acc = Value::Bool(false);
+                self.done = true;
+            },
+            _ => {}
+        }
+
+        self.inx += 1;
+        Ok(())
+    }
+
+    pub(crate) fn write(&self, w: &mut Writer) -> Result<(), ProgramError> {
+        w.str(self.method.name());
+        w.ptr(&self.ptr);
+        w.u32(self.blk);
+        w.u32(self.inx);
+        w.value(&self.acc)?;
+        w.bool(self.done);
+        match &self.sort {
+            Some(sort) => {
+                w.u8(1);
+                sort.write(w);
+            },
+            None => w.u8(0)
+        }
+        w.u32(self.items.len() as u32);
+        for item in &self.items {
+            w.value(item)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read(r: &mut Reader) -> Result<Self, ProgramError> {
+        let name = r.str()?;
+        let method = match ListMethod::from_name(&name) {
+            Some(m) => m,
+            None => return Err(ProgramError::Invalid(format!("unknown list method {}", name)))
+        };
+        Ok(Self {
+            method,
+            ptr: r.ptr()?,
+            blk: r.u32()?,
+            inx: r.u32()?,
+            acc: r.value()?,
+            done: r.bool()?,
+            sort: match r.u8()? {
+                0 => None,
+                _ => Some(SortState::read(r)?)
+            },
+            items: {
+                let mut items = vec![];
+                for _ in 0..r.len()? {
+                    items.push(r.value()?);
+                }
+                items
+            },
+        })
+    }
+}
+
+/// What a list method call does next.
+pub enum MethodResult {
+    /// The method is done and gives this value.
+    Value(StackValue),
+    /// The method calls a script function, see `ListCall`.
+    Callback(ListCall),
+}
+
+/// Equality used by `contains` and `index_of`, where 1 equals 1.0.
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Int(i), Value::Float(f)) | (Value::Float(f), Value::Int(i)) => *i as f64 == *f,
+        _ => a == b,
+    }
+}
+
+fn natural_order(a: &Value, b: &Value) -> Result<Ordering, String> {
+    let order = match (a, b) {
+        (Value::Int(a), Value::Int(b)) => a.cmp(b),
+        (Value::Str(a), Value::Str(b)) => a.cmp(b),
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+            let (a, b) = (as_f64(a), as_f64(b));
+            a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+        },
+        _ => return Err(format!("can not compare {} with {}, pass a comparator", value_kind(a), value_kind(b)))
+    };
+    Ok(order)
+}
+
+fn as_f64(val: &Value) -> f64 {
+    match val {
+        Value::Int(i) => *i as f64,
+        Value::Float(f) => *f,
+        _ => 0.0,
+    }
+}
+
+fn index(val: Option<&Value>, method: &str, len: usize) -> Result<usize, String> {
+    match val {
+        Some(Value::Int(i)) if *i < 0 => Ok((len as i64 + i).max(0) as usize),
+        Some(Value::Int(i)) => Ok((*i as usize).min(len)),
+        Some(v) => Err(format!("{} expects an int index but got {}", method, value_kind(v))),
+        None => Err(format!("{} expects an index", method)),
+    }
+}
+
+fn items_of(scope: &mut ScopeManager, ptr: &Ptr) -> Result<Vec<Value>, String> {
+    match scope.lookup(ptr) {
+        Some(Value::List(l)) => Ok(l.clone()),
+        Some(v) => Err(format!("expected a list but got {}", value_kind(v))),
+        None => Err("list not found".to_string()),
+    }
+}
+
+/// Calls a list method other than `push`, `pop` and `map`. `args` are
+/// the raw arguments, new lists are stored in `scope_id`.
+pub(crate) fn call_method(
+    scope: &mut ScopeManager,
+    ptr: &Ptr,
+    name: &str,
+    args: Vec<Value>,
+    scope_id: u32,
+) -> Result<MethodResult, String> {
+    if let Some(method) = ListMethod::from_name(name) {
+        let items = items_of(scope, ptr)?;
+        let blk = match args.first() {
+            Some(Value::Fn(blk)) => *blk,
+            None if method == ListMethod::Sort => {
+                let mut items = items;
+                let mut resolved = vec![];
+                for item in &items {
+                    resolved.push(scope.copy_value(item.clone()));
+                }
+                let mut perm = (0..items.len()).collect::<Vec<_>>();
+                let mut err = None;
+                perm.sort_by(|a, b| natural_order(&resolved[*a], &resolved[*b]).unwrap_or_else(|e| {
+                    err = Some(e);
+                    Ordering::Equal
+                }));
+                if let Some(e) = err {
+                    return Err(e);
+                }
+                items = perm.into_iter().map(|i| items[i].clone()).collect();
+                if let Some(Value::List(l)) = scope.lookup(ptr) {
+                    *l = items;
+                }
+                return Ok(MethodResult::Value(StackValue::Ptr(ptr.clone())));
+            },
+            Some(v) => return Err(format!("{} expects a function but got {}", name, value_kind(v))),
+            None => return Err(format!("{} expects a function", name)),
+        };
+
+        let mut inx = 0;
+        let acc = match method {
+            ListMethod::Filter | ListMethod::FlatMap => Value::List(vec![]),
+            ListMethod::Reduce => match args.get(1) {
+                Some(init) => init.clone(),
+                None => {
+                    // Without a start value the first item is the start
+                    inx = 1;
+                    match items.first() {
+                        Some(first) => first.clone(),
+                        None => return Err("reduce of an empty list needs a start value".to_string()),
+                    }
+                }
+            },
+            ListMethod::FindIndex => Value::Int(-1),
+            ListMethod::Any => Value::Bool(false),
+            ListMethod::All => Value::Bool(true),
+            _ => Value::None,
+        };
+
+        return Ok(MethodResult::Callback(ListCall {
+            method,
+            ptr: ptr.clone(),
+            blk,
+            inx,
+            acc,
+            done: false,
+            sort: match method {
+                ListMethod::Sort => Some(SortState::new(items.len())),
+                _ => None,
+            },
+            items,
+        }));
+    }
+
+    let resolved = args.iter().map(|a| scope.copy_value(a.clone())).collect::<Vec<_>>();
+    let items = items_of(scope, ptr)?;
+    let list = |scope: &mut ScopeManager, items: Vec<Value>| scope.alloc(scope_id, Value::List(items));
+
+    let val = match name {
+        "len" => StackValue::Int(items.len() as i64),
+        "reverse" => {
+            if let Some(Value::List(l)) = scope.lookup(ptr) {
+                l.reverse();
+            }
+            StackValue::Ptr(ptr.clone())
+        },
+        "slice" => {
+            let start = index(resolved.first(), name, items.len())?;
+            let end = match resolved.get(1) {
+                Some(_) => index(resolved.get(1), name, items.len())?,
+                None => items.len(),
+            };
+            list(scope, items[start..end.max(start)].to_vec())
+        },
+        "concat" => {
+            let mut out = items;
+            for arg in &args {
+                match arg {
+                    Value::Ptr(p) => out.extend(items_of(scope, p)?),
+                    v => return Err(format!("concat expects lists but got {}", value_kind(v))),
+                }
+            }
+            list(scope, out)
+        },
+        "join" => {
+            let sep = match resolved.first() {
+                Some(Value::Str(s)) => s.clone(),
+                Some(v) => return Err(format!("join expects a string separator but got {}", value_kind(v))),
+                None => ",".to_string(),
+            };
+            let parts = items.into_iter()
+                .map(|i| text(&scope.copy_value(i)))
+                .collect::<Vec<_>>();
+            StackValue::Str(parts.join(&sep))
+        },
+        "contains" | "index_of" => {
+            let needle = match resolved.first() {
+                Some(v) => v,
+                None => return Err(format!("{} expects a value", name)),
+            };
+            let pos = items.into_iter().position(|i| same(&scope.copy_value(i), needle));
+            match name {
+                "contains" => StackValue::Bool(pos.is_some()),
+                _ => StackValue::Int(pos.map(|p| p as i64).unwrap_or(-1)),
+            }
+        },
+        "insert" => {
+            let at = match resolved.first() {
+                Some(Value::Int(i)) if *i >= 0 && *i as usize <= items.len() => *i as usize,
+                Some(v) => return Err(format!("insert index {} is out of range for length {}", v, items.len())),
+                None => return Err("insert expects an index".to_string()),
+            };
+            let val = match args.get(1) {
+                Some(v) => v.clone(),
+                None => return Err("insert expects a value".to_string()),
+            };
+            if let Some(Value::List(l)) = scope.lookup(ptr) {
+                l.insert(at, val);
+            }
+            StackValue::None
+        },
+        "remove" => {
+            let at = match resolved.first() {
+                Some(Value::Int(i)) if *i >= 0 && (*i as usize) < items.len() => *i as usize,
+                Some(v) => return Err(format!("remove index {} is out of range for length {}", v, items.len())),
+                None => return Err("remove expects an index".to_string()),
+            };
+            let removed = match scope.lookup(ptr) {
+                Some(Value::List(l)) => l.remove(at),
+                _ => Value::None,
+            };
+            StackValue::from(&removed)
+        },
+        "zip" | "enumerate" => {
+            let others = match (name, args.first()) {
+                ("enumerate", _) => (0..items.len() as i64).map(Value::Int).collect(),
+                (_, Some(Value::Ptr(p))) => items_of(scope, p)?,
+                (_, Some(v)) => return Err(format!("zip expects a list but got {}", value_kind(v))),
+                (_, None) => return Err("zip expects a list".to_string()),
+            };
+            let mut pairs = vec![];
+            for (item, other) in items.into_iter().zip(others) {
+                let pair = match name {
+                    "enumerate" => vec![other, item],
+                    _ => vec![item, other],
+                };
+                pairs.push(Value::Ptr(scope.store_unamed(scope_id, Value::List(pair))));
+            }
+            list(scope, pairs)
+        },
+        _ => return Err(format!("list has no method {}", name)),
+    };
+
+    Ok(MethodResult::Value(val))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::ints;
+    use crate::test_util::run;
+    use crate::test_util::run_err;
+    use crate::RunResult;
+    use crate::Value;
+    use crate::Vm;
+
+    #[test]
+    fn callback_methods() {
+        assert_eq!(run("a = [1, 2, 3, 4]\nreturn a.filter(x => return x - 2)"), ints(&[3, 4]));
+        assert_eq!(run("a = [1, 2, 3]\nreturn a.reduce((acc, x) => return acc + x, 10)"), Value::Int(16));
+        assert_eq!(run("a = [1, 2, 3]\nreturn a.reduce((acc, x) => return acc * x)"), Value::Int(6));
+        assert_eq!(run("a = [1, 5, 7]\nreturn a.find(x => return x - 4)"), Value::Int(5));
+        assert_eq!(run("a = [1, 5, 7]\nreturn a.find(x => return x - 9)"), Value::None);
+        assert_eq!(run("a = [1, 5, 7]\nreturn a.find_index(x => return x - 4)"), Value::Int(1));
+        assert_eq!(run("a = [1, 5]\nreturn a.find_index(x => return x - 9)"), Value::Int(-1));
+        assert_eq!(run("a = [1, 5]\nreturn a.any(x => return x == 5)"), Value::Bool(true));
+        assert_eq!(run("a = [1, 5]\nreturn a.all(x => return x == 5)"), Value::Bool(false));
+        assert_eq!(run("a = [1, 2]\nreturn a.flat_map(x => return [x, x])"), ints(&[1, 1, 2, 2]));
+        assert_eq!(run("a = [1, 2]\nreturn a.map((i, x) => return i * 10 + x)"), ints(&[1, 12]));
+
+        let code = "sum = [0]
+a = [1, 2, 3]
+a.for_each(x => {
+    sum.push(x)
+})
+return sum";
+        assert_eq!(run(code), ints(&[0, 1, 2, 3]));
+    }
+
+    #[test]
+    fn sort_with_comparator() {
+        let code = "a = [5, 3, 9, 1, 4, 8, 2]
+return a.sort((x, y) => return y - x)";
+        assert_eq!(run(code), ints(&[9, 8, 5, 4, 3, 2, 1]));
+        assert_eq!(run("a = [3, 1, 2]\nreturn a.sort()"), ints(&[1, 2, 3]));
+        assert_eq!(run("a = []\nreturn a.sort((x, y) => return x - y)"), ints(&[]));
+    }
+
+    #[test]
+    fn callbacks_changing_the_list() {
+        let code = "a = [3, 1, 2]
+return a.sort((x, y) => {
+    a.pop()
+    return x - y
+})";
+        assert_eq!(run(code), ints(&[1, 2, 3]));
+
+        let code = "a = [1, 2, 3]
+b = a.filter(x => {
+    a.pop()
+    return x
+})
+return [a, b]";
+        assert_eq!(run(code), Value::List(vec![ints(&[]), ints(&[1, 2, 3])]));
+
+        let code = "a = [1, 2, 3]
+return a.reduce((s, x) => {
+    a.push(1)
+    return s + x
+}, 0)";
+        assert_eq!(run(code), Value::Int(6));
+
+        let code = "a = [1, 2, 3]
+return a.map((i, x) => {
+    a.pop()
+    return x * 10
+})";
+        assert_eq!(run(code), ints(&[10]));
+    }
+
+    #[test]
+    fn plain_methods() {
+        assert_eq!(run("a = [1, 2, 3]\nreturn a.len()"), Value::Int(3));
+        assert_eq!(run("a = [1, 2, 3]\nreturn a.reverse()"), ints(&[3, 2, 1]));
+        assert_eq!(run("a = [1, 2, 3, 4]\nreturn a.slice(1, -1)"), ints(&[2, 3]));
+        assert_eq!(run("a = [1]\nb = [2, 3]\nreturn a.concat(b, a)"), ints(&[1, 2, 3, 1]));
+        assert_eq!(run("a = [1, \"b\", 2.5]\nreturn a.join(\"-\")"), Value::Str("1-b-2.5".to_string()));
+        assert_eq!(run("a = [1, 2]\nreturn a.contains(2.0)"), Value::Bool(true));
+        assert_eq!(run("a = [1, 2]\nreturn a.index_of(3)"), Value::Int(-1));
+        assert_eq!(run("a = [1, 3]\na.insert(1, 2)\nreturn a"), ints(&[1, 2, 3]));
+        assert_eq!(run("a = [1, 2, 3]\nb = a.remove(0)\nreturn b"), Value::Int(1));
+
+        let pairs = Value::List(vec![ints(&[1, 3]), ints(&[2, 4])]);
+        assert_eq!(run("a = [1, 2]\nb = [3, 4, 5]\nreturn a.zip(b)"), pairs);
+        let pairs = Value::List(vec![ints(&[0, 7]), ints(&[1, 8])]);
+        assert_eq!(run("a = [7, 8]\nreturn a.enumerate()"), pairs);
+    }
+
+    #[test]
+    fn await_inside_callback() {
+        let mut vm = Vm::new();
+        let code = "a = [1, 2, 3]
+return a.filter(x => {
+    return await(keep(x))
+})";
+        let mut res = vm.run_code(code);
+        let mut asked = vec![];
+        while let RunResult::Await { stack_id, value } = res {
+            let arg = match value {
+                Value::UndefCall { args, .. } => args[0].clone(),
+                v => panic!("Unexpected await {:?}", v),
+            };
+            let keep = arg != Value::Int(2);
+            asked.push(arg);
+            res = vm.cont(stack_id, Value::Bool(keep));
+        }
+        assert_eq!(Value::List(asked), ints(&[1, 2, 3]));
+        assert_eq!(res, RunResult::Value(ints(&[1, 3])));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(run_err("a = [1]\nreturn a.nope()"), "list has no method nope");
+        assert_eq!(run_err("a = [1]\nreturn a.filter(1)"), "filter expects a function but got int");
+        assert_eq!(run_err("a = [1, 2]\nreturn a.map(5)"), "map expects a function but got int");
+        assert_eq!(run_err("a = [1, 2]\nreturn a.map()"), "map expects a function");
+        assert_eq!(run_err("a = []\nreturn a.pop()"), "pop of an empty list");
+        assert_eq!(run_err("a = []\nreturn a.reduce((a, b) => return a)"), "reduce of an empty list needs a start value");
+        assert!(run_err("a = [1, \"a\"]\nreturn a.sort()").starts_with("can not compare"));
+        assert_eq!(run_err("a = [1]\nreturn a.remove(3)"), "remove index 3 is out of range for length 1");
+    }
+}
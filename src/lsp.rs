@@ -0,0 +1,407 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::ops::Range;
+
+use serde_json::json;
+
+use crate::analysis::Analysis;
+use crate::analysis::CompletionKind;
+use crate::analysis::DefKind;
+use crate::dap::read_message;
+use crate::dap::write_message;
+
+struct Document {
+    text: String,
+    analysis: Analysis,
+}
+
+/// Language server for FlexScript sources. Documents are synced in full
+/// and analysed on every change.
+pub struct LspServer<R, W> {
+    reader: R,
+    writer: W,
+    docs: HashMap<String, Document>,
+    done: bool,
+}
+
+impl<R: BufRead, W: Write> LspServer<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader,
+            writer,
+            docs: HashMap::new(),
+            done: false,
+        }
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        while !self.done {
+            match read_message(&mut self.reader)? {
+                Some(msg) => self.handle(msg)?,
+                None => break
+            }
+        }
+        Ok(())
+    }
+
+    fn respond(&mut self, req: &serde_json::Value, result: serde_json::Value) -> io::Result<()> {
+        write_message(&mut self.writer, &json!({
+            "jsonrpc": "2.0",
+            "id": req["id"],
+            "result": result
+        }))
+    }
+
+    fn notify(&mut self, method: &str, params: serde_json::Value) -> io::Result<()> {
+        write_message(&mut self.writer, &json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params
+        }))
+    }
+
+    pub fn handle(&mut self, msg: serde_json::Value) -> io::Result<()> {
+        let params = &msg["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+
+        match msg["method"].as_str().unwrap_or_default() {
+            "initialize" => {
+                self.respond(&msg, json!({
+                    "capabilities": {
+                        "textDocumentSync": 1,
+                        "documentSymbolProvider": true,
+                        "definitionProvider": true,
+                        "hoverProvider": true,
+                        "completionProvider": { "triggerCharacters": ["."] }
+                    },
+                    "serverInfo": { "name": "flexscript" }
+                }))?;
+            },
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.update(uri, text.to_string())?;
+            },
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|c| c.last()).and_then(|c| c["text"].as_str()) {
+                    self.update(uri, text.to_string())?;
+                }
+            },
+            "textDocument/didClose" => {
+                self.docs.remove(&uri);
+                self.notify("textDocument/publishDiagnostics", json!({ "uri": uri, "diagnostics": [] }))?;
+            },
+            "textDocument/documentSymbol" => {
+                let symbols = match self.docs.get(&uri) {
+                    Some(doc) => doc.analysis.symbols().map(|d| {
+                        let range = range(&doc.text, &d.span);
+                        json!({
+                            "name": d.name,
+                            "detail": d.typ,
+                            "kind": match d.kind {
+                                DefKind::Struct => 23,
+                                DefKind::Enum => 10,
+                                _ => 12,
+                            },
+                            "range": range,
+                            "selectionRange": range
+                        })
+                    }).collect(),
+                    None => vec![]
+                };
+                self.respond(&msg, json!(symbols))?;
+            },
+            "textDocument/definition" => {
+                let result = self.at_position(params, |doc, offset| {
+                    let def = doc.analysis.definition_at(offset)?;
+                    Some(json!({ "uri": uri, "range": range(&doc.text, &def.span) }))
+                });
+                self.respond(&msg, result)?;
+            },
+            "textDocument/hover" => {
+                let result = self.at_position(params, |doc, offset| {
+                    let (text, span) = doc.analysis.hover_at(offset)?;
+                    Some(json!({
+                        "contents": {
+                            "kind": "markdown",
+                            "value": format!("```flexscript\n{}\n```", text)
+                        },
+                        "range": range(&doc.text, &span)
+                    }))
+                });
+                self.respond(&msg, result)?;
+            },
+            "textDocument/completion" => {
+                let result = self.at_position(params, |doc, offset| {
+                    let items = doc.analysis.completions_at(&doc.text, offset).into_iter().map(|c| {
+                        json!({
+                            "label": c.label,
+                            "kind": match c.kind {
+                                CompletionKind::Method => 2,
+                                CompletionKind::Function => 3,
+                                CompletionKind::Variable => 6,
+                                CompletionKind::Struct => 22,
+                            },
+                            "detail": c.detail
+                        })
+                    }).collect::<Vec<_>>();
+                    Some(json!(items))
+                });
+                self.respond(&msg, result)?;
+            },
+            "shutdown" => {
+                self.respond(&msg, json!(null))?;
+            },
+            "exit" => {
+                self.done = true;
+            },
+            method => {
+                // Notifications we do not handle are dropped, requests get an error
+                if !msg["id"].is_null() {
+                    write_message(&mut self.writer, &json!({
+                        "jsonrpc": "2.0",
+                        "id": msg["id"],
+                        "error": { "code": -32601, "message": format!("Unknown method {}", method) }
+                    }))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn update(&mut self, uri: String, text: String) -> io::Result<()> {
+        let analysis = Analysis::new(&text);
+
+        let diagnostics = match &analysis.error {
+            Some(err) => vec![json!({
+                "range": range(&text, &err.span),
+                "severity": 1,
+                "source": "flexscript",
+                "message": err.msg
+            })],
+            None => vec![]
+        };
+
+        self.docs.insert(uri.clone(), Document { text, analysis });
+        self.notify("textDocument/publishDiagnostics", json!({
+            "uri": uri,
+            "diagnostics": diagnostics
+        }))
+    }
+
+    fn at_position<F>(&self, params: &serde_json::Value, f: F) -> serde_json::Value
+    where
+        F: Fn(&Document, usize) -> Option<serde_json::Value>
+    {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let doc = match self.docs.get(uri) {
+            Some(doc) => doc,
+            None => return json!(null)
+        };
+
+        let line = params["position"]["line"].as_u64().unwrap_or(0) as usize;
+        let character = params["position"]["character"].as_u64().unwrap_or(0) as usize;
+
+        f(doc, offset(&doc.text, line, character)).unwrap_or(json!(null))
+    }
+}
+
+/// LSP position of a byte offset. Characters are counted in UTF-16
+/// code units as the protocol requires.
+fn position(text: &str, offset: usize) -> serde_json::Value {
+    let offset = offset.min(text.len());
+    let line_start = text[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+
+    json!({
+        "line": text[..offset].matches('\n').count(),
+        "character": text[line_start..offset].encode_utf16().count()
+    })
+}
+
+fn range(text: &str, span: &Range<usize>) -> serde_json::Value {
+    json!({ "start": position(text, span.start), "end": position(text, span.end) })
+}
+
+fn offset(text: &str, line: usize, character: usize) -> usize {
+    let line_start = match line {
+        0 => 0,
+        _ => match text.match_indices('\n').nth(line - 1) {
+            Some((i, _)) => i + 1,
+            None => return text.len()
+        }
+    };
+
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+
+    text.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::analysis::LIST_METHODS;
+
+    const URI: &str = "file:///main.fs";
+
+    struct Session {
+        server: LspServer<Cursor<Vec<u8>>, Vec<u8>>,
+        id: i64,
+    }
+
+    impl Session {
+        fn new(text: &str) -> Self {
+            let mut s = Session { server: LspServer::new(Cursor::new(vec![]), vec![]), id: 0 };
+            s.request("initialize", json!({}));
+            s.notify("textDocument/didOpen", json!({
+                "textDocument": { "uri": URI, "languageId": "flexscript", "version": 1, "text": text }
+            }));
+            s
+        }
+
+        fn messages(&mut self) -> Vec<serde_json::Value> {
+            let mut out = Cursor::new(std::mem::take(&mut self.server.writer));
+            let mut msgs = vec![];
+            while let Some(msg) = read_message(&mut out).unwrap() {
+                msgs.push(msg);
+            }
+            msgs
+        }
+
+        fn notify(&mut self, method: &str, params: serde_json::Value) -> Vec<serde_json::Value> {
+            self.server.handle(json!({ "jsonrpc": "2.0", "method": method, "params": params })).unwrap();
+            self.messages()
+        }
+
+        fn request(&mut self, method: &str, params: serde_json::Value) -> serde_json::Value {
+            self.id += 1;
+            self.server.handle(json!({ "jsonrpc": "2.0", "id": self.id, "method": method, "params": params })).unwrap();
+            let msgs = self.messages();
+            assert_eq!(msgs[0]["id"], self.id);
+            msgs[0]["result"].clone()
+        }
+
+        fn at(&mut self, method: &str, line: u32, character: u32) -> serde_json::Value {
+            self.request(method, json!({
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character }
+            }))
+        }
+    }
+
+    const CODE: &str = "struct User { name: String }
+greet = (user) => {
+    msg = user.name
+    return msg
+}
+u = User { name: \"ann\" }
+who = u.name
+greet(u)";
+
+    #[test]
+    fn diagnostics_follow_edits() {
+        let mut s = Session::new("a = 1");
+
+        let msgs = s.notify("textDocument/didChange", json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": "a = 1\nb = (a" }]
+        }));
+        let diag = &msgs[0]["params"]["diagnostics"][0];
+        assert_eq!(msgs[0]["method"], "textDocument/publishDiagnostics");
+        assert_eq!(diag["range"]["start"], json!({ "line": 1, "character": 6 }));
+        assert_eq!(diag["message"], "Expected CloseParen but got end of input");
+
+        let msgs = s.notify("textDocument/didChange", json!({
+            "textDocument": { "uri": URI, "version": 3 },
+            "contentChanges": [{ "text": "a = 1\nb = (a)" }]
+        }));
+        assert_eq!(msgs[0]["params"]["diagnostics"], json!([]));
+    }
+
+    #[test]
+    fn symbols_definition_and_hover() {
+        let mut s = Session::new(CODE);
+
+        let symbols = s.request("textDocument/documentSymbol", json!({ "textDocument": { "uri": URI } }));
+        assert_eq!(symbols[0]["name"], "User");
+        assert_eq!(symbols[0]["kind"], 23);
+        assert_eq!(symbols[1]["name"], "greet");
+        assert_eq!(symbols[1]["kind"], 12);
+        assert_eq!(symbols[1]["range"]["start"], json!({ "line": 1, "character": 0 }));
+
+        // `msg` in the return statement
+        let def = s.at("textDocument/definition", 3, 12);
+        assert_eq!(def["range"]["start"], json!({ "line": 2, "character": 4 }));
+        // `User` in the instance resolves to the struct
+        let def = s.at("textDocument/definition", 5, 5);
+        assert_eq!(def["range"]["start"], json!({ "line": 0, "character": 7 }));
+
+        let hover = s.at("textDocument/hover", 7, 2);
+        assert_eq!(hover["contents"]["value"], "```flexscript\ngreet: fn(user)\n```");
+        let hover = s.at("textDocument/hover", 6, 1);
+        assert_eq!(hover["contents"]["value"], "```flexscript\nwho: String\n```");
+        assert_eq!(hover["range"]["end"], json!({ "line": 6, "character": 3 }));
+        assert_eq!(s.at("textDocument/hover", 0, 0), json!(null));
+    }
+
+    #[test]
+    fn completion_of_identifiers_and_methods() {
+        let mut s = Session::new("items = [1, 2]\nitems.\ncount = 1\n");
+
+        let items = s.at("textDocument/completion", 1, 6);
+        let labels = items.as_array().unwrap().iter().map(|i| i["label"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(labels, LIST_METHODS);
+
+        s.notify("textDocument/didChange", json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": "items = [1, 2]\nf = (x) => {\n    \n}\ncount = 1\n" }]
+        }));
+        let items = s.at("textDocument/completion", 2, 4);
+        let labels = items.as_array().unwrap().iter().map(|i| i["label"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(labels, ["x", "items", "f", "count"]);
+        assert_eq!(items[0]["kind"], 6);
+        assert_eq!(items[2]["kind"], 3);
+    }
+
+    #[test]
+    fn framed_session_over_streams() {
+        let mut input = vec![];
+        for msg in [
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                "textDocument": { "uri": URI, "languageId": "flexscript", "version": 1, "text": "x = )" }
+            }}),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "workspace/symbol", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ] {
+            write_message(&mut input, &msg).unwrap();
+        }
+
+        let mut server = LspServer::new(Cursor::new(input), vec![]);
+        server.run().unwrap();
+
+        let mut out = Cursor::new(server.writer);
+        let mut msgs = vec![];
+        while let Some(msg) = read_message(&mut out).unwrap() {
+            msgs.push(msg);
+        }
+
+        assert_eq!(msgs.len(), 4);
+        assert_eq!(msgs[0]["result"]["capabilities"]["hoverProvider"], true);
+        assert_eq!(msgs[1]["params"]["diagnostics"][0]["message"], "Unexpected token CloseParen");
+        assert_eq!(msgs[2]["error"]["code"], -32601);
+        assert_eq!(msgs[3]["id"], 3);
+    }
+}
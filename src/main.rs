@@ -0,0 +1,618 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::panic;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::bail;
+use anyhow::Context;
+use flexscript::format_code;
+use flexscript::module_docs;
+use flexscript::lint;
+use flexscript::DapServer;
+use flexscript::DocFormat;
+use flexscript::FsLoader;
+use flexscript::LintConfig;
+use flexscript::LspServer;
+use flexscript::Parser;
+use flexscript::PROGRAM_MAGIC;
+use flexscript::Repl;
+use flexscript::ReplOutput;
+use flexscript::run_tests;
+use flexscript::Outcome;
+use flexscript::RunResult;
+use flexscript::Severity;
+use flexscript::StdLib;
+use flexscript::Value;
+use flexscript::Vm;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+const USAGE: &str = "usage: flexscript [--log N] <command> [args]
+
+commands:
+    run <file> [--stubs <file.json>]   run a script or compiled program and print the returned value
+    compile <file> [-o <out.fsb>]      compile a script to a program file
+    parse <file>                       print the syntax tree of a script
+    fmt <file> [--check]               format a script in place, or fail if it is not formatted
+    lint <file> [--config <file.json>] [--json]
+                                       report likely mistakes, fail on error level findings
+    test [file or dir]                 run the test blocks of *_test.fs files, default in .
+    disasm <file>                      print the compiled bytecode with source lines
+    doc <file> [--html] [-o <out>]     print the API docs of a module as markdown or html
+    repl                               start an interactive session
+    dap [--stubs <file.json>]          serve the debug adapter protocol over stdio
+    lsp                                serve the language server protocol over stdio
+";
+
+struct Args {
+    log: usize,
+    command: String,
+    file: Option<String>,
+    stubs: Option<String>,
+    output: Option<String>,
+    check: bool,
+    config: Option<String>,
+    json: bool,
+    html: bool,
+}
+
+fn parse_args(args: Vec<String>) -> anyhow::Result<Args> {
+    let mut parsed = Args {
+        log: 0,
+        command: String::new(),
+        file: None,
+        stubs: None,
+        output: None,
+        check: false,
+        config: None,
+        json: false,
+        html: false,
+    };
+
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--log" => {
+                let level = args.next().context("--log requires a level")?;
+                parsed.log = level.parse().context("--log level must be a number")?;
+            },
+            "--stubs" => {
+                parsed.stubs = Some(args.next().context("--stubs requires a file")?);
+            },
+            "-o" => {
+                parsed.output = Some(args.next().context("-o requires a file")?);
+            },
+            "--check" => {
+                parsed.check = true;
+            },
+            "--config" => {
+                parsed.config = Some(args.next().context("--config requires a file")?);
+            },
+            "--json" => {
+                parsed.json = true;
+            },
+            "--html" => {
+                parsed.html = true;
+            },
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                process::exit(0);
+            },
+            _ => {
+                if parsed.command.is_empty() {
+                    parsed.command = arg;
+                } else if parsed.file.is_none() {
+                    parsed.file = Some(arg);
+                } else {
+                    bail!("unexpected argument {}", arg);
+                }
+            }
+        }
+    }
+
+    if parsed.command.is_empty() {
+        bail!("missing command\n\n{}", USAGE);
+    }
+
+    Ok(parsed)
+}
+
+/// Answers awaited calls to host functions so scripts can be run offline.
+struct Stubs {
+    values: HashMap<String, Value>,
+    echo: fn(&str),
+}
+
+fn print_stdout(line: &str) {
+    println!("{}", line);
+}
+
+fn print_stderr(line: &str) {
+    eprintln!("{}", line);
+}
+
+impl Stubs {
+    fn new() -> Self {
+        Self {
+            values: HashMap::new(),
+            echo: print_stdout,
+        }
+    }
+
+    /// Loads stub replies from a JSON object which maps function names
+    /// to the value the awaited call returns.
+    fn load(path: &str) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("failed to read {}", path))?;
+        let json: HashMap<String, serde_json::Value> = serde_json::from_str(&text)
+            .with_context(|| format!("invalid stub file {}", path))?;
+
+        let mut stubs = Self::new();
+        for (name, val) in json {
+            let val = flexscript::to_value(&val).with_context(|| format!("invalid stub {}", name))?;
+            stubs.values.insert(name, val);
+        }
+
+        Ok(stubs)
+    }
+
+    fn answer(&self, vm: &Vm, value: Value) -> anyhow::Result<Value> {
+        let (ident, args) = match value {
+            Value::UndefCall { ident, args } => (ident, args),
+            _ => return Ok(value)
+        };
+
+        let name = vm.idt_name(ident).unwrap_or_default();
+
+        if let Some(v) = self.values.get(name) {
+            return Ok(v.clone());
+        }
+
+        match name {
+            "print" | "println" => {
+                let line = args.iter().map(|a| match a {
+                    Value::Str(s) => s.clone(),
+                    _ => a.to_string()
+                }).collect::<Vec<_>>().join(" ");
+                (self.echo)(&line);
+                Ok(Value::None)
+            },
+            "sleep" => Ok(Value::None),
+            "now" => {
+                let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+                Ok(Value::Int(millis as i64))
+            },
+            _ => bail!("no stub for awaited call {}", name)
+        }
+    }
+}
+
+fn read_file(args: &Args) -> anyhow::Result<String> {
+    let path = match &args.file {
+        Some(f) => f,
+        None => bail!("{} requires a file", args.command)
+    };
+
+    fs::read_to_string(path).with_context(|| format!("failed to read {}", path))
+}
+
+/// Imports of a script resolve relative to its directory.
+fn script_loader(path: &str) -> FsLoader {
+    let dir = Path::new(path).parent().unwrap_or(Path::new("."));
+    FsLoader::new(dir)
+}
+
+fn run(args: &Args) -> anyhow::Result<()> {
+    let path = match &args.file {
+        Some(f) => f,
+        None => bail!("run requires a file")
+    };
+    let bytes = fs::read(path).with_context(|| format!("failed to read {}", path))?;
+    let stubs = match &args.stubs {
+        Some(path) => Stubs::load(path)?,
+        None => Stubs::new()
+    };
+
+    let mut vm = Vm::new();
+    vm.log = args.log;
+    vm.set_loader(script_loader(path));
+    vm.open_std(&StdLib::ALL);
+
+    let mut res = if bytes.starts_with(PROGRAM_MAGIC) {
+        let blk = vm.load_program(&bytes).with_context(|| format!("failed to load {}", path))?;
+        vm.run_blk(blk, Value::None)
+    } else {
+        let code = String::from_utf8(bytes).with_context(|| format!("{} is not utf-8", path))?;
+        vm.run_code(&code)
+    };
+
+    loop {
+        match res {
+            RunResult::Await { stack_id, value } => {
+                let reply = stubs.answer(&vm, value)?;
+                res = vm.cont(stack_id, reply);
+            },
+            RunResult::Value(v) => {
+                println!("{}", v);
+                return Ok(());
+            },
+            RunResult::Paused { .. } => bail!("script paused without a debugger"),
+            RunResult::None => return Ok(())
+        }
+    }
+}
+
+fn compile(args: &Args) -> anyhow::Result<()> {
+    let code = read_file(args)?;
+    let output = match &args.output {
+        Some(o) => o.clone(),
+        None => {
+            let file = args.file.as_deref().unwrap_or_default();
+            format!("{}.fsb", file.strip_suffix(".fs").unwrap_or(file))
+        }
+    };
+
+    let mut vm = Vm::new();
+    vm.log = args.log;
+    vm.set_loader(script_loader(args.file.as_deref().unwrap_or_default()));
+    vm.open_std(&StdLib::ALL);
+    let blk = vm.compile_code(&code);
+    fs::write(&output, vm.save_program(blk)).with_context(|| format!("failed to write {}", output))?;
+
+    Ok(())
+}
+
+fn parse(args: &Args) -> anyhow::Result<()> {
+    let code = read_file(args)?;
+    let ast = Parser::new(&code).set_loglevel(args.log).try_parse()?;
+    println!("{:#?}", ast);
+    Ok(())
+}
+
+fn fmt(args: &Args) -> anyhow::Result<()> {
+    let code = read_file(args)?;
+    let path = args.file.as_deref().unwrap_or_default();
+    let formatted = format_code(&code).with_context(|| format!("failed to parse {}", path))?;
+
+    if formatted == code {
+        return Ok(());
+    }
+
+    if args.check {
+        bail!("{} is not formatted", path);
+    }
+
+    fs::write(path, formatted).with_context(|| format!("failed to write {}", path))
+}
+
+fn lint_file(args: &Args) -> anyhow::Result<()> {
+    let code = read_file(args)?;
+    let path = args.file.as_deref().unwrap_or_default();
+    let config = match &args.config {
+        Some(c) => {
+            let text = fs::read_to_string(c).with_context(|| format!("failed to read {}", c))?;
+            LintConfig::from_json(&text)?
+        },
+        None => LintConfig::default()
+    };
+
+    let lints = lint(&code, &config).with_context(|| format!("failed to parse {}", path))?;
+
+    if args.json {
+        let list = lints.iter().map(|l| {
+            let mut json = l.to_json();
+            json["file"] = path.into();
+            json
+        }).collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&list)?);
+    } else {
+        for l in &lints {
+            println!("{}:{}", path, l);
+        }
+    }
+
+    let errors = lints.iter().filter(|l| l.severity == Severity::Error).count();
+    if errors > 0 {
+        bail!("{} has {} lint error(s)", path, errors);
+    }
+
+    Ok(())
+}
+
+/// Test files below `dir`, in sorted order.
+fn find_test_files(dir: &Path, out: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|e| e.path());
+
+    for entry in entries {
+        let path = entry.path();
+        if path.is_dir() {
+            find_test_files(&path, out)?;
+        } else if path.to_string_lossy().ends_with("_test.fs") {
+            out.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn test(args: &Args) -> anyhow::Result<()> {
+    // Script errors fail the test they happen in
+    panic::set_hook(Box::new(|_| {}));
+
+    let root = Path::new(args.file.as_deref().unwrap_or("."));
+    let mut files = vec![];
+    if root.is_dir() {
+        find_test_files(root, &mut files).with_context(|| format!("failed to read {}", root.display()))?;
+    } else {
+        files.push(root.to_path_buf());
+    }
+
+    let mut passed = 0;
+    let mut failures = vec![];
+
+    for file in &files {
+        let code = fs::read_to_string(file).with_context(|| format!("failed to read {}", file.display()))?;
+        let results = run_tests(&code).with_context(|| format!("failed to parse {}", file.display()))?;
+
+        for res in results {
+            let status = if res.passed() { "ok" } else { "FAILED" };
+            println!("{}:{}: {} ... {}", file.display(), res.line, res.name, status);
+            match res.passed() {
+                true => passed += 1,
+                false => failures.push((file, res)),
+            }
+        }
+    }
+
+    for (file, res) in &failures {
+        if let Outcome::Failed { message, line } = &res.outcome {
+            println!("\n{}:{}: {}", file.display(), line.unwrap_or(res.line), res.name);
+            println!("{}", message);
+            for line in &res.output {
+                println!("output: {}", line);
+            }
+        }
+    }
+
+    println!("\n{} passed; {} failed", passed, failures.len());
+    if !failures.is_empty() {
+        bail!("{} test(s) failed", failures.len());
+    }
+
+    Ok(())
+}
+
+fn disasm(args: &Args) -> anyhow::Result<()> {
+    let code = read_file(args)?;
+    let mut vm = Vm::new();
+    vm.log = args.log;
+    vm.set_loader(script_loader(args.file.as_deref().unwrap_or_default()));
+    vm.open_std(&StdLib::ALL);
+    let blk = vm.compile_code(&code);
+    print!("{}", vm.disassemble(blk));
+
+    Ok(())
+}
+
+fn doc(args: &Args) -> anyhow::Result<()> {
+    let code = read_file(args)?;
+    let path = args.file.as_deref().unwrap_or_default();
+    let name = Path::new(path).file_stem().unwrap_or_default().to_string_lossy();
+    let format = if args.html { DocFormat::Html } else { DocFormat::Markdown };
+    let docs = module_docs(&name, &code, format).with_context(|| format!("failed to parse {}", path))?;
+
+    match &args.output {
+        Some(out) => fs::write(out, docs).with_context(|| format!("failed to write {}", out)),
+        None => {
+            print!("{}", docs);
+            Ok(())
+        }
+    }
+}
+
+fn dap(args: &Args) -> anyhow::Result<()> {
+    // Script errors are sent to the editor as output events
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut stubs = match &args.stubs {
+        Some(path) => Stubs::load(path)?,
+        None => Stubs::new()
+    };
+    // Stdout carries the protocol
+    stubs.echo = print_stderr;
+
+    let stdin = std::io::stdin();
+    let mut server = DapServer::new(stdin.lock(), std::io::stdout());
+    server.on_await(move |vm, value| stubs.answer(vm, value).map_err(|e| e.to_string()));
+    server.run()?;
+
+    Ok(())
+}
+
+fn lsp() -> anyhow::Result<()> {
+    let stdin = std::io::stdin();
+    let mut server = LspServer::new(stdin.lock(), std::io::stdout());
+    server.run()?;
+
+    Ok(())
+}
+
+fn history_path() -> Option<String> {
+    std::env::var("HOME").ok().map(|home| format!("{}/.flexscript_history", home))
+}
+
+fn repl(args: &Args) -> anyhow::Result<()> {
+    // Errors are reported by the repl itself
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut repl = Repl::new();
+    repl.vm().log = args.log;
+    repl.vm().set_loader(FsLoader::new("."));
+    repl.vm().open_std(&StdLib::ALL);
+
+    let mut editor = DefaultEditor::new()?;
+    let history = history_path();
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        let line = match editor.readline(repl.prompt()) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into())
+        };
+
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str())?;
+        }
+
+        match repl.feed(&line) {
+            ReplOutput::Continue => {},
+            ReplOutput::Text(text) => {
+                if !text.is_empty() {
+                    println!("{}", text);
+                }
+            },
+            ReplOutput::Quit => break
+        }
+    }
+
+    if let Some(path) = &history {
+        let _ = editor.save_history(path);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    // Script errors are raised as panics inside the vm, report them
+    // as plain errors instead of a rust backtrace.
+    panic::set_hook(Box::new(|info| {
+        let payload = info.payload();
+        let msg = match payload.downcast_ref::<&str>() {
+            Some(s) => s.to_string(),
+            None => match payload.downcast_ref::<String>() {
+                Some(s) => s.clone(),
+                None => "unknown error".to_string()
+            }
+        };
+        eprintln!("error: {}", msg);
+    }));
+
+    let args = match parse_args(std::env::args().skip(1).collect()) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(2);
+        }
+    };
+
+    let res = panic::catch_unwind(|| {
+        match args.command.as_str() {
+            "run" => run(&args),
+            "compile" => compile(&args),
+            "parse" => parse(&args),
+            "disasm" => disasm(&args),
+            "doc" => doc(&args),
+            "fmt" => fmt(&args),
+            "lint" => lint_file(&args),
+            "test" => test(&args),
+            "repl" => repl(&args),
+            "dap" => dap(&args),
+            "lsp" => lsp(),
+            _ => Err(anyhow::anyhow!("unknown command {}\n\n{}", args.command, USAGE))
+        }
+    });
+
+    match res {
+        Ok(Ok(())) => {},
+        Ok(Err(e)) => {
+            eprintln!("error: {:#}", e);
+            process::exit(1);
+        },
+        Err(_) => process::exit(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_run_args() {
+        let parsed = parse_args(args(&["--log", "2", "run", "main.fs", "--stubs", "stubs.json"])).unwrap();
+        assert_eq!(parsed.log, 2);
+        assert_eq!(parsed.command, "run");
+        assert_eq!(parsed.file, Some("main.fs".to_string()));
+        assert_eq!(parsed.stubs, Some("stubs.json".to_string()));
+    }
+
+    #[test]
+    fn parse_fmt_check_args() {
+        let parsed = parse_args(args(&["fmt", "--check", "main.fs"])).unwrap();
+        assert_eq!(parsed.command, "fmt");
+        assert_eq!(parsed.file, Some("main.fs".to_string()));
+        assert!(parsed.check);
+    }
+
+    #[test]
+    fn parse_lint_args() {
+        let parsed = parse_args(args(&["lint", "main.fs", "--config", "lint.json", "--json"])).unwrap();
+        assert_eq!(parsed.command, "lint");
+        assert_eq!(parsed.file, Some("main.fs".to_string()));
+        assert_eq!(parsed.config, Some("lint.json".to_string()));
+        assert!(parsed.json);
+    }
+
+    #[test]
+    fn parse_doc_args() {
+        let parsed = parse_args(args(&["doc", "lib/math.fs", "--html", "-o", "math.html"])).unwrap();
+        assert_eq!(parsed.command, "doc");
+        assert_eq!(parsed.file, Some("lib/math.fs".to_string()));
+        assert_eq!(parsed.output, Some("math.html".to_string()));
+        assert!(parsed.html);
+    }
+
+    #[test]
+    fn missing_command() {
+        assert!(parse_args(args(&[])).is_err());
+        assert!(parse_args(args(&["--log", "x", "run"])).is_err());
+    }
+
+    #[test]
+    fn answer_awaited_call_from_stub() {
+        let mut vm = Vm::new();
+        let res = vm.run_code("return await(fetch(1))");
+        let (stack_id, value) = match res {
+            RunResult::Await { stack_id, value } => (stack_id, value),
+            _ => panic!("Invalid result")
+        };
+
+        let mut stubs = Stubs::new();
+        stubs.values.insert("fetch".to_string(), Value::Int(5));
+        let reply = stubs.answer(&vm, value).unwrap();
+        assert_eq!(vm.cont(stack_id, reply), RunResult::Value(Value::Int(5)));
+    }
+
+    #[test]
+    fn unknown_awaited_call() {
+        let mut vm = Vm::new();
+        let value = match vm.run_code("await(missing())") {
+            RunResult::Await { value, .. } => value,
+            _ => panic!("Invalid result")
+        };
+        assert!(Stubs::new().answer(&vm, value).is_err());
+    }
+}
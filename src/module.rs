@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::PathBuf;
+
+use crate::vm::DeclScope;
+use crate::vm_types::ByteCode;
+use crate::vm_types::LineTable;
+use crate::ASTNode;
+use crate::Assign;
+use crate::Obj;
+use crate::ObjProp;
+use crate::Parser;
+use crate::ProbAccess;
+use crate::RunResult;
+use crate::Value;
+use crate::Vm;
+
+/// Finds the source of imported modules. Names are the dotted path of
+/// the import, `import lib.util` asks for `lib.util`.
+pub trait ModuleLoader {
+    fn load(&self, name: &str) -> io::Result<String>;
+}
+
+/// Loads `lib.util` from `lib/util.fs` below a root directory.
+#[derive(Debug, Clone)]
+pub struct FsLoader {
+    root: PathBuf,
+}
+
+impl FsLoader {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn path_of(&self, name: &str) -> PathBuf {
+        let mut path = self.root.clone();
+        for part in name.split('.') {
+            path.push(part);
+        }
+        path.set_extension("fs");
+        path
+    }
+}
+
+impl ModuleLoader for FsLoader {
+    fn load(&self, name: &str) -> io::Result<String> {
+        fs::read_to_string(self.path_of(name))
+    }
+}
+
+/// Modules held in memory, for embedding and tests.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLoader {
+    modules: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, name: &str, source: &str) -> &mut Self {
+        self.modules.insert(name.to_string(), source.to_string());
+        self
+    }
+}
+
+impl ModuleLoader for MemoryLoader {
+    fn load(&self, name: &str) -> io::Result<String> {
+        match self.modules.get(name) {
+            Some(source) => Ok(source.clone()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("module {} not found", name))),
+        }
+    }
+}
+
+impl Vm {
+    pub fn set_loader(&mut self, loader: impl ModuleLoader + 'static) {
+        self.loader = Some(Box::new(loader));
+    }
+
+    pub(crate) fn compile_import(&mut self, block: &mut Vec<ByteCode>, path: &[String]) {
+        // The standard library is a global, `import std.math` only names it
+        if path[0] == "std" && self.has_std() {
+            return self.compile_use(block, path);
+        }
+
+        let name = path.join(".");
+        let blk = self.load_module(&name);
+        self.load_namespace(block, blk);
+        let i = self.store_idt(path[path.len() - 1].clone());
+        block.push(ByteCode::Store(i));
+    }
+
+    pub(crate) fn compile_inline_module(&mut self, block: &mut Vec<ByteCode>, name: &str, body: &[ASTNode]) {
+        let blk = self.build_module(name, |vm, block| {
+            for node in body {
+                vm.compile_stmt(block, node);
+            }
+        });
+        self.load_namespace(block, blk);
+        let i = self.store_idt(name.to_string());
+        block.push(ByteCode::Store(i));
+    }
+
+    /// `use a::b::c` reads `a.b.c` into `c`.
+    pub(crate) fn compile_use(&mut self, block: &mut Vec<ByteCode>, path: &[String]) {
+        let mut value = ASTNode::Ident(path[0].clone());
+        for part in &path[1..] {
+            value = ASTNode::ProbAccess(ProbAccess {
+                object: Box::new(value),
+                property: part.clone(),
+                optional: false,
+            });
+        }
+
+        self.compile_node(block, &ASTNode::Assign(Assign {
+            left: Box::new(ASTNode::Ident(path[path.len() - 1].clone())),
+            right: Box::new(value),
+            op: None,
+        }));
+    }
+
+    pub(crate) fn compile_export(&mut self, block: &mut Vec<ByteCode>, node: &ASTNode) {
+        let name = match node {
+            ASTNode::Assign(a) => match &*a.left {
+                ASTNode::Ident(name) => Some(name.clone()),
+                _ => None,
+            },
+            ASTNode::Decl(d) => Some(d.name.clone()),
+            ASTNode::Module(m) => Some(m.name.clone()),
+            ASTNode::Import(i) => i.path.last().cloned(),
+            _ => None,
+        };
+
+        // Exports outside of a module are plain statements
+        if let (Some(exports), Some(name)) = (self.exports.last_mut(), name) {
+            exports.push(name);
+        }
+        self.compile_node(block, node);
+    }
+
+    /// Compiles and runs a module file the first time it is imported and
+    /// returns the block of its body.
+    fn load_module(&mut self, name: &str) -> u32 {
+        if let Some(blk) = self.module_blocks.get(name) {
+            return *blk;
+        }
+
+        if self.loading.iter().any(|m| m == name) {
+            let mut chain = self.loading.clone();
+            chain.push(name.to_string());
+            panic!("Circular import {}", chain.join(" -> "));
+        }
+
+        let source = match &self.loader {
+            Some(loader) => match loader.load(name) {
+                Ok(s) => s,
+                Err(e) => panic!("Failed to load module {}: {}", name, e),
+            },
+            None => panic!("Can not import {} without a module loader", name),
+        };
+
+        let mut parser = Parser::new(&source);
+        let ast = match parser.try_parse() {
+            Ok(ast) => ast,
+            Err(e) => panic!("Module {}: {}", name, e),
+        };
+
+        // The module has its own source and line table, the importer's
+        // are picked up again afterwards.
+        self.sources.push(source);
+        let source_id = Some((self.sources.len() - 1) as u32);
+        let outer_source = mem::replace(&mut self.curr_source, source_id);
+        let outer_lines = mem::replace(&mut self.pending_lines, parser.stmt_lines().into());
+
+        self.loading.push(name.to_string());
+        let blk = self.build_module(name, |vm, block| {
+            for node in &ast {
+                vm.compile_stmt(block, node);
+            }
+        });
+        self.loading.pop();
+
+        self.curr_source = outer_source;
+        self.pending_lines = outer_lines;
+        self.module_blocks.insert(name.to_string(), blk);
+
+        blk
+    }
+
+    /// Compiles a module body into its own block and runs it in a scope
+    /// of its own. The exports are stored as an object in a global which
+    /// scripts can not name.
+    fn build_module(&mut self, name: &str, compile: impl FnOnce(&mut Vm, &mut Vec<ByteCode>)) -> u32 {
+        let mut block = Vec::new();
+        self.line_stack.push(LineTable::default());
+        self.exports.push(Vec::new());
+        // A module sees none of the names declared around it
+        let outer_decls = mem::replace(&mut self.decls, vec![DeclScope::new(true)]);
+        compile(self, &mut block);
+        self.decls = outer_decls;
+        let exports = self.exports.pop().unwrap();
+        let lines = self.line_stack.pop().unwrap();
+
+        // Functions of the module find its private names when called
+        // from outside, other calls keep using the caller's scope.
+        let scope_id = self.scope.create_child_scope(self.globals);
+        for c in &block {
+            if let ByteCode::Fun(f) = c {
+                self.home_scopes.insert(*f, scope_id);
+            }
+        }
+
+        self.push_block(block, lines);
+        let blk = (self.code_blocks.len() - 1) as u32;
+
+        match self.run_blk_in(blk, scope_id) {
+            RunResult::Await { .. } => panic!("Module {} can not await while it is loaded", name),
+            RunResult::Paused { .. } => panic!("Module {} paused while it was loaded", name),
+            _ => {}
+        }
+
+        let vars = self.scope.named_vars(scope_id);
+        let mut props = vec![];
+        for export in exports {
+            let id = self.store_idt(export.clone());
+            let value = match vars.iter().find(|(i, _)| *i == id) {
+                Some((_, v)) => self.scope.copy_value(v.clone()),
+                None => Value::None,
+            };
+            props.push(ObjProp { name: export, value });
+        }
+
+        let namespace = Value::Obj(Obj {
+            name: Some(name.to_string()),
+            props: props.into(),
+        });
+        self.set_global(&module_global(blk), namespace);
+
+        blk
+    }
+
+    fn load_namespace(&mut self, block: &mut Vec<ByteCode>, blk: u32) {
+        let i = self.store_idt(module_global(blk));
+        block.push(ByteCode::Load(i));
+    }
+}
+
+/// Name of the global holding the exports of a module.
+fn module_global(blk: u32) -> String {
+    format!("<module {}>", blk)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+    use std::panic::AssertUnwindSafe;
+
+    use super::*;
+    use crate::repl::panic_message;
+
+    fn vm_with(modules: &[(&str, &str)]) -> Vm {
+        let mut loader = MemoryLoader::new();
+        for (name, source) in modules {
+            loader.add(name, source);
+        }
+        let mut vm = Vm::new();
+        vm.set_loader(loader);
+        vm
+    }
+
+    fn run_err(vm: &mut Vm, code: &str) -> String {
+        let res = panic::catch_unwind(AssertUnwindSafe(|| vm.run_code(code)));
+        match res {
+            Ok(r) => panic!("Expected error but got {:?}", r),
+            Err(e) => panic_message(e.as_ref()),
+        }
+    }
+
+    #[test]
+    fn imports_exports_of_a_file() {
+        let mut vm = vm_with(&[("lib.math", "offset = 10
+add = (a, b) => {
+    return a + b + offset
+}
+export double = x => {
+    return add(x, x)
+}
+export name = \"math\"")]);
+
+        let code = "import lib.math
+use math::double
+a = double(1)
+b = math.double(2)
+return a + b";
+        assert_eq!(vm.run_code(code), RunResult::Value(Value::Int(26)));
+
+        let res = vm.run_code("import lib.math\nreturn math.name");
+        assert_eq!(res, RunResult::Value(Value::Str("math".to_string())));
+        assert_eq!(vm.run_code("import lib.math\nreturn math.add"), RunResult::Value(Value::None));
+    }
+
+    #[test]
+    fn modules_are_loaded_once() {
+        let mut vm = vm_with(&[
+            ("a", "import shared\nexport v = shared.count"),
+            ("b", "import shared\nexport v = shared.count"),
+            ("shared", "export count = 1"),
+        ]);
+
+        let blocks = vm.block_count();
+        vm.run_code("import a\nimport b\nimport shared");
+        let main = vm.block_count() - blocks;
+        // a, b, shared and the script
+        assert_eq!(main, 4);
+
+        let before = vm.block_count();
+        vm.run_code("import shared");
+        assert_eq!(vm.block_count(), before + 1);
+    }
+
+    #[test]
+    fn inline_modules_and_nesting() {
+        let mut vm = Vm::new();
+        let code = "mod io {
+    prefix = 1
+    export mod inner {
+        export one = () => {
+            return 1
+        }
+    }
+    export copy = x => {
+        return x + prefix
+    }
+}
+use io::copy
+use io::inner::one
+return copy(one())";
+        assert_eq!(vm.run_code(code), RunResult::Value(Value::Int(2)));
+    }
+
+    #[test]
+    fn import_errors() {
+        let mut vm = vm_with(&[
+            ("a", "import b\nexport x = 1"),
+            ("b", "import a\nexport y = 2"),
+            ("bad", "x = ("),
+        ]);
+
+        assert_eq!(run_err(&mut vm, "import a"), "Circular import a -> b -> a");
+        assert!(run_err(&mut vm, "import missing").starts_with("Failed to load module missing"));
+        assert!(run_err(&mut vm, "import bad").starts_with("Module bad: 1:6"));
+        assert_eq!(run_err(&mut Vm::new(), "import a"), "Can not import a without a module loader");
+    }
+}
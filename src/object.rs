@@ -0,0 +1,181 @@
+use crate::scope::ScopeManager;
+use crate::value_kind;
+use crate::Ptr;
+use crate::StackValue;
+use crate::Value;
+
+/// Methods every object has unless it has a function of the same name.
+pub const OBJECT_METHODS: [&str; 5] = ["keys", "values", "entries", "has", "remove"];
+
+/// Property read through `.__name`, gives the type name of the object.
+pub const NAME_PROP: &str = "__name";
+
+/// Moves nested lists and objects into unnamed slots, so the value can be
+/// kept in a list the script reads from.
+fn lift(scope: &mut ScopeManager, scope_id: u32, val: Value) -> Value {
+    match val {
+        Value::List(_) | Value::Obj(_) | Value::Enum(_) => Value::Ptr(scope.store_unamed(scope_id, val)),
+        v => v,
+    }
+}
+
+/// `[name, value]` pairs of every property, in order.
+pub(crate) fn entries(scope: &mut ScopeManager, ptr: &Ptr, scope_id: u32) -> Vec<Value> {
+    let props = match scope.lookup(ptr) {
+        Some(Value::Obj(obj)) => obj.props.clone(),
+        _ => return vec![],
+    };
+
+    props.into_iter().map(|p| {
+        let pair = vec![Value::Str(p.name), lift(scope, scope_id, p.value)];
+        Value::Ptr(scope.store_unamed(scope_id, Value::List(pair)))
+    }).collect()
+}
+
+fn name_arg<'a>(args: &'a [Value], method: &str) -> Result<&'a str, String> {
+    match args.first() {
+        Some(Value::Str(s)) => Ok(s),
+        Some(v) => Err(format!("{} expects a property name but got {}", method, value_kind(v))),
+        None => Err(format!("{} expects a property name", method)),
+    }
+}
+
+/// Calls one of `OBJECT_METHODS`. `args` have their pointers resolved.
+pub(crate) fn call_method(
+    scope: &mut ScopeManager,
+    ptr: &Ptr,
+    name: &str,
+    args: Vec<Value>,
+    scope_id: u32,
+) -> Result<StackValue, String> {
+    let list = |scope: &mut ScopeManager, items: Vec<Value>| scope.alloc(scope_id, Value::List(items));
+    let obj = match scope.lookup(ptr) {
+        Some(Value::Obj(obj)) => obj,
+        _ => return Err("object not found".to_string()),
+    };
+
+    let val = match name {
+        "keys" => {
+            let keys = obj.props.iter().map(|p| Value::Str(p.name.clone())).collect();
+            list(scope, keys)
+        },
+        "values" => {
+            let values = obj.props.iter().map(|p| p.value.clone()).collect::<Vec<_>>();
+            let values = values.into_iter().map(|v| lift(scope, scope_id, v)).collect();
+            list(scope, values)
+        },
+        "entries" => {
+            let pairs = entries(scope, ptr, scope_id);
+            list(scope, pairs)
+        },
+        "has" => StackValue::Bool(obj.props.contains(name_arg(&args, name)?)),
+        "remove" => {
+            let removed = obj.props.remove(name_arg(&args, name)?).unwrap_or_default();
+            scope.alloc(scope_id, removed)
+        },
+        _ => return Err(format!("object has no method {}", name)),
+    };
+
+    Ok(val)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::list;
+    use crate::test_util::run;
+    use crate::test_util::run_err;
+    use crate::test_util::str;
+    use crate::Obj;
+    use crate::ObjProp;
+    use crate::Value;
+
+    #[test]
+    fn methods() {
+        let code = "o = { a: 1, b: [2], c: \"x\" }";
+        let run_with = |end: &str| run(&format!("{}\n{}", code, end));
+
+        assert_eq!(run_with("return o.keys()"), list(&[str("a"), str("b"), str("c")]));
+        assert_eq!(run_with("return o.values()"), list(&[Value::Int(1), list(&[Value::Int(2)]), str("x")]));
+        assert_eq!(run_with("return o.entries()"), list(&[
+            list(&[str("a"), Value::Int(1)]),
+            list(&[str("b"), list(&[Value::Int(2)])]),
+            list(&[str("c"), str("x")]),
+        ]));
+        assert_eq!(run_with("return o.has(\"b\")"), Value::Bool(true));
+        assert_eq!(run_with("return o.has(\"z\")"), Value::Bool(false));
+        assert_eq!(run_with("v = o.remove(\"a\")\nreturn [v, o.keys()]"), list(&[
+            Value::Int(1),
+            list(&[str("b"), str("c")]),
+        ]));
+        assert_eq!(run_with("return o.remove(\"z\")"), Value::None);
+    }
+
+    #[test]
+    fn own_functions_come_first() {
+        let code = "o = { keys: () => {
+    return 7
+} }
+return o.keys()";
+        assert_eq!(run(code), Value::Int(7));
+    }
+
+    #[test]
+    fn spread_and_merge() {
+        let code = "base = Point { x: 1, y: 2 }
+extra = { z: 3, y: 5 }
+return { ...base, x: 10, ...extra }";
+        let expected = Value::Obj(Obj {
+            name: None,
+            props: vec![
+                ObjProp { name: "x".to_string(), value: Value::Int(10) },
+                ObjProp { name: "y".to_string(), value: Value::Int(5) },
+                ObjProp { name: "z".to_string(), value: Value::Int(3) },
+            ].into(),
+        });
+        assert_eq!(run(code), expected);
+    }
+
+    #[test]
+    fn iterates_entries() {
+        let code = "o = { a: 1, b: 2 }
+keys = []
+sum = 0
+for [k, v] in o {
+    keys.push(k)
+    sum = sum + v
+}
+return [keys, sum]";
+        assert_eq!(run(code), list(&[list(&[str("a"), str("b")]), Value::Int(3)]));
+
+        let code = "out = []
+items = [\"x\", \"y\"]
+for [i, s] in (items.enumerate()) {
+    out.push(s * (i + 1))
+}
+return out";
+        assert_eq!(run(code), list(&[str("x"), str("yy")]));
+    }
+
+    #[test]
+    fn type_name() {
+        assert_eq!(run("p = Point { x: 1 }\nreturn p.__name"), str("Point"));
+        assert_eq!(run("p = { x: 1 }\nreturn p.__name"), Value::None);
+    }
+
+    #[test]
+    fn large_objects_keep_order_and_lookups() {
+        let mut props = String::new();
+        for i in 0..20 {
+            props.push_str(&format!("p{}: {}, ", i, i));
+        }
+        let code = format!("o = {{ {} }}\no.remove(\"p3\")\nkeys = o.keys()\nreturn [o.p17, o.has(\"p3\"), keys.len()]", props);
+        assert_eq!(run(&code), list(&[Value::Int(17), Value::Bool(false), Value::Int(19)]));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(run_err("o = { a: 1 }\nreturn o.has(1)"), "has expects a property name but got int");
+        assert_eq!(run_err("o = { a: 1 }\nreturn o.nope()"), "object has no function nope");
+        assert_eq!(run_err("a = 1\nreturn { ...a }"), "Can only spread objects, got int");
+    }
+}
@@ -1,4 +1,7 @@
+use std::fmt;
+use std::mem;
 use std::ops::Range;
+use logos::FilterResult;
 use logos::Logos;
 use logos::skip;
 
@@ -6,19 +9,38 @@ use crate::For;
 use crate::ForCond;
 use crate::If;
 use crate::Param;
+use crate::cst::NodeKind;
+use crate::cst::TokenKind;
 use crate::types::ASTNode;
 use crate::types::Array;
 use crate::types::Assign;
 use crate::types::BinOp;
 use crate::types::Call;
+use crate::types::Decl;
+use crate::types::EnumDef;
 use crate::types::Fun;
+use crate::types::Match;
+use crate::types::MatchArm;
+use crate::types::Pattern;
+use crate::types::PatternFields;
 use crate::types::Op;
 use crate::types::ProbAccess;
 use crate::types::Property;
 use crate::types::Ret;
+use crate::types::StructDef;
+use crate::types::TestDef;
+use crate::types::Import;
+use crate::types::Index;
+use crate::types::ModuleDef;
+use crate::types::Use;
+use crate::types::TypeField;
+use crate::types::VarType;
+use crate::types::VariantArgs;
+use crate::types::VariantDef;
+use crate::types::VariantIns;
+use crate::types::VariantShape;
 use crate::types::ObjIns;
 use crate::types::Value;
-use crate::types::Var;
 
 #[derive(Logos, Debug, PartialEq, Clone)]
 enum Token {
@@ -28,6 +50,7 @@ enum Token {
 	#[token("\r", skip)]
 	Whitespace,
 	#[regex("//[^\n]*", skip)]
+	#[token("/*", block_comment)]
 	Comment_,
 	#[token("for")]
 	For,
@@ -55,25 +78,51 @@ enum Token {
 	CloseBracket,
 	#[token("::")]
 	DoubleColon,
+	#[token("?.")]
+	QuestionDot,
+	#[token("??")]
+	DoubleQuestion,
+	#[token("?")]
+	Question,
 	#[token(":")]
 	Colon,
 	#[token(",")]
 	Comma,
+	#[token("...")]
+	Spread,
 	#[token(".")]
 	Dot,
 	#[token("==")]
 	Eq,
+	#[token("<")]
+	Lt,
+	#[token(">")]
+	Gt,
 	#[token("=")]
 	Assign,
+	#[token("+=")]
+	PlusAssign,
+	#[token("-=")]
+	MinusAssign,
+	#[token("*=")]
+	MulAssign,
+	#[token("/=")]
+	DivAssign,
 	#[token("true")]
 	True,
 	#[token("false")]
 	False,
+	#[token("none")]
+	None,
 	#[regex(r#""[^"]*""#, |t| t.slice()[1..t.slice().len()-1].to_string())]
 	String(String),
-	#[regex(r"-?[0-9]+", |t| t.slice().parse::<i64>().ok(), priority = 2)]
-	Int(i64),
-	#[regex(r"-?[0-9]*\.[0-9]+", |t| t.slice().parse::<f64>().ok())]
+	#[regex(r"[0-9][0-9_]*", |t| parse_int(t.slice()), priority = 3)]
+	#[regex(r"0x[0-9a-fA-F_]+", |t| parse_int(t.slice()), priority = 3)]
+	#[regex(r"0o[0-7_]+", |t| parse_int(t.slice()), priority = 3)]
+	#[regex(r"0b[01_]+", |t| parse_int(t.slice()), priority = 3)]
+	Int(u64),
+	#[regex(r"([0-9][0-9_]*)?\.[0-9][0-9_]*([eE][+-]?[0-9]+)?", |t| parse_float(t.slice()), priority = 3)]
+	#[regex(r"[0-9][0-9_]*[eE][+-]?[0-9]+", |t| parse_float(t.slice()), priority = 3)]
 	Float(f64),
 	#[token("struct")]
 	Struct,
@@ -97,28 +146,246 @@ enum Token {
 	Ident(String),
 }
 
+/// Value of an integer literal: decimal, or hex, octal and binary with a
+/// `0x`, `0o` or `0b` prefix. Underscores between digits are ignored.
+pub(crate) fn parse_int(text: &str) -> Option<u64> {
+	let digits = text.replace('_', "");
+	let (radix, digits) = match digits.get(..2) {
+		Some("0x") => (16, &digits[2..]),
+		Some("0o") => (8, &digits[2..]),
+		Some("0b") => (2, &digits[2..]),
+		_ => (10, &digits[..]),
+	};
+	u64::from_str_radix(digits, radix).ok()
+}
+
+/// Value of a float literal like `1_000.5` or `2.5e-3`.
+pub(crate) fn parse_float(text: &str) -> Option<f64> {
+	text.replace('_', "").parse().ok()
+}
+
+/// Skips a block comment after its opening `/*`, up to the matching `*/`.
+fn block_comment(lex: &mut logos::Lexer<Token>) -> FilterResult<(), ()> {
+	match block_comment_end(lex.remainder()) {
+		Some(len) => {
+			lex.bump(len);
+			FilterResult::Skip
+		},
+		None => {
+			lex.bump(lex.remainder().len());
+			FilterResult::Error(())
+		},
+	}
+}
+
+/// Offset just past the `*/` closing a block comment, `rest` follows the
+/// opening `/*`. Block comments nest.
+fn block_comment_end(rest: &str) -> Option<usize> {
+	let bytes = rest.as_bytes();
+	let mut depth = 1;
+	let mut i = 0;
+
+	while i + 1 < bytes.len() {
+		match &bytes[i..i + 2] {
+			b"/*" => {
+				depth += 1;
+				i += 2;
+			},
+			b"*/" => {
+				depth -= 1;
+				i += 2;
+				if depth == 0 {
+					return Some(i);
+				}
+			},
+			_ => i += 1,
+		}
+	}
+
+	None
+}
+
+/// Byte ranges of the `//` and `/* */` comments in `text`, which holds
+/// only whitespace and comments like the gaps between tokens. A line
+/// comment ends before the line break.
+pub(crate) fn comment_spans(text: &str) -> Vec<Range<usize>> {
+	let mut spans = Vec::new();
+	let mut i = 0;
+
+	while let Some(n) = text[i..].find('/') {
+		let start = i + n;
+		let rest = &text[start..];
+		let end = if rest.starts_with("//") {
+			start + rest.find('\n').unwrap_or(rest.len())
+		} else if let Some(body) = rest.strip_prefix("/*") {
+			start + 2 + block_comment_end(body).unwrap_or(body.len())
+		} else {
+			i = start + 1;
+			continue;
+		};
+		spans.push(start..end);
+		i = end;
+	}
+
+	spans
+}
+
+/// Name of the function assignment, struct or enum definition a doc
+/// comment can be attached to.
+fn documented_name(node: &ASTNode) -> Option<String> {
+	match node {
+		ASTNode::Assign(Assign { left, right, op: None }) => match (left.as_ref(), right.as_ref()) {
+			(ASTNode::Ident(name), ASTNode::Fun(_)) => Some(name.clone()),
+			_ => None,
+		},
+		ASTNode::Decl(Decl { name, value, .. }) if matches!(**value, ASTNode::Fun(_)) => Some(name.clone()),
+		ASTNode::Export(item) => documented_name(item),
+		ASTNode::StructDef(def) => Some(def.name.clone()),
+		ASTNode::EnumDef(def) => Some(def.name.clone()),
+		_ => None,
+	}
+}
+
+/// `-node`, folded into the literal when negating a number.
+pub(crate) fn negate(node: ASTNode) -> ASTNode {
+	match node {
+		// `-9223372036854775808` is read as its own negation
+		ASTNode::Lit(Value::Int(i)) => ASTNode::Lit(Value::Int(i.wrapping_neg())),
+		ASTNode::Lit(Value::Float(f)) => ASTNode::Lit(Value::Float(-f)),
+		node => ASTNode::Neg(Box::new(node)),
+	}
+}
+
+/// Returns true when the input ends inside an unclosed `{`, `(` or `[`,
+/// an unterminated string or a block comment, so more lines are needed
+/// to parse it.
+pub fn is_incomplete(input: &str) -> bool {
+	let mut depth: i32 = 0;
+	let mut lexer = Token::lexer(input);
+
+	while let Some(token) = lexer.next() {
+		match token {
+			Ok(Token::OpenBrace) | Ok(Token::OpenParen) | Ok(Token::OpenBracket) => depth += 1,
+			Ok(Token::CloseBrace) | Ok(Token::CloseParen) | Ok(Token::CloseBracket) => depth -= 1,
+			Ok(_) => {},
+			Err(_) => {
+				if lexer.slice().starts_with('"') || lexer.slice().starts_with("/*") {
+					return true;
+				}
+			}
+		}
+	}
+
+	depth > 0
+}
+
+/// One based line number of a byte offset.
+pub fn line_of(input: &str, offset: usize) -> u32 {
+	input[..offset.min(input.len())].matches('\n').count() as u32 + 1
+}
+
+/// One based column, in characters, of a byte offset.
+pub fn col_of(input: &str, offset: usize) -> u32 {
+	let offset = offset.min(input.len());
+	let line_start = input[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+	input[line_start..offset].chars().count() as u32 + 1
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+	pub msg: String,
+	/// Byte range of the offending token, empty at the end of input.
+	pub span: Range<usize>,
+	/// One based line and column (in characters) of the span start.
+	pub line: u32,
+	pub col: u32,
+}
+
+impl ParseError {
+	fn new(input: &str, msg: String, span: Range<usize>) -> ParseError {
+		ParseError {
+			msg,
+			line: line_of(input, span.start),
+			col: col_of(input, span.start),
+			span,
+		}
+	}
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}:{}: {}", self.line, self.col, self.msg)
+	}
+}
+
+impl std::error::Error for ParseError {}
+
+/// The `///` lines right above a function assignment or struct
+/// definition, which document it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocComment {
+	/// Name of the function or struct.
+	pub name: String,
+	/// The lines without the slashes and one space after them.
+	pub text: String,
+	/// Byte range of the documented statement, `export` included.
+	pub span: Range<usize>,
+}
+
+type ParseResult<T> = Result<T, ParseError>;
+
 pub struct Parser {
 	tokens: Vec<(Token, Range<usize>)>,
 	i: usize,
 	loglevel: usize,
 	callstack: Vec<String>,
-	input: String
+	input: String,
+	stmts: Vec<Range<usize>>,
+	blocks: Vec<Range<usize>>,
+	nodes: Vec<(NodeKind, Range<usize>)>,
+	idents: Vec<(String, Range<usize>)>,
+	funs: Vec<Range<usize>>,
+	docs: Vec<DocComment>,
+	lex_error: Option<ParseError>,
+	/// Set by a `-` for the factor after it, see `int_lit`.
+	negating: bool
 }
 
 impl Parser {
 	pub fn new(input: &str) -> Parser {
-		let lexer = Token::lexer(input);
+		let mut tokens = Vec::new();
+		let mut lex_error = None;
 
-        let tokens = lexer.spanned()
-			.map(|(token, span)| (token.unwrap(), span.into()))
-			.collect();
+		for (token, span) in Token::lexer(input).spanned() {
+			match token {
+				Ok(token) => tokens.push((token, span)),
+				Err(_) => {
+					let text = &input[span.clone()];
+					let msg = match text.chars().next() {
+						_ if text.starts_with("/*") => "Unterminated block comment".to_string(),
+						Some(c) if c.is_ascii_digit() => "Integer literal out of range".to_string(),
+						_ => format!("Unexpected character {:?}", text),
+					};
+					lex_error = Some(ParseError::new(input, msg, span));
+					break;
+				}
+			}
+		}
 
 		Parser {
 			input: input.to_string(),
 			i: 0,
 			loglevel: 0,
 			callstack: Vec::new(),
-			tokens: tokens
+			tokens: tokens,
+			stmts: Vec::new(),
+			blocks: Vec::new(),
+			nodes: Vec::new(),
+			idents: Vec::new(),
+			funs: Vec::new(),
+			docs: Vec::new(),
+			lex_error,
+			negating: false
 		}
 	}
 
@@ -128,10 +395,103 @@ impl Parser {
 		self
 	}
 
+	/// Parses the input and panics with the error message when it is invalid.
 	pub fn parse(&mut self) -> Vec<ASTNode> {
+		match self.try_parse() {
+			Ok(nodes) => nodes,
+			Err(err) => panic!("{}", err),
+		}
+	}
+
+	pub fn try_parse(&mut self) -> Result<Vec<ASTNode>, ParseError> {
+		if let Some(err) = &self.lex_error {
+			return Err(err.clone());
+		}
+
 		self.parse_block()
 	}
 
+	/// Source lines of the parsed statements in the order they appear,
+	/// nested statements included. The compiler walks statements in the
+	/// same order which lets it build a line table for each block.
+	pub fn stmt_lines(&self) -> Vec<u32> {
+		self.stmts.iter().map(|span| line_of(&self.input, span.start)).collect()
+	}
+
+	/// Byte ranges of the parsed statements, in the same order as `stmt_lines`.
+	pub fn stmt_spans(&self) -> &[Range<usize>] {
+		&self.stmts
+	}
+
+	/// Byte ranges of the `{ ... }` bodies of functions, ifs and loops,
+	/// ordered by where they open.
+	pub fn block_spans(&self) -> &[Range<usize>] {
+		&self.blocks
+	}
+
+	/// Byte ranges of the `//` and `/* */` comments the lexer skipped,
+	/// without the line break.
+	pub fn comments(&self) -> Vec<Range<usize>> {
+		let mut comments = Vec::new();
+		let mut pos = 0;
+
+		let gaps = self.tokens.iter()
+			.map(|(_, span)| span.clone())
+			.chain(std::iter::once(self.input.len()..self.input.len()));
+
+		for span in gaps {
+			let gap = comment_spans(&self.input[pos..span.start]);
+			comments.extend(gap.into_iter().map(|c| pos + c.start..pos + c.end));
+			pos = span.end;
+		}
+
+		comments
+	}
+
+	/// Doc comments of the parsed functions and structs in source order.
+	pub fn doc_comments(&self) -> &[DocComment] {
+		&self.docs
+	}
+
+	/// Every identifier the parser consumed with its byte range, in source
+	/// order. Walking the AST depth first visits identifiers in the same
+	/// order, which is how tooling maps nodes back to the source.
+	pub fn ident_spans(&self) -> &[(String, Range<usize>)] {
+		&self.idents
+	}
+
+	/// Byte ranges of function literals, parameters included, ordered by
+	/// where they start.
+	pub fn fun_spans(&self) -> &[Range<usize>] {
+		&self.funs
+	}
+
+	/// Kinds and byte ranges of every token.
+	pub(crate) fn token_spans(&self) -> Vec<(TokenKind, Range<usize>)> {
+		self.tokens.iter().map(|(token, span)| {
+			let kind = match token {
+				Token::Ident(_) => TokenKind::Ident,
+				Token::Int(_) => TokenKind::Int,
+				Token::Float(_) => TokenKind::Float,
+				Token::String(_) => TokenKind::Str,
+				Token::For | Token::In | Token::If | Token::Else | Token::True | Token::False | Token::None
+				| Token::Struct | Token::IntDef | Token::FloatDef | Token::StringDef
+				| Token::Ret => TokenKind::Keyword,
+				_ => TokenKind::Punct,
+			};
+			(kind, span.clone())
+		}).collect()
+	}
+
+	/// Syntax nodes as token index ranges, children before their parents.
+	pub(crate) fn node_spans(&self) -> &[(NodeKind, Range<usize>)] {
+		&self.nodes
+	}
+
+	fn mark(&mut self, kind: NodeKind, start: usize) {
+		self.nodes.push((kind, start..self.i));
+	}
+
 	fn peek(&self, i: usize) -> Option<Token> {
 		if self.loglevel > 0 {
 			self.log(&format!("peek: {} {:?}", i, self.tokens.get(self.i + i)));
@@ -143,13 +503,23 @@ impl Parser {
 		}
 	}
 
-	fn peek_unwrap(&self, i: usize) -> Token {
+	/// Whether the token `i` ahead starts on the line the previous token
+	/// ends on. A `[` on a new line begins a list, not an index.
+	fn same_line(&self, i: usize) -> bool {
+		let end = match (self.i + i).checked_sub(1).and_then(|p| self.tokens.get(p)) {
+			Some((_, span)) => span.end,
+			None => return false,
+		};
+		match self.tokens.get(self.i + i) {
+			Some((_, span)) => !self.input[end..span.start].contains('\n'),
+			None => false,
+		}
+	}
+
+	fn peek_unwrap(&self, i: usize) -> ParseResult<Token> {
 		match self.peek(i) {
-			Some(token) => token,
-			None => {
-				println!("{}", self.curr_loc());
-				panic!("Unexpected end of input");
-			},
+			Some(token) => Ok(token),
+			None => self.error_at(self.i + i, "Unexpected end of input".to_string()),
 		}
 	}
 
@@ -168,23 +538,21 @@ impl Parser {
 		Some(token.clone())
 	}
 
-	fn expect_eat(&mut self, token: Token) {
+	fn expect_eat(&mut self, token: Token) -> ParseResult<()> {
 		if self.loglevel > 0 {
 			self.log(&format!("expect_eat: {:?}", token));
 		}
 
 		let next = match self.eat() {
 			Some(token) => token,
-			None => {
-				println!("{}", self.curr_loc());
-				panic!("Unexpected end of input");
-			},
+			None => return self.error_at(self.i, format!("Expected {:?} but got end of input", token)),
 		};
 
 		if next != token {
-			println!("{}", self.curr_loc());
-			panic!("Expected {:?} but got {:?}", token, next);
+			return self.error_at(self.i - 1, format!("Expected {:?} but got {:?}", token, next));
 		}
+
+		Ok(())
 	}
 
 	fn skip(&mut self, n: usize) {
@@ -195,26 +563,42 @@ impl Parser {
 		self.i += n;
 	}
 
-	fn expect_ident(&mut self) -> String {
+	fn expect_ident(&mut self) -> ParseResult<String> {
 		if self.loglevel > 0 {
 			self.log(&format!("expect_ident"));
 		}
 
 		let token = match self.eat() {
 			Some(token) => token,
-			None => {
-				println!("{}", self.curr_loc());
-				panic!("Unexpected end of input");
-			},
+			None => return self.error_at(self.i, "Expected ident but got end of input".to_string()),
 		};
 
 		match token {
-			Token::Ident(ident) => ident,
-			_ => {
-				println!("{}", self.curr_loc());
-				panic!("Expected ident but got {:?}", token)
+			Token::Ident(ident) => {
+				self.record_ident(self.i - 1, &ident);
+				Ok(ident)
 			},
+			_ => self.error_at(self.i - 1, format!("Expected ident but got {:?}", token)),
+		}
+	}
+
+	fn record_ident(&mut self, i: usize, name: &str) {
+		if let Some((_, span)) = self.tokens.get(i) {
+			self.idents.push((name.to_string(), span.clone()));
+		}
+	}
+
+	fn error_at<T>(&self, i: usize, msg: String) -> ParseResult<T> {
+		if self.loglevel > 0 {
+			self.log(&self.curr_loc());
 		}
+
+		let span = match self.tokens.get(i) {
+			Some((_, span)) => span.clone(),
+			None => self.input.len()..self.input.len(),
+		};
+
+		Err(ParseError::new(&self.input, msg, span))
 	}
 
 	fn log(&self, msg: &str) {
@@ -255,7 +639,7 @@ impl Parser {
 		text.to_string()
 	}
 
-	fn parse_block(&mut self) -> Vec<ASTNode> {
+	fn parse_block(&mut self) -> ParseResult<Vec<ASTNode>> {
 		if self.loglevel > 0 {
 			self.callstack.push("parse_block".to_string());
 		}
@@ -263,7 +647,7 @@ impl Parser {
 		let mut nodes = Vec::new();
 
 		loop {
-			match self.parse_item() {
+			match self.parse_stmt()? {
 				Some(n) => nodes.push(n),
 				None => break,
 			};
@@ -273,53 +657,141 @@ impl Parser {
 			self.callstack.pop();
 		}
 
-		nodes
+		Ok(nodes)
+	}
+
+	fn parse_stmt(&mut self) -> ParseResult<Option<ASTNode>> {
+		let inx = self.stmts.len();
+		// Docs of nested statements are recorded after the outer one
+		let doc_inx = self.docs.len();
+		let doc = match self.tokens.get(self.i) {
+			Some(_) => self.doc_before(self.i),
+			None => None,
+		};
+		if let Some((_, span)) = self.tokens.get(self.i) {
+			self.stmts.push(span.start..span.end);
+		}
+
+		let item = self.parse_item()?;
+
+		if let Some(span) = self.stmts.get_mut(inx) {
+			span.end = self.tokens[self.i - 1].1.end;
+		}
+
+		if let (Some(name), Some(text)) = (item.as_ref().and_then(documented_name), doc) {
+			let span = self.stmts[inx].clone();
+			self.docs.insert(doc_inx, DocComment { name, text, span });
+		}
+
+		Ok(item)
+	}
+
+	/// Text of the `///` lines right above the token at `first`. A blank
+	/// line or any other comment in between ends the doc comment.
+	fn doc_before(&self, first: usize) -> Option<String> {
+		let from = match first {
+			0 => 0,
+			_ => self.tokens[first - 1].1.end,
+		};
+		let gap = &self.input[from..self.tokens[first].1.start];
+
+		let mut lines = Vec::new();
+		let mut end = gap.len();
+
+		for span in comment_spans(gap).into_iter().rev() {
+			let text = &gap[span.clone()];
+			let own_line = first == 0 || gap[..span.start].contains('\n');
+			if !text.starts_with("///") || text.starts_with("////") || !own_line
+				|| gap[span.end..end].matches('\n').count() != 1 {
+				break;
+			}
+
+			let line = &text[3..];
+			lines.push(line.strip_prefix(' ').unwrap_or(line).trim_end());
+			end = span.start;
+		}
+
+		if lines.is_empty() {
+			return None;
+		}
+
+		lines.reverse();
+		Some(lines.join("\n"))
+	}
+
+	/// Parses statements up to and including the closing brace, the
+	/// opening one has been eaten.
+	fn parse_body(&mut self) -> ParseResult<Vec<ASTNode>> {
+		let inx = self.blocks.len();
+		let start = self.tokens[self.i - 1].1.start;
+		self.blocks.push(start..start);
+
+		let mut body = Vec::new();
+
+		loop {
+			match self.peek(0) {
+				Some(Token::CloseBrace) => {
+					self.blocks[inx].end = self.tokens[self.i].1.end;
+					self.skip(1);
+					break;
+				},
+				Some(_) => body.extend(self.parse_stmt()?),
+				None => return self.error_at(self.i, "Expected CloseBrace but got end of input".to_string()),
+			}
+		}
+
+		Ok(body)
 	}
 
-	fn parse_array(&mut self) -> ASTNode {
+	fn parse_array(&mut self) -> ParseResult<ASTNode> {
 		if self.loglevel > 0 {
 			self.callstack.push("parse_item".to_string());
 		}
 
+		let start = self.i;
 		self.skip(1);
 		let mut items = Vec::new();
 
-		while let Some(token) = self.peek(0) {
-			match token {
-				Token::CloseBracket => {
+		loop {
+			match self.peek(0) {
+				Some(Token::CloseBracket) => {
 					self.skip(1);
 					break;
 				},
-				Token::Comma => {
+				Some(Token::Comma) => {
 					self.skip(1);
 				},
-				_ => {
-					items.push(self.parse_item().unwrap());
-				}
+				Some(_) => {
+					items.extend(self.parse_item()?);
+				},
+				None => return self.error_at(self.i, "Expected CloseBracket but got end of input".to_string()),
 			}
 		}
 
 		let left = ASTNode::Array(Array { items });
+		self.mark(NodeKind::Array, start);
 
 		match self.peek(0) {
-			Some(Token::Dot) => self.parse_prob_access(left),
-			_ => left
+			Some(Token::Dot | Token::QuestionDot) => self.parse_prob_access(left, start),
+			Some(Token::OpenBracket) if self.same_line(0) => self.parse_index(left, start),
+			_ => Ok(left)
 		}
 	}
 
-	fn parse_item(&mut self) -> Option<ASTNode> {
+	fn parse_item(&mut self) -> ParseResult<Option<ASTNode>> {
 		if self.loglevel > 0 {
 			self.callstack.push("parse_item".to_string());
 		}
 
 		let token = match self.peek(0) {
 			Some(token) => token.clone(),
-			None => return None,
+			None => return Ok(None),
 		};
+		let start = self.i;
 
 		let ret = match token {
 			Token::OpenBrace => {
-				Some(self.parse_obj_props(None))
+				Some(self.parse_obj_props(None, start)?)
 			}
 			Token::Ident(ident) => {
 				if self.loglevel > 0 {
@@ -328,39 +800,62 @@ impl Parser {
 
 				match self.peek(1) {
 					Some(Token::Assign) => {
+						self.record_ident(self.i, &ident);
 						self.skip(2);
 
+						let right = match self.parse_item()? {
+							Some(right) => right,
+							None => return self.error_at(self.i, "Unexpected end of input".to_string()),
+						};
+
 						let a = Assign { 
 							left: Box::new(ASTNode::Ident(ident.clone())), 
-							right: Box::new(self.parse_item().unwrap())
+							right: Box::new(right),
+							op: None,
 						};
+						self.mark(NodeKind::Assign, start);
 
 
 						Some(ASTNode::Assign(a))
 					},
-					Some(Token::Ident(name)) => {
-						self.skip(2);
-						Some(
-							ASTNode::Var(
-								Var {
-									name: name.to_string(),
-									typ: ident.to_string(),
-								}
-							)
-						)
+					Some(Token::Ident(_)) if ident == "import" => {
+						Some(self.parse_import()?)
+					},
+					Some(Token::Ident(_)) if ident == "use" && self.peek(2) == Some(Token::DoubleColon) => {
+						Some(self.parse_use()?)
+					},
+					Some(Token::Ident(_)) if ident == "mod" && self.peek(2) == Some(Token::OpenBrace) => {
+						Some(self.parse_mod()?)
+					},
+					Some(Token::Ident(_)) if (ident == "let" || ident == "const") && self.peek(2) == Some(Token::Assign) => {
+						Some(self.parse_decl()?)
+					},
+					Some(_) if ident == "export" => {
+						Some(self.parse_export()?)
+					},
+					Some(Token::Ident(_)) if ident == "enum" && self.peek(2) == Some(Token::OpenBrace) => {
+						Some(self.parse_enum()?)
+					},
+					Some(Token::Ident(_) | Token::Int(_) | Token::Float(_) | Token::String(_) | Token::True | Token::False)
+						if ident == "match" => {
+						Some(self.parse_match()?)
 					},
 					Some(Token::OpenBrace) => {
-						Some(self.parse_obj_ins())
+						Some(self.parse_obj_ins()?)
 					},
 					Some(Token::Arrow) => {
-						Some(self.parse_fun())
+						Some(self.parse_fun()?)
+					},
+					Some(Token::String(_)) if ident == "test" => {
+						Some(self.parse_test()?)
 					},
 					_ => {
-						Some(self.parse_expr())
+						let left = self.parse_expr()?;
+						Some(self.parse_assign(left, start)?)
 					}
 				}
 			}
-			Token::OpenBracket => Some(self.parse_array()),
+			Token::OpenBracket => Some(self.parse_array()?),
 			Token::OpenParen => {
 				// In here we check if future tokens contain an close paren and an arrow
 				// If so, we parse a function, otherwise we parse an expression
@@ -377,78 +872,503 @@ impl Parser {
 		
 				Some(match self.peek(i) {
 					Some(Token::Arrow) => {
-						self.parse_fun()
+						self.parse_fun()?
 					}
-					_ => self.parse_expr()
+					_ => self.parse_expr()?
 				})
 			}
 			Token::Ret => {
 				self.skip(1);
 				
+				let value = self.parse_item()?;
+				self.mark(NodeKind::Ret, start);
+
 				Some(ASTNode::Ret(Ret {
-					value: Box::new(self.parse_item()),
+					value: Box::new(value),
 				}))
 			}
 			Token::If => {
-				Some(self.parse_if())
+				Some(self.parse_if()?)
 			}
 			Token::For => {
-				Some(self.parse_for())
+				Some(self.parse_for()?)
+			}
+			Token::Struct => {
+				Some(self.parse_struct()?)
 			}
-			_ => Some(self.parse_expr())
+			_ => Some(self.parse_expr()?)
 		};
 
 		if self.loglevel > 0 {
 			self.callstack.pop();
 		}
 
-		ret
+		Ok(ret)
 	}
 
-	fn parse_for_it(&mut self) -> ASTNode {
-		let token = match self.peek(0) {
-			Some(token) => token.clone(),
-			None => {
-				println!("{}", self.curr_loc());
-				panic!("Unexpected end of input");
+	/// Reads `= value` or a compound `+= value` after an expression which
+	/// started a statement, and gives the expression back when neither follows.
+	fn parse_assign(&mut self, left: ASTNode, start: usize) -> ParseResult<ASTNode> {
+		let op = match self.peek(0) {
+			Some(Token::Assign) => None,
+			Some(Token::PlusAssign) => Some(Op::Plus),
+			Some(Token::MinusAssign) => Some(Op::Minus),
+			Some(Token::MulAssign) => Some(Op::Mul),
+			Some(Token::DivAssign) => Some(Op::Div),
+			_ => return Ok(left),
+		};
+
+		if !matches!(left, ASTNode::Ident(_) | ASTNode::ProbAccess(_) | ASTNode::Index(_)) {
+			return self.error_at(self.i, "Can only assign to a name, property or index".to_string());
+		}
+		if matches!(&left, ASTNode::ProbAccess(p) if p.optional) {
+			return self.error_at(self.i, "Can not assign through ?.".to_string());
+		}
+		self.skip(1);
+
+		let right = match self.parse_item()? {
+			Some(right) => right,
+			None => return self.error_at(self.i, "Unexpected end of input".to_string()),
+		};
+		self.mark(NodeKind::Assign, start);
+
+		Ok(ASTNode::Assign(Assign {
+			left: Box::new(left),
+			right: Box::new(right),
+			op,
+		}))
+	}
+
+	/// `let name = value` or `const name = value`.
+	fn parse_decl(&mut self) -> ParseResult<ASTNode> {
+		let start = self.i;
+		let constant = self.peek(0) == Some(Token::Ident("const".to_string()));
+		self.skip(1);
+		let name = self.expect_ident()?;
+		self.expect_eat(Token::Assign)?;
+
+		let value = match self.parse_item()? {
+			Some(value) => value,
+			None => return self.error_at(self.i, "Unexpected end of input".to_string()),
+		};
+		self.mark(NodeKind::Decl, start);
+
+		Ok(ASTNode::Decl(Decl {
+			name,
+			value: Box::new(value),
+			constant,
+		}))
+	}
+
+	fn parse_struct(&mut self) -> ParseResult<ASTNode> {
+		let start = self.i;
+		self.skip(1);
+		let name = self.expect_ident()?;
+		self.expect_eat(Token::OpenBrace)?;
+		let fields = self.parse_fields()?;
+
+		self.mark(NodeKind::Struct, start);
+		Ok(ASTNode::StructDef(StructDef { name, fields }))
+	}
+
+	/// `name: Type` fields up to and including the closing brace, the
+	/// opening one has been eaten. `name?: Type` is short for `name: Type?`.
+	fn parse_fields(&mut self) -> ParseResult<Vec<TypeField>> {
+		let mut fields = Vec::new();
+
+		loop {
+			match self.peek(0) {
+				Some(Token::CloseBrace) => {
+					self.skip(1);
+					break;
+				}
+				Some(Token::Comma) => {
+					self.skip(1);
+				}
+				_ => {
+					let field = self.expect_ident()?;
+					let optional = self.peek(0) == Some(Token::Question);
+					if optional {
+						self.skip(1);
+					}
+					self.expect_eat(Token::Colon)?;
+					let typ = match optional {
+						true => self.parse_type()?.optional(),
+						false => self.parse_type()?,
+					};
+					fields.push(TypeField { name: field, typ });
+				}
+			}
+		}
+
+		Ok(fields)
+	}
+
+	/// A type name or `[Type]`, optional with a `?` after it.
+	fn parse_type(&mut self) -> ParseResult<VarType> {
+		let typ = match self.eat() {
+			Some(Token::IntDef) => VarType::Int,
+			Some(Token::FloatDef) => VarType::Float,
+			Some(Token::StringDef) => VarType::String,
+			Some(Token::Ident(idt)) => {
+				self.record_ident(self.i - 1, &idt);
+				VarType::Ident(idt)
+			},
+			Some(Token::OpenBracket) => {
+				let item = self.parse_type()?;
+				self.expect_eat(Token::CloseBracket)?;
+				VarType::List(Box::new(item))
 			},
+			Some(token) => return self.error_at(self.i - 1, format!("Expected type but got {:?}", token)),
+			None => return self.error_at(self.i, "Expected type but got end of input".to_string()),
 		};
 
+		match self.peek(0) {
+			Some(Token::Question) => {
+				self.skip(1);
+				Ok(typ.optional())
+			},
+			_ => Ok(typ),
+		}
+	}
+
+	/// `enum Name { Unit, Tuple(A, B), Struct { a: A } }`, the variants
+	/// separated by commas or new lines.
+	fn parse_enum(&mut self) -> ParseResult<ASTNode> {
+		let start = self.i;
+		self.skip(1);
+		let name = self.expect_ident()?;
+		self.expect_eat(Token::OpenBrace)?;
+
+		let mut variants = Vec::new();
+
+		loop {
+			match self.peek(0) {
+				Some(Token::CloseBrace) => {
+					self.skip(1);
+					break;
+				},
+				Some(Token::Comma) => self.skip(1),
+				_ => {
+					let name = self.expect_ident()?;
+					let shape = match self.peek(0) {
+						Some(Token::OpenParen) => {
+							self.skip(1);
+							let mut types = Vec::new();
+							loop {
+								match self.peek(0) {
+									Some(Token::CloseParen) => {
+										self.skip(1);
+										break;
+									},
+									Some(Token::Comma) => self.skip(1),
+									_ => types.push(self.parse_type()?),
+								}
+							}
+							VariantShape::Tuple(types)
+						},
+						Some(Token::OpenBrace) => {
+							self.skip(1);
+							VariantShape::Struct(self.parse_fields()?)
+						},
+						_ => VariantShape::Unit,
+					};
+					variants.push(VariantDef { name, shape });
+				}
+			}
+		}
+
+		self.mark(NodeKind::Enum, start);
+		Ok(ASTNode::EnumDef(EnumDef { name, variants }))
+	}
+
+	/// `Name::Variant` with its fields, the enum name has been eaten. A
+	/// brace only starts fields when a `name:` follows, so the body of an
+	/// `if` after a unit variant is not read as its fields.
+	fn parse_variant(&mut self, enum_name: String, start: usize) -> ParseResult<ASTNode> {
+		self.skip(1);
+		let name = self.expect_ident()?;
+
+		let args = match self.peek(0) {
+			Some(Token::OpenParen) => {
+				self.skip(1);
+				let mut args = Vec::new();
+				loop {
+					match self.peek(0) {
+						Some(Token::CloseParen) => {
+							self.skip(1);
+							break;
+						},
+						Some(Token::Comma) => self.skip(1),
+						Some(_) => args.extend(self.parse_item()?),
+						None => return self.error_at(self.i, "Expected CloseParen but got end of input".to_string()),
+					}
+				}
+				VariantArgs::Tuple(args)
+			},
+			Some(Token::OpenBrace) if matches!(self.peek(1), Some(Token::Ident(_))) && self.peek(2) == Some(Token::Colon) => {
+				self.skip(1);
+				let mut props = Vec::new();
+				loop {
+					match self.peek(0) {
+						Some(Token::CloseBrace) => {
+							self.skip(1);
+							break;
+						},
+						Some(Token::Comma) => self.skip(1),
+						Some(_) => {
+							let name = self.expect_ident()?;
+							self.expect_eat(Token::Colon)?;
+							let value = match self.parse_item()? {
+								Some(value) => value,
+								None => return self.error_at(self.i, "Unexpected end of input".to_string()),
+							};
+							props.push(Property { name, value: Box::new(value) });
+						},
+						None => return self.error_at(self.i, "Expected CloseBrace but got end of input".to_string()),
+					}
+				}
+				VariantArgs::Struct(props)
+			},
+			_ => VariantArgs::Unit,
+		};
+
+		self.mark(NodeKind::Variant, start);
+		Ok(ASTNode::Variant(VariantIns { enum_name, name, args }))
+	}
+
+	/// `match value { pattern => body ... }`. A body is a braced block or a
+	/// single statement.
+	fn parse_match(&mut self) -> ParseResult<ASTNode> {
+		let start = self.i;
+		self.skip(1);
+		let value = self.parse_expr()?;
+		self.expect_eat(Token::OpenBrace)?;
+
+		let mut arms = Vec::new();
+
+		loop {
+			match self.peek(0) {
+				Some(Token::CloseBrace) => {
+					self.skip(1);
+					break;
+				},
+				Some(Token::Comma) => self.skip(1),
+				Some(_) => {
+					let arm_start = self.i;
+					let pattern = self.parse_pattern()?;
+					self.expect_eat(Token::Arrow)?;
+					let body = match self.peek(0) {
+						Some(Token::OpenBrace) => {
+							self.skip(1);
+							self.parse_body()?
+						},
+						Some(_) => self.parse_stmt()?.into_iter().collect(),
+						None => return self.error_at(self.i, "Unexpected end of input".to_string()),
+					};
+					self.mark(NodeKind::MatchArm, arm_start);
+					arms.push(MatchArm { pattern, body });
+				},
+				None => return self.error_at(self.i, "Expected CloseBrace but got end of input".to_string()),
+			}
+		}
+
+		self.mark(NodeKind::Match, start);
+		Ok(ASTNode::Match(Match { value: Box::new(value), arms }))
+	}
+
+	fn parse_pattern(&mut self) -> ParseResult<Pattern> {
+		let start = self.i;
+
+		let pattern = match self.eat() {
+			Some(Token::Ident(name)) if name == "_" => Pattern::Wildcard,
+			Some(Token::Ident(enum_name)) if self.peek(0) == Some(Token::DoubleColon) => {
+				self.record_ident(self.i - 1, &enum_name);
+				self.skip(1);
+				let name = self.expect_ident()?;
+
+				let fields = match self.peek(0) {
+					Some(Token::OpenParen) => {
+						self.skip(1);
+						let mut items = Vec::new();
+						loop {
+							match self.peek(0) {
+								Some(Token::CloseParen) => {
+									self.skip(1);
+									break;
+								},
+								Some(Token::Comma) => self.skip(1),
+								_ => items.push(self.parse_pattern()?),
+							}
+						}
+						PatternFields::Tuple(items)
+					},
+					Some(Token::OpenBrace) => {
+						self.skip(1);
+						let mut fields = Vec::new();
+						loop {
+							match self.peek(0) {
+								Some(Token::CloseBrace) => {
+									self.skip(1);
+									break;
+								},
+								Some(Token::Comma) => self.skip(1),
+								_ => {
+									let field = self.expect_ident()?;
+									let pattern = match self.peek(0) {
+										Some(Token::Colon) => {
+											self.skip(1);
+											self.parse_pattern()?
+										},
+										_ => Pattern::Bind(field.clone()),
+									};
+									fields.push((field, pattern));
+								}
+							}
+						}
+						PatternFields::Struct(fields)
+					},
+					_ => PatternFields::Unit,
+				};
+
+				Pattern::Variant { enum_name, name, fields }
+			},
+			Some(Token::Ident(name)) => {
+				self.record_ident(self.i - 1, &name);
+				Pattern::Bind(name)
+			},
+			Some(Token::Int(i)) => Pattern::Lit(Value::Int(self.int_lit(i, false)?)),
+			Some(Token::Float(f)) => Pattern::Lit(Value::Float(f)),
+			Some(Token::String(s)) => Pattern::Lit(Value::Str(s)),
+			Some(Token::True) => Pattern::Lit(Value::Bool(true)),
+			Some(Token::False) => Pattern::Lit(Value::Bool(false)),
+			Some(Token::None) => Pattern::Lit(Value::None),
+			Some(Token::Minus) => match self.eat() {
+				Some(Token::Int(i)) => Pattern::Lit(Value::Int(self.int_lit(i, true)?.wrapping_neg())),
+				Some(Token::Float(f)) => Pattern::Lit(Value::Float(-f)),
+				Some(token) => return self.error_at(self.i - 1, format!("Expected number but got {:?}", token)),
+				None => return self.error_at(self.i, "Expected number but got end of input".to_string()),
+			},
+			Some(token) => return self.error_at(self.i - 1, format!("Expected pattern but got {:?}", token)),
+			None => return self.error_at(self.i, "Expected pattern but got end of input".to_string()),
+		};
+
+		self.mark(NodeKind::Pattern, start);
+		Ok(pattern)
+	}
+
+	fn parse_test(&mut self) -> ParseResult<ASTNode> {
+		let start = self.i;
+		self.skip(1);
+		let name = match self.eat() {
+			Some(Token::String(name)) => name,
+			_ => return self.error_at(self.i - 1, "Expected test name".to_string()),
+		};
+		self.expect_eat(Token::OpenBrace)?;
+		let body = self.parse_body()?;
+
+		self.mark(NodeKind::Test, start);
+		Ok(ASTNode::Test(TestDef { name, body }))
+	}
+
+	fn parse_import(&mut self) -> ParseResult<ASTNode> {
+		let start = self.i;
+		self.skip(1);
+		let mut path = vec![self.module_name()?];
+		while self.peek(0) == Some(Token::Dot) {
+			self.skip(1);
+			path.push(self.module_name()?);
+		}
+		let name = path[path.len() - 1].clone();
+		self.record_ident(self.i - 1, &name);
+
+		self.mark(NodeKind::Import, start);
+		Ok(ASTNode::Import(Import { path }))
+	}
+
+	fn parse_use(&mut self) -> ParseResult<ASTNode> {
+		let start = self.i;
+		self.skip(1);
+		let module = self.module_name()?;
+		self.record_ident(self.i - 1, &module);
+		let mut path = vec![module];
+		while self.peek(0) == Some(Token::DoubleColon) {
+			self.skip(1);
+			path.push(self.module_name()?);
+		}
+		let name = path[path.len() - 1].clone();
+		self.record_ident(self.i - 1, &name);
+
+		self.mark(NodeKind::Use, start);
+		Ok(ASTNode::Use(Use { path }))
+	}
+
+	fn parse_mod(&mut self) -> ParseResult<ASTNode> {
+		let start = self.i;
+		self.skip(1);
+		let name = self.expect_ident()?;
+		self.expect_eat(Token::OpenBrace)?;
+		let body = self.parse_body()?;
+
+		self.mark(NodeKind::Module, start);
+		Ok(ASTNode::Module(ModuleDef { name, body }))
+	}
+
+	fn parse_export(&mut self) -> ParseResult<ASTNode> {
+		let start = self.i;
+		self.skip(1);
+		let item = match self.parse_item()? {
+			Some(item @ (ASTNode::Assign(_) | ASTNode::Decl(_) | ASTNode::Module(_) | ASTNode::Import(_))) => item,
+			Some(item @ (ASTNode::StructDef(_) | ASTNode::EnumDef(_))) => item,
+			_ => return self.error_at(start + 1, "Only assignments, declarations, modules, imports, structs and enums can be exported".to_string()),
+		};
+
+		self.mark(NodeKind::Export, start);
+		Ok(ASTNode::Export(Box::new(item)))
+	}
+
+	/// Eats a segment of a module path without recording it.
+	fn module_name(&mut self) -> ParseResult<String> {
+		match self.eat() {
+			Some(Token::Ident(name)) => Ok(name),
+			Some(token) => self.error_at(self.i - 1, format!("Expected module name but got {:?}", token)),
+			None => self.error_at(self.i, "Expected module name but got end of input".to_string()),
+		}
+	}
+
+	fn parse_for_it(&mut self) -> ParseResult<ASTNode> {
+		let token = self.peek_unwrap(0)?;
+
 		match token {
 			Token::OpenBracket => self.parse_array(),
 			Token::Ident(idt) => {
+				self.record_ident(self.i, &idt);
 				self.skip(1);
-				ASTNode::Ident(idt)
+				self.mark(NodeKind::Ident, self.i - 1);
+				Ok(ASTNode::Ident(idt))
 			}
 			_ => self.parse_expr()
 		}
 	}
 
-	fn parse_for(&mut self) -> ASTNode {
+	fn parse_for(&mut self) -> ParseResult<ASTNode> {
+		let start = self.i;
 		self.skip(1);
 
 		match self.peek(0) {
 			Some(Token::Ident(idt)) => {
+				self.record_ident(self.i, &idt);
 				self.skip(1);
 				match self.peek(0) {
 					Some(Token::In) => {
 						self.skip(1);
-						let it = self.parse_for_it();
-						self.expect_eat(Token::OpenBrace);
+						let it = self.parse_for_it()?;
+						self.expect_eat(Token::OpenBrace)?;
 
-						let mut body = Vec::new();
-
-						while let Some(token) = self.peek(0) {
-							match token {
-								Token::CloseBrace => {
-									self.skip(1);
-									break;
-								},
-								_ => body.push(self.parse_item().unwrap()),
-							}
-						}
+						let body = self.parse_body()?;
+						self.mark(NodeKind::For, start);
 
-						ASTNode::For(
+						Ok(ASTNode::For(
 							For {
 								cond: ForCond::FromIt {
 									ident: idt.to_string(),
@@ -456,167 +1376,170 @@ impl Parser {
 								},
 								body: body,
 							}
-						)
+						))
 					},
-					_ => {
-						println!("{}", self.curr_loc());
-						panic!("Expected in but got {:?}", self.peek(0));
+					next => {
+						self.error_at(self.i, format!("Expected in but got {:?}", next))
 					}
 				}
 			},
-			Some(Token::OpenBrace) => {
+			Some(Token::OpenBracket) => {
 				self.skip(1);
-				let mut body = Vec::new();
-
-				while let Some(token) = self.peek(0) {
-					match token {
-						Token::CloseBrace => {
+				let mut idents = vec![];
+				loop {
+					match self.peek(0) {
+						Some(Token::CloseBracket) => {
 							self.skip(1);
 							break;
 						},
-						_ => body.push(self.parse_item().unwrap()),
+						Some(Token::Comma) => self.skip(1),
+						_ => idents.push(self.expect_ident()?),
 					}
 				}
+				self.expect_eat(Token::In)?;
+				let it = self.parse_for_it()?;
+				self.expect_eat(Token::OpenBrace)?;
+
+				let body = self.parse_body()?;
+				self.mark(NodeKind::For, start);
+
+				Ok(ASTNode::For(
+					For {
+						cond: ForCond::Unpack {
+							idents,
+							it: Box::new(it),
+						},
+						body,
+					}
+				))
+			},
+			Some(Token::OpenBrace) => {
+				self.skip(1);
+				let body = self.parse_body()?;
+				self.mark(NodeKind::For, start);
 
-				ASTNode::For(
+				Ok(ASTNode::For(
 					For {
 						cond: ForCond::None,
 						body: body,
 					}
-				)
+				))
 			},
-			_ => {
-				println!("{}", self.curr_loc());
-				panic!("Expected open brace got {:?}", self.peek(0));
+			next => {
+				self.error_at(self.i, format!("Expected open brace got {:?}", next))
 			}
 		}
 	}
 
-	fn parse_if(&mut self) -> ASTNode {
+	fn parse_if(&mut self) -> ParseResult<ASTNode> {
+		let start = self.i;
 		self.skip(1);
-		let cond = self.parse_expr();
-		self.expect_eat(Token::OpenBrace);
-
-		let mut body = Vec::new();
+		let cond = self.parse_expr()?;
+		self.expect_eat(Token::OpenBrace)?;
 
-		while let Some(token) = self.peek(0) {
-			match token {
-				Token::CloseBrace => {
-					self.skip(1);
-					break;
-				},
-				_ => body.push(self.parse_item().unwrap()),
-			}
-		}
+		let body = self.parse_body()?;
+		let mut els = None;
 
 		if let Some(Token::Else) = self.peek(0) {
 			self.skip(1);
 
 			match self.peek(0) {
 				Some(Token::If) => {
-					body.push(self.parse_if());
+					els = Some(self.parse_stmt()?.into_iter().collect::<Vec<_>>());
 				},
 				Some(Token::OpenBrace) => {
 					self.skip(1);
-					while let Some(token) = self.peek(0) {
-						match token {
-							Token::CloseBrace => {
-								self.skip(1);
-								break;
-							},
-							_ => body.push(self.parse_item().unwrap()),
-						}
-					}
+					els = Some(self.parse_body()?);
 				},
-				_ => {
-					println!("{}", self.curr_loc());
-					panic!("Expected if or open brace got {:?}", self.peek(0));
+				next => {
+					return self.error_at(self.i, format!("Expected if or open brace got {:?}", next));
 				}
 			}
 		}
 
-		ASTNode::If(
+		self.mark(NodeKind::If, start);
+
+		Ok(ASTNode::If(
 			If {
 				cond: Box::new(cond),
 				body: body,
-				els: None,
+				// An empty else is the same as none
+				els: els.filter(|e| !e.is_empty()),
 			}
-		)
+		))
 	}
 
-	fn parse_fun(&mut self) -> ASTNode {
+	fn parse_fun(&mut self) -> ParseResult<ASTNode> {
 		if self.loglevel > 0 {
 			self.callstack.push("parse_fun".to_string());
 		}
 
 		let next = match self.peek(0) {
 			Some(token) => token,
-			None => {
-				println!("{}", self.curr_loc());
-				panic!("Expected token but got None")
-			},
+			None => return self.error_at(self.i, "Expected token but got None".to_string()),
 		};
 
+		let start_inx = self.i;
+		let start = self.tokens[self.i].1.start;
+		let fun_inx = self.funs.len();
+		self.funs.push(start..start);
+
 		let mut params = Vec::new();
 
 		match next {
 			Token::OpenParen => {
 				self.skip(1);
 
-				while let Some(token) = self.peek(0) {
-					match token {
-						Token::CloseParen => {
+				loop {
+					match self.peek(0) {
+						Some(Token::CloseParen) => {
 							self.skip(1);
 							break;
 						},
-						Token::Comma => {
+						Some(Token::Comma) => {
 							self.skip(1);
 						},
-						Token::Ident(name) => {
+						Some(Token::Ident(name)) => {
+							self.record_ident(self.i, &name);
 							self.skip(1);
 							params.push(Param { name: name });
 						},
-						_ => panic!("Expected ident or ) but got {:?}", self.peek(0)),
+						next => return self.error_at(self.i, format!("Expected ident or ) but got {:?}", next)),
 					}
 				}
 				
 			}
 			Token::Ident(idt) => {
+				self.record_ident(self.i, &idt);
 				self.skip(1);
 				params.push(Param { name: idt });
 			}
 			_ => {
-				println!("{}", self.curr_loc());
-				panic!("Expected ( or ident but got {:?}", next);
+				return self.error_at(self.i, format!("Expected ( or ident but got {:?}", next));
 			}
 		}
 
-		self.expect_eat(Token::Arrow);
+		self.expect_eat(Token::Arrow)?;
 
-		let next = self.peek_unwrap(0);
+		let next = self.peek_unwrap(0)?;
 
 		let mut body = Vec::new();
 
 		match next {
 			Token::OpenBrace => {
 				self.skip(1);
-				while let Some(token) = self.peek(0) {
-					match token {
-						Token::CloseBrace => {
-							self.skip(1);
-							break;
-						},
-						_ => body.push(self.parse_item().unwrap()),
-					}
-				}
+				body = self.parse_body()?;
 			},
 			_ => {
-				body.push(self.parse_item().unwrap());
+				body.extend(self.parse_stmt()?);
 			}
 		}
 
 		// self.expect_eat(Token::OpenBrace);
 
+		self.funs[fun_inx].end = self.tokens[self.i - 1].1.end;
+		self.mark(NodeKind::Fun, start_inx);
+
 		let f = Fun {
 			params: params,
 			body: body,
@@ -626,22 +1549,23 @@ impl Parser {
 			self.callstack.pop();
 		}
 
-		ASTNode::Fun(f)
+		Ok(ASTNode::Fun(f))
 	}
 
-	fn parse_obj_ins(&mut self) -> ASTNode {
-		let name = self.expect_ident();
+	fn parse_obj_ins(&mut self) -> ParseResult<ASTNode> {
+		let start = self.i;
+		let name = self.expect_ident()?;
 
 		if self.loglevel > 0 {
 			self.callstack.push("parse_obj_ins".to_string());
 			self.log(&format!("name: {}", name));
 		}
 
-		self.parse_obj_props(Some(name))
+		self.parse_obj_props(Some(name), start)
 	}
 
-	fn parse_obj_props(&mut self, name: Option<String>) -> ASTNode {
-		self.expect_eat(Token::OpenBrace);
+	fn parse_obj_props(&mut self, name: Option<String>, start: usize) -> ParseResult<ASTNode> {
+		self.expect_eat(Token::OpenBrace)?;
 
 		let mut props = Vec::new();
 
@@ -654,13 +1578,32 @@ impl Parser {
 				Some(Token::Comma) => {
 					self.skip(1);
 				}
+				Some(Token::Spread) => {
+					let spread_start = self.i;
+					self.skip(1);
+					let value = match self.parse_item()? {
+						Some(value) => value,
+						None => return self.error_at(self.i, "Unexpected end of input".to_string()),
+					};
+					self.mark(NodeKind::Spread, spread_start);
+
+					props.push(Property {
+						name: String::new(),
+						value: Box::new(ASTNode::Spread(Box::new(value)))
+					});
+				}
 				_ => {
-					let prob_name = self.expect_ident();
-					self.expect_eat(Token::Colon);
+					let prob_name = self.expect_ident()?;
+					self.expect_eat(Token::Colon)?;
+
+					let value = match self.parse_item()? {
+						Some(value) => value,
+						None => return self.error_at(self.i, "Unexpected end of input".to_string()),
+					};
 
 					let prob = Property {
 						name: prob_name,
-						value: Box::new(self.parse_item().unwrap())
+						value: Box::new(value)
 					};
 
 					props.push(prob);
@@ -672,16 +1615,18 @@ impl Parser {
 			name: name,
 			props,
 		};
+		self.mark(NodeKind::ObjIns, start);
 
-		ASTNode::ObjIns(b)
+		Ok(ASTNode::ObjIns(b))
 	}
 
-	fn parse_expr(&mut self) -> ASTNode {
+	fn parse_expr(&mut self) -> ParseResult<ASTNode> {
 		if self.loglevel > 0 {
 			self.callstack.push("parse_expr".to_string());
 		}
 
-		let left = self.parse_term();
+		let start = self.i;
+		let left = self.parse_term()?;
 
 		let next = match self.peek(0) {
 			Some(t) => t,
@@ -689,10 +1634,12 @@ impl Parser {
 				if self.loglevel > 0 {
 					self.callstack.pop();
 				}
-				return left;
+				return Ok(left);
 			}
 		};
 
+		// A `-` starting a line negates the next statement instead.
+		let continues = next != Token::Minus || self.same_line(0);
 		let ret = match next {
 			Token::Plus => {
 				if self.loglevel > 0 {
@@ -703,11 +1650,11 @@ impl Parser {
 					BinOp { 
 						left: Box::new(left), 
 						op: Op::Plus,
-						right: Box::new(self.parse_expr()) 
+						right: Box::new(self.parse_expr()?) 
 					}
 				)
 			},
-			Token::Minus => {
+			Token::Minus if continues => {
 				if self.loglevel > 0 {
 					self.log("Minus");
 				}
@@ -716,19 +1663,29 @@ impl Parser {
 					BinOp { 
 						left: Box::new(left), 
 						op: Op::Minus,
-						right: Box::new(self.parse_expr()) 
+						right: Box::new(self.parse_expr()?) 
 					}
 				)
 			},
-			Token::OpenParen => self.parse_call(left),
-			Token::Dot => self.parse_prob_access(left),
-			Token::Eq => {
+			Token::Eq | Token::Lt | Token::Gt | Token::DoubleQuestion => {
 				self.skip(1);
+				let op = match next {
+					Token::Lt => Op::Lt,
+					Token::Gt => Op::Gt,
+					Token::DoubleQuestion => Op::Coalesce,
+					_ => Op::Eq,
+				};
+				// `x ?? []` and `x ?? {}` give a list or object by default
+				let right = match self.peek(0) {
+					Some(Token::OpenBracket) if op == Op::Coalesce => self.parse_array()?,
+					Some(Token::OpenBrace) if op == Op::Coalesce => self.parse_obj_props(None, self.i)?,
+					_ => self.parse_expr()?,
+				};
 				ASTNode::BinOp(
 					BinOp { 
 						left: Box::new(left), 
-						op: Op::Eq,
-						right: Box::new(self.parse_expr()) 
+						op,
+						right: Box::new(right) 
 					}
 				)
 			},
@@ -737,14 +1694,31 @@ impl Parser {
 			}
 		};
 
+		if continues && matches!(next, Token::Plus | Token::Minus | Token::Eq | Token::Lt | Token::Gt | Token::DoubleQuestion) {
+			self.mark(NodeKind::BinOp, start);
+		}
+
 		if self.loglevel > 0 {
 			self.callstack.pop();
 		}
 
-		ret
+		Ok(ret)
+	}
+
+	/// The calls, property reads and indexes after an operand, which bind
+	/// tighter than any operator.
+	fn parse_postfix(&mut self, mut operand: ASTNode, start: usize) -> ParseResult<ASTNode> {
+		loop {
+			operand = match self.peek(0) {
+				Some(Token::OpenParen) => self.parse_call(operand, start)?,
+				Some(Token::Dot | Token::QuestionDot) => self.parse_prob_access(operand, start)?,
+				Some(Token::OpenBracket) if self.same_line(0) => self.parse_index(operand, start)?,
+				_ => return Ok(operand),
+			};
+		}
 	}
 
-	fn parse_call(&mut self, caller: ASTNode) -> ASTNode {
+	fn parse_call(&mut self, caller: ASTNode, start: usize) -> ParseResult<ASTNode> {
 		if self.loglevel > 0 {
 			self.callstack.push("parse_call".to_string());
 		}
@@ -753,18 +1727,19 @@ impl Parser {
 
 		let mut args = Vec::new();
 
-		while let Some(token) = self.peek(0) {
-			match token {
-				Token::CloseParen => {
+		loop {
+			match self.peek(0) {
+				Some(Token::CloseParen) => {
 					self.skip(1);
 					break;
 				},
-				Token::Comma => {
+				Some(Token::Comma) => {
 					self.skip(1);
 				},
-				_ => {
-					args.push(self.parse_item().unwrap());
-				}
+				Some(_) => {
+					args.extend(self.parse_item()?);
+				},
+				None => return self.error_at(self.i, "Expected CloseParen but got end of input".to_string()),
 			}
 		}
 
@@ -778,12 +1753,14 @@ impl Parser {
 				args: args,
 			}
 		);
+		self.mark(NodeKind::Call, start);
 
 		let ret = match self.peek(0) {
 			Some(t) => match t {
 				Token::OpenParen => {
-					self.parse_call(call)
+					self.parse_call(call, start)?
 				}
+				Token::OpenBracket if self.same_line(0) => self.parse_index(call, start)?,
 				_ => call,
 			},
 			None => call,
@@ -793,30 +1770,34 @@ impl Parser {
 			self.callstack.pop();
 		}
 
-		ret
+		Ok(ret)
 	}
 
-	fn parse_prob_access(&mut self, left: ASTNode) -> ASTNode {
+	fn parse_prob_access(&mut self, left: ASTNode, start: usize) -> ParseResult<ASTNode> {
 		if self.loglevel > 0 {
 			self.callstack.push("parse_prob_access".to_string());
 		}
 
-		self.skip(1);
+		let optional = self.eat() == Some(Token::QuestionDot);
 
-		let ident = self.expect_ident();
+		let ident = self.expect_ident()?;
 		
 		let prob_access = ASTNode::ProbAccess(
 			ProbAccess {
 				object: Box::new(left),
 				property: ident,
+				optional,
 			}
 		);
+		self.mark(NodeKind::ProbAccess, start);
 
 		let ret = match self.peek(0) {
 			Some(t) => match t {
 				Token::OpenParen => {
-					self.parse_call(prob_access)
+					self.parse_call(prob_access, start)?
 				},
+				Token::Dot | Token::QuestionDot => self.parse_prob_access(prob_access, start)?,
+				Token::OpenBracket if self.same_line(0) => self.parse_index(prob_access, start)?,
 				_ => prob_access,
 			},
 			None => prob_access,
@@ -826,15 +1807,51 @@ impl Parser {
 			self.callstack.pop();
 		}
 
-		ret
+		Ok(ret)
+	}
+
+	fn parse_index(&mut self, left: ASTNode, start: usize) -> ParseResult<ASTNode> {
+		if self.loglevel > 0 {
+			self.callstack.push("parse_index".to_string());
+		}
+
+		self.skip(1);
+
+		let index = match self.parse_item()? {
+			Some(index) => index,
+			None => return self.error_at(self.i, "Expected index but got end of input".to_string()),
+		};
+		self.expect_eat(Token::CloseBracket)?;
+
+		let index = ASTNode::Index(
+			Index {
+				object: Box::new(left),
+				index: Box::new(index),
+			}
+		);
+		self.mark(NodeKind::Index, start);
+
+		let ret = match self.peek(0) {
+			Some(Token::OpenParen) => self.parse_call(index, start)?,
+			Some(Token::Dot | Token::QuestionDot) => self.parse_prob_access(index, start)?,
+			Some(Token::OpenBracket) if self.same_line(0) => self.parse_index(index, start)?,
+			_ => index,
+		};
+
+		if self.loglevel > 0 {
+			self.callstack.pop();
+		}
+
+		Ok(ret)
 	}
 
-	fn parse_term(&mut self) -> ASTNode {
+	fn parse_term(&mut self) -> ParseResult<ASTNode> {
 		if self.loglevel > 0 {
 			self.callstack.push("parse_term".to_string());
 		}
 
-		let left = self.parse_factor();
+		let start = self.i;
+		let left = self.parse_factor()?;
 
 		let next = match self.peek(0) {
 			Some(t) => t,
@@ -842,7 +1859,7 @@ impl Parser {
 				if self.loglevel > 0 {
 					self.callstack.pop();
 				}
-				return left;
+				return Ok(left);
 			}
 		};
 
@@ -856,7 +1873,7 @@ impl Parser {
 					BinOp { 
 						left: Box::new(left), 
 						op: Op::Mul,
-						right: Box::new(self.parse_factor()) 
+						right: Box::new(self.parse_factor()?) 
 					}
 				)
 			},
@@ -869,7 +1886,7 @@ impl Parser {
 					BinOp { 
 						left: Box::new(left), 
 						op: Op::Div,
-						right: Box::new(self.parse_factor()) 
+						right: Box::new(self.parse_factor()?) 
 					}
 				)
 			},
@@ -878,22 +1895,38 @@ impl Parser {
 			}
 		};
 
+		if matches!(next, Token::Multiply | Token::Divide) {
+			self.mark(NodeKind::BinOp, start);
+		}
+
 		if self.loglevel > 0 {
 			self.callstack.pop();
 		}
 
-		ret
+		Ok(ret)
 	}
 
-	fn parse_factor(&mut self) -> ASTNode {
+	/// The value of the int literal just eaten. Its magnitude can be one
+	/// past `i64::MAX` when it is `negated`, for `-9223372036854775808`.
+	fn int_lit(&self, n: u64, negated: bool) -> ParseResult<i64> {
+		match i64::try_from(n) {
+			Ok(i) => Ok(i),
+			Err(_) if negated && n == i64::MIN.unsigned_abs() => Ok(i64::MIN),
+			Err(_) => self.error_at(self.i - 1, "Integer literal out of range".to_string()),
+		}
+	}
+
+	fn parse_factor(&mut self) -> ParseResult<ASTNode> {
 		if self.loglevel > 0 {
 			self.callstack.push("parse_factor".to_string());
 		}
 
+		let start = self.i;
+		let negated = mem::take(&mut self.negating);
 		let next = match self.eat() {
 			Some(t) => t,
 			None => {
-				panic!("Unexpected end of tokens");
+				return self.error_at(self.i, "Unexpected end of tokens".to_string());
 			}
 		};
 
@@ -903,29 +1936,58 @@ impl Parser {
 					self.log(&format!("Ident: {}", ident));
 				}
 
+				self.record_ident(self.i - 1, &ident);
+				if self.peek(0) == Some(Token::DoubleColon) {
+					let variant = self.parse_variant(ident, start)?;
+					if self.loglevel > 0 {
+						self.callstack.pop();
+					}
+					return self.parse_postfix(variant, start);
+				}
 				ASTNode::Ident(ident.to_string())
 			}
 			Token::String(s) => ASTNode::Lit(Value::Str(s)),
-			Token::Int(num) => ASTNode::Lit(Value::Int(num)),
+			Token::Int(num) => ASTNode::Lit(Value::Int(self.int_lit(num, negated)?)),
 			Token::Float(num) => ASTNode::Lit(Value::Float(num)),
 			Token::True => ASTNode::Lit(Value::Bool(true)),
 			Token::False => ASTNode::Lit(Value::Bool(false)),
+			Token::None => ASTNode::Lit(Value::None),
 			Token::OpenParen => {
-				let node = self.parse_expr();	
-				self.expect_eat(Token::CloseParen);
-				return node;
+				let node = self.parse_expr()?;	
+				self.expect_eat(Token::CloseParen)?;
+				self.mark(NodeKind::Paren, start);
+				if self.loglevel > 0 {
+					self.callstack.pop();
+				}
+				return self.parse_postfix(node, start);
+			},
+			Token::Minus => {
+				// The operand takes its property reads, calls and indexes
+				// along, `-a.b` negates the property
+				self.negating = true;
+				let operand = self.parse_factor()?;
+				self.mark(NodeKind::Neg, start);
+
+				if self.loglevel > 0 {
+					self.callstack.pop();
+				}
+				return Ok(negate(operand));
 			},
 			_ => {
-				println!("{}", self.curr_loc());
-				panic!("Unexpected token {:?}", next);
+				return self.error_at(self.i - 1, format!("Unexpected token {:?}", next));
 			}
 		};
 
+		match ret {
+			ASTNode::Ident(_) => self.mark(NodeKind::Ident, start),
+			_ => self.mark(NodeKind::Lit, start),
+		}
+
 		if self.loglevel > 0 {
 			self.callstack.pop();
 		}
 
-		ret
+		self.parse_postfix(ret, start)
 	}
 }
 
@@ -959,7 +2021,8 @@ mod tests {
 								right: Box::new(ASTNode::Lit(Value::Int(2))),
 							}
 						)
-					)
+					),
+					op: None,
 				}
 			)
 		];
@@ -988,7 +2051,8 @@ mod tests {
 								right: Box::new(ASTNode::Lit(Value::Int(2))),
 							}
 						)
-					)
+					),
+					op: None,
 				}
 			)
 		];
@@ -1017,7 +2081,8 @@ mod tests {
 								right: Box::new(ASTNode::Lit(Value::Int(2))),
 							}
 						)
-					)
+					),
+					op: None,
 				}
 			)
 		];
@@ -1046,7 +2111,8 @@ mod tests {
 								right: Box::new(ASTNode::Lit(Value::Int(2))),
 							}
 						)
-					)
+					),
+					op: None,
 				}
 			)
 		];
@@ -1083,7 +2149,8 @@ mod tests {
 								right: Box::new(ASTNode::Lit(Value::Int(3))),
 							}
 						)
-					)
+					),
+					op: None,
 				}
 			)
 		];
@@ -1120,7 +2187,8 @@ mod tests {
 								),
 							}
 						)
-					)
+					),
+					op: None,
 				}
 			)
 		];
@@ -1148,7 +2216,8 @@ mod tests {
 								args: vec![],
 							}
 						)
-					)
+					),
+					op: None,
 				}
 			)
 		];
@@ -1178,7 +2247,8 @@ mod tests {
 								],
 							}
 						)
-					)
+					),
+					op: None,
 				}
 			)
 		];
@@ -1219,6 +2289,7 @@ mod tests {
 
 						)
 					),
+					op: None,
 				},
 			)
 		];
@@ -1240,6 +2311,7 @@ mod tests {
 				ProbAccess {
 					object: Box::new(ASTNode::Ident("foo".to_string())),
 					property: "bar".to_string(),
+					optional: false,
 				}
 			)
 		];
@@ -1264,6 +2336,7 @@ mod tests {
 							ProbAccess {
 								object: Box::new(ASTNode::Ident("foo".to_string())),
 								property: "bar".to_string(),
+								optional: false,
 							}
 						)
 					),
@@ -1327,6 +2400,7 @@ mod tests {
 							}
 						)
 					),
+					op: None,
 				}
 			)
 		];
@@ -1358,6 +2432,7 @@ mod tests {
 							}
 						)
 					),
+					op: None,
 				}
 			)
 		];
@@ -1445,6 +2520,7 @@ mod tests {
 							}
 						)
 					),
+					op: None,
 				}
 			)
 		];
@@ -1486,6 +2562,7 @@ mod tests {
 							}
 						)
 					),
+					op: None,
 				}
 			)
 		];
@@ -1561,6 +2638,7 @@ mod tests {
 							}
 						)
 					),
+					op: None,
 				}
 			)
 		];
@@ -1599,6 +2677,7 @@ mod tests {
 							}
 						)
 					),
+					op: None,
 				}
 			)
 		];
@@ -1951,6 +3030,26 @@ mod tests {
 		assert_eq!(ast, expected);
 	}
 
+	#[test]
+	fn incomplete_input() {
+		assert!(is_incomplete("a = {"));
+		assert!(is_incomplete("foo(1,"));
+		assert!(is_incomplete("[1, [2]"));
+		assert!(is_incomplete("a = \"abc"));
+		assert!(!is_incomplete("a = { b: [1] }"));
+		assert!(!is_incomplete("a = \"{\""));
+		assert!(is_incomplete("a = 1 /* one /* two */"));
+		assert!(!is_incomplete("a = 1 /* { */"));
+	}
+
+	#[test]
+	fn statement_lines() {
+		let code = "a = 1\nf = (x) => {\n\treturn x\n}\n\nif a == 1 {\n\tf(a)\n}";
+		let mut parser = Parser::new(code);
+		parser.parse();
+		assert_eq!(parser.stmt_lines(), vec![1, 2, 3, 6, 7]);
+	}
+
 	#[test]
 	fn single_line_comments() {
 		let code = r#"
@@ -1966,6 +3065,7 @@ mod tests {
 				Assign {
 					left: Box::new(ASTNode::Ident("a".to_string())),
 					right: Box::new(ASTNode::Lit(Value::Int(1))),
+					op: None,
 				}
 			)
 		];
@@ -1973,6 +3073,64 @@ mod tests {
 		assert_eq!(ast, expected);
 	}
 
+	#[test]
+	fn block_comments() {
+		let code = "/* header\n /* nested */ still comment */\na = /* inline */ 1 // line\n/**/b = 2";
+		let mut parser = Parser::new(code);
+		let ast = parser.parse();
+		assert_eq!(ast.len(), 2);
+
+		let comments = parser.comments().iter().map(|c| &code[c.clone()]).collect::<Vec<_>>();
+		assert_eq!(comments, vec![
+			"/* header\n /* nested */ still comment */",
+			"/* inline */",
+			"// line",
+			"/**/",
+		]);
+	}
+
+	#[test]
+	fn doc_comments() {
+		let code = "/// Adds two numbers.
+///
+/// Ints or floats.
+export add = (a, b) => a + b
+
+/// Not attached, a blank line follows.
+
+/// A point.
+struct Point { x: Int }
+
+//// Four slashes are no doc.
+f = x => {
+\treturn x
+}
+/// Only functions and structs are documented.
+n = 1
+mod m {
+\t// a plain comment
+\t/// Inside a module.
+\tg = () => {
+\t\t/// Nested.
+\t\th = x => x
+\t}
+}
+a = 1 /// trailing, not above
+k = x => x";
+		let mut parser = Parser::new(code);
+		parser.parse();
+
+		let docs = parser.doc_comments().iter()
+			.map(|d| (d.name.as_str(), d.text.as_str(), &code[d.span.clone()]))
+			.collect::<Vec<_>>();
+		assert_eq!(docs, vec![
+			("add", "Adds two numbers.\n\nInts or floats.", "export add = (a, b) => a + b"),
+			("Point", "A point.", "struct Point { x: Int }"),
+			("g", "Inside a module.", "g = () => {\n\t\t/// Nested.\n\t\th = x => x\n\t}"),
+			("h", "Nested.", "h = x => x"),
+		]);
+	}
+
 	#[test]
 	fn list_map() {
 		let code = "[1].map(p => p * 2)";
@@ -1983,6 +3141,7 @@ mod tests {
 					items: vec![ASTNode::Lit(Value::Int(1))],
 				})),
 				property: "map".to_string(),
+				optional: false,
 			})),
 			args: vec![ASTNode::Fun(Fun {
 				params: vec![Param {
@@ -2024,8 +3183,378 @@ mod tests {
 					},
 				],
 			})),
+			op: None,
+		})];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn struct_definition() {
+		let code = "struct Order { id: Int, total: Float\n owner: User }";
+
+		let mut parser = Parser::new(code);
+		let ast = parser.parse();
+		let expected = vec![ASTNode::StructDef(StructDef {
+			name: "Order".to_string(),
+			fields: vec![
+				TypeField { name: "id".to_string(), typ: VarType::Int },
+				TypeField { name: "total".to_string(), typ: VarType::Float },
+				TypeField { name: "owner".to_string(), typ: VarType::Ident("User".to_string()) },
+			],
 		})];
 
 		assert_eq!(ast, expected);
+
+		let idents = parser.ident_spans().iter().map(|(n, span)| (n.as_str(), &code[span.clone()])).collect::<Vec<_>>();
+		assert_eq!(idents.len(), 5);
+		assert!(idents.iter().all(|(n, text)| n == text));
+	}
+
+	#[test]
+	fn parse_errors() {
+		let err = Parser::new("a = 1\nb = [1, 2").try_parse().unwrap_err();
+		assert_eq!((err.line, err.col), (2, 10));
+		assert_eq!(err.msg, "Expected CloseBracket but got end of input");
+
+		let err = Parser::new("f = (x) => {\n  x = }").try_parse().unwrap_err();
+		assert_eq!(err.to_string(), "2:7: Unexpected token CloseBrace");
+
+		let err = Parser::new("a = 1 # 2").try_parse().unwrap_err();
+		assert_eq!(err.span, 6..7);
+
+		let err = Parser::new("a = 99999999999999999999").try_parse().unwrap_err();
+		assert_eq!(err.msg, "Integer literal out of range");
+
+		let err = Parser::new("a = 9223372036854775808").try_parse().unwrap_err();
+		assert_eq!((err.msg.as_str(), err.span.clone()), ("Integer literal out of range", 4..23));
+
+		let err = Parser::new("a = 1 -9223372036854775808").try_parse().unwrap_err();
+		assert_eq!(err.msg, "Integer literal out of range");
+
+		let err = Parser::new("a = 1\n/* open /* nested */").try_parse().unwrap_err();
+		assert_eq!(err.to_string(), "2:1: Unterminated block comment");
+
+		let err = Parser::new("f() = 1").try_parse().unwrap_err();
+		assert_eq!(err.to_string(), "1:5: Can only assign to a name, property or index");
+	}
+
+	#[test]
+	fn test_minus() {
+		let code = r#"
+			a -1
+			b = -x.y
+		"#;
+
+		let ast = Parser::new(code)
+			.parse();
+
+		let expected = vec![
+			ASTNode::BinOp(
+				BinOp {
+					left: Box::new(ASTNode::Ident("a".to_string())),
+					op: Op::Minus,
+					right: Box::new(ASTNode::Lit(Value::Int(1))),
+				}
+			),
+			ASTNode::Assign(
+				Assign {
+					left: Box::new(ASTNode::Ident("b".to_string())),
+					right: Box::new(ASTNode::Neg(Box::new(
+						ASTNode::ProbAccess(
+							ProbAccess {
+								object: Box::new(ASTNode::Ident("x".to_string())),
+								property: "y".to_string(),
+								optional: false,
+							}
+						)
+					))),
+					op: None,
+				}
+			),
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_decl() {
+		let ast = Parser::new("let a = 1\nconst f = (x) => x\nlet = 2\nb = a\nc").parse();
+		assert_eq!(ast, vec![
+			ASTNode::Decl(Decl {
+				name: "a".to_string(),
+				value: Box::new(ASTNode::Lit(Value::Int(1))),
+				constant: false,
+			}),
+			ASTNode::Decl(Decl {
+				name: "f".to_string(),
+				value: Box::new(ASTNode::Fun(Fun {
+					params: vec![Param { name: "x".to_string() }],
+					body: vec![ASTNode::Ident("x".to_string())],
+				})),
+				constant: true,
+			}),
+			// Without a name after it `let` is a plain name
+			ASTNode::Assign(Assign {
+				left: Box::new(ASTNode::Ident("let".to_string())),
+				right: Box::new(ASTNode::Lit(Value::Int(2))),
+				op: None,
+			}),
+			// A name at the end of a statement does not swallow the next one
+			ASTNode::Assign(Assign {
+				left: Box::new(ASTNode::Ident("b".to_string())),
+				right: Box::new(ASTNode::Ident("a".to_string())),
+				op: None,
+			}),
+			ASTNode::Ident("c".to_string()),
+		]);
+
+		let ast = Parser::new("export const pi = 3").parse();
+		assert!(matches!(&ast[0], ASTNode::Export(d) if matches!(**d, ASTNode::Decl(_))));
+
+		let ast = Parser::new("export struct P { x: Int }\nexport enum E { A }").parse();
+		assert!(matches!(&ast[0], ASTNode::Export(d) if matches!(**d, ASTNode::StructDef(_))));
+		assert!(matches!(&ast[1], ASTNode::Export(d) if matches!(**d, ASTNode::EnumDef(_))));
+
+		let err = Parser::new("export 1").try_parse().unwrap_err();
+		assert_eq!(err.to_string(), "1:8: Only assignments, declarations, modules, imports, structs and enums can be exported");
+
+		let err = Parser::new("let 1 = 2").try_parse().unwrap_err();
+		assert_eq!(err.to_string(), "1:7: Unexpected token Assign");
+	}
+
+	#[test]
+	fn test_enum_and_match() {
+		let code = r#"
+			enum Status {
+				Ok(Human)
+				Error { code: Int, message: String },
+				Pending
+			}
+			match s {
+				Status::Error { code: 404, message } => print(message)
+				Status::Ok(h) => { return h }
+				_ => {}
+			}
+			if s == Status::Pending { x = Status::Ok(1) }
+		"#;
+
+		let ast = Parser::new(code).parse();
+
+		let expected = vec![
+			ASTNode::EnumDef(EnumDef {
+				name: "Status".to_string(),
+				variants: vec![
+					VariantDef { name: "Ok".to_string(), shape: VariantShape::Tuple(vec![VarType::Ident("Human".to_string())]) },
+					VariantDef {
+						name: "Error".to_string(),
+						shape: VariantShape::Struct(vec![
+							TypeField { name: "code".to_string(), typ: VarType::Int },
+							TypeField { name: "message".to_string(), typ: VarType::String },
+						]),
+					},
+					VariantDef { name: "Pending".to_string(), shape: VariantShape::Unit },
+				],
+			}),
+			ASTNode::Match(Match {
+				value: Box::new(ASTNode::Ident("s".to_string())),
+				arms: vec![
+					MatchArm {
+						pattern: Pattern::Variant {
+							enum_name: "Status".to_string(),
+							name: "Error".to_string(),
+							fields: PatternFields::Struct(vec![
+								("code".to_string(), Pattern::Lit(Value::Int(404))),
+								("message".to_string(), Pattern::Bind("message".to_string())),
+							]),
+						},
+						body: vec![ASTNode::Call(Call {
+							callee: Box::new(ASTNode::Ident("print".to_string())),
+							args: vec![ASTNode::Ident("message".to_string())],
+						})],
+					},
+					MatchArm {
+						pattern: Pattern::Variant {
+							enum_name: "Status".to_string(),
+							name: "Ok".to_string(),
+							fields: PatternFields::Tuple(vec![Pattern::Bind("h".to_string())]),
+						},
+						body: vec![ASTNode::Ret(Ret { value: Box::new(Some(ASTNode::Ident("h".to_string()))) })],
+					},
+					MatchArm { pattern: Pattern::Wildcard, body: vec![] },
+				],
+			}),
+			// A brace after a unit variant is the body of the `if`
+			ASTNode::If(If {
+				cond: Box::new(ASTNode::BinOp(BinOp {
+					left: Box::new(ASTNode::Ident("s".to_string())),
+					op: Op::Eq,
+					right: Box::new(ASTNode::Variant(VariantIns {
+						enum_name: "Status".to_string(),
+						name: "Pending".to_string(),
+						args: VariantArgs::Unit,
+					})),
+				})),
+				body: vec![ASTNode::Assign(Assign {
+					left: Box::new(ASTNode::Ident("x".to_string())),
+					right: Box::new(ASTNode::Variant(VariantIns {
+						enum_name: "Status".to_string(),
+						name: "Ok".to_string(),
+						args: VariantArgs::Tuple(vec![ASTNode::Lit(Value::Int(1))]),
+					})),
+					op: None,
+				})],
+				els: None,
+			}),
+		];
+
+		assert_eq!(ast, expected);
+
+		let err = Parser::new("match s {\n  + => 1\n}").try_parse().unwrap_err();
+		assert_eq!(err.to_string(), "2:3: Expected pattern but got Plus");
+
+		let err = Parser::new("match s { -a => 1 }").try_parse().unwrap_err();
+		assert_eq!(err.to_string(), "1:12: Expected number but got Ident(\"a\")");
+	}
+
+	#[test]
+	fn test_index_assign() {
+		let code = r#"
+			rows[i][0] += 1
+			a.b = 2
+			x
+			[1]
+		"#;
+
+		let ast = Parser::new(code)
+			.parse();
+
+		let ident = |s: &str| Box::new(ASTNode::Ident(s.to_string()));
+		let expected = vec![
+			ASTNode::Assign(
+				Assign {
+					left: Box::new(ASTNode::Index(
+						Index {
+							object: Box::new(ASTNode::Index(
+								Index {
+									object: ident("rows"),
+									index: ident("i"),
+								}
+							)),
+							index: Box::new(ASTNode::Lit(Value::Int(0))),
+						}
+					)),
+					right: Box::new(ASTNode::Lit(Value::Int(1))),
+					op: Some(Op::Plus),
+				}
+			),
+			ASTNode::Assign(
+				Assign {
+					left: Box::new(ASTNode::ProbAccess(
+						ProbAccess {
+							object: ident("a"),
+							property: "b".to_string(),
+							optional: false,
+						}
+					)),
+					right: Box::new(ASTNode::Lit(Value::Int(2))),
+					op: None,
+				}
+			),
+			// A bracket on a new line starts a list
+			ASTNode::Ident("x".to_string()),
+			ASTNode::Array(Array { items: vec![ASTNode::Lit(Value::Int(1))] }),
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_optional() {
+		let code = r#"
+			struct Person { children?: [Person], color: String? }
+			a = p?.b.c ?? none
+			f() + 1
+			x
+			-1
+		"#;
+
+		let ast = Parser::new(code)
+			.parse();
+
+		let ident = |s: &str| Box::new(ASTNode::Ident(s.to_string()));
+		let person = VarType::Ident("Person".to_string());
+		let expected = vec![
+			ASTNode::StructDef(StructDef {
+				name: "Person".to_string(),
+				fields: vec![
+					TypeField { name: "children".to_string(), typ: VarType::List(Box::new(person)).optional() },
+					TypeField { name: "color".to_string(), typ: VarType::String.optional() },
+				],
+			}),
+			ASTNode::Assign(
+				Assign {
+					left: ident("a"),
+					right: Box::new(ASTNode::BinOp(
+						BinOp {
+							left: Box::new(ASTNode::ProbAccess(
+								ProbAccess {
+									object: Box::new(ASTNode::ProbAccess(
+										ProbAccess {
+											object: ident("p"),
+											property: "b".to_string(),
+											optional: true,
+										}
+									)),
+									property: "c".to_string(),
+									optional: false,
+								}
+							)),
+							op: Op::Coalesce,
+							right: Box::new(ASTNode::Lit(Value::None)),
+						}
+					)),
+					op: None,
+				}
+			),
+			ASTNode::BinOp(
+				BinOp {
+					left: Box::new(ASTNode::Call(Call { callee: ident("f"), args: vec![] })),
+					op: Op::Plus,
+					right: Box::new(ASTNode::Lit(Value::Int(1))),
+				}
+			),
+			// A minus on a new line starts a new statement
+			ASTNode::Ident("x".to_string()),
+			ASTNode::Lit(Value::Int(-1)),
+		];
+
+		assert_eq!(ast, expected);
+
+		// An empty list or object is a common default
+		let ast = Parser::new("b ?? []\nd ?? { e: 1 }").parse();
+		let expected = vec![
+			ASTNode::BinOp(
+				BinOp {
+					left: ident("b"),
+					op: Op::Coalesce,
+					right: Box::new(ASTNode::Array(Array { items: vec![] })),
+				}
+			),
+			ASTNode::BinOp(
+				BinOp {
+					left: ident("d"),
+					op: Op::Coalesce,
+					right: Box::new(ASTNode::ObjIns(ObjIns {
+						name: None,
+						props: vec![Property { name: "e".to_string(), value: Box::new(ASTNode::Lit(Value::Int(1))) }],
+					})),
+				}
+			),
+		];
+		assert_eq!(ast, expected);
+
+		let err = Parser::new("a?.b = 1").try_parse().unwrap_err();
+		assert_eq!(err.to_string(), "1:6: Can not assign through ?.");
 	}
 }
@@ -0,0 +1,1006 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ListIter;
+use crate::Obj;
+use crate::ObjProp;
+use crate::Ptr;
+use crate::StackValue;
+use crate::Value;
+use crate::Variant;
+use crate::VariantFields;
+use crate::vm::is_builtin_idt;
+use crate::vm_types::ByteCode;
+use crate::vm_types::LineTable;
+use crate::Vm;
+
+pub const PROGRAM_MAGIC: &[u8; 4] = b"FLXB";
+pub const PROGRAM_VERSION: u16 = 2;
+
+const HEADER_LEN: usize = 14;
+
+/// Deepest nesting of lists, objects and variants read from a file, so a
+/// crafted file can not overflow the stack.
+const MAX_VALUE_DEPTH: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgramError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    ChecksumMismatch,
+    Truncated,
+    Invalid(String),
+}
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProgramError::BadMagic => f.write_str("not a compiled flexscript program"),
+            ProgramError::UnsupportedVersion(v) => write!(f, "unsupported program version {}", v),
+            ProgramError::ChecksumMismatch => f.write_str("program checksum does not match"),
+            ProgramError::Truncated => f.write_str("program is truncated"),
+            ProgramError::Invalid(msg) => write!(f, "invalid program: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ProgramError {}
+
+/// FNV-1a hash of the payload, stored in the header to catch corruption.
+pub(crate) fn checksum(bytes: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for b in bytes {
+        hash ^= *b as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+/// Prepends the magic, version, checksum and length to a payload.
+pub(crate) fn seal(magic: &[u8; 4], version: u16, payload: Vec<u8>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_LEN + payload.len());
+    out.extend_from_slice(magic);
+    out.extend_from_slice(&version.to_le_bytes());
+    out.extend_from_slice(&checksum(&payload).to_le_bytes());
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend(payload);
+    out
+}
+
+/// Checks the header written by `seal` and returns the payload.
+pub(crate) fn unseal<'a>(magic: &[u8; 4], version: u16, bytes: &'a [u8]) -> Result<&'a [u8], ProgramError> {
+    if bytes.len() < 4 || &bytes[..4] != magic {
+        return Err(ProgramError::BadMagic);
+    }
+
+    let mut header = Reader::new(&bytes[4..]);
+    let found = header.u16()?;
+    if found != version {
+        return Err(ProgramError::UnsupportedVersion(found));
+    }
+    let sum = header.u32()?;
+    let len = header.u32()? as usize;
+
+    let payload = &bytes[HEADER_LEN..];
+    if payload.len() < len {
+        return Err(ProgramError::Truncated);
+    }
+    if payload.len() > len {
+        return Err(ProgramError::Invalid("trailing bytes after payload".to_string()));
+    }
+    if checksum(payload) != sum {
+        return Err(ProgramError::ChecksumMismatch);
+    }
+
+    Ok(payload)
+}
+
+pub(crate) struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn new() -> Self {
+        Self {
+            buf: Vec::new()
+        }
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub(crate) fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    pub(crate) fn u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub(crate) fn i64(&mut self, v: i64) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub(crate) fn f64(&mut self, v: f64) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub(crate) fn bool(&mut self, v: bool) {
+        self.u8(v as u8);
+    }
+
+    pub(crate) fn str(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+
+    pub(crate) fn opt_u32(&mut self, v: Option<u32>) {
+        match v {
+            Some(v) => {
+                self.u8(1);
+                self.u32(v);
+            },
+            None => self.u8(0)
+        }
+    }
+
+    pub(crate) fn ptr(&mut self, ptr: &Ptr) {
+        self.u32(ptr.id);
+        self.u32(ptr.scope_id);
+    }
+
+    pub(crate) fn value(&mut self, val: &Value) -> Result<(), ProgramError> {
+        match val {
+            Value::None => self.u8(0),
+            Value::Int(i) => {
+                self.u8(1);
+                self.i64(*i);
+            },
+            Value::Float(f) => {
+                self.u8(2);
+                self.f64(*f);
+            },
+            Value::Str(s) => {
+                self.u8(3);
+                self.str(s);
+            },
+            Value::Bool(b) => {
+                self.u8(4);
+                self.bool(*b);
+            },
+            Value::List(list) => {
+                self.u8(5);
+                self.u32(list.len() as u32);
+                for v in list {
+                    self.value(v)?;
+                }
+            },
+            Value::Ptr(ptr) => {
+                self.u8(6);
+                self.ptr(ptr);
+            },
+            Value::Fn(blk) => {
+                self.u8(7);
+                self.u32(*blk);
+            },
+            Value::Obj(obj) => {
+                self.u8(8);
+                match &obj.name {
+                    Some(name) => {
+                        self.u8(1);
+                        self.str(name);
+                    },
+                    None => self.u8(0)
+                }
+                self.u32(obj.props.len() as u32);
+                for prop in &obj.props {
+                    self.str(&prop.name);
+                    self.value(&prop.value)?;
+                }
+            },
+            Value::ListIter(iter) => {
+                self.u8(9);
+                self.u32(iter.inx);
+                self.ptr(&iter.ptr);
+            },
+            Value::UndefIdent(id) => {
+                self.u8(10);
+                self.u32(*id);
+            },
+            Value::UndefCall { ident, args } => {
+                self.u8(11);
+                self.u32(*ident);
+                self.u32(args.len() as u32);
+                for v in args {
+                    self.value(v)?;
+                }
+            },
+            Value::Native(id) => {
+                self.u8(12);
+                self.u32(*id);
+            },
+            Value::Enum(v) => {
+                self.u8(13);
+                self.str(&v.enum_name);
+                self.str(&v.name);
+                match &v.fields {
+                    VariantFields::Unit => self.u8(0),
+                    VariantFields::Tuple(items) => {
+                        self.u8(1);
+                        self.u32(items.len() as u32);
+                        for v in items {
+                            self.value(v)?;
+                        }
+                    },
+                    VariantFields::Struct(props) => {
+                        self.u8(2);
+                        self.u32(props.len() as u32);
+                        for prop in props {
+                            self.str(&prop.name);
+                            self.value(&prop.value)?;
+                        }
+                    }
+                }
+            },
+            Value::UserData(ud) => {
+                return Err(ProgramError::Invalid(format!("cannot serialize userdata {}", ud.type_name())));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn stack_value(&mut self, val: &StackValue) -> Result<(), ProgramError> {
+        match val {
+            StackValue::None => self.u8(0),
+            StackValue::Int(i) => {
+                self.u8(1);
+                self.i64(*i);
+            },
+            StackValue::Float(f) => {
+                self.u8(2);
+                self.f64(*f);
+            },
+            StackValue::Str(s) => {
+                self.u8(3);
+                self.str(s);
+            },
+            StackValue::Bool(b) => {
+                self.u8(4);
+                self.bool(*b);
+            },
+            StackValue::Ptr(ptr) => {
+                self.u8(5);
+                self.ptr(ptr);
+            },
+            StackValue::Undef(id) => {
+                self.u8(6);
+                self.u32(*id);
+            },
+            StackValue::Fn(blk) => {
+                self.u8(7);
+                self.u32(*blk);
+            },
+            StackValue::UndefCall { ident, args } => {
+                self.u8(8);
+                self.u32(*ident);
+                self.u32(args.len() as u32);
+                for v in args {
+                    self.stack_value(v)?;
+                }
+            },
+            StackValue::PropAccess { ptr, prop } => {
+                self.u8(9);
+                self.ptr(ptr);
+                self.u32(*prop);
+            },
+            StackValue::Native(id) => {
+                self.u8(10);
+                self.u32(*id);
+            },
+            StackValue::StrMethod { s, prop } => {
+                self.u8(11);
+                self.str(s);
+                self.u32(*prop);
+            },
+            StackValue::UserData(ud) | StackValue::UserDataMethod { ud, .. } => {
+                return Err(ProgramError::Invalid(format!("cannot serialize userdata {}", ud.type_name())));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn code(&mut self, c: &ByteCode) {
+        let (op, arg) = match c {
+            ByteCode::Load(a) => (0, Some(*a)),
+            ByteCode::LoadConst(a) => (1, Some(*a)),
+            ByteCode::Store(a) => (2, Some(*a)),
+            ByteCode::BinMul => (3, None),
+            ByteCode::BinAdd => (4, None),
+            ByteCode::BinMinus => (5, None),
+            ByteCode::BinDivide => (6, None),
+            ByteCode::Jump(a) => (7, Some(*a)),
+            ByteCode::JumpIfFalse(a) => (8, Some(*a)),
+            ByteCode::Call(a) => (9, Some(*a)),
+            ByteCode::Cmp => (10, None),
+            ByteCode::BeginScope => (11, None),
+            ByteCode::EndScope => (12, None),
+            ByteCode::Fun(a) => (13, Some(*a)),
+            ByteCode::MakeStruct => (14, None),
+            ByteCode::MakeArray(a) => (15, Some(*a)),
+            ByteCode::Obj(a) => (16, Some(*a)),
+            ByteCode::Assign => (17, None),
+            ByteCode::Ret(a) => (18, Some(*a)),
+            ByteCode::Var(a) => (19, Some(*a)),
+            ByteCode::Next => (20, None),
+            ByteCode::MakeIter => (21, None),
+            ByteCode::Await => (22, None),
+            ByteCode::AccessProp(a) => (23, Some(*a)),
+            ByteCode::Lt => (24, None),
+            ByteCode::Gt => (25, None),
+            ByteCode::Unpack(a) => (26, Some(*a)),
+            ByteCode::Index => (27, None),
+            ByteCode::StoreProp(a) => (28, Some(*a)),
+            ByteCode::StoreIndex => (29, None),
+            ByteCode::Dup(a) => (30, Some(*a)),
+            ByteCode::Neg => (31, None),
+            ByteCode::LoadCallee(a) => (32, Some(*a)),
+            ByteCode::Variant(a) => (33, Some(*a)),
+            ByteCode::Matches(a) => (34, Some(*a)),
+            ByteCode::JumpIfNone(a) => (35, Some(*a)),
+            ByteCode::JumpIfSome(a) => (36, Some(*a)),
+            ByteCode::Lift(a) => (37, Some(*a)),
+        };
+
+        self.u8(op);
+        if let Some(arg) = arg {
+            self.u32(arg);
+        }
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Self {
+            buf,
+            pos: 0
+        }
+    }
+
+    /// Fails if anything is left after the last expected field.
+    pub(crate) fn finish(&self) -> Result<(), ProgramError> {
+        if self.pos < self.buf.len() {
+            return Err(ProgramError::Invalid("unexpected data at end".to_string()));
+        }
+        Ok(())
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ProgramError> {
+        if self.buf.len() - self.pos < n {
+            return Err(ProgramError::Truncated);
+        }
+        let bytes = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, ProgramError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, ProgramError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, ProgramError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn i64(&mut self) -> Result<i64, ProgramError> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn f64(&mut self) -> Result<f64, ProgramError> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, ProgramError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => Err(ProgramError::Invalid(format!("invalid bool {}", b)))
+        }
+    }
+
+    /// Reads a length prefix, rejecting counts which cannot fit in the
+    /// remaining bytes so corrupt input cannot trigger huge allocations.
+    pub(crate) fn len(&mut self) -> Result<usize, ProgramError> {
+        let len = self.u32()? as usize;
+        if len > self.buf.len() - self.pos {
+            return Err(ProgramError::Truncated);
+        }
+        Ok(len)
+    }
+
+    pub(crate) fn str(&mut self) -> Result<String, ProgramError> {
+        let len = self.len()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| ProgramError::Invalid("invalid utf-8 string".to_string()))
+    }
+
+    pub(crate) fn opt_u32(&mut self) -> Result<Option<u32>, ProgramError> {
+        match self.bool()? {
+            true => Ok(Some(self.u32()?)),
+            false => Ok(None)
+        }
+    }
+
+    pub(crate) fn ptr(&mut self) -> Result<Ptr, ProgramError> {
+        Ok(Ptr {
+            id: self.u32()?,
+            scope_id: self.u32()?
+        })
+    }
+
+    pub(crate) fn value(&mut self) -> Result<Value, ProgramError> {
+        self.nested_value(0)
+    }
+
+    /// Reads a constant, which can only be a literal or a variant, before
+    /// reading any values nested in it.
+    pub(crate) fn constant(&mut self) -> Result<Value, ProgramError> {
+        match self.buf.get(self.pos) {
+            Some(0..=4 | 13) => self.value(),
+            Some(t) => Err(ProgramError::Invalid(format!("constant tag {} is not a literal", t))),
+            None => Err(ProgramError::Truncated)
+        }
+    }
+
+    fn nested_value(&mut self, depth: usize) -> Result<Value, ProgramError> {
+        if depth > MAX_VALUE_DEPTH {
+            return Err(ProgramError::Invalid("values nested too deeply".to_string()));
+        }
+        let val = match self.u8()? {
+            0 => Value::None,
+            1 => Value::Int(self.i64()?),
+            2 => Value::Float(self.f64()?),
+            3 => Value::Str(self.str()?),
+            4 => Value::Bool(self.bool()?),
+            5 => {
+                let len = self.len()?;
+                let mut list = Vec::with_capacity(len);
+                for _ in 0..len {
+                    list.push(self.nested_value(depth + 1)?);
+                }
+                Value::List(list)
+            },
+            6 => Value::Ptr(self.ptr()?),
+            7 => Value::Fn(self.u32()?),
+            8 => {
+                let name = match self.bool()? {
+                    true => Some(self.str()?),
+                    false => None
+                };
+                let len = self.len()?;
+                let mut props = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let name = self.str()?;
+                    let value = self.nested_value(depth + 1)?;
+                    props.push(ObjProp { name, value });
+                }
+                Value::Obj(Obj { name, props: props.into() })
+            },
+            9 => {
+                let inx = self.u32()?;
+                let ptr = self.ptr()?;
+                Value::ListIter(ListIter { inx, ptr })
+            },
+            10 => Value::UndefIdent(self.u32()?),
+            11 => {
+                let ident = self.u32()?;
+                let len = self.len()?;
+                let mut args = Vec::with_capacity(len);
+                for _ in 0..len {
+                    args.push(self.nested_value(depth + 1)?);
+                }
+                Value::UndefCall { ident, args }
+            },
+            12 => Value::Native(self.u32()?),
+            13 => {
+                let enum_name = self.str()?;
+                let name = self.str()?;
+                let fields = match self.u8()? {
+                    0 => VariantFields::Unit,
+                    1 => {
+                        let len = self.len()?;
+                        let mut items = Vec::with_capacity(len);
+                        for _ in 0..len {
+                            items.push(self.nested_value(depth + 1)?);
+                        }
+                        VariantFields::Tuple(items)
+                    },
+                    2 => {
+                        let len = self.len()?;
+                        let mut props = Vec::with_capacity(len);
+                        for _ in 0..len {
+                            let name = self.str()?;
+                            let value = self.nested_value(depth + 1)?;
+                            props.push(ObjProp { name, value });
+                        }
+                        VariantFields::Struct(props.into())
+                    },
+                    t => return Err(ProgramError::Invalid(format!("unknown variant fields tag {}", t)))
+                };
+                Value::Enum(Variant { enum_name, name, fields })
+            },
+            t => return Err(ProgramError::Invalid(format!("unknown value tag {}", t)))
+        };
+
+        Ok(val)
+    }
+
+    pub(crate) fn stack_value(&mut self) -> Result<StackValue, ProgramError> {
+        self.nested_stack_value(0)
+    }
+
+    fn nested_stack_value(&mut self, depth: usize) -> Result<StackValue, ProgramError> {
+        if depth > MAX_VALUE_DEPTH {
+            return Err(ProgramError::Invalid("values nested too deeply".to_string()));
+        }
+        let val = match self.u8()? {
+            0 => StackValue::None,
+            1 => StackValue::Int(self.i64()?),
+            2 => StackValue::Float(self.f64()?),
+            3 => StackValue::Str(self.str()?),
+            4 => StackValue::Bool(self.bool()?),
+            5 => StackValue::Ptr(self.ptr()?),
+            6 => StackValue::Undef(self.u32()?),
+            7 => StackValue::Fn(self.u32()?),
+            8 => {
+                let ident = self.u32()?;
+                let len = self.len()?;
+                let mut args = Vec::with_capacity(len);
+                for _ in 0..len {
+                    args.push(self.nested_stack_value(depth + 1)?);
+                }
+                StackValue::UndefCall { ident, args }
+            },
+            9 => {
+                let ptr = self.ptr()?;
+                let prop = self.u32()?;
+                StackValue::PropAccess { ptr, prop }
+            },
+            10 => StackValue::Native(self.u32()?),
+            11 => StackValue::StrMethod {
+                s: self.str()?,
+                prop: self.u32()?
+            },
+            t => return Err(ProgramError::Invalid(format!("unknown stack value tag {}", t)))
+        };
+
+        Ok(val)
+    }
+
+    pub(crate) fn code(&mut self) -> Result<ByteCode, ProgramError> {
+        let c = match self.u8()? {
+            0 => ByteCode::Load(self.u32()?),
+            1 => ByteCode::LoadConst(self.u32()?),
+            2 => ByteCode::Store(self.u32()?),
+            3 => ByteCode::BinMul,
+            4 => ByteCode::BinAdd,
+            5 => ByteCode::BinMinus,
+            6 => ByteCode::BinDivide,
+            7 => ByteCode::Jump(self.u32()?),
+            8 => ByteCode::JumpIfFalse(self.u32()?),
+            9 => ByteCode::Call(self.u32()?),
+            10 => ByteCode::Cmp,
+            11 => ByteCode::BeginScope,
+            12 => ByteCode::EndScope,
+            13 => ByteCode::Fun(self.u32()?),
+            14 => ByteCode::MakeStruct,
+            15 => ByteCode::MakeArray(self.u32()?),
+            16 => ByteCode::Obj(self.u32()?),
+            17 => ByteCode::Assign,
+            18 => ByteCode::Ret(self.u32()?),
+            19 => ByteCode::Var(self.u32()?),
+            20 => ByteCode::Next,
+            21 => ByteCode::MakeIter,
+            22 => ByteCode::Await,
+            23 => ByteCode::AccessProp(self.u32()?),
+            24 => ByteCode::Lt,
+            25 => ByteCode::Gt,
+            26 => ByteCode::Unpack(self.u32()?),
+            27 => ByteCode::Index,
+            28 => ByteCode::StoreProp(self.u32()?),
+            29 => ByteCode::StoreIndex,
+            30 => ByteCode::Dup(self.u32()?),
+            31 => ByteCode::Neg,
+            32 => ByteCode::LoadCallee(self.u32()?),
+            33 => ByteCode::Variant(self.u32()?),
+            34 => ByteCode::Matches(self.u32()?),
+            35 => ByteCode::JumpIfNone(self.u32()?),
+            36 => ByteCode::JumpIfSome(self.u32()?),
+            37 => ByteCode::Lift(self.u32()?),
+            op => return Err(ProgramError::Invalid(format!("unknown opcode {}", op)))
+        };
+
+        Ok(c)
+    }
+}
+
+/// Compiled code as stored in a program file, with identifiers and
+/// block numbers relative to the file.
+pub(crate) struct Program {
+    pub(crate) idents: HashMap<u32, String>,
+    pub(crate) constants: Vec<Value>,
+    pub(crate) sources: Vec<String>,
+    pub(crate) blocks: Vec<Vec<ByteCode>>,
+    pub(crate) line_tables: Vec<LineTable>,
+}
+
+impl Program {
+    pub(crate) fn read(r: &mut Reader) -> Result<Self, ProgramError> {
+        let mut idents = HashMap::new();
+        for _ in 0..r.len()? {
+            let id = r.u32()?;
+            let name = r.str()?;
+            idents.insert(id, name);
+        }
+
+        let mut constants = Vec::new();
+        for _ in 0..r.len()? {
+            constants.push(r.constant()?);
+        }
+
+        let mut sources = Vec::new();
+        for _ in 0..r.len()? {
+            sources.push(r.str()?);
+        }
+
+        let mut blocks = Vec::new();
+        let mut line_tables = Vec::new();
+        for _ in 0..r.len()? {
+            let mut block = Vec::new();
+            for _ in 0..r.len()? {
+                block.push(r.code()?);
+            }
+            let source = r.opt_u32()?;
+            let mut entries = Vec::new();
+            for _ in 0..r.len()? {
+                entries.push((r.u32()?, r.u32()?));
+            }
+            let mut lets = Vec::new();
+            for _ in 0..r.len()? {
+                lets.push((r.u32()?, r.u32()?, r.u32()?));
+            }
+            blocks.push(block);
+            line_tables.push(LineTable { source, entries, lets });
+        }
+
+        Ok(Self {
+            idents,
+            constants,
+            sources,
+            blocks,
+            line_tables
+        })
+    }
+
+    /// Rejects anything the vm would otherwise panic on while running.
+    pub(crate) fn validate(&self) -> Result<(), ProgramError> {
+        let invalid = |msg: String| Err(ProgramError::Invalid(msg));
+
+        for (i, c) in self.constants.iter().enumerate() {
+            match c {
+                Value::Int(_) |
+                Value::Float(_) |
+                Value::Str(_) |
+                Value::Bool(_) |
+                Value::None => {},
+                // Variants built by `Variant` and compared by `Matches`
+                Value::Enum(_) => {},
+                _ => return invalid(format!("constant {} is not a literal", i))
+            }
+        }
+
+        for (blk, block) in self.blocks.iter().enumerate() {
+            for (pc, c) in block.iter().enumerate() {
+                let err = match c {
+                    ByteCode::Jump(t) |
+                    ByteCode::JumpIfFalse(t) |
+                    ByteCode::JumpIfNone(t) |
+                    ByteCode::JumpIfSome(t) if *t as usize > block.len() => {
+                        format!("jump target {} out of range", t)
+                    },
+                    ByteCode::LoadConst(i) |
+                    ByteCode::Matches(i) if *i as usize >= self.constants.len() => {
+                        format!("constant {} out of range", i)
+                    },
+                    ByteCode::LoadConst(i) if matches!(self.constants[*i as usize], Value::Enum(_)) => {
+                        format!("constant {} is not a literal", i)
+                    },
+                    ByteCode::Variant(i) if !matches!(self.constants.get(*i as usize), Some(Value::Enum(_))) => {
+                        format!("constant {} is not a variant", i)
+                    },
+                    ByteCode::Load(i) |
+                    ByteCode::LoadCallee(i) |
+                    ByteCode::Store(i) |
+                    ByteCode::Var(i) |
+                    ByteCode::AccessProp(i) |
+                    ByteCode::StoreProp(i) if !is_builtin_idt(*i) && !self.idents.contains_key(i) => {
+                        format!("unknown identifier {}", i)
+                    },
+                    ByteCode::Fun(i) if *i as usize >= self.blocks.len() => {
+                        format!("function block {} out of range", i)
+                    },
+                    _ => continue
+                };
+
+                return invalid(format!("blk {} pc {}: {}", blk, pc, err));
+            }
+        }
+
+        for (blk, lines) in self.line_tables.iter().enumerate() {
+            if let Some(s) = lines.source {
+                if s as usize >= self.sources.len() {
+                    return invalid(format!("blk {}: source {} out of range", blk, s));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Vm {
+    /// Writes the code section shared by program files and snapshots.
+    pub(crate) fn write_code(&self, w: &mut Writer) {
+        let mut idents = self.id_idt_map.iter().collect::<Vec<_>>();
+        idents.sort();
+        w.u32(idents.len() as u32);
+        for (id, name) in idents {
+            w.u32(*id);
+            w.str(name);
+        }
+
+        w.u32(self.constants.len() as u32);
+        for c in &self.constants {
+            w.value(c).expect("constants are literals");
+        }
+
+        w.u32(self.sources.len() as u32);
+        for s in &self.sources {
+            w.str(s);
+        }
+
+        w.u32(self.code_blocks.len() as u32);
+        for (block, lines) in self.code_blocks.iter().zip(&self.line_tables) {
+            w.u32(block.len() as u32);
+            for c in block {
+                w.code(c);
+            }
+            w.opt_u32(lines.source);
+            w.u32(lines.entries.len() as u32);
+            for (pc, line) in &lines.entries {
+                w.u32(*pc);
+                w.u32(*line);
+            }
+            w.u32(lines.lets.len() as u32);
+            for (idt, start, end) in &lines.lets {
+                w.u32(*idt);
+                w.u32(*start);
+                w.u32(*end);
+            }
+        }
+    }
+
+    /// Serializes every compiled code block with its constants,
+    /// identifiers and line tables. `entry` is the block `load_program`
+    /// returns, usually the one returned by `compile_code`.
+    pub fn save_program(&self, entry: u32) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.u32(entry);
+        self.write_code(&mut w);
+        seal(PROGRAM_MAGIC, PROGRAM_VERSION, w.finish())
+    }
+
+    /// Loads a program written by `save_program` next to the code already
+    /// in this vm and returns its entry block, ready for `run_blk`.
+    /// Identifiers are matched by name, so globals set before loading
+    /// are visible to the program.
+    pub fn load_program(&mut self, bytes: &[u8]) -> Result<u32, ProgramError> {
+        let payload = unseal(PROGRAM_MAGIC, PROGRAM_VERSION, bytes)?;
+        let mut r = Reader::new(payload);
+        let entry = r.u32()?;
+        let program = Program::read(&mut r)?;
+        r.finish()?;
+        program.validate()?;
+
+        if entry as usize >= program.blocks.len() {
+            return Err(ProgramError::Invalid(format!("entry block {} out of range", entry)));
+        }
+
+        let mut idents = HashMap::new();
+        for (id, name) in &program.idents {
+            idents.insert(*id, self.store_idt(name.clone()));
+        }
+        let idt = |i: &u32| match idents.get(i) {
+            Some(i) => *i,
+            None => *i
+        };
+
+        let const_offset = self.constants.len() as u32;
+        let blk_offset = self.code_blocks.len() as u32;
+        let source_offset = self.sources.len() as u32;
+
+        self.constants.extend(program.constants);
+        self.sources.extend(program.sources);
+
+        for (block, lines) in program.blocks.into_iter().zip(program.line_tables) {
+            let block = block.into_iter().map(|c| match c {
+                ByteCode::LoadConst(i) => ByteCode::LoadConst(i + const_offset),
+                ByteCode::Variant(i) => ByteCode::Variant(i + const_offset),
+                ByteCode::Matches(i) => ByteCode::Matches(i + const_offset),
+                ByteCode::Fun(i) => ByteCode::Fun(i + blk_offset),
+                ByteCode::Load(i) => ByteCode::Load(idt(&i)),
+                ByteCode::LoadCallee(i) => ByteCode::LoadCallee(idt(&i)),
+                ByteCode::Store(i) => ByteCode::Store(idt(&i)),
+                ByteCode::Var(i) => ByteCode::Var(idt(&i)),
+                ByteCode::AccessProp(i) => ByteCode::AccessProp(idt(&i)),
+                ByteCode::StoreProp(i) => ByteCode::StoreProp(idt(&i)),
+                c => c
+            }).collect();
+
+            self.code_blocks.push(block);
+            self.line_tables.push(LineTable {
+                source: lines.source.map(|s| s + source_offset),
+                entries: lines.entries,
+                lets: lines.lets.iter().map(|(i, start, end)| (idt(i), *start, *end)).collect()
+            });
+        }
+
+        Ok(entry + blk_offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RunResult;
+
+    const CODE: &str = r#"
+    double = (x) => {
+        return x * 2
+    }
+    res = []
+    for a in [1, 2, 3] {
+        res.push(double(a))
+    }
+    return res
+    "#;
+
+    fn saved(code: &str) -> Vec<u8> {
+        let mut vm = Vm::new();
+        let blk = vm.compile_code(code);
+        vm.save_program(blk)
+    }
+
+    #[test]
+    fn run_loaded_program() {
+        let bytes = saved(CODE);
+        let mut vm = Vm::new();
+        let blk = vm.load_program(&bytes).unwrap();
+        assert_eq!(vm.run_blk(blk, Value::None), RunResult::Value(Value::List(vec![
+            Value::Int(2),
+            Value::Int(4),
+            Value::Int(6),
+        ])));
+    }
+
+    #[test]
+    fn run_loaded_enums() {
+        let code = "enum E { A(int), B { b: string }, C }\nv = [E::A(1), E::B { b: \"x\" }, E::C]\nmatch v[1] {\n    E::B { b } => v.push(b)\n}\nreturn v";
+        let bytes = saved(code);
+        let mut vm = Vm::new();
+        let blk = vm.load_program(&bytes).unwrap();
+        match vm.run_blk(blk, Value::None) {
+            RunResult::Value(v) => assert_eq!(v.to_string(), r#"[E::A(1), E::B { b: "x" }, E::C, "x"]"#),
+            r => panic!("Expected a value but got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn identifiers_matched_by_name() {
+        let bytes = saved("return limit + 1");
+        let mut vm = Vm::new();
+        vm.set_global("other", Value::Int(0));
+        vm.set_global("limit", Value::Int(41));
+        let blk = vm.load_program(&bytes).unwrap();
+        assert_eq!(vm.run_blk(blk, Value::None), RunResult::Value(Value::Int(42)));
+    }
+
+    #[test]
+    fn keeps_source_lines() {
+        let bytes = saved("a = 1\nreturn a");
+        let mut vm = Vm::new();
+        let blk = vm.load_program(&bytes).unwrap();
+        assert!(vm.disassemble(blk).contains("     2 | return a"));
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        let mut bytes = saved(CODE);
+        let mut vm = Vm::new();
+        assert_eq!(vm.load_program(b"nope"), Err(ProgramError::BadMagic));
+        assert_eq!(vm.load_program(&bytes[..bytes.len() - 1]), Err(ProgramError::Truncated));
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        assert_eq!(vm.load_program(&bytes), Err(ProgramError::ChecksumMismatch));
+
+        bytes[4] = 9;
+        assert_eq!(vm.load_program(&bytes), Err(ProgramError::UnsupportedVersion(9)));
+    }
+
+    fn program(code: Vec<ByteCode>) -> Vec<u8> {
+        let mut vm = Vm::new();
+        vm.store_const(Value::Int(1));
+        vm.code_blocks.push(code);
+        vm.line_tables.push(LineTable::default());
+        vm.save_program(0)
+    }
+
+    #[test]
+    fn rejects_invalid_code() {
+        let mut vm = Vm::new();
+
+        let err = vm.load_program(&program(vec![ByteCode::Jump(5)])).unwrap_err();
+        assert_eq!(err.to_string(), "invalid program: blk 0 pc 0: jump target 5 out of range");
+
+        let err = vm.load_program(&program(vec![ByteCode::LoadConst(1)])).unwrap_err();
+        assert_eq!(err.to_string(), "invalid program: blk 0 pc 0: constant 1 out of range");
+
+        let err = vm.load_program(&program(vec![ByteCode::Load(30)])).unwrap_err();
+        assert_eq!(err.to_string(), "invalid program: blk 0 pc 0: unknown identifier 30");
+
+        let err = vm.load_program(&program(vec![ByteCode::Fun(1)])).unwrap_err();
+        assert_eq!(err.to_string(), "invalid program: blk 0 pc 0: function block 1 out of range");
+
+        assert_eq!(vm.block_count(), 0);
+    }
+
+    /// A list nested `depth` times around none.
+    fn nested_lists(depth: usize) -> Vec<u8> {
+        let mut bytes = vec![];
+        for _ in 0..depth {
+            bytes.push(5);
+            bytes.extend_from_slice(&1u32.to_le_bytes());
+        }
+        bytes.push(0);
+        bytes
+    }
+
+    #[test]
+    fn rejects_deeply_nested_values() {
+        // entry, no identifiers and one constant
+        let mut payload = vec![0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0];
+        payload.extend(nested_lists(200_000));
+        let bytes = seal(PROGRAM_MAGIC, PROGRAM_VERSION, payload);
+        let err = Vm::new().load_program(&bytes).unwrap_err();
+        assert_eq!(err.to_string(), "invalid program: constant tag 5 is not a literal");
+
+        let bytes = nested_lists(200_000);
+        let err = Reader::new(&bytes).value().unwrap_err();
+        assert_eq!(err.to_string(), "invalid program: values nested too deeply");
+
+        let bytes = nested_lists(10);
+        assert!(Reader::new(&bytes).value().is_ok());
+    }
+}
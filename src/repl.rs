@@ -0,0 +1,318 @@
+use std::any::Any;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+
+use crate::is_incomplete;
+use crate::ASTNode;
+use crate::Parser;
+use crate::Ret;
+use crate::RunResult;
+use crate::Value;
+use crate::Vm;
+
+const HELP: &str = ":ast [code]    print the syntax tree of the code or the last input
+:bytecode      print the bytecode compiled from the last input
+:history       print the inputs of this session
+:reset         forget all variables
+:help          print this help
+:quit          exit the repl";
+
+const PRETTY_WIDTH: usize = 60;
+
+/// Message of a script error raised as a panic inside the vm.
+pub(crate) fn panic_message(err: &(dyn Any + Send)) -> String {
+    match err.downcast_ref::<&str>() {
+        Some(s) => s.to_string(),
+        None => match err.downcast_ref::<String>() {
+            Some(s) => s.clone(),
+            None => "unknown error".to_string()
+        }
+    }
+}
+
+/// Formats a value on one line when it is short and indented over
+/// several lines otherwise.
+pub fn pretty(val: &Value) -> String {
+    let mut out = String::new();
+    write_pretty(&mut out, val, 0);
+    out
+}
+
+fn write_pretty(out: &mut String, val: &Value, indent: usize) {
+    let line = val.to_string();
+    if line.len() <= PRETTY_WIDTH {
+        out.push_str(&line);
+        return;
+    }
+
+    let pad = "    ".repeat(indent + 1);
+
+    match val {
+        Value::List(list) => {
+            out.push_str("[\n");
+            for item in list {
+                out.push_str(&pad);
+                write_pretty(out, item, indent + 1);
+                out.push_str(",\n");
+            }
+            out.push_str(&"    ".repeat(indent));
+            out.push(']');
+        },
+        Value::Obj(obj) => {
+            if let Some(name) = &obj.name {
+                out.push_str(name);
+                out.push(' ');
+            }
+            out.push_str("{\n");
+            for prop in &obj.props {
+                out.push_str(&pad);
+                out.push_str(&prop.name);
+                out.push_str(": ");
+                write_pretty(out, &prop.value, indent + 1);
+                out.push_str(",\n");
+            }
+            out.push_str(&"    ".repeat(indent));
+            out.push('}');
+        },
+        _ => out.push_str(&line)
+    }
+}
+
+/// Returns the value of a trailing expression, so typing `a + 1`
+/// prints the result like `return a + 1` would.
+fn return_last_expr(mut ast: Vec<ASTNode>) -> Vec<ASTNode> {
+    let is_expr = match ast.last() {
+        Some(node) => !matches!(
+            node,
+            ASTNode::Assign(_) |
+            ASTNode::Decl(_) |
+            ASTNode::Ret(_) |
+            ASTNode::If(_) |
+            ASTNode::For(_) |
+            ASTNode::StructDef(_) |
+            ASTNode::EnumDef(_) |
+            ASTNode::Match(_) |
+            ASTNode::TypeDef(_) |
+            ASTNode::Test(_) |
+            ASTNode::Module(_) |
+            ASTNode::Import(_) |
+            ASTNode::Use(_) |
+            ASTNode::Export(_)
+        ),
+        None => false
+    };
+
+    if is_expr {
+        let node = ast.pop().unwrap();
+        ast.push(ASTNode::Ret(Ret {
+            value: Box::new(Some(node))
+        }));
+    }
+
+    ast
+}
+
+pub enum ReplOutput {
+    /// Input is not finished, another line is needed.
+    Continue,
+    Text(String),
+    Quit,
+}
+
+/// Interactive session where successive inputs share one scope.
+pub struct Repl {
+    vm: Vm,
+    buffer: String,
+    history: Vec<String>,
+    last_input: Option<String>,
+    last_blk: Option<u32>,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self {
+            vm: Vm::new(),
+            buffer: String::new(),
+            history: Vec::new(),
+            last_input: None,
+            last_blk: None,
+        }
+    }
+
+    pub fn vm(&mut self) -> &mut Vm {
+        &mut self.vm
+    }
+
+    pub fn prompt(&self) -> &'static str {
+        if self.buffer.is_empty() {
+            ">> "
+        } else {
+            ".. "
+        }
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    pub fn feed(&mut self, line: &str) -> ReplOutput {
+        if self.buffer.is_empty() && line.trim_start().starts_with(':') {
+            return self.command(line.trim());
+        }
+
+        if !self.buffer.is_empty() {
+            self.buffer.push('\n');
+        }
+        self.buffer.push_str(line);
+
+        if is_incomplete(&self.buffer) {
+            return ReplOutput::Continue;
+        }
+
+        let input = std::mem::take(&mut self.buffer);
+        if input.trim().is_empty() {
+            return ReplOutput::Text(String::new());
+        }
+
+        self.history.push(input.clone());
+        self.last_input = Some(input.clone());
+
+        ReplOutput::Text(self.eval(&input))
+    }
+
+    fn eval(&mut self, input: &str) -> String {
+        let vm = &mut self.vm;
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut parser = Parser::new(input);
+            let ast = return_last_expr(parser.parse());
+            let blk = vm.compile_with_lines(&ast, input, parser.stmt_lines());
+            (blk, vm.eval_blk(blk))
+        }));
+
+        match res {
+            Ok((blk, res)) => {
+                self.last_blk = Some(blk);
+                match res {
+                    RunResult::Value(v) => pretty(&v),
+                    RunResult::Await { value, .. } => format!("awaiting {}", pretty(&value)),
+                    RunResult::Paused { blk, pc, .. } => format!("paused at blk {} pc {}", blk, pc),
+                    RunResult::None => String::new()
+                }
+            },
+            Err(e) => format!("error: {}", panic_message(e.as_ref()))
+        }
+    }
+
+    fn command(&mut self, line: &str) -> ReplOutput {
+        let (cmd, arg) = match line.split_once(' ') {
+            Some((cmd, arg)) => (cmd, arg.trim()),
+            None => (line, "")
+        };
+
+        let text = match cmd {
+            ":ast" => {
+                let code = if arg.is_empty() {
+                    self.last_input.clone().unwrap_or_default()
+                } else {
+                    arg.to_string()
+                };
+                match panic::catch_unwind(|| Parser::new(&code).parse()) {
+                    Ok(ast) => format!("{:#?}", ast),
+                    Err(_) => "error: invalid syntax".to_string()
+                }
+            },
+            ":bytecode" => {
+                match self.last_blk {
+                    Some(blk) => self.vm.disassemble(blk).trim_end().to_string(),
+                    None => String::new()
+                }
+            },
+            ":history" => {
+                self.history.iter()
+                    .enumerate()
+                    .map(|(i, h)| format!("{:>3} {}", i + 1, h))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            },
+            ":reset" => {
+                let log = self.vm.log;
+                let loader = self.vm.loader.take();
+                let std_libs = self.vm.std_libs.clone();
+                self.vm = Vm::new();
+                self.vm.log = log;
+                self.vm.loader = loader;
+                self.vm.open_std(&std_libs);
+                self.last_blk = None;
+                "session reset".to_string()
+            },
+            ":help" => HELP.to_string(),
+            ":quit" | ":q" => return ReplOutput::Quit,
+            _ => format!("unknown command {}, try :help", cmd)
+        };
+
+        ReplOutput::Text(text)
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(out: ReplOutput) -> String {
+        match out {
+            ReplOutput::Text(t) => t,
+            ReplOutput::Continue => panic!("Expected text but got continue"),
+            ReplOutput::Quit => panic!("Expected text but got quit"),
+        }
+    }
+
+    #[test]
+    fn variables_persist_between_inputs() {
+        let mut repl = Repl::new();
+        assert_eq!(text(repl.feed("a = 1")), "");
+        assert_eq!(text(repl.feed("b = [a, 2]")), "");
+        assert_eq!(text(repl.feed("a + 1")), "2");
+        assert_eq!(text(repl.feed("b")), "[1, 2]");
+    }
+
+    #[test]
+    fn continuation_lines() {
+        let mut repl = Repl::new();
+        assert!(matches!(repl.feed("f = (x) => {"), ReplOutput::Continue));
+        assert_eq!(repl.prompt(), ".. ");
+        assert!(matches!(repl.feed("return x * 2"), ReplOutput::Continue));
+        assert_eq!(text(repl.feed("}")), "");
+        assert_eq!(text(repl.feed("f(4)")), "8");
+        assert_eq!(repl.history().len(), 2);
+    }
+
+    #[test]
+    fn reset_forgets_variables() {
+        let mut repl = Repl::new();
+        repl.feed("a = 1");
+        assert_eq!(text(repl.feed(":reset")), "session reset");
+        assert_eq!(text(repl.feed("a")), "error: undefined variable a");
+    }
+
+    #[test]
+    fn bytecode_of_last_input() {
+        let mut repl = Repl::new();
+        repl.feed("1 + 2");
+        let out = text(repl.feed(":bytecode"));
+        assert!(out.contains("BinAdd"));
+    }
+
+    #[test]
+    fn pretty_long_object() {
+        let mut repl = Repl::new();
+        let out = text(repl.feed(r#"Person { name: "a long name for a person", city: "a long city name too" }"#));
+        assert!(out.starts_with("Person {\n    "));
+        assert!(out.ends_with(",\n}"));
+    }
+}
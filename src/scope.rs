@@ -2,7 +2,11 @@ use std::collections::HashMap;
 use crate::Obj;
 use crate::ObjProp;
 use crate::Ptr;
+use crate::StackValue;
 use crate::Value;
+use crate::program::ProgramError;
+use crate::program::Reader;
+use crate::program::Writer;
 
 const UNAMED_VAR_ID: u32 = 2147483648;
 
@@ -64,6 +68,15 @@ impl ScopeManager {
         }
     }
 
+    /// Places a value on the stack, moving lists and objects into an
+    /// unnamed slot of the given scope.
+    pub fn alloc(&mut self, scope_id: u32, val: Value) -> StackValue {
+        match val {
+            Value::List(_) | Value::Obj(_) | Value::Enum(_) => StackValue::Ptr(self.store_unamed(scope_id, val)),
+            _ => StackValue::from(&val)
+        }
+    }
+
     pub fn remove_var(&mut self, scope_id: u32, var_id: &u32) {
         let scope = self.scopes.get_mut(scope_id as usize).unwrap();
         scope.remove_var(var_id);
@@ -122,6 +135,68 @@ impl ScopeManager {
         self.scopes.get(scope_id as usize).is_some()
     }
 
+    /// Variables declared by name in the scope itself, without parents.
+    pub fn named_vars(&self, scope_id: u32) -> Vec<(u32, Value)> {
+        match self.scopes.get(scope_id as usize) {
+            Some(scope) => scope.vars.iter()
+                .filter(|(id, _)| **id < UNAMED_VAR_ID)
+                .map(|(id, val)| (*id, val.clone()))
+                .collect(),
+            None => vec![]
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.scopes.len()
+    }
+
+    pub(crate) fn write(&self, w: &mut Writer) -> Result<(), ProgramError> {
+        w.u32(self.scopes.len() as u32);
+        for scope in &self.scopes {
+            w.opt_u32(scope.parent_id);
+            w.u32(scope.unamed_var_id);
+            w.u32(scope.unused_unamed_ids.len() as u32);
+            for id in &scope.unused_unamed_ids {
+                w.u32(*id);
+            }
+
+            let mut vars = scope.vars.iter().collect::<Vec<_>>();
+            vars.sort_by_key(|(id, _)| **id);
+            w.u32(vars.len() as u32);
+            for (id, val) in vars {
+                w.u32(*id);
+                w.value(val)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read(r: &mut Reader) -> Result<Self, ProgramError> {
+        let mut scopes = vec![];
+        for _ in 0..r.len()? {
+            let mut scope = Scope::new(r.opt_u32()?);
+            scope.unamed_var_id = r.u32()?;
+            for _ in 0..r.len()? {
+                scope.unused_unamed_ids.push(r.u32()?);
+            }
+            for _ in 0..r.len()? {
+                let id = r.u32()?;
+                scope.vars.insert(id, r.value()?);
+            }
+            scopes.push(scope);
+        }
+
+        for (i, scope) in scopes.iter().enumerate() {
+            if let Some(parent) = scope.parent_id {
+                if parent as usize >= scopes.len() {
+                    return Err(ProgramError::Invalid(format!("scope {}: parent {} out of range", i, parent)));
+                }
+            }
+        }
+
+        Ok(Self { scopes })
+    }
+
     pub fn copy_value(&mut self, val: Value) -> Value  {
         match val {
             Value::Ptr(ptr) => {
@@ -144,6 +219,12 @@ impl ScopeManager {
             Value::List(list) => {
                 Value::List(list.iter().map(|item| self.copy_value(item.clone())).collect())
             },
+            Value::Enum(mut variant) => {
+                for field in variant.values_mut() {
+                    *field = self.copy_value(field.clone());
+                }
+                Value::Enum(variant)
+            },
             _ => val
         }
     }
@@ -167,6 +248,12 @@ impl ScopeManager {
                     }
                 )
             },
+            Value::Enum(mut variant) => {
+                for field in variant.values_mut() {
+                    *field = self.move_value(field.clone(), to_scope_id);
+                }
+                Value::Enum(variant)
+            },
             _ => val
         }
     }
@@ -254,7 +341,7 @@ mod tests {
                         name: "a".to_string(),
                         value: Value::Int(10)
                     }
-                ]
+                ].into()
             }
         ));
         let new_ptr = scope_manager.move_to(&ptr, scope_id);
@@ -268,7 +355,7 @@ mod tests {
                         name: "a".to_string(),
                         value: Value::Int(10)
                     }
-                ]
+                ].into()
             }
         ), *val);
         assert_eq!(new_ptr.scope_id, scope_id);
@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+
+use crate::callstack::Callstack;
+use crate::program::seal;
+use crate::program::unseal;
+use crate::program::Program;
+use crate::program::ProgramError;
+use crate::program::Reader;
+use crate::program::Writer;
+use crate::scope::ScopeManager;
+use crate::stdlib::Native;
+use crate::Vm;
+
+pub const SNAPSHOT_MAGIC: &[u8; 4] = b"FLXS";
+pub const SNAPSHOT_VERSION: u16 = 5;
+
+impl Vm {
+    /// Serializes the complete state of the vm: compiled code, every
+    /// scope with its values and every callstack, including the ones
+    /// suspended at an await. Fails if a scope or stack holds userdata,
+    /// as host objects cannot leave the process.
+    pub fn snapshot(&self) -> Result<Vec<u8>, ProgramError> {
+        let mut w = Writer::new();
+        self.write_code(&mut w);
+
+        w.u32(self.next_idt);
+        w.u32(self.globals);
+        w.opt_u32(self.session);
+        self.scope.write(&mut w)?;
+
+        let mut modules: Vec<_> = self.module_blocks.iter().collect();
+        modules.sort();
+        w.u32(modules.len() as u32);
+        for (name, blk) in modules {
+            w.str(name);
+            w.u32(*blk);
+        }
+
+        let mut home_scopes: Vec<_> = self.home_scopes.iter().collect();
+        home_scopes.sort();
+        w.u32(home_scopes.len() as u32);
+        for (blk, scope_id) in home_scopes {
+            w.u32(*blk);
+            w.u32(*scope_id);
+        }
+
+        w.u32(self.natives.len() as u32);
+        for native in &self.natives {
+            w.str(&native.name);
+        }
+
+        w.u32(self.callstacks.len() as u32);
+        for stack in &self.callstacks {
+            stack.write(&mut w)?;
+        }
+
+        Ok(seal(SNAPSHOT_MAGIC, SNAPSHOT_VERSION, w.finish()))
+    }
+
+    /// Rebuilds a vm from a snapshot. A script which was suspended when
+    /// the snapshot was taken is resumed with `cont` and its stack id.
+    /// Userdata types have to be registered again on the new vm.
+    pub fn restore(bytes: &[u8]) -> Result<Vm, ProgramError> {
+        let payload = unseal(SNAPSHOT_MAGIC, SNAPSHOT_VERSION, bytes)?;
+        let mut r = Reader::new(payload);
+
+        let program = Program::read(&mut r)?;
+        program.validate()?;
+
+        let next_idt = r.u32()?;
+        let globals = r.u32()?;
+        let session = r.opt_u32()?;
+        let scope = ScopeManager::read(&mut r)?;
+
+        let mut module_blocks = HashMap::new();
+        for _ in 0..r.len()? {
+            let name = r.str()?;
+            module_blocks.insert(name, r.u32()?);
+        }
+
+        let mut home_scopes = HashMap::new();
+        for _ in 0..r.len()? {
+            let blk = r.u32()?;
+            home_scopes.insert(blk, r.u32()?);
+        }
+
+        // Natives are registered again by the host, by name
+        let mut natives = vec![];
+        for _ in 0..r.len()? {
+            natives.push(Native { name: r.str()?, f: None });
+        }
+
+        let mut callstacks = vec![];
+        for _ in 0..r.len()? {
+            callstacks.push(Callstack::read(&mut r)?);
+        }
+        r.finish()?;
+
+        let invalid = |msg: String| Err(ProgramError::Invalid(msg));
+
+        for id in [Some(globals), session].into_iter().flatten() {
+            if id as usize >= scope.count() {
+                return invalid(format!("scope {} out of range", id));
+            }
+        }
+
+        for blk in module_blocks.values().chain(home_scopes.keys()) {
+            if *blk as usize >= program.blocks.len() {
+                return invalid(format!("module block {} out of range", blk));
+            }
+        }
+        for id in home_scopes.values() {
+            if *id as usize >= scope.count() {
+                return invalid(format!("scope {} out of range", id));
+            }
+        }
+
+        for (i, stack) in callstacks.iter().enumerate() {
+            for call in stack.frames() {
+                if call.blk as usize >= program.blocks.len() {
+                    return invalid(format!("stack {}: block {} out of range", i, call.blk));
+                }
+                if call.pc as usize > program.blocks[call.blk as usize].len() {
+                    return invalid(format!("stack {}: pc {} out of range", i, call.pc));
+                }
+                if call.scope_id as usize >= scope.count() {
+                    return invalid(format!("stack {}: scope {} out of range", i, call.scope_id));
+                }
+            }
+        }
+
+        let mut vm = Vm::new();
+        for (id, name) in program.idents {
+            vm.idt_map.insert(name.clone(), id);
+            vm.id_idt_map.insert(id, name);
+        }
+        vm.constants = program.constants;
+        vm.sources = program.sources;
+        vm.code_blocks = program.blocks;
+        vm.line_tables = program.line_tables;
+        vm.next_idt = next_idt;
+        vm.globals = globals;
+        vm.session = session;
+        vm.scope = scope;
+        vm.callstacks = callstacks;
+        vm.module_blocks = module_blocks;
+        vm.home_scopes = home_scopes;
+        vm.natives = natives;
+
+        Ok(vm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::RunResult;
+    use crate::Value;
+    use crate::Vm;
+
+    fn suspended(res: RunResult) -> (usize, Value) {
+        match res {
+            RunResult::Await { stack_id, value } => (stack_id, value),
+            _ => panic!("Expected await but got {:?}", res)
+        }
+    }
+
+    #[test]
+    fn resume_in_new_vm() {
+        let mut vm = Vm::new();
+        let res = vm.run_code(r#"
+        order = Order { id: 7 }
+        items = ["a", "b"]
+        approved = await(approval(order.id))
+        items.push("c")
+        return [approved, items]
+        "#);
+        let (stack_id, value) = suspended(res);
+
+        let bytes = vm.snapshot().unwrap();
+        drop(vm);
+
+        let mut vm = Vm::restore(&bytes).unwrap();
+        match value {
+            Value::UndefCall { ident, args } => {
+                assert_eq!(vm.idt_name(ident), Some("approval"));
+                assert_eq!(args, vec![Value::Int(7)]);
+            },
+            _ => panic!("Invalid await value")
+        }
+
+        let res = vm.cont(stack_id, Value::Bool(true));
+        assert_eq!(res, RunResult::Value(Value::List(vec![
+            Value::Bool(true),
+            Value::List(vec![
+                Value::Str("a".to_string()),
+                Value::Str("b".to_string()),
+                Value::Str("c".to_string()),
+            ])
+        ])));
+    }
+
+    #[test]
+    fn resume_inside_map() {
+        let mut vm = Vm::new();
+        let res = vm.run_code(r#"
+        res = [1, 2].map((inx, x) => {
+            return await(fetch(x))
+        })
+        return res
+        "#);
+        let (stack_id, _) = suspended(res);
+
+        let mut vm = Vm::restore(&vm.snapshot().unwrap()).unwrap();
+        let (stack_id, _) = suspended(vm.cont(stack_id, Value::Int(10)));
+
+        let mut vm = Vm::restore(&vm.snapshot().unwrap()).unwrap();
+        let res = vm.cont(stack_id, Value::Int(20));
+        assert_eq!(res, RunResult::Value(Value::List(vec![
+            Value::Int(10),
+            Value::Int(20),
+        ])));
+    }
+
+    #[test]
+    fn resume_inside_sort() {
+        let mut vm = Vm::new();
+        let mut res = vm.run_code(r#"
+        items = [3, 1, 2]
+        return items.sort((a, b) => {
+            return await(compare(a, b))
+        })
+        "#);
+
+        while let RunResult::Await { stack_id, value } = res {
+            let order = match value {
+                Value::UndefCall { args, .. } => match (&args[0], &args[1]) {
+                    (Value::Int(a), Value::Int(b)) => a - b,
+                    _ => panic!("Invalid args {:?}", args)
+                },
+                _ => panic!("Invalid await value")
+            };
+            vm = Vm::restore(&vm.snapshot().unwrap()).unwrap();
+            res = vm.cont(stack_id, Value::Int(order));
+        }
+
+        assert_eq!(res, RunResult::Value(Value::List(vec![
+            Value::Int(1),
+            Value::Int(2),
+            Value::Int(3),
+        ])));
+    }
+
+    #[test]
+    fn restore_keeps_globals_and_session() {
+        let mut vm = Vm::new();
+        vm.set_global("base", Value::Int(40));
+        vm.eval("a = 2");
+
+        let mut vm = Vm::restore(&vm.snapshot().unwrap()).unwrap();
+        assert_eq!(vm.eval("return base + a"), RunResult::Value(Value::Int(42)));
+    }
+
+    #[test]
+    fn resume_inside_module_function() {
+        let mut loader = crate::MemoryLoader::new();
+        loader.add("orders", "base = 100
+export total = id => {
+    v = await(price(id))
+    return v + base
+}");
+        let mut vm = Vm::new();
+        vm.set_loader(loader);
+        let (stack_id, _) = suspended(vm.run_code("import orders\nreturn orders.total(1)"));
+
+        let mut vm = Vm::restore(&vm.snapshot().unwrap()).unwrap();
+        assert_eq!(vm.cont(stack_id, Value::Int(5)), RunResult::Value(Value::Int(105)));
+
+        let before = vm.block_count();
+        vm.run_code("import orders");
+        assert_eq!(vm.block_count(), before + 1);
+    }
+
+    #[test]
+    fn natives_are_registered_again() {
+        let mut vm = Vm::new();
+        vm.open_std(&[crate::StdLib::Math]);
+        let (stack_id, _) = suspended(vm.run_code("sqrt = std.math.sqrt\nn = await(size())\nreturn sqrt(n)"));
+        let bytes = vm.snapshot().unwrap();
+
+        let mut vm = Vm::restore(&bytes).unwrap();
+        vm.open_std(&[crate::StdLib::Math]);
+        assert_eq!(vm.cont(stack_id, Value::Int(9)), RunResult::Value(Value::Float(3.0)));
+
+        let mut vm = Vm::restore(&bytes).unwrap();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| vm.cont(stack_id, Value::Int(9))));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn rejects_userdata_and_programs() {
+        struct Handle;
+        impl crate::UserData for Handle {
+            fn add_methods(_: &mut crate::UserDataMethods<Self>) {}
+        }
+
+        let mut vm = Vm::new();
+        let blk = vm.compile_code("return 1");
+        assert!(Vm::restore(&vm.save_program(blk)).is_err());
+
+        let handle = vm.create_userdata(Handle);
+        vm.set_global("handle", handle);
+        assert!(vm.snapshot().is_err());
+    }
+}
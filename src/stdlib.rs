@@ -0,0 +1,402 @@
+use std::rc::Rc;
+
+use crate::value_kind;
+use crate::Obj;
+use crate::ObjProp;
+use crate::Value;
+use crate::Vm;
+
+/// Function implemented by the host and called synchronously by scripts.
+pub type NativeFn = Rc<dyn Fn(Vec<Value>) -> Result<Value, String>>;
+
+type StdFn = fn(Vec<Value>) -> Result<Value, String>;
+
+/// A registered native. Restored snapshots know only the names until the
+/// host registers the functions again.
+pub(crate) struct Native {
+    pub(crate) name: String,
+    pub(crate) f: Option<NativeFn>,
+}
+
+/// Parts of the standard library a vm can open.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StdLib {
+    /// `std.print`
+    Io,
+    /// `std.len`, `std.type_of`, `std.to_string`, `std.parse_int` and
+    /// `std.parse_float`
+    Core,
+    /// `std.math`
+    Math,
+}
+
+impl StdLib {
+    pub const ALL: [StdLib; 3] = [StdLib::Io, StdLib::Core, StdLib::Math];
+}
+
+pub(crate) fn call_native(natives: &[Native], id: u32, args: Vec<Value>) -> Value {
+    let native = match natives.get(id as usize) {
+        Some(n) => n,
+        None => panic!("Unknown native function {}", id)
+    };
+    let f = match &native.f {
+        Some(f) => f,
+        None => panic!("Native function {} is not registered", native.name)
+    };
+
+    match f(args) {
+        Ok(v) => v,
+        Err(e) => panic!("{}: {}", native.name, e)
+    }
+}
+
+impl Vm {
+    /// Registers a host function under a unique name and returns the value
+    /// scripts call it through. Registering a name again replaces the
+    /// function but keeps the value, which is how natives are restored
+    /// after `Vm::restore`.
+    pub fn register_native<F>(&mut self, name: &str, f: F) -> Value
+    where
+        F: Fn(Vec<Value>) -> Result<Value, String> + 'static
+    {
+        let f: NativeFn = Rc::new(f);
+        match self.natives.iter().position(|n| n.name == name) {
+            Some(i) => {
+                self.natives[i].f = Some(f);
+                Value::Native(i as u32)
+            },
+            None => {
+                self.natives.push(Native { name: name.to_string(), f: Some(f) });
+                Value::Native((self.natives.len() - 1) as u32)
+            }
+        }
+    }
+
+    /// Makes the given parts of the standard library available as the
+    /// global `std`. Parts opened earlier are kept.
+    pub fn open_std(&mut self, libs: &[StdLib]) {
+        for lib in libs {
+            if !self.std_libs.contains(lib) {
+                self.std_libs.push(*lib);
+            }
+        }
+        if self.std_libs.is_empty() {
+            return;
+        }
+
+        let mut props = vec![];
+        for lib in StdLib::ALL {
+            if !self.std_libs.contains(&lib) {
+                continue;
+            }
+
+            match lib {
+                StdLib::Io => {
+                    props.push(self.std_fn("print", print));
+                },
+                StdLib::Core => {
+                    props.push(self.std_fn("len", len));
+                    props.push(self.std_fn("type_of", type_of));
+                    props.push(self.std_fn("to_string", to_string));
+                    props.push(self.std_fn("parse_int", parse_int));
+                    props.push(self.std_fn("parse_float", parse_float));
+                },
+                StdLib::Math => {
+                    let math = self.math();
+                    props.push(ObjProp { name: "math".to_string(), value: math });
+                },
+            }
+        }
+
+        self.set_global("std", Value::Obj(Obj {
+            name: Some("std".to_string()),
+            props: props.into(),
+        }));
+    }
+
+    /// Whether `import std` refers to the built-in library.
+    pub(crate) fn has_std(&self) -> bool {
+        !self.std_libs.is_empty()
+    }
+
+    fn math(&mut self) -> Value {
+        let mut props = vec![
+            ObjProp { name: "pi".to_string(), value: Value::Float(std::f64::consts::PI) },
+            ObjProp { name: "e".to_string(), value: Value::Float(std::f64::consts::E) },
+        ];
+
+        let natives: [(&str, StdFn); 17] = [
+            ("abs", abs),
+            ("min", min),
+            ("max", max),
+            ("clamp", clamp),
+            ("floor", |args| rounded(args, f64::floor)),
+            ("ceil", |args| rounded(args, f64::ceil)),
+            ("round", |args| rounded(args, f64::round)),
+            ("sqrt", |args| float_fn(args, f64::sqrt)),
+            ("pow", pow),
+            ("sin", |args| float_fn(args, f64::sin)),
+            ("cos", |args| float_fn(args, f64::cos)),
+            ("tan", |args| float_fn(args, f64::tan)),
+            ("asin", |args| float_fn(args, f64::asin)),
+            ("acos", |args| float_fn(args, f64::acos)),
+            ("atan", |args| float_fn(args, f64::atan)),
+            ("atan2", atan2),
+            ("hypot", hypot),
+        ];
+        for (name, f) in natives {
+            props.push(self.std_fn(&format!("math.{}", name), f));
+        }
+
+        Value::Obj(Obj {
+            name: Some("math".to_string()),
+            props: props.into(),
+        })
+    }
+
+    fn std_fn(&mut self, path: &str, f: StdFn) -> ObjProp {
+        let value = self.register_native(&format!("std.{}", path), f);
+        let name = match path.rsplit_once('.') {
+            Some((_, name)) => name,
+            None => path
+        };
+        ObjProp { name: name.to_string(), value }
+    }
+}
+
+fn arity(args: &[Value], n: usize) -> Result<(), String> {
+    match args.len() == n {
+        true => Ok(()),
+        false => Err(format!("expected {} arguments but got {}", n, args.len()))
+    }
+}
+
+fn number(val: &Value) -> Result<f64, String> {
+    match val {
+        Value::Int(i) => Ok(*i as f64),
+        Value::Float(f) => Ok(*f),
+        _ => Err(format!("expected a number but got {}", value_kind(val)))
+    }
+}
+
+/// Text of a value as `print` shows it, strings without quotes.
+pub(crate) fn text(val: &Value) -> String {
+    match val {
+        Value::Str(s) => s.clone(),
+        _ => val.to_string()
+    }
+}
+
+fn print(args: Vec<Value>) -> Result<Value, String> {
+    let line = args.iter().map(text).collect::<Vec<_>>().join(" ");
+    println!("{}", line);
+    Ok(Value::None)
+}
+
+fn len(args: Vec<Value>) -> Result<Value, String> {
+    arity(&args, 1)?;
+    let n = match &args[0] {
+        Value::Str(s) => s.chars().count(),
+        Value::List(l) => l.len(),
+        Value::Obj(o) => o.props.len(),
+        v => return Err(format!("{} has no length", value_kind(v)))
+    };
+    Ok(Value::Int(n as i64))
+}
+
+fn type_of(args: Vec<Value>) -> Result<Value, String> {
+    arity(&args, 1)?;
+    Ok(Value::Str(value_kind(&args[0]).to_string()))
+}
+
+fn to_string(args: Vec<Value>) -> Result<Value, String> {
+    arity(&args, 1)?;
+    Ok(Value::Str(text(&args[0])))
+}
+
+fn parse_int(args: Vec<Value>) -> Result<Value, String> {
+    arity(&args, 1)?;
+    match &args[0] {
+        Value::Str(s) => Ok(s.trim().parse().map(Value::Int).unwrap_or(Value::None)),
+        v => Err(format!("expected a string but got {}", value_kind(v)))
+    }
+}
+
+fn parse_float(args: Vec<Value>) -> Result<Value, String> {
+    arity(&args, 1)?;
+    match &args[0] {
+        Value::Str(s) => Ok(s.trim().parse().map(Value::Float).unwrap_or(Value::None)),
+        v => Err(format!("expected a string but got {}", value_kind(v)))
+    }
+}
+
+fn abs(args: Vec<Value>) -> Result<Value, String> {
+    arity(&args, 1)?;
+    match &args[0] {
+        Value::Int(i) => match i.checked_abs() {
+            Some(v) => Ok(Value::Int(v)),
+            None => Err("integer overflow".to_string())
+        },
+        v => Ok(Value::Float(number(v)?.abs()))
+    }
+}
+
+/// Ints stay ints, any float makes the result a float.
+fn pick(args: Vec<Value>, smaller: bool) -> Result<Value, String> {
+    let mut best: Option<Value> = None;
+    for arg in args {
+        let n = number(&arg)?;
+        best = match best {
+            Some(b) if (n < number(&b)?) != smaller => Some(b),
+            _ => Some(arg)
+        };
+    }
+    best.ok_or_else(|| "expected at least one argument".to_string())
+}
+
+fn min(args: Vec<Value>) -> Result<Value, String> {
+    pick(args, true)
+}
+
+fn max(args: Vec<Value>) -> Result<Value, String> {
+    pick(args, false)
+}
+
+fn clamp(args: Vec<Value>) -> Result<Value, String> {
+    arity(&args, 3)?;
+    let (v, lo, hi) = (number(&args[0])?, number(&args[1])?, number(&args[2])?);
+    if lo > hi {
+        return Err(format!("min {} is greater than max {}", lo, hi));
+    }
+    let res = match v {
+        v if v < lo => args[1].clone(),
+        v if v > hi => args[2].clone(),
+        _ => args[0].clone()
+    };
+    Ok(res)
+}
+
+fn rounded(args: Vec<Value>, f: fn(f64) -> f64) -> Result<Value, String> {
+    arity(&args, 1)?;
+    match &args[0] {
+        Value::Int(i) => Ok(Value::Int(*i)),
+        v => Ok(Value::Int(f(number(v)?) as i64))
+    }
+}
+
+fn float_fn(args: Vec<Value>, f: fn(f64) -> f64) -> Result<Value, String> {
+    arity(&args, 1)?;
+    Ok(Value::Float(f(number(&args[0])?)))
+}
+
+fn pow(args: Vec<Value>) -> Result<Value, String> {
+    arity(&args, 2)?;
+    match (&args[0], &args[1]) {
+        (Value::Int(b), Value::Int(e)) if *e >= 0 => match u32::try_from(*e).ok().and_then(|e| b.checked_pow(e)) {
+            Some(v) => Ok(Value::Int(v)),
+            None => Err("integer overflow".to_string())
+        },
+        (b, e) => Ok(Value::Float(number(b)?.powf(number(e)?)))
+    }
+}
+
+fn atan2(args: Vec<Value>) -> Result<Value, String> {
+    arity(&args, 2)?;
+    Ok(Value::Float(number(&args[0])?.atan2(number(&args[1])?)))
+}
+
+fn hypot(args: Vec<Value>) -> Result<Value, String> {
+    arity(&args, 2)?;
+    Ok(Value::Float(number(&args[0])?.hypot(number(&args[1])?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+    use std::panic::AssertUnwindSafe;
+
+    use super::*;
+    use crate::repl::panic_message;
+    use crate::RunResult;
+
+    fn run(vm: &mut Vm, code: &str) -> Value {
+        match vm.run_code(code) {
+            RunResult::Value(v) => v,
+            res => panic!("Expected value but got {:?}", res)
+        }
+    }
+
+    #[test]
+    fn math_functions() {
+        let mut vm = Vm::new();
+        vm.open_std(&StdLib::ALL);
+
+        assert_eq!(run(&mut vm, "return std.math.sqrt(16)"), Value::Float(4.0));
+        assert_eq!(run(&mut vm, "return std.math.abs(-3)"), Value::Int(3));
+        assert_eq!(run(&mut vm, "return std.math.max(1, 2.5, 2)"), Value::Float(2.5));
+        assert_eq!(run(&mut vm, "return std.math.min(3, 1, 2)"), Value::Int(1));
+        assert_eq!(run(&mut vm, "return std.math.clamp(12, 0, 10)"), Value::Int(10));
+        assert_eq!(run(&mut vm, "return std.math.floor(2.7)"), Value::Int(2));
+        assert_eq!(run(&mut vm, "return std.math.ceil(2.1)"), Value::Int(3));
+        assert_eq!(run(&mut vm, "return std.math.round(2.5)"), Value::Int(3));
+        assert_eq!(run(&mut vm, "return std.math.pow(2, 10)"), Value::Int(1024));
+        assert_eq!(run(&mut vm, "return std.math.pow(4, 0.5)"), Value::Float(2.0));
+        assert_eq!(run(&mut vm, "return std.math.cos(0)"), Value::Float(1.0));
+        assert_eq!(run(&mut vm, "return std.math.pi"), Value::Float(std::f64::consts::PI));
+    }
+
+    #[test]
+    fn core_functions() {
+        let mut vm = Vm::new();
+        vm.open_std(&[StdLib::Core]);
+
+        assert_eq!(run(&mut vm, "return std.len([1, 2, 3])"), Value::Int(3));
+        assert_eq!(run(&mut vm, "return std.len(\"häst\")"), Value::Int(4));
+        assert_eq!(run(&mut vm, "return std.type_of(1.5)"), Value::Str("float".to_string()));
+        assert_eq!(run(&mut vm, "return std.to_string(12)"), Value::Str("12".to_string()));
+        assert_eq!(run(&mut vm, "return std.to_string(\"a\")"), Value::Str("a".to_string()));
+        assert_eq!(run(&mut vm, "return std.parse_int(\" 42 \")"), Value::Int(42));
+        assert_eq!(run(&mut vm, "return std.parse_int(\"x\")"), Value::None);
+        assert_eq!(run(&mut vm, "return std.parse_float(\"0.5\")"), Value::Float(0.5));
+
+        let code = "import std
+len = std.len
+items = [1, 2]
+return len(items)";
+        assert_eq!(run(&mut vm, code), Value::Int(2));
+    }
+
+    #[test]
+    fn opt_in_parts() {
+        let mut vm = Vm::new();
+        vm.open_std(&[StdLib::Math]);
+        assert_eq!(run(&mut vm, "import std.math\nreturn math.sqrt(4)"), Value::Float(2.0));
+
+        let res = panic::catch_unwind(AssertUnwindSafe(|| vm.run_code("return std.len([1])")));
+        assert_eq!(panic_message(res.unwrap_err().as_ref()), "std has no function len");
+
+        vm.open_std(&[StdLib::Core]);
+        assert_eq!(run(&mut vm, "return std.len([1])"), Value::Int(1));
+        assert_eq!(run(&mut vm, "return std.math.sqrt(9)"), Value::Float(3.0));
+
+        let res = panic::catch_unwind(AssertUnwindSafe(|| vm.run_code("return std.math.sqrt(\"a\")")));
+        assert_eq!(panic_message(res.unwrap_err().as_ref()), "std.math.sqrt: expected a number but got string");
+
+        let res = panic::catch_unwind(AssertUnwindSafe(|| vm.run_code("return std.math.abs(-9223372036854775807 - 1)")));
+        assert_eq!(panic_message(res.unwrap_err().as_ref()), "std.math.abs: integer overflow");
+
+        let res = panic::catch_unwind(AssertUnwindSafe(|| Vm::new().run_code("return std.len([1])")));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn host_natives() {
+        let mut vm = Vm::new();
+        let double = vm.register_native("double", |args| match args.first() {
+            Some(Value::Int(i)) => Ok(Value::Int(i * 2)),
+            _ => Err("expected an int".to_string())
+        });
+        vm.set_global("double", double);
+        assert_eq!(run(&mut vm, "return double(21)"), Value::Int(42));
+    }
+}
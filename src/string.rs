@@ -0,0 +1,167 @@
+use crate::scope::ScopeManager;
+use crate::stdlib::text;
+use crate::value_kind;
+use crate::StackValue;
+use crate::Value;
+
+fn str_arg<'a>(args: &'a [Value], i: usize, method: &str) -> Result<&'a str, String> {
+    match args.get(i) {
+        Some(Value::Str(s)) => Ok(s),
+        Some(v) => Err(format!("{} expects a string but got {}", method, value_kind(v))),
+        None => Err(format!("{} expects a string", method)),
+    }
+}
+
+/// Position `i` counted in chars, negative from the end, clamped to `len`.
+fn char_index(val: Option<&Value>, method: &str, len: usize) -> Result<usize, String> {
+    match val {
+        Some(Value::Int(i)) if *i < 0 => Ok((len as i64 + i).max(0) as usize),
+        Some(Value::Int(i)) => Ok((*i as usize).min(len)),
+        Some(v) => Err(format!("{} expects an int index but got {}", method, value_kind(v))),
+        None => Err(format!("{} expects an index", method)),
+    }
+}
+
+/// Longest string `*` builds, in bytes. A failed allocation aborts the
+/// host, so scripts get an error well before that.
+const MAX_REPEAT_LEN: usize = 1 << 27;
+
+/// `count` copies of `s`, for `*`.
+pub(crate) fn repeat(s: &str, count: i64) -> String {
+    if count < 0 {
+        panic!("Can not repeat a string {} times", count);
+    }
+    match usize::try_from(count).ok().and_then(|c| s.len().checked_mul(c)) {
+        Some(len) if len <= MAX_REPEAT_LEN => s.repeat(count as usize),
+        _ => panic!("Can not repeat a string {} times, the result is too long", count),
+    }
+}
+
+/// Joins two values for `+` when one of them is a string and the other
+/// a string, number or bool.
+pub(crate) fn concat(a: &StackValue, b: &StackValue) -> Option<String> {
+    if !matches!(a, StackValue::Str(_)) && !matches!(b, StackValue::Str(_)) {
+        return None;
+    }
+
+    let part = |v: &StackValue| match v {
+        StackValue::Str(_) | StackValue::Int(_) | StackValue::Float(_) | StackValue::Bool(_) => {
+            Some(text(&Value::from(v.clone())))
+        },
+        _ => None,
+    };
+    Some(part(a)? + &part(b)?)
+}
+
+/// Calls a method on a string. Lengths and positions count Unicode
+/// scalars, not bytes. `args` have their pointers resolved.
+pub(crate) fn call_method(
+    scope: &mut ScopeManager,
+    s: &str,
+    name: &str,
+    args: Vec<Value>,
+    scope_id: u32,
+) -> Result<StackValue, String> {
+    let list = |scope: &mut ScopeManager, parts: Vec<String>| {
+        scope.alloc(scope_id, Value::List(parts.into_iter().map(Value::Str).collect()))
+    };
+
+    let val = match name {
+        "len" => StackValue::Int(s.chars().count() as i64),
+        "split" => {
+            let parts = match args.first() {
+                None => s.split_whitespace().map(String::from).collect(),
+                Some(_) => match str_arg(&args, 0, name)? {
+                    "" => s.chars().map(String::from).collect(),
+                    sep => s.split(sep).map(String::from).collect(),
+                },
+            };
+            list(scope, parts)
+        },
+        "trim" => StackValue::Str(s.trim().to_string()),
+        "upper" => StackValue::Str(s.to_uppercase()),
+        "lower" => StackValue::Str(s.to_lowercase()),
+        "starts_with" => StackValue::Bool(s.starts_with(str_arg(&args, 0, name)?)),
+        "ends_with" => StackValue::Bool(s.ends_with(str_arg(&args, 0, name)?)),
+        "contains" => StackValue::Bool(s.contains(str_arg(&args, 0, name)?)),
+        "replace" => StackValue::Str(s.replace(str_arg(&args, 0, name)?, str_arg(&args, 1, name)?)),
+        "find" => {
+            let pos = s.find(str_arg(&args, 0, name)?)
+                .map(|byte| s[..byte].chars().count() as i64)
+                .unwrap_or(-1);
+            StackValue::Int(pos)
+        },
+        "substring" => {
+            let len = s.chars().count();
+            let start = char_index(args.first(), name, len)?;
+            let end = match args.get(1) {
+                Some(_) => char_index(args.get(1), name, len)?,
+                None => len,
+            };
+            StackValue::Str(s.chars().skip(start).take(end.saturating_sub(start)).collect())
+        },
+        "chars" => list(scope, s.chars().map(String::from).collect()),
+        _ => return Err(format!("string has no method {}", name)),
+    };
+
+    Ok(val)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::run;
+    use crate::test_util::run_err;
+    use crate::test_util::str;
+    use crate::test_util::strs;
+    use crate::Value;
+
+    #[test]
+    fn operators() {
+        assert_eq!(run("name = \"Ada\"\nreturn \"Hello \" + name"), str("Hello Ada"));
+        assert_eq!(run("return \"n: \" + 1"), str("n: 1"));
+        assert_eq!(run("return \"ab\" * 3"), str("ababab"));
+        assert_eq!(run("return 2 * \"é\""), str("éé"));
+        assert_eq!(run("return \"apple\" < \"banana\""), Value::Bool(true));
+        assert_eq!(run("return \"b\" > \"banana\""), Value::Bool(false));
+        assert_eq!(run("return 2 > 1.5"), Value::Bool(true));
+    }
+
+    #[test]
+    fn methods() {
+        assert_eq!(run("s = \"héllo wörld\"\nreturn s.len()"), Value::Int(11));
+        assert_eq!(run("s = \"a,b,,c\"\nreturn s.split(\",\")"), strs(&["a", "b", "", "c"]));
+        assert_eq!(run("s = \" a  b \"\nreturn s.split()"), strs(&["a", "b"]));
+        assert_eq!(run("s = \"  hi \"\nreturn s.trim()"), str("hi"));
+        assert_eq!(run("s = \"Straße\"\nreturn s.upper()"), str("STRASSE"));
+        assert_eq!(run("s = \"ÄB\"\nreturn s.lower()"), str("äb"));
+        assert_eq!(run("s = \"flex\"\nreturn s.starts_with(\"fl\")"), Value::Bool(true));
+        assert_eq!(run("s = \"flex\"\nreturn s.ends_with(\"fl\")"), Value::Bool(false));
+        assert_eq!(run("s = \"flex\"\nreturn s.contains(\"le\")"), Value::Bool(true));
+        assert_eq!(run("s = \"a-b-c\"\nreturn s.replace(\"-\", \"+\")"), str("a+b+c"));
+        assert_eq!(run("s = \"日本語\"\nreturn s.find(\"語\")"), Value::Int(2));
+        assert_eq!(run("s = \"日本語\"\nreturn s.find(\"x\")"), Value::Int(-1));
+        assert_eq!(run("s = \"日本語です\"\nreturn s.substring(1, -1)"), str("本語で"));
+        assert_eq!(run("s = \"日本語\"\nreturn s.substring(1)"), str("本語"));
+        assert_eq!(run("s = \"añb\"\nreturn s.chars()"), strs(&["a", "ñ", "b"]));
+        assert_eq!(run("return \"abc\".upper()"), str("ABC"));
+    }
+
+    #[test]
+    fn iterates_chars() {
+        let code = "out = []
+for c in \"ñé\" {
+    out.push(c + \"!\")
+}
+return out";
+        assert_eq!(run(code), strs(&["ñ!", "é!"]));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(run_err("s = \"a\"\nreturn s.nope()"), "string has no method nope");
+        assert_eq!(run_err("s = \"a\"\nreturn s.contains(1)"), "contains expects a string but got int");
+        assert_eq!(run_err("return \"a\" * -1"), "Can not repeat a string -1 times");
+        assert_eq!(run_err("return \"ab\" * 100000000000"), "Can not repeat a string 100000000000 times, the result is too long");
+        assert_eq!(run_err("return 9223372036854775807 * \"ab\""), "Can not repeat a string 9223372036854775807 times, the result is too long");
+    }
+}
@@ -0,0 +1,40 @@
+use std::panic;
+use std::panic::AssertUnwindSafe;
+
+use crate::repl::panic_message;
+use crate::RunResult;
+use crate::Value;
+use crate::Vm;
+
+/// Runs a script and gives the value it returns.
+pub(crate) fn run(code: &str) -> Value {
+    match Vm::new().run_code(code) {
+        RunResult::Value(v) => v,
+        r => panic!("Expected a value but got {:?}", r),
+    }
+}
+
+/// Runs a script that has to fail and gives its error message.
+pub(crate) fn run_err(code: &str) -> String {
+    let res = panic::catch_unwind(AssertUnwindSafe(|| Vm::new().run_code(code)));
+    match res {
+        Ok(r) => panic!("Expected error but got {:?}", r),
+        Err(e) => panic_message(e.as_ref()),
+    }
+}
+
+pub(crate) fn str(s: &str) -> Value {
+    Value::Str(s.to_string())
+}
+
+pub(crate) fn strs(items: &[&str]) -> Value {
+    Value::List(items.iter().map(|s| str(s)).collect())
+}
+
+pub(crate) fn list(items: &[Value]) -> Value {
+    Value::List(items.to_vec())
+}
+
+pub(crate) fn ints(items: &[i64]) -> Value {
+    Value::List(items.iter().map(|i| Value::Int(*i)).collect())
+}
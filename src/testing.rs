@@ -0,0 +1,451 @@
+use std::collections::HashMap;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+
+use crate::line_of;
+use crate::repl::panic_message;
+use crate::ASTNode;
+use crate::Call;
+use crate::ForCond;
+use crate::NodeKind;
+use crate::ParseError;
+use crate::Parser;
+use crate::RunResult;
+use crate::StdLib;
+use crate::SyntaxNode;
+use crate::SyntaxTree;
+use crate::Value;
+use crate::VariantArgs;
+use crate::Vm;
+
+/// Functions answered by the test runner. Test files call them without
+/// `await`, the runner awaits them itself.
+pub const TEST_FUNCTIONS: [&str; 3] = ["assert", "assert_eq", "mock"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed {
+        message: String,
+        /// Line of the failed assertion, none for runtime errors.
+        line: Option<u32>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    pub name: String,
+    /// Line of the `test` keyword.
+    pub line: u32,
+    pub outcome: Outcome,
+    /// Lines printed with `print` or `println`.
+    pub output: Vec<String>,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.outcome == Outcome::Passed
+    }
+}
+
+/// Runs every `test "name" { ... }` block of a script. Each test gets a
+/// fresh vm which runs the rest of the script first, so tests see its
+/// functions and top level `mock`s but nothing set by other tests.
+///
+/// Awaited host calls are answered by values registered with
+/// `mock("name", value)`; awaiting a call without a mock fails the test.
+pub fn run_tests(source: &str) -> Result<Vec<TestResult>, ParseError> {
+    let tree = SyntaxTree::parse(source)?;
+    let mut tests = vec![];
+    find_tests(&tree.root, source, &mut tests);
+
+    let mut parser = Parser::new(source);
+    let mut ast = parser.try_parse()?;
+    for node in &mut ast {
+        await_test_calls(node);
+    }
+    let stmt_lines = parser.stmt_lines();
+
+    let mut results = vec![];
+    for (i, (name, line)) in tests.into_iter().enumerate() {
+        let mut vm = Vm::new();
+        vm.open_std(&StdLib::ALL);
+        let mut runner = Runner {
+            mocks: HashMap::new(),
+            output: vec![],
+        };
+
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            let blk = vm.compile_with_lines(&ast, source, stmt_lines.clone());
+            let test_blk = vm.tests()[i].1;
+            runner.run(&mut vm, blk).and_then(|_| runner.run(&mut vm, test_blk))
+        }));
+
+        let outcome = match res {
+            Ok(Ok(())) => Outcome::Passed,
+            Ok(Err(outcome)) => outcome,
+            Err(e) => Outcome::Failed { message: panic_message(e.as_ref()), line: None },
+        };
+
+        results.push(TestResult {
+            name,
+            line,
+            outcome,
+            output: runner.output,
+        });
+    }
+
+    Ok(results)
+}
+
+/// Names and lines of the test blocks in the order the vm compiles them.
+fn find_tests(node: &SyntaxNode, source: &str, out: &mut Vec<(String, u32)>) {
+    if node.kind == NodeKind::Test {
+        let tokens = node.tokens();
+        if let (Some(keyword), Some(name)) = (tokens.first(), tokens.get(1)) {
+            let name = name.text[1..name.text.len() - 1].to_string();
+            out.push((name, line_of(source, keyword.span.start)));
+        }
+    }
+    for child in node.nodes() {
+        find_tests(child, source, out);
+    }
+}
+
+/// Calls the runner awaits in test files besides the test functions.
+const OUTPUT_FUNCTIONS: [&str; 2] = ["print", "println"];
+
+/// Wraps calls to the test and output functions in `await` so they
+/// reach the runner.
+fn await_test_calls(node: &mut ASTNode) {
+    await_children(node);
+
+    let is_test_call = match node {
+        ASTNode::Call(c) => matches!(
+            &*c.callee,
+            ASTNode::Ident(name) if TEST_FUNCTIONS.contains(&name.as_str()) || OUTPUT_FUNCTIONS.contains(&name.as_str())
+        ),
+        _ => false,
+    };
+    if is_test_call {
+        let call = std::mem::replace(node, ASTNode::Lit(Value::None));
+        *node = ASTNode::Call(Call {
+            callee: Box::new(ASTNode::Ident("await".to_string())),
+            args: vec![call],
+        });
+    }
+}
+
+fn await_children(node: &mut ASTNode) {
+    match node {
+        ASTNode::Assign(a) => {
+            await_test_calls(&mut a.left);
+            await_test_calls(&mut a.right);
+        },
+        ASTNode::Decl(d) => await_test_calls(&mut d.value),
+        ASTNode::ObjIns(o) => o.props.iter_mut().for_each(|p| await_test_calls(&mut p.value)),
+        ASTNode::Array(a) => a.items.iter_mut().for_each(await_test_calls),
+        ASTNode::Call(c) => {
+            // Calls passed to `await` already reach the runner
+            let awaited = matches!(&*c.callee, ASTNode::Ident(name) if name == "await");
+            await_test_calls(&mut c.callee);
+            for arg in &mut c.args {
+                match awaited {
+                    true => await_children(arg),
+                    false => await_test_calls(arg),
+                }
+            }
+        },
+        ASTNode::Property(_, v) => await_test_calls(v),
+        ASTNode::Fun(f) => f.body.iter_mut().for_each(await_test_calls),
+        ASTNode::Test(t) => t.body.iter_mut().for_each(await_test_calls),
+        ASTNode::ProbAccess(p) => await_test_calls(&mut p.object),
+        ASTNode::Index(i) => {
+            await_test_calls(&mut i.object);
+            await_test_calls(&mut i.index);
+        },
+        ASTNode::Neg(v) => await_test_calls(v),
+        ASTNode::Ret(r) => {
+            if let Some(v) = &mut *r.value {
+                await_test_calls(v);
+            }
+        },
+        ASTNode::BinOp(b) => {
+            await_test_calls(&mut b.left);
+            await_test_calls(&mut b.right);
+        },
+        ASTNode::If(i) => {
+            await_test_calls(&mut i.cond);
+            i.body.iter_mut().for_each(await_test_calls);
+            if let Some(els) = &mut i.els {
+                els.iter_mut().for_each(await_test_calls);
+            }
+        },
+        ASTNode::For(f) => {
+            if let ForCond::FromIt { it, .. } | ForCond::Unpack { it, .. } = &mut f.cond {
+                await_test_calls(it);
+            }
+            f.body.iter_mut().for_each(await_test_calls);
+        },
+        ASTNode::Variant(v) => match &mut v.args {
+            VariantArgs::Unit => {},
+            VariantArgs::Tuple(args) => args.iter_mut().for_each(await_test_calls),
+            VariantArgs::Struct(props) => props.iter_mut().for_each(|p| await_test_calls(&mut p.value)),
+        },
+        ASTNode::Match(m) => {
+            await_test_calls(&mut m.value);
+            for arm in &mut m.arms {
+                arm.body.iter_mut().for_each(await_test_calls);
+            }
+        },
+        _ => {}
+    }
+}
+
+struct Runner {
+    mocks: HashMap<String, Value>,
+    output: Vec<String>,
+}
+
+impl Runner {
+    fn run(&mut self, vm: &mut Vm, blk: u32) -> Result<(), Outcome> {
+        let mut res = vm.eval_blk(blk);
+
+        loop {
+            match res {
+                RunResult::Await { stack_id, value } => {
+                    let reply = self.answer(vm, stack_id, value)?;
+                    res = vm.cont(stack_id, reply);
+                },
+                RunResult::Paused { .. } => {
+                    return Err(Outcome::Failed { message: "test paused".to_string(), line: None });
+                },
+                RunResult::Value(_) | RunResult::None => return Ok(()),
+            }
+        }
+    }
+
+    fn answer(&mut self, vm: &Vm, stack_id: usize, value: Value) -> Result<Value, Outcome> {
+        let (ident, args) = match value {
+            Value::UndefCall { ident, args } => (ident, args),
+            _ => return Ok(value),
+        };
+        let name = vm.idt_name(ident).unwrap_or_default().to_string();
+        let line = vm.frames(stack_id).first().and_then(|f| f.line);
+        let fail = |message: String| Err(Outcome::Failed { message, line });
+
+        match name.as_str() {
+            "assert" => {
+                if args.first() == Some(&Value::Bool(true)) {
+                    return Ok(Value::None);
+                }
+                match args.get(1) {
+                    Some(Value::Str(msg)) => fail(format!("assertion failed: {}", msg)),
+                    _ => fail("assertion failed".to_string()),
+                }
+            },
+            "assert_eq" => {
+                let left = args.first().cloned().unwrap_or(Value::None);
+                let right = args.get(1).cloned().unwrap_or(Value::None);
+                if left == right {
+                    return Ok(Value::None);
+                }
+                fail(eq_failure(&left, &right))
+            },
+            "mock" => match (args.first(), args.get(1)) {
+                (Some(Value::Str(target)), Some(reply)) => {
+                    self.mocks.insert(target.clone(), reply.clone());
+                    Ok(Value::None)
+                },
+                _ => fail("mock expects a function name and a value".to_string()),
+            },
+            "print" | "println" => {
+                let line = args.iter().map(|a| match a {
+                    Value::Str(s) => s.clone(),
+                    _ => a.to_string(),
+                }).collect::<Vec<_>>().join(" ");
+                self.output.push(line);
+                Ok(Value::None)
+            },
+            _ => match self.mocks.get(&name) {
+                Some(v) => Ok(v.clone()),
+                None => fail(format!("no mock for awaited call {}", name)),
+            },
+        }
+    }
+}
+
+/// Failure message of `assert_eq`. Lists and objects are compared line
+/// by line, other values are shown side by side.
+fn eq_failure(left: &Value, right: &Value) -> String {
+    let structured = |v: &Value| matches!(v, Value::List(_) | Value::Obj(_));
+    if !structured(left) || !structured(right) {
+        return format!("assert_eq failed\n  left: {}\n right: {}", left, right);
+    }
+
+    let mut a = vec![];
+    let mut b = vec![];
+    expand(left, 0, "", "", &mut a);
+    expand(right, 0, "", "", &mut b);
+
+    let mut out = "assert_eq failed (- left, + right)".to_string();
+    for line in diff(&a, &b) {
+        out.push('\n');
+        out.push_str(&line);
+    }
+    out
+}
+
+/// One line per list item and object field.
+fn expand(val: &Value, indent: usize, head: &str, tail: &str, out: &mut Vec<String>) {
+    let pad = "    ".repeat(indent);
+
+    match val {
+        Value::List(items) if !items.is_empty() => {
+            out.push(format!("{}{}[", pad, head));
+            for item in items {
+                expand(item, indent + 1, "", ",", out);
+            }
+            out.push(format!("{}]{}", pad, tail));
+        },
+        Value::Obj(obj) if !obj.props.is_empty() => {
+            let name = obj.name.as_ref().map(|n| format!("{} ", n)).unwrap_or_default();
+            out.push(format!("{}{}{}{{", pad, head, name));
+            for prop in &obj.props {
+                expand(&prop.value, indent + 1, &format!("{}: ", prop.name), ",", out);
+            }
+            out.push(format!("{}}}{}", pad, tail));
+        },
+        _ => out.push(format!("{}{}{}{}", pad, head, val, tail)),
+    }
+}
+
+/// Line diff over the longest common subsequence.
+fn diff(a: &[String], b: &[String]) -> Vec<String> {
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = match a[i] == b[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+
+    let mut out = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            out.push(format!("  {}", a[i]));
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push(format!("- {}", a[i]));
+            i += 1;
+        } else {
+            out.push(format!("+ {}", b[j]));
+            j += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcomes(code: &str) -> Vec<(String, u32, Outcome)> {
+        run_tests(code).unwrap().into_iter()
+            .map(|r| (r.name, r.line, r.outcome))
+            .collect()
+    }
+
+    #[test]
+    fn runs_each_test_with_the_script() {
+        let code = "add = (a, b) => {
+    return a + b
+}
+count = 0
+
+test \"adds\" {
+    assert_eq(add(1, 2), 3)
+    count = 1
+}
+
+test \"is isolated\" {
+    assert(count == 0)
+    assert_eq(add(2, 2), 5)
+}";
+
+        assert_eq!(outcomes(code), vec![
+            ("adds".to_string(), 6, Outcome::Passed),
+            ("is isolated".to_string(), 11, Outcome::Failed {
+                message: "assert_eq failed\n  left: 4\n right: 5".to_string(),
+                line: Some(13),
+            }),
+        ]);
+    }
+
+    #[test]
+    fn mocks_answer_awaited_calls() {
+        let code = "mock(\"base\", 10)
+load = id => {
+    item = await(fetch(id))
+    offset = await(base())
+    return item + offset
+}
+
+test \"fetch\" {
+    mock(\"fetch\", 5)
+    print(\"loading\")
+    await(println(\"done\"))
+    assert_eq(load(1), 15)
+}
+
+test \"unmocked\" {
+    load(1)
+}";
+
+        let results = run_tests(code).unwrap();
+        assert!(results[0].passed());
+        assert_eq!(results[0].output, vec!["loading".to_string(), "done".to_string()]);
+        assert_eq!(results[1].outcome, Outcome::Failed {
+            message: "no mock for awaited call fetch".to_string(),
+            line: Some(3),
+        });
+    }
+
+    #[test]
+    fn diffs_lists_and_objects() {
+        let code = "test \"objects\" {
+    assert_eq({ b: [1, 2] }, { b: [1, 3] })
+}
+
+test \"message\" {
+    assert(1 == 2, \"numbers differ\")
+}";
+
+        let results = run_tests(code).unwrap();
+        let message = |i: usize| match &results[i].outcome {
+            Outcome::Failed { message, .. } => message.clone(),
+            Outcome::Passed => String::new(),
+        };
+
+        assert_eq!(message(0), "assert_eq failed (- left, + right)
+  {
+      b: [
+          1,
+-         2,
++         3,
+      ],
+  }");
+        assert_eq!(message(1), "assertion failed: numbers differ");
+    }
+
+    #[test]
+    fn runtime_errors_fail_the_test() {
+        let results = run_tests("test \"boom\" {\n    x = missing.field\n}").unwrap();
+        assert!(!results[0].passed());
+        assert!(run_tests("test \"open\" {").is_err());
+    }
+}
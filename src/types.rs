@@ -1,3 +1,8 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
+
+use crate::UserDataRef;
 
 #[derive(Debug, PartialEq, Clone, Default)]
 pub struct Ptr {
@@ -14,6 +19,7 @@ pub enum StackValue {
 	Ptr(Ptr),
 	Undef(u32),
 	Fn(u32),
+	Native(u32),
 	UndefCall {
 		ident: u32,
 		args: Vec<StackValue>
@@ -22,6 +28,16 @@ pub enum StackValue {
 		ptr: Ptr,
 		prop: u32
 	},
+	UserData(UserDataRef),
+	UserDataMethod {
+		ud: UserDataRef,
+		prop: u32
+	},
+	/// A method of a string, resolved by the following call.
+	StrMethod {
+		s: String,
+		prop: u32
+	},
 	None,
 }
 
@@ -41,6 +57,8 @@ impl From<&Value> for StackValue {
 			Value::Ptr(p) => Self::Ptr(p.clone()),
 			Value::UndefIdent(u) => Self::Undef(*u),
 			Value::Fn(f) => Self::Fn(*f),
+			Value::Native(n) => Self::Native(*n),
+			Value::UserData(ud) => Self::UserData(ud.clone()),
 			Value::None => Self::None,
 			_ => panic!("Cannot convert value to stack value")
 		}
@@ -56,7 +74,230 @@ pub struct ObjProp {
 #[derive(Debug, PartialEq, Clone)]
 pub struct Obj {
 	pub name: Option<String>,
-	pub props: Vec<ObjProp>
+	pub props: Props
+}
+
+/// Objects with more properties than this also index them by name.
+const INDEX_FROM: usize = 8;
+
+/// Properties of an object in the order they were added. Names are
+/// unique, and objects with many properties keep an index from name to
+/// position so lookups do not scan the list.
+#[derive(Clone, Default)]
+pub struct Props {
+	list: Vec<ObjProp>,
+	index: HashMap<String, usize>,
+}
+
+impl Props {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	fn position(&self, name: &str) -> Option<usize> {
+		if self.list.len() > INDEX_FROM {
+			return self.index.get(name).copied();
+		}
+		self.list.iter().position(|p| p.name == name)
+	}
+
+	pub fn get(&self, name: &str) -> Option<&Value> {
+		self.position(name).map(|i| &self.list[i].value)
+	}
+
+	pub fn get_mut(&mut self, name: &str) -> Option<&mut Value> {
+		self.position(name).map(|i| &mut self.list[i].value)
+	}
+
+	pub fn contains(&self, name: &str) -> bool {
+		self.position(name).is_some()
+	}
+
+	/// Sets a property. An existing one keeps its position.
+	pub fn set(&mut self, name: String, value: Value) {
+		if let Some(i) = self.position(&name) {
+			self.list[i].value = value;
+			return;
+		}
+
+		self.list.push(ObjProp { name, value });
+		match self.list.len() {
+			n if n == INDEX_FROM + 1 => self.reindex(),
+			n if n > INDEX_FROM + 1 => {
+				self.index.insert(self.list[n - 1].name.clone(), n - 1);
+			},
+			_ => {}
+		}
+	}
+
+	pub fn push(&mut self, prop: ObjProp) {
+		self.set(prop.name, prop.value);
+	}
+
+	/// Removes a property and returns its value.
+	pub fn remove(&mut self, name: &str) -> Option<Value> {
+		let i = self.position(name)?;
+		let prop = self.list.remove(i);
+		self.reindex();
+		Some(prop.value)
+	}
+
+	/// Changes every value in place, names stay as they are.
+	pub fn values_mut(&mut self) -> impl Iterator<Item = &mut Value> {
+		self.list.iter_mut().map(|p| &mut p.value)
+	}
+
+	fn reindex(&mut self) {
+		self.index.clear();
+		if self.list.len() > INDEX_FROM {
+			for (i, p) in self.list.iter().enumerate() {
+				self.index.insert(p.name.clone(), i);
+			}
+		}
+	}
+}
+
+impl Deref for Props {
+	type Target = [ObjProp];
+
+	fn deref(&self) -> &[ObjProp] {
+		&self.list
+	}
+}
+
+impl fmt::Debug for Props {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.list.fmt(f)
+	}
+}
+
+impl PartialEq for Props {
+	fn eq(&self, other: &Self) -> bool {
+		self.list == other.list
+	}
+}
+
+impl FromIterator<ObjProp> for Props {
+	fn from_iter<I: IntoIterator<Item = ObjProp>>(iter: I) -> Self {
+		let mut props = Props::new();
+		for prop in iter {
+			props.push(prop);
+		}
+		props
+	}
+}
+
+impl From<Vec<ObjProp>> for Props {
+	fn from(list: Vec<ObjProp>) -> Self {
+		list.into_iter().collect()
+	}
+}
+
+impl IntoIterator for Props {
+	type Item = ObjProp;
+	type IntoIter = std::vec::IntoIter<ObjProp>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.list.into_iter()
+	}
+}
+
+impl<'a> IntoIterator for &'a Props {
+	type Item = &'a ObjProp;
+	type IntoIter = std::slice::Iter<'a, ObjProp>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.list.iter()
+	}
+}
+
+/// A value of an enum: the variant it was built as and the fields it
+/// carries.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Variant {
+	pub enum_name: String,
+	pub name: String,
+	pub fields: VariantFields,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum VariantFields {
+	Unit,
+	Tuple(Vec<Value>),
+	Struct(Props),
+}
+
+impl Variant {
+	pub fn unit(enum_name: &str, name: &str) -> Self {
+		Self { enum_name: enum_name.to_string(), name: name.to_string(), fields: VariantFields::Unit }
+	}
+
+	pub fn tuple(enum_name: &str, name: &str, fields: Vec<Value>) -> Self {
+		Self { enum_name: enum_name.to_string(), name: name.to_string(), fields: VariantFields::Tuple(fields) }
+	}
+
+	pub fn record(enum_name: &str, name: &str, fields: Vec<ObjProp>) -> Self {
+		Self { enum_name: enum_name.to_string(), name: name.to_string(), fields: VariantFields::Struct(fields.into()) }
+	}
+
+	/// Whether this is the variant `name` of the enum `enum_name`.
+	pub fn is(&self, enum_name: &str, name: &str) -> bool {
+		self.enum_name == enum_name && self.name == name
+	}
+
+	/// Field of a tuple variant by position.
+	pub fn item(&self, i: usize) -> Option<&Value> {
+		match &self.fields {
+			VariantFields::Tuple(items) => items.get(i),
+			_ => None
+		}
+	}
+
+	/// Field of a struct variant by name.
+	pub fn field(&self, name: &str) -> Option<&Value> {
+		match &self.fields {
+			VariantFields::Struct(props) => props.get(name),
+			_ => None
+		}
+	}
+
+	/// The fields changed in place, in the order they are declared.
+	pub fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut Value> + '_> {
+		match &mut self.fields {
+			VariantFields::Unit => Box::new(std::iter::empty()),
+			VariantFields::Tuple(items) => Box::new(items.iter_mut()),
+			VariantFields::Struct(props) => Box::new(props.values_mut()),
+		}
+	}
+}
+
+impl fmt::Display for Variant {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}::{}", self.enum_name, self.name)?;
+		match &self.fields {
+			VariantFields::Unit => Ok(()),
+			VariantFields::Tuple(items) => {
+				write!(f, "(")?;
+				for (i, item) in items.iter().enumerate() {
+					if i > 0 {
+						write!(f, ", ")?;
+					}
+					write!(f, "{}", item)?;
+				}
+				write!(f, ")")
+			},
+			VariantFields::Struct(props) => {
+				write!(f, " {{ ")?;
+				for (i, prop) in props.iter().enumerate() {
+					if i > 0 {
+						write!(f, ", ")?;
+					}
+					write!(f, "{}: {}", prop.name, prop.value)?;
+				}
+				write!(f, " }}")
+			},
+		}
+	}
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -74,13 +315,17 @@ pub enum Value {
     List(Vec<Value>),
 	Ptr(Ptr),
 	Fn(u32),
+	/// Function implemented by the host, see `Vm::register_native`.
+	Native(u32),
 	Obj(Obj),
+	Enum(Variant),
 	ListIter(ListIter),
 	UndefIdent(u32),
 	UndefCall {
 		ident: u32,
 		args: Vec<Value>
 	},
+	UserData(UserDataRef),
     None,
 }
 
@@ -90,6 +335,52 @@ impl Default for Value {
 	}
 }
 
+impl fmt::Display for Value {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Value::Int(i) => write!(f, "{}", i),
+			Value::Float(n) => write!(f, "{:?}", n),
+			Value::Str(s) => write!(f, "{:?}", s),
+			Value::Bool(b) => write!(f, "{}", b),
+			Value::List(list) => {
+				write!(f, "[")?;
+				for (i, item) in list.iter().enumerate() {
+					if i > 0 {
+						write!(f, ", ")?;
+					}
+					write!(f, "{}", item)?;
+				}
+				write!(f, "]")
+			},
+			Value::Obj(obj) => {
+				if let Some(name) = &obj.name {
+					write!(f, "{} ", name)?;
+				}
+				if obj.props.is_empty() {
+					return write!(f, "{{}}");
+				}
+				write!(f, "{{ ")?;
+				for (i, prop) in obj.props.iter().enumerate() {
+					if i > 0 {
+						write!(f, ", ")?;
+					}
+					write!(f, "{}: {}", prop.name, prop.value)?;
+				}
+				write!(f, " }}")
+			},
+			Value::Ptr(p) => write!(f, "<ptr {}:{}>", p.scope_id, p.id),
+			Value::Fn(blk) => write!(f, "<fn {}>", blk),
+			Value::Native(id) => write!(f, "<native fn {}>", id),
+			Value::ListIter(_) => write!(f, "<iterator>"),
+			Value::UndefIdent(i) => write!(f, "<undefined {}>", i),
+			Value::UndefCall { ident, .. } => write!(f, "<undefined call {}>", ident),
+			Value::Enum(v) => write!(f, "{}", v),
+			Value::UserData(ud) => write!(f, "<{}>", ud.type_name()),
+			Value::None => write!(f, "none"),
+		}
+	}
+}
+
 impl From<StackValue> for Value {
 	fn from(val: StackValue) -> Self {
 		match val {
@@ -100,6 +391,8 @@ impl From<StackValue> for Value {
 			StackValue::Ptr(p) => Self::Ptr(p),
 			StackValue::Undef(u) => Self::UndefIdent(u),
 			StackValue::Fn(f) => Self::Fn(f),
+			StackValue::Native(n) => Self::Native(n),
+			StackValue::UserData(ud) => Self::UserData(ud),
 			StackValue::None => Self::None,
 			_ => todo!("{:?}", val)
 		}
@@ -110,6 +403,8 @@ impl From<StackValue> for Value {
 pub struct Assign {
 	pub left: Box<ASTNode>,
 	pub right: Box<ASTNode>,
+	/// The operator of a compound assignment like `+=`.
+	pub op: Option<Op>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -135,6 +430,32 @@ pub struct Call {
 	pub args: Vec<ASTNode>,
 }
 
+/// `let name = value` or `const name = value`, a new name in the
+/// enclosing block which may shadow an outer one.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Decl {
+	pub name: String,
+	pub value: Box<ASTNode>,
+	/// Declared with `const`, assigning to it again is a compile error.
+	pub constant: bool,
+}
+
+/// `Name::Variant`, `Name::Variant(a, b)` or `Name::Variant { f: a }`,
+/// a value of an enum.
+#[derive(Debug, PartialEq, Clone)]
+pub struct VariantIns {
+	pub enum_name: String,
+	pub name: String,
+	pub args: VariantArgs,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum VariantArgs {
+	Unit,
+	Tuple(Vec<ASTNode>),
+	Struct(Vec<Property>),
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum VarType {
 	Int,
@@ -144,6 +465,20 @@ pub enum VarType {
 	StrLit(String),
 	FnDef(Fun),
 	Ident(String),
+	/// `[Type]`, a list of the type.
+	List(Box<VarType>),
+	/// `Type?`, the type or none.
+	Optional(Box<VarType>),
+}
+
+impl VarType {
+	/// The type or none, an optional type stays as it is.
+	pub fn optional(self) -> VarType {
+		match self {
+			VarType::Optional(_) => self,
+			typ => VarType::Optional(Box::new(typ)),
+		}
+	}
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -158,6 +493,54 @@ pub struct StructDef {
 	pub fields: Vec<TypeField>,
 }
 
+/// `enum Name { A, B(T), C { f: T } }`, a type whose values are one of
+/// its variants.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EnumDef {
+	pub name: String,
+	pub variants: Vec<VariantDef>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct VariantDef {
+	pub name: String,
+	pub shape: VariantShape,
+}
+
+/// The fields a variant carries.
+#[derive(Debug, PartialEq, Clone)]
+pub enum VariantShape {
+	Unit,
+	Tuple(Vec<VarType>),
+	Struct(Vec<TypeField>),
+}
+
+/// A `test "name" { ... }` block, run by the test runner only.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TestDef {
+	pub name: String,
+	pub body: Vec<ASTNode>,
+}
+
+/// `mod name { ... }`, a module declared inline.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ModuleDef {
+	pub name: String,
+	pub body: Vec<ASTNode>,
+}
+
+/// `import a.b`, binds the module to its last name.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Import {
+	pub path: Vec<String>,
+}
+
+/// `use a::b`, binds an export of a module to its own name.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Use {
+	pub path: Vec<String>,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Param {
 	pub name: String
@@ -176,15 +559,18 @@ pub struct TypeDef {
 }
 
 #[derive(Debug, PartialEq, Clone)]
-pub struct Var {
-	pub name: String,
-	pub typ: String,
+pub struct ProbAccess {
+	pub object: Box<ASTNode>,
+	pub property: String,
+	/// Read with `?.`, which gives none when the object is none.
+	pub optional: bool,
 }
 
+/// `object[index]` on a list, string or object.
 #[derive(Debug, PartialEq, Clone)]
-pub struct ProbAccess {
+pub struct Index {
 	pub object: Box<ASTNode>,
-	pub property: String,
+	pub index: Box<ASTNode>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -194,6 +580,10 @@ pub enum Op {
 	Mul,
 	Div,
 	Eq,
+	Lt,
+	Gt,
+	/// `??`, the left side unless it is none.
+	Coalesce,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -221,6 +611,11 @@ pub enum ForCond {
 	FromIt {
 		ident: String,
 		it: Box<ASTNode>,
+	},
+	/// `for [k, v] in it`, unpacks each item into the names.
+	Unpack {
+		idents: Vec<String>,
+		it: Box<ASTNode>,
 	}
 }
 
@@ -230,10 +625,50 @@ pub struct For {
 	pub body: Vec<ASTNode>,
 }
 
+/// What a `match` arm compares its value with.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Pattern {
+	/// `_`, matches anything.
+	Wildcard,
+	/// A name, matches anything and binds the value to it.
+	Bind(String),
+	Lit(Value),
+	/// `Name::Variant` matches the variant whatever its fields are, with
+	/// fields given each of them has to match too.
+	Variant {
+		enum_name: String,
+		name: String,
+		fields: PatternFields,
+	},
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PatternFields {
+	Unit,
+	Tuple(Vec<Pattern>),
+	/// `{ code, message: m }`, a field without a pattern binds its name.
+	Struct(Vec<(String, Pattern)>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatchArm {
+	pub pattern: Pattern,
+	pub body: Vec<ASTNode>,
+}
+
+/// `match value { pattern => body ... }`, runs the body of the first arm
+/// whose pattern matches and nothing when none does.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Match {
+	pub value: Box<ASTNode>,
+	pub arms: Vec<MatchArm>,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum ASTNode {
 	Ident(String),
 	Assign(Assign),
+	Decl(Decl),
 	ObjIns(ObjIns),
 	Array(Array),
 	Call(Call),
@@ -242,13 +677,25 @@ pub enum ASTNode {
 	LiteralPercent(f64),
 	Fun(Fun),
 	StructDef(StructDef),
+	EnumDef(EnumDef),
+	Variant(VariantIns),
 	TypeDef(TypeDef),
-	Var(Var),
+	Test(TestDef),
+	Module(ModuleDef),
+	Import(Import),
+	Use(Use),
+	Export(Box<ASTNode>),
+	/// `...base` inside an object, the value of a nameless property.
+	Spread(Box<ASTNode>),
 	ProbAccess(ProbAccess),
+	Index(Index),
+	/// `-value` of anything but a number literal, which is folded.
+	Neg(Box<ASTNode>),
 	Ret(Ret),
 	BinOp(BinOp),
 	If(If),
 	For(For),
+	Match(Match),
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -258,5 +705,11 @@ pub enum RunResult {
 		stack_id: usize,
 		value: Value,
 	},
+	/// Stopped by a breakpoint or a step before running `pc` of `blk`.
+	Paused {
+		stack_id: usize,
+		blk: u32,
+		pc: u32,
+	},
 	None
 }
\ No newline at end of file
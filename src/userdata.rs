@@ -0,0 +1,296 @@
+use std::any::Any;
+use std::any::TypeId;
+use std::cell::Ref;
+use std::cell::RefCell;
+use std::cell::RefMut;
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use crate::ConvertError;
+use crate::FromValue;
+use crate::IntoValue;
+use crate::Value;
+
+type Method = Box<dyn Fn(&mut dyn Any, Vec<Value>) -> Result<Value, String>>;
+type Getter = Box<dyn Fn(&dyn Any) -> Value>;
+type Setter = Box<dyn Fn(&mut dyn Any, Value) -> Result<(), String>>;
+
+/// Host type which can be handed to scripts as a live object.
+pub trait UserData: 'static {
+    fn add_methods(methods: &mut UserDataMethods<Self>) where Self: Sized;
+}
+
+/// Method and field accessor table of a single userdata type.
+pub struct UserDataMethods<T> {
+    methods: HashMap<String, Method>,
+    getters: HashMap<String, Getter>,
+    setters: HashMap<String, Setter>,
+    typ: PhantomData<T>,
+}
+
+impl<T: 'static> UserDataMethods<T> {
+    fn new() -> Self {
+        Self {
+            methods: HashMap::new(),
+            getters: HashMap::new(),
+            setters: HashMap::new(),
+            typ: PhantomData,
+        }
+    }
+
+    pub fn add_method<F>(&mut self, name: &str, f: F)
+    where
+        F: Fn(&mut T, Vec<Value>) -> Result<Value, String> + 'static
+    {
+        self.methods.insert(name.to_string(), Box::new(move |any, args| {
+            f(any.downcast_mut::<T>().unwrap(), args)
+        }));
+    }
+
+    pub fn add_field_getter<F>(&mut self, name: &str, f: F)
+    where
+        F: Fn(&T) -> Value + 'static
+    {
+        self.getters.insert(name.to_string(), Box::new(move |any| {
+            f(any.downcast_ref::<T>().unwrap())
+        }));
+    }
+
+    pub fn add_field_setter<F>(&mut self, name: &str, f: F)
+    where
+        F: Fn(&mut T, Value) -> Result<(), String> + 'static
+    {
+        self.setters.insert(name.to_string(), Box::new(move |any, val| {
+            f(any.downcast_mut::<T>().unwrap(), val)
+        }));
+    }
+}
+
+pub(crate) struct UserDataType {
+    methods: HashMap<String, Method>,
+    getters: HashMap<String, Getter>,
+    setters: HashMap<String, Setter>,
+}
+
+impl UserDataType {
+    pub(crate) fn new<T: UserData>() -> Self {
+        let mut methods = UserDataMethods::<T>::new();
+        T::add_methods(&mut methods);
+        Self {
+            methods: methods.methods,
+            getters: methods.getters,
+            setters: methods.setters,
+        }
+    }
+
+    pub(crate) fn call(&self, ud: &UserDataRef, name: &str, args: Vec<Value>) -> Result<Value, String> {
+        let method = match self.methods.get(name) {
+            Some(m) => m,
+            None => return Err(format!("{} has no method {}", ud.type_name(), name))
+        };
+        let mut inner = ud.inner.borrow_mut();
+        method(&mut *inner, args)
+    }
+
+    pub(crate) fn get(&self, ud: &UserDataRef, name: &str) -> Result<Value, String> {
+        let getter = match self.getters.get(name) {
+            Some(g) => g,
+            None => return Err(format!("{} has no field {}", ud.type_name(), name))
+        };
+        let inner = ud.inner.borrow();
+        Ok(getter(&*inner))
+    }
+
+    pub(crate) fn set(&self, ud: &UserDataRef, name: &str, val: Value) -> Result<(), String> {
+        let setter = match self.setters.get(name) {
+            Some(s) => s,
+            None => return Err(format!("can not set field {} on {}", name, ud.type_name()))
+        };
+        let mut inner = ud.inner.borrow_mut();
+        setter(&mut *inner, val)
+    }
+}
+
+/// Shared handle to a host value. The host value is dropped when the
+/// last handle, either in the host or in the script, goes away.
+#[derive(Clone)]
+pub struct UserDataRef {
+    inner: Rc<RefCell<dyn Any>>,
+    type_id: TypeId,
+    type_name: &'static str,
+}
+
+impl UserDataRef {
+    pub fn new<T: UserData>(val: T) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(val)),
+            type_id: TypeId::of::<T>(),
+            type_name: std::any::type_name::<T>(),
+        }
+    }
+
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    pub fn is<T: 'static>(&self) -> bool {
+        self.type_id == TypeId::of::<T>()
+    }
+
+    pub fn borrow<T: 'static>(&self) -> Option<Ref<'_, T>> {
+        Ref::filter_map(self.inner.borrow(), |v| v.downcast_ref::<T>()).ok()
+    }
+
+    pub fn borrow_mut<T: 'static>(&self) -> Option<RefMut<'_, T>> {
+        RefMut::filter_map(self.inner.borrow_mut(), |v| v.downcast_mut::<T>()).ok()
+    }
+}
+
+impl fmt::Debug for UserDataRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "UserData({})", self.type_name)
+    }
+}
+
+impl PartialEq for UserDataRef {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl FromValue for UserDataRef {
+    fn from_value(val: Value) -> Result<Self, ConvertError> {
+        match val {
+            Value::UserData(ud) => Ok(ud),
+            _ => Err(ConvertError::expected("userdata", &val))
+        }
+    }
+}
+
+impl IntoValue for UserDataRef {
+    fn into_value(self) -> Value {
+        Value::UserData(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::RunResult;
+    use crate::Vm;
+
+    struct Database {
+        name: String,
+        rows: Vec<i64>,
+        dropped: Rc<Cell<bool>>,
+    }
+
+    impl Drop for Database {
+        fn drop(&mut self) {
+            self.dropped.set(true);
+        }
+    }
+
+    impl UserData for Database {
+        fn add_methods(methods: &mut UserDataMethods<Self>) {
+            methods.add_field_getter("name", |db| Value::Str(db.name.clone()));
+            methods.add_field_setter("name", |db, val| {
+                db.name = String::from_value(val).map_err(|e| e.to_string())?;
+                Ok(())
+            });
+            methods.add_method("insert", |db, args| {
+                for arg in args {
+                    db.rows.push(i64::from_value(arg).map_err(|e| e.to_string())?);
+                }
+                Ok(Value::None)
+            });
+            methods.add_method("count", |db, _| Ok(Value::Int(db.rows.len() as i64)));
+        }
+    }
+
+    fn database(dropped: &Rc<Cell<bool>>) -> Database {
+        Database {
+            name: "main".to_string(),
+            rows: vec![],
+            dropped: dropped.clone(),
+        }
+    }
+
+    #[test]
+    fn call_method_on_global() {
+        let dropped = Rc::new(Cell::new(false));
+        let mut vm = Vm::new();
+        let db = vm.create_userdata(database(&dropped));
+        vm.set_global("db", db.clone());
+
+        let res = vm.run_code(r#"
+        db.insert(1, 2)
+        db.insert(3)
+        return db.count()
+        "#);
+        assert_eq!(res, RunResult::Value(Value::Int(3)));
+
+        let ud = UserDataRef::from_value(db).unwrap();
+        assert_eq!(ud.borrow::<Database>().unwrap().rows, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn read_field() {
+        let dropped = Rc::new(Cell::new(false));
+        let mut vm = Vm::new();
+        let db = vm.create_userdata(database(&dropped));
+        vm.set_global("db", db);
+
+        let res = vm.run_code("return db.name");
+        assert_eq!(res, RunResult::Value(Value::Str("main".to_string())));
+    }
+
+    #[test]
+    fn write_field() {
+        let dropped = Rc::new(Cell::new(false));
+        let mut vm = Vm::new();
+        let db = vm.create_userdata(database(&dropped));
+        vm.set_global("db", db.clone());
+
+        let res = vm.run_code("db.name = \"backup\"\nreturn db.name");
+        assert_eq!(res, RunResult::Value(Value::Str("backup".to_string())));
+
+        let ud = UserDataRef::from_value(db).unwrap();
+        assert_eq!(ud.borrow::<Database>().unwrap().name, "backup");
+    }
+
+    #[test]
+    fn dropped_when_released() {
+        let dropped = Rc::new(Cell::new(false));
+        let mut vm = Vm::new();
+        let res = vm.run_code(r#"
+        db = await(open_db())
+        db.insert(1)
+        db = 0
+        return await(done())
+        "#);
+
+        let stack_id = match res {
+            RunResult::Await { stack_id, .. } => stack_id,
+            _ => panic!("Invalid result")
+        };
+        let db = vm.create_userdata(database(&dropped));
+        let res = vm.cont(stack_id, db);
+        assert!(dropped.get());
+
+        match res {
+            RunResult::Await { stack_id, .. } => {
+                assert_eq!(vm.cont(stack_id, Value::Int(1)), RunResult::Value(Value::Int(1)));
+            },
+            _ => panic!("Invalid result")
+        }
+    }
+}
@@ -0,0 +1,679 @@
+use serde::de;
+use serde::de::DeserializeOwned;
+use serde::de::IntoDeserializer;
+use serde::de::Visitor;
+use serde::ser;
+use serde::Serialize;
+
+use crate::convert::ConvertError;
+use crate::convert::value_kind;
+use crate::Obj;
+use crate::ObjProp;
+use crate::Value;
+use crate::VariantFields;
+
+impl de::Error for ConvertError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        ConvertError::new(msg.to_string())
+    }
+}
+
+impl ser::Error for ConvertError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        ConvertError::new(msg.to_string())
+    }
+}
+
+/// Deserializes any `serde` type from a script value.
+pub fn from_value<T: DeserializeOwned>(val: Value) -> Result<T, ConvertError> {
+    T::deserialize(val)
+}
+
+/// Serializes any `serde` type into a script value.
+pub fn to_value<T: Serialize + ?Sized>(val: &T) -> Result<Value, ConvertError> {
+    val.serialize(ValueSerializer)
+}
+
+fn obj_from_props(name: Option<String>, props: Vec<(String, Value)>) -> Value {
+    Value::Obj(
+        Obj {
+            name,
+            props: props.into_iter().map(|(name, value)| ObjProp { name, value }).collect()
+        }
+    )
+}
+
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = ConvertError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            Value::Int(i) => visitor.visit_i64(i),
+            Value::Float(f) => visitor.visit_f64(f),
+            Value::Str(s) => visitor.visit_string(s),
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::None => visitor.visit_unit(),
+            Value::List(list) => visitor.visit_seq(SeqAccess {
+                items: list.into_iter()
+            }),
+            Value::Obj(obj) => visitor.visit_map(MapAccess {
+                props: obj.props.into_iter(),
+                value: None
+            }),
+            _ => Err(ConvertError::new(format!("cannot deserialize {}", value_kind(&self))))
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            Value::None => visitor.visit_none(),
+            _ => visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V
+    ) -> Result<V::Value, Self::Error> {
+        match self {
+            Value::Str(s) => visitor.visit_enum(s.into_deserializer()),
+            Value::Obj(obj) if obj.props.len() == 1 => {
+                let prop = obj.props.into_iter().next().unwrap();
+                visitor.visit_enum(EnumAccess {
+                    variant: prop.name,
+                    value: prop.value
+                })
+            },
+            Value::Enum(v) => {
+                let value = match v.fields {
+                    VariantFields::Unit => Value::None,
+                    VariantFields::Tuple(mut items) if items.len() == 1 => items.remove(0),
+                    VariantFields::Tuple(items) => Value::List(items),
+                    VariantFields::Struct(props) => Value::Obj(Obj { name: None, props }),
+                };
+                visitor.visit_enum(EnumAccess {
+                    variant: v.name,
+                    value
+                })
+            },
+            _ => Err(ConvertError::expected("enum", &self))
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, ConvertError> for Value {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+struct SeqAccess {
+    items: std::vec::IntoIter<Value>
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess {
+    type Error = ConvertError;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T
+    ) -> Result<Option<T::Value>, Self::Error> {
+        match self.items.next() {
+            Some(v) => seed.deserialize(v).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+struct MapAccess {
+    props: std::vec::IntoIter<ObjProp>,
+    value: Option<Value>
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess {
+    type Error = ConvertError;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.props.next() {
+            Some(prop) => {
+                self.value = Some(prop.value);
+                seed.deserialize(Value::Str(prop.name)).map(Some)
+            },
+            None => Ok(None)
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: V
+    ) -> Result<V::Value, Self::Error> {
+        match self.value.take() {
+            Some(v) => seed.deserialize(v),
+            None => Err(ConvertError::new("value requested before key"))
+        }
+    }
+}
+
+struct EnumAccess {
+    variant: String,
+    value: Value
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = ConvertError;
+    type Variant = VariantAccess;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V
+    ) -> Result<(V::Value, Self::Variant), Self::Error> {
+        let variant = seed.deserialize(Value::Str(self.variant))?;
+        Ok((variant, VariantAccess { value: self.value }))
+    }
+}
+
+struct VariantAccess {
+    value: Value
+}
+
+impl<'de> de::VariantAccess<'de> for VariantAccess {
+    type Error = ConvertError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        match self.value {
+            Value::None => Ok(()),
+            _ => Err(ConvertError::expected("unit variant", &self.value))
+        }
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(
+        self,
+        seed: T
+    ) -> Result<T::Value, Self::Error> {
+        seed.deserialize(self.value)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        de::Deserializer::deserialize_seq(self.value, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V
+    ) -> Result<V::Value, Self::Error> {
+        de::Deserializer::deserialize_map(self.value, visitor)
+    }
+}
+
+pub struct ValueSerializer;
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = ConvertError;
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeList;
+    type SerializeMap = SerializeObj;
+    type SerializeStruct = SerializeObj;
+    type SerializeStructVariant = SerializeObj;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, ConvertError> {
+        Ok(Value::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, ConvertError> {
+        Ok(Value::Int(v as i64))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, ConvertError> {
+        Ok(Value::Int(v as i64))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, ConvertError> {
+        Ok(Value::Int(v as i64))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, ConvertError> {
+        Ok(Value::Int(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, ConvertError> {
+        Ok(Value::Int(v as i64))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, ConvertError> {
+        Ok(Value::Int(v as i64))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, ConvertError> {
+        Ok(Value::Int(v as i64))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, ConvertError> {
+        match i64::try_from(v) {
+            Ok(i) => Ok(Value::Int(i)),
+            Err(_) => Err(ConvertError::new(format!("{} does not fit in int", v)))
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, ConvertError> {
+        Ok(Value::Float(v as f64))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, ConvertError> {
+        Ok(Value::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, ConvertError> {
+        Ok(Value::Str(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, ConvertError> {
+        Ok(Value::Str(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, ConvertError> {
+        Ok(Value::List(v.iter().map(|b| Value::Int(*b as i64)).collect()))
+    }
+
+    fn serialize_none(self) -> Result<Value, ConvertError> {
+        Ok(Value::None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value, ConvertError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, ConvertError> {
+        Ok(Value::None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, ConvertError> {
+        Ok(Value::None)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str
+    ) -> Result<Value, ConvertError> {
+        Ok(Value::Str(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T
+    ) -> Result<Value, ConvertError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T
+    ) -> Result<Value, ConvertError> {
+        Ok(obj_from_props(None, vec![(variant.to_string(), value.serialize(self)?)]))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeList, ConvertError> {
+        Ok(SerializeList {
+            variant: None,
+            items: Vec::with_capacity(len.unwrap_or(0))
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeList, ConvertError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize
+    ) -> Result<SerializeList, ConvertError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize
+    ) -> Result<SerializeList, ConvertError> {
+        Ok(SerializeList {
+            variant: Some(variant),
+            items: Vec::with_capacity(len)
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeObj, ConvertError> {
+        Ok(SerializeObj {
+            name: None,
+            variant: None,
+            props: vec![],
+            key: None
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        _len: usize
+    ) -> Result<SerializeObj, ConvertError> {
+        Ok(SerializeObj {
+            name: Some(name.to_string()),
+            variant: None,
+            props: vec![],
+            key: None
+        })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize
+    ) -> Result<SerializeObj, ConvertError> {
+        Ok(SerializeObj {
+            name: None,
+            variant: Some(variant),
+            props: vec![],
+            key: None
+        })
+    }
+}
+
+pub struct SerializeList {
+    variant: Option<&'static str>,
+    items: Vec<Value>
+}
+
+impl SerializeList {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        self.items.push(value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value, ConvertError> {
+        let list = Value::List(self.items);
+        match self.variant {
+            Some(variant) => Ok(obj_from_props(None, vec![(variant.to_string(), list)])),
+            None => Ok(list)
+        }
+    }
+}
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = Value;
+    type Error = ConvertError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, ConvertError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = Value;
+    type Error = ConvertError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, ConvertError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = Value;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, ConvertError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeList {
+    type Ok = Value;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, ConvertError> {
+        self.finish()
+    }
+}
+
+pub struct SerializeObj {
+    name: Option<String>,
+    variant: Option<&'static str>,
+    props: Vec<(String, Value)>,
+    key: Option<String>
+}
+
+impl SerializeObj {
+    fn finish(self) -> Result<Value, ConvertError> {
+        let obj = obj_from_props(self.name, self.props);
+        match self.variant {
+            Some(variant) => Ok(obj_from_props(None, vec![(variant.to_string(), obj)])),
+            None => Ok(obj)
+        }
+    }
+}
+
+impl ser::SerializeMap for SerializeObj {
+    type Ok = Value;
+    type Error = ConvertError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), ConvertError> {
+        match key.serialize(ValueSerializer)? {
+            Value::Str(s) => self.key = Some(s),
+            Value::Int(i) => self.key = Some(i.to_string()),
+            v => return Err(ConvertError::expected("string key", &v))
+        }
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        let key = match self.key.take() {
+            Some(k) => k,
+            None => return Err(ConvertError::new("value serialized before key"))
+        };
+        self.props.push((key, value.serialize(ValueSerializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, ConvertError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for SerializeObj {
+    type Ok = Value;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T
+    ) -> Result<(), ConvertError> {
+        self.props.push((key.to_string(), value.serialize(ValueSerializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, ConvertError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeObj {
+    type Ok = Value;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T
+    ) -> Result<(), ConvertError> {
+        self.props.push((key.to_string(), value.serialize(ValueSerializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, ConvertError> {
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::RunResult;
+    use crate::Vm;
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct Server {
+        host: String,
+        port: u16,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct Config {
+        name: String,
+        debug: bool,
+        ratio: f64,
+        servers: Vec<Server>,
+        retries: Option<u32>,
+    }
+
+    #[test]
+    fn deserialize_config_from_script() {
+        let mut vm = Vm::new();
+        let res = vm.run_code(r#"
+        return Config {
+            name: "prod",
+            debug: false,
+            ratio: 0.5,
+            servers: [
+                Server { host: "a", port: 80 },
+                Server { host: "b", port: 81 }
+            ]
+        }"#);
+
+        let value = match res {
+            RunResult::Value(v) => v,
+            _ => panic!("Invalid result")
+        };
+
+        let config: Config = from_value(value).unwrap();
+        assert_eq!(config, Config {
+            name: "prod".to_string(),
+            debug: false,
+            ratio: 0.5,
+            servers: vec![
+                Server { host: "a".to_string(), port: 80 },
+                Server { host: "b".to_string(), port: 81 },
+            ],
+            retries: None,
+        });
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let config = Config {
+            name: "dev".to_string(),
+            debug: true,
+            ratio: 1.5,
+            servers: vec![Server { host: "localhost".to_string(), port: 8080 }],
+            retries: Some(3),
+        };
+
+        let value = to_value(&config).unwrap();
+        match &value {
+            Value::Obj(obj) => assert_eq!(obj.name, Some("Config".to_string())),
+            _ => panic!("Invalid value")
+        }
+        let back: Config = from_value(value).unwrap();
+        assert_eq!(back, config);
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    enum Shape {
+        Circle(f64),
+        Rect(i64, i64),
+        Named { name: String },
+        Empty,
+    }
+
+    #[test]
+    fn deserialize_script_enums() {
+        let mut vm = Vm::new();
+        let res = vm.run_code(r#"
+        enum Shape { Circle(float), Rect(int, int), Named { name: string }, Empty }
+        return [Shape::Circle(1.5), Shape::Rect(2, 3), Shape::Named { name: "n" }, Shape::Empty]"#);
+
+        let value = match res {
+            RunResult::Value(v) => v,
+            _ => panic!("Invalid result")
+        };
+
+        let shapes: Vec<Shape> = from_value(value).unwrap();
+        assert_eq!(shapes, vec![
+            Shape::Circle(1.5),
+            Shape::Rect(2, 3),
+            Shape::Named { name: "n".to_string() },
+            Shape::Empty,
+        ]);
+    }
+
+    #[test]
+    fn deserialize_wrong_type() {
+        let res: Result<Server, _> = from_value(Value::Int(1));
+        assert!(res.is_err());
+    }
+}
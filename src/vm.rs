@@ -1,21 +1,52 @@
+use std::any::TypeId;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::mem;
 
 use crate::ASTNode;
+use crate::Assign;
+use crate::EnumDef;
 use crate::ForCond;
 use crate::ListIter;
 use crate::Obj;
 use crate::ObjProp;
+use crate::Props;
 use crate::Op;
 use crate::Parser;
+use crate::Pattern;
+use crate::PatternFields;
 use crate::Ptr;
 use crate::RunResult;
 use crate::StackValue;
+use crate::UserData;
+use crate::UserDataRef;
 use crate::Value;
+use crate::Variant;
+use crate::VariantArgs;
+use crate::VariantFields;
+use crate::VariantShape;
+use crate::value_kind;
 use crate::callstack::BuildIn;
 use crate::callstack::Call;
 use crate::callstack::Callstack;
+use crate::index;
+use crate::list;
+use crate::list::MethodResult;
+use crate::list::Step;
+use crate::object;
+use crate::object::NAME_PROP;
+use crate::object::OBJECT_METHODS;
+use crate::module::ModuleLoader;
+use crate::stdlib::call_native;
+use crate::stdlib::Native;
+use crate::stdlib::StdLib;
+use crate::string;
+use crate::debug::Debugger;
 use crate::scope::ScopeManager;
+use crate::userdata::UserDataType;
 use crate::vm_types::ByteCode;
+use crate::vm_types::LineTable;
 
 const PUSH_METHOD: u32 = 1;
 const POP_METHOD: u32 = 2;
@@ -50,6 +81,118 @@ fn format_num(digits: u32, num: u32) -> String {
     s
 }
 
+pub(crate) fn prop_name(id_idt_map: &HashMap<u32, String>, id: u32) -> String {
+    match id {
+        PUSH_METHOD => "push".to_string(),
+        POP_METHOD => "pop".to_string(),
+        MAP_METHOD => "map".to_string(),
+        _ => match id_idt_map.get(&id) {
+            Some(name) => name.clone(),
+            None => panic!("Unknown identifier {}", id)
+        }
+    }
+}
+
+fn op_code(op: &Op) -> ByteCode {
+    match op {
+        Op::Plus => ByteCode::BinAdd,
+        Op::Minus => ByteCode::BinMinus,
+        Op::Mul => ByteCode::BinMul,
+        Op::Div => ByteCode::BinDivide,
+        Op::Eq => ByteCode::Cmp,
+        Op::Lt => ByteCode::Lt,
+        Op::Gt => ByteCode::Gt,
+        Op::Coalesce => unreachable!("?? is compiled to a jump"),
+    }
+}
+
+/// Moves the target of a jump compiled on its own to where the code is
+/// put in the block.
+fn relocate(code: ByteCode, base: u32) -> ByteCode {
+    match code {
+        ByteCode::Jump(t) => ByteCode::Jump(t + base),
+        ByteCode::JumpIfFalse(t) => ByteCode::JumpIfFalse(t + base),
+        ByteCode::JumpIfNone(t) => ByteCode::JumpIfNone(t + base),
+        ByteCode::JumpIfSome(t) => ByteCode::JumpIfSome(t + base),
+        code => code,
+    }
+}
+
+/// What a variant takes, for errors about its fields.
+fn shape_text(shape: &VariantShape) -> String {
+    match shape {
+        VariantShape::Unit => "takes no fields".to_string(),
+        VariantShape::Tuple(types) if types.len() == 1 => "takes 1 field".to_string(),
+        VariantShape::Tuple(types) => format!("takes {} fields", types.len()),
+        VariantShape::Struct(_) => "takes named fields".to_string(),
+    }
+}
+
+pub(crate) fn is_builtin_idt(id: u32) -> bool {
+    matches!(id, PUSH_METHOD | POP_METHOD | MAP_METHOD)
+}
+
+/// Whether a chain of property reads, calls and indexes has a `?.`.
+fn has_optional(node: &ASTNode) -> bool {
+    match node {
+        ASTNode::ProbAccess(p) => p.optional || has_optional(&p.object),
+        ASTNode::Index(x) => has_optional(&x.object),
+        ASTNode::Call(c) => has_optional(&c.callee),
+        _ => false,
+    }
+}
+
+/// Whether a value counts as true for `if`, `for` and the list methods
+/// which test their callback's result.
+pub(crate) fn truthy(val: &Value) -> Result<bool, String> {
+    match val {
+        Value::Bool(b) => Ok(*b),
+        Value::None => Ok(false),
+        Value::Int(i) => Ok(*i >= 1),
+        Value::List(l) => Ok(!l.is_empty()),
+        Value::Str(s) => Ok(!s.is_empty()),
+        v => Err(format!("{} can not be used as a condition", value_kind(v))),
+    }
+}
+
+/// The name scripts write for an identifier, without the ` <3>` which
+/// `declare` adds to names of blocks. None for names the compiler makes
+/// up, like `<match>`.
+pub(crate) fn source_name(name: &str) -> Option<&str> {
+    match name.split_once(" <") {
+        Some((name, _)) => Some(name),
+        None if name.starts_with('<') => None,
+        None => Some(name),
+    }
+}
+
+/// A name declared with `let` or `const` in a block being compiled.
+pub(crate) struct Binding {
+    name: String,
+    idt: u32,
+    constant: bool,
+}
+
+/// The names declared in a block being compiled. Bodies of functions,
+/// modules and scripts run in a scope of their own, the blocks of ifs and
+/// loops share the scope around them.
+pub(crate) struct DeclScope {
+    bindings: Vec<Binding>,
+    own_scope: bool,
+    /// The pc the block starts at.
+    start: u32,
+}
+
+impl DeclScope {
+    pub(crate) fn new(own_scope: bool) -> Self {
+        Self { bindings: Vec::new(), own_scope, start: 0 }
+    }
+
+    fn nested(start: usize) -> Self {
+        Self { bindings: Vec::new(), own_scope: false, start: start as u32 }
+    }
+}
+
 struct BuildinRes {
     call: Option<Call>,
     stack_val: Option<StackValue>,
@@ -58,37 +201,101 @@ struct BuildinRes {
 
 pub struct Vm {
     scopes: Vec<usize>,
-    constants: Vec<Value>,
-    code_blocks: Vec<Vec<ByteCode>>,
-    callstacks: Vec<Callstack>,
-    idt_map: HashMap<String, u32>,
-    id_idt_map: HashMap<u32, String>,
-    next_idt: u32,
-    scope: ScopeManager,
+    pub(crate) constants: Vec<Value>,
+    pub(crate) code_blocks: Vec<Vec<ByteCode>>,
+    pub(crate) line_tables: Vec<LineTable>,
+    pub(crate) sources: Vec<String>,
+    pub(crate) curr_source: Option<u32>,
+    pub(crate) pending_lines: VecDeque<u32>,
+    pub(crate) line_stack: Vec<LineTable>,
+    pub(crate) callstacks: Vec<Callstack>,
+    pub(crate) idt_map: HashMap<String, u32>,
+    pub(crate) id_idt_map: HashMap<u32, String>,
+    pub(crate) next_idt: u32,
+    pub(crate) scope: ScopeManager,
+    pub(crate) globals: u32,
+    pub(crate) session: Option<u32>,
+    userdata_types: HashMap<TypeId, UserDataType>,
+    pub(crate) debugger: Debugger,
+    tests: Vec<(String, u32)>,
+    pub(crate) loader: Option<Box<dyn ModuleLoader>>,
+    pub(crate) module_blocks: HashMap<String, u32>,
+    pub(crate) loading: Vec<String>,
+    pub(crate) exports: Vec<Vec<String>>,
+    /// Scopes of the modules which defined a function, by block.
+    pub(crate) home_scopes: HashMap<u32, u32>,
+    pub(crate) natives: Vec<Native>,
+    pub(crate) std_libs: Vec<StdLib>,
+    /// Blocks being compiled with the names declared in them, innermost last.
+    pub(crate) decls: Vec<DeclScope>,
+    /// Enums declared by the compiled scripts, by name.
+    pub(crate) enums: HashMap<String, EnumDef>,
+    /// Jumps of the `?.` in the chain being compiled, to its end.
+    pub(crate) none_jumps: Vec<usize>,
     pub log: usize
 }
 
 impl Vm {
     pub fn new() -> Self {
+        let mut scope = ScopeManager::new();
+        let globals = scope.create_scope();
+
         Self {
             scopes: Vec::new(),
             constants: Vec::new(),
             code_blocks: Vec::new(),
+            line_tables: Vec::new(),
+            sources: Vec::new(),
+            curr_source: None,
+            pending_lines: VecDeque::new(),
+            line_stack: Vec::new(),
             callstacks: Vec::new(),
-            scope: ScopeManager::new(),
+            scope,
+            globals,
+            session: None,
             idt_map: HashMap::new(),
             id_idt_map: HashMap::new(),
+            userdata_types: HashMap::new(),
             next_idt: FIRST_IDT,
+            debugger: Debugger::default(),
+            tests: Vec::new(),
+            loader: None,
+            module_blocks: HashMap::new(),
+            loading: Vec::new(),
+            exports: Vec::new(),
+            home_scopes: HashMap::new(),
+            natives: Vec::new(),
+            std_libs: Vec::new(),
+            decls: Vec::new(),
+            enums: HashMap::new(),
+            none_jumps: Vec::new(),
             log: 0
         }
     }
 
-    pub fn compile_ast(&mut self, ast: &Vec<ASTNode>) -> u32 {
+    /// Defines a variable which is visible to every script run by this vm.
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        let i = self.store_idt(name.to_string());
+        let v = self.scope.alloc(self.globals, value);
+        self.scope.store_named(self.globals, i, Value::from(v));
+    }
+
+    pub fn register_userdata<T: UserData>(&mut self) {
+        self.userdata_types.entry(TypeId::of::<T>()).or_insert_with(UserDataType::new::<T>);
+    }
+
+    /// Wraps a host value so it can be passed to scripts as a live object.
+    pub fn create_userdata<T: UserData>(&mut self, val: T) -> Value {
+        self.register_userdata::<T>();
+        Value::UserData(UserDataRef::new(val))
+    }
+
+    pub fn compile_ast(&mut self, ast: &[ASTNode]) -> u32 {
         let mut code_block = Vec::new();
-        for node in ast {
-            self.compile_node(&mut code_block, node);
-        }   
-        self.code_blocks.push(code_block);
+        self.line_stack.push(LineTable::default());
+        self.compile_block(&mut code_block, ast, true);
+        let lines = self.line_stack.pop().unwrap();
+        self.push_block(code_block, lines);
 
         if self.log > 1 {
             println!("code blocks: {:?}", self.code_blocks);
@@ -98,8 +305,103 @@ impl Vm {
     }
 
     pub fn compile_code(&mut self, code: &str) -> u32 {
-        let ast = Parser::new(code).parse();
-        self.compile_ast(&ast)
+        let mut parser = Parser::new(code);
+        let ast = parser.parse();
+        self.compile_with_lines(&ast, code, parser.stmt_lines())
+    }
+
+    /// Compiles the ast and records which source line each instruction
+    /// came from. `lines` are the statement lines reported by the parser.
+    pub fn compile_with_lines(&mut self, ast: &[ASTNode], source: &str, lines: Vec<u32>) -> u32 {
+        self.sources.push(source.to_string());
+        self.curr_source = Some((self.sources.len() - 1) as u32);
+        self.pending_lines = lines.into();
+        // Left over when an earlier compile failed
+        self.decls.clear();
+        let blk = self.compile_ast(ast);
+        self.curr_source = None;
+        self.pending_lines.clear();
+        blk
+    }
+
+    pub(crate) fn push_block(&mut self, block: Vec<ByteCode>, lines: LineTable) {
+        self.code_blocks.push(block);
+        self.line_tables.push(LineTable {
+            source: self.curr_source,
+            ..lines
+        });
+    }
+
+    pub(crate) fn compile_stmt(&mut self, block: &mut Vec<ByteCode>, node: &ASTNode) {
+        if let Some(line) = self.pending_lines.pop_front() {
+            if let Some(lines) = self.line_stack.last_mut() {
+                lines.entries.push((block.len() as u32, line));
+            }
+        }
+
+        self.compile_node(block, node);
+    }
+
+    /// Compiles statements as a block which ends the names declared in it.
+    pub(crate) fn compile_block(&mut self, block: &mut Vec<ByteCode>, nodes: &[ASTNode], own_scope: bool) {
+        // Enums can be used above their declaration
+        for node in nodes {
+            if let ASTNode::EnumDef(def) = node {
+                self.enums.insert(def.name.clone(), def.clone());
+            }
+        }
+
+        self.decls.push(match own_scope {
+            true => DeclScope::new(true),
+            false => DeclScope::nested(block.len()),
+        });
+        for node in nodes {
+            self.compile_stmt(block, node);
+        }
+        self.end_decls(block);
+    }
+
+    /// Ends the innermost block. The debugger shows the names of a block
+    /// which shares its scope only while it runs.
+    fn end_decls(&mut self, block: &[ByteCode]) {
+        let decls = match self.decls.pop() {
+            Some(d) if !d.own_scope => d,
+            _ => return,
+        };
+        if let Some(lines) = self.line_stack.last_mut() {
+            for b in decls.bindings {
+                lines.lets.push((b.idt, decls.start, block.len() as u32));
+            }
+        }
+    }
+
+    /// The innermost declaration of a name in the blocks being compiled.
+    fn binding(&self, name: &str) -> Option<&Binding> {
+        self.decls.iter().rev()
+            .flat_map(|d| d.bindings.iter().rev())
+            .find(|b| b.name == name)
+    }
+
+    /// The identifier a name refers to where it is compiled.
+    fn resolve(&mut self, name: &str) -> u32 {
+        match self.binding(name) {
+            Some(b) => b.idt,
+            None => self.store_idt(name.to_string()),
+        }
+    }
+
+    /// Declares a name in the innermost block. Blocks of ifs and loops run
+    /// in the scope around them, so their names get an identifier of their
+    /// own which no other name shares.
+    fn declare(&mut self, name: &str, constant: bool) -> u32 {
+        let idt = match self.decls.last().is_none_or(|d| d.own_scope) {
+            true => self.store_idt(name.to_string()),
+            false => self.store_idt(format!("{} <{}>", name, self.next_idt)),
+        };
+        if let Some(d) = self.decls.last_mut() {
+            d.bindings.push(Binding { name: name.to_string(), idt, constant });
+        }
+        idt
     }
 
     pub fn compile_node(&mut self, block: &mut Vec<ByteCode>, node: &ASTNode) {
@@ -123,53 +425,86 @@ impl Vm {
 
                 block.push(ByteCode::Ret(a));
             },
+            ASTNode::BinOp(binop) if binop.op == Op::Coalesce => {
+                self.compile_node(block, &binop.left);
+                block.push(ByteCode::JumpIfSome(0));
+                let jump = block.len() - 1;
+                self.compile_node(block, &binop.right);
+                block[jump] = ByteCode::JumpIfSome(block.len() as u32);
+            },
             ASTNode::BinOp(binop) => {
                 self.compile_node(block, &binop.left);
                 self.compile_node(block, &binop.right);
-
-                match binop.op {
-                    Op::Plus => block.push(ByteCode::BinAdd),
-                    Op::Minus => block.push(ByteCode::BinMinus),
-                    Op::Mul => block.push(ByteCode::BinMul),
-                    Op::Div => block.push(ByteCode::BinDivide),
-                    Op::Eq => block.push(ByteCode::Cmp),
-                    // Op::Neq => block.push(ByteCode::Cmp),
-                    // Op::Lt => block.push(ByteCode::Cmp),
-                    // Op::Gt => block.push(ByteCode::Cmp),
-                    // Op::Leq => block.push(ByteCode::Cmp),
-                    // Op::Geq => block.push(ByteCode::Cmp),
-                    _ => panic!("Invalid operation")
-                }
+                block.push(op_code(&binop.op));
             },
             ASTNode::If(ifs) => {
                 self.compile_node(block, &ifs.cond);
                 block.push(ByteCode::JumpIfFalse(0));
                 let jump_if_false_inx = block.len() - 1;
 
-                for node in &ifs.body {
-                    self.compile_node(block, node);
-                }
+                self.compile_block(block, &ifs.body, false);
 
-                block[jump_if_false_inx] = ByteCode::JumpIfFalse(block.len() as u32);
+                match &ifs.els {
+                    Some(els) => {
+                        block.push(ByteCode::Jump(0));
+                        let jump_inx = block.len() - 1;
+                        block[jump_if_false_inx] = ByteCode::JumpIfFalse(block.len() as u32);
 
-                if let Some(els) = &ifs.els {
-                    for node in els {
-                        self.compile_node(block, node);
+                        self.compile_block(block, els, false);
+
+                        block[jump_inx] = ByteCode::Jump(block.len() as u32);
+                    },
+                    None => {
+                        block[jump_if_false_inx] = ByteCode::JumpIfFalse(block.len() as u32);
                     }
                 }
             },
             ASTNode::Assign(a) => {
-                self.compile_node(block, &a.right);
+                // Compound assignments read the target once, its object
+                // and index are kept on the stack for the store.
                 match &*a.left {
                     ASTNode::Ident(ref name) => {
-                        let i = self.store_idt(name.clone());
+                        if self.binding(name).is_some_and(|b| b.constant) {
+                            panic!("Can not assign to constant {}", name);
+                        }
+                        let i = self.resolve(name);
+                        if a.op.is_some() {
+                            block.push(ByteCode::Load(i));
+                        }
+                        self.compile_value(block, a);
                         block.push(ByteCode::Store(i));
                     },
+                    ASTNode::ProbAccess(p) => {
+                        self.compile_node(block, &p.object);
+                        let i = self.store_idt(p.property.clone());
+                        if a.op.is_some() {
+                            block.push(ByteCode::Dup(1));
+                            block.push(ByteCode::AccessProp(i));
+                        }
+                        self.compile_value(block, a);
+                        block.push(ByteCode::StoreProp(i));
+                    },
+                    ASTNode::Index(x) => {
+                        self.compile_node(block, &x.object);
+                        self.compile_node(block, &x.index);
+                        if a.op.is_some() {
+                            block.push(ByteCode::Dup(2));
+                            block.push(ByteCode::Index);
+                        }
+                        self.compile_value(block, a);
+                        block.push(ByteCode::StoreIndex);
+                    },
                     _ => panic!("Invalid operation")
                 }
             },
+            ASTNode::Decl(d) => {
+                // The value still sees the names the declaration shadows
+                self.compile_node(block, &d.value);
+                let i = self.declare(&d.name, d.constant);
+                block.push(ByteCode::Store(i));
+            },
             ASTNode::Ident(idt) => {
-                let i = self.store_idt(idt.clone());
+                let i = self.resolve(idt);
                 block.push(ByteCode::Load(i));
             },
             ASTNode::Array(a) => {
@@ -180,34 +515,22 @@ impl Vm {
             },
             ASTNode::Fun(f) => {
                 let mut fun_block = Vec::new();
-                for param in &f.params {
-                    let i = self.store_idt(param.name.clone());
+                self.line_stack.push(LineTable::default());
+                self.decls.push(DeclScope::new(true));
+                // Args are popped from the end
+                for param in f.params.iter().rev() {
+                    let i = self.declare(&param.name, false);
                     fun_block.push(ByteCode::Store(i));
                 }
                 for node in &f.body {
-                    self.compile_node(&mut fun_block, node);
+                    self.compile_stmt(&mut fun_block, node);
                 }
-                self.code_blocks.push(fun_block);
+                self.decls.pop();
+                let lines = self.line_stack.pop().unwrap();
+                self.push_block(fun_block, lines);
                 block.push(ByteCode::Fun((self.code_blocks.len() - 1) as u32));
             },
-            ASTNode::Call(c) => {
-                for arg in &c.args {
-                    self.compile_node(block, arg);
-                }
-
-                match &*c.callee {
-                    ASTNode::Ident(idt) => {
-                        if idt == "await" {
-                            block.push(ByteCode::Await);
-                            return;
-                        }
-                    },
-                    _ => {}
-                }
-
-                self.compile_node(block, &c.callee);
-                block.push(ByteCode::Call(c.args.len() as u32));
-            },
+            ASTNode::Call(_) => self.compile_chain(block, node),
             ASTNode::For(f) => {
                 let mut start_pc = 0;
                 let mut false_jump_pc = 0;
@@ -217,18 +540,34 @@ impl Vm {
                         block.push(ByteCode::MakeIter);
                         block.push(ByteCode::Next);
                         start_pc = block.len() - 1;
-                        let i = self.store_idt(ident.clone());
+                        let i = self.resolve(ident);
                         block.push(ByteCode::Store(i));
                         block.push(ByteCode::Load(i));
                         block.push(ByteCode::JumpIfFalse(0));
                         false_jump_pc = block.len() - 1;
                     },
+                    ForCond::Unpack { idents, it } => {
+                        self.compile_node(block, it);
+                        block.push(ByteCode::MakeIter);
+                        block.push(ByteCode::Next);
+                        start_pc = block.len() - 1;
+                        // The item is kept in a name scripts can not use
+                        let item = self.store_idt("<for item>".to_string());
+                        block.push(ByteCode::Store(item));
+                        block.push(ByteCode::Load(item));
+                        block.push(ByteCode::JumpIfFalse(0));
+                        false_jump_pc = block.len() - 1;
+                        block.push(ByteCode::Load(item));
+                        block.push(ByteCode::Unpack(idents.len() as u32));
+                        for ident in idents.iter().rev() {
+                            let i = self.resolve(ident);
+                            block.push(ByteCode::Store(i));
+                        }
+                    },
                     _ => todo!()
                 }
 
-                for node in &f.body {
-                    self.compile_node(block, node);
-                }
+                self.compile_block(block, &f.body, false);
 
                 block.push(ByteCode::Jump(start_pc as u32));
                 block[false_jump_pc] = ByteCode::JumpIfFalse(block.len() as u32);
@@ -236,9 +575,14 @@ impl Vm {
             ASTNode::ObjIns(obj) => {
                 // block.push(ByteCode::LoadConst(self.store_const(Value::Str(obj.name.clone()))));
                 for prop in &obj.props {
-                    let c = self.store_const(Value::Str(prop.name.clone()));
+                    // Spreads have no key, their properties are merged in
+                    let (key, value) = match &*prop.value {
+                        ASTNode::Spread(v) => (Value::None, &**v),
+                        v => (Value::Str(prop.name.clone()), v),
+                    };
+                    let c = self.store_const(key);
                     block.push(ByteCode::LoadConst(c));
-                    self.compile_node(block, &prop.value);
+                    self.compile_node(block, value);
                 }
                 if let Some(name) = &obj.name {
                     block.push(ByteCode::LoadConst(self.store_const(Value::Str(name.clone()))));
@@ -247,24 +591,264 @@ impl Vm {
                 }
                 block.push(ByteCode::Obj(obj.props.len() as u32));
             },
+            ASTNode::ProbAccess(_) | ASTNode::Index(_) => self.compile_chain(block, node),
+            ASTNode::Neg(v) => {
+                self.compile_node(block, v);
+                block.push(ByteCode::Neg);
+            },
+            // Struct definitions only describe shapes for tooling
+            ASTNode::StructDef(_) => {},
+            ASTNode::EnumDef(def) => {
+                self.enums.insert(def.name.clone(), def.clone());
+            },
+            ASTNode::Variant(v) => {
+                let shape = self.variant_shape(&v.enum_name, &v.name);
+                let path = format!("{}::{}", v.enum_name, v.name);
+
+                // The fields are pushed in source order and put in the
+                // order of the declaration by the template
+                let fields = match (&shape, &v.args) {
+                    (VariantShape::Unit, VariantArgs::Unit) => VariantFields::Unit,
+                    (VariantShape::Tuple(types), VariantArgs::Tuple(args)) if types.len() == args.len() => {
+                        for arg in args {
+                            self.compile_node(block, arg);
+                        }
+                        VariantFields::Tuple(vec![Value::None; args.len()])
+                    },
+                    (VariantShape::Struct(fields), VariantArgs::Struct(props)) => {
+                        for prop in props {
+                            if !fields.iter().any(|f| f.name == prop.name) {
+                                panic!("{} has no field {}", path, prop.name);
+                            }
+                            let c = self.store_const(Value::Str(prop.name.clone()));
+                            block.push(ByteCode::LoadConst(c));
+                            self.compile_node(block, &prop.value);
+                        }
+                        if let Some(f) = fields.iter().find(|f| !props.iter().any(|p| p.name == f.name)) {
+                            panic!("{} is missing field {}", path, f.name);
+                        }
+                        VariantFields::Struct(fields.iter().map(|f| ObjProp { name: f.name.clone(), value: Value::None }).collect())
+                    },
+                    (shape, _) => panic!("{} {}", path, shape_text(shape)),
+                };
+
+                let c = self.store_const(Value::Enum(Variant { enum_name: v.enum_name.clone(), name: v.name.clone(), fields }));
+                block.push(ByteCode::Variant(c));
+            },
+            ASTNode::Match(m) => {
+                self.compile_node(block, &m.value);
+                // The value is kept in a name scripts can not use
+                let value = self.store_idt("<match>".to_string());
+                block.push(ByteCode::Store(value));
+
+                let mut end_jumps = vec![];
+                for arm in &m.arms {
+                    // The names an arm binds end with its body
+                    self.decls.push(DeclScope::nested(block.len()));
+                    let mut fail_jumps = vec![];
+                    self.compile_pattern(block, &arm.pattern, &[ByteCode::Load(value)], &mut fail_jumps);
+                    for node in &arm.body {
+                        self.compile_stmt(block, node);
+                    }
+                    self.end_decls(block);
+
+                    block.push(ByteCode::Jump(0));
+                    end_jumps.push(block.len() - 1);
+                    for j in fail_jumps {
+                        block[j] = ByteCode::JumpIfFalse(block.len() as u32);
+                    }
+                }
+                for j in end_jumps {
+                    block[j] = ByteCode::Jump(block.len() as u32);
+                }
+            },
+            ASTNode::Test(t) => {
+                // Compiled like a function body but never called by the
+                // script, the test runner runs the block after the script.
+                let mut test_block = Vec::new();
+                self.line_stack.push(LineTable::default());
+                self.compile_block(&mut test_block, &t.body, true);
+                let lines = self.line_stack.pop().unwrap();
+                self.push_block(test_block, lines);
+                self.tests.push((t.name.clone(), (self.code_blocks.len() - 1) as u32));
+            },
+            ASTNode::Module(m) => self.compile_inline_module(block, &m.name, &m.body),
+            ASTNode::Import(i) => self.compile_import(block, &i.path),
+            ASTNode::Use(u) => self.compile_use(block, &u.path),
+            ASTNode::Export(node) => self.compile_export(block, node),
+            _ => todo!("{:?}", node)
+        }
+    }
+
+    /// Compiles a chain of property reads, calls and indexes. A `?.` which
+    /// meets none skips the rest of the chain, so `a?.b.c` is none too.
+    fn compile_chain(&mut self, block: &mut Vec<ByteCode>, node: &ASTNode) {
+        let outer = mem::take(&mut self.none_jumps);
+        self.compile_link(block, node);
+        let end = block.len() as u32;
+        for j in mem::replace(&mut self.none_jumps, outer) {
+            block[j] = ByteCode::JumpIfNone(end);
+        }
+    }
+
+    fn compile_link(&mut self, block: &mut Vec<ByteCode>, node: &ASTNode) {
+        match node {
             ASTNode::ProbAccess(a) => {
-                self.compile_node(block, &a.object);
-                let i = self.store_idt(a.property.clone());
+                self.compile_link(block, &a.object);
+                if a.optional {
+                    self.skip_if_none(block);
+                }
+                block.push(ByteCode::AccessProp(self.store_idt(a.property.clone())));
+            },
+            ASTNode::Index(x) => {
+                self.compile_link(block, &x.object);
+                self.compile_node(block, &x.index);
+                block.push(ByteCode::Index);
+            },
+            ASTNode::Call(c) => self.compile_call(block, &c.callee, &c.args),
+            node => self.compile_node(block, node),
+        }
+    }
+
+    fn skip_if_none(&mut self, block: &mut Vec<ByteCode>) {
+        block.push(ByteCode::JumpIfNone(0));
+        self.none_jumps.push(block.len() - 1);
+    }
+
+    fn compile_call(&mut self, block: &mut Vec<ByteCode>, callee: &ASTNode, args: &[ASTNode]) {
+        let is_await = match callee {
+            ASTNode::Ident(idt) => idt == "await",
+            _ => false
+        };
+
+        // `a?.f(x)` skips the arguments and the call when `a` is none,
+        // so the callee is checked first and kept under them
+        if has_optional(callee) {
+            let prop = match callee {
+                ASTNode::ProbAccess(p) => {
+                    self.compile_link(block, &p.object);
+                    if p.optional {
+                        self.skip_if_none(block);
+                    }
+                    Some(self.store_idt(p.property.clone()))
+                },
+                node => {
+                    self.compile_link(block, node);
+                    None
+                }
+            };
+            for arg in args {
+                self.compile_node(block, arg);
+            }
+            block.push(ByteCode::Lift(args.len() as u32));
+            if let Some(i) = prop {
                 block.push(ByteCode::AccessProp(i));
+            }
+            block.push(ByteCode::Call(args.len() as u32));
+            return;
+        }
+
+        // The callee is compiled ahead of the args so statements
+        // of nested functions are met in source order.
+        // A name nothing defines is a call to the host
+        let mut code = Vec::new();
+        match callee {
+            _ if is_await => {},
+            ASTNode::Ident(name) => {
+                let i = self.resolve(name);
+                code.push(ByteCode::LoadCallee(i));
             },
-            _ => todo!("{:?}", node)
+            node => self.compile_node(&mut code, node),
+        }
+
+        for arg in args {
+            self.compile_node(block, arg);
+        }
+
+        if is_await {
+            block.push(ByteCode::Await);
+            return;
+        }
+
+        let base = block.len() as u32;
+        block.extend(code.into_iter().map(|c| relocate(c, base)));
+        block.push(ByteCode::Call(args.len() as u32));
+    }
+
+    /// The fields of a variant as its enum declares them.
+    fn variant_shape(&self, enum_name: &str, name: &str) -> VariantShape {
+        let def = match self.enums.get(enum_name) {
+            Some(def) => def,
+            None => panic!("Unknown enum {}", enum_name),
+        };
+        match def.variants.iter().find(|v| v.name == name) {
+            Some(v) => v.shape.clone(),
+            None => panic!("{} has no variant {}", enum_name, name),
+        }
+    }
+
+    /// Checks the value `load` pushes against a pattern, each check jumping
+    /// away when it fails, and binds the names of the pattern.
+    fn compile_pattern(&mut self, block: &mut Vec<ByteCode>, pattern: &Pattern, load: &[ByteCode], fails: &mut Vec<usize>) {
+        let mut check = |vm: &mut Vm, block: &mut Vec<ByteCode>, c: Value| {
+            block.extend_from_slice(load);
+            let c = vm.store_const(c);
+            block.push(ByteCode::Matches(c));
+            block.push(ByteCode::JumpIfFalse(0));
+            fails.push(block.len() - 1);
+        };
+
+        match pattern {
+            Pattern::Wildcard => {},
+            Pattern::Bind(name) => {
+                block.extend_from_slice(load);
+                let i = self.declare(name, false);
+                block.push(ByteCode::Store(i));
+            },
+            Pattern::Lit(v) => check(self, block, v.clone()),
+            Pattern::Variant { enum_name, name, fields } => {
+                let shape = self.variant_shape(enum_name, name);
+                let path = format!("{}::{}", enum_name, name);
+                check(self, block, Value::Enum(Variant::unit(enum_name, name)));
+
+                match (fields, &shape) {
+                    (PatternFields::Unit, _) => {},
+                    (PatternFields::Tuple(items), VariantShape::Tuple(types)) if items.len() == types.len() => {
+                        for (i, item) in items.iter().enumerate() {
+                            let mut load = load.to_vec();
+                            load.push(ByteCode::LoadConst(self.store_const(Value::Int(i as i64))));
+                            load.push(ByteCode::Index);
+                            self.compile_pattern(block, item, &load, fails);
+                        }
+                    },
+                    (PatternFields::Struct(items), VariantShape::Struct(declared)) => {
+                        for (field, item) in items {
+                            if !declared.iter().any(|f| &f.name == field) {
+                                panic!("{} has no field {}", path, field);
+                            }
+                            let mut load = load.to_vec();
+                            load.push(ByteCode::AccessProp(self.store_idt(field.clone())));
+                            self.compile_pattern(block, item, &load, fails);
+                        }
+                    },
+                    (_, shape) => panic!("{} {}", path, shape_text(shape)),
+                }
+            },
+        }
+    }
+
+    /// The right side of an assignment, combined with the current value
+    /// of the target for `+=` and the like.
+    fn compile_value(&mut self, block: &mut Vec<ByteCode>, a: &Assign) {
+        self.compile_node(block, &a.right);
+        if let Some(op) = &a.op {
+            block.push(op_code(op));
         }
     }
 
     pub fn cont(&mut self, stack_id: usize, value: Value) -> RunResult {
         let stack = self.callstacks.get_mut(stack_id).unwrap();
-        let val = match value {
-            Value::Int(i) => StackValue::Int(i),
-            Value::Float(f) => StackValue::Float(f),
-            Value::Str(s) => StackValue::Str(s),
-            Value::Bool(b) => StackValue::Bool(b),
-            _ => todo!("{:?}", value)
-        };
+        let val = self.scope.alloc(stack.scope_id(), value);
         stack.push_value(val);
         self.run_stack(stack_id)
     }
@@ -320,10 +904,7 @@ impl Vm {
                                 Value::List(list) => {
                                     match list.get(i) {
                                         Some(v) => StackValue::from(v),
-                                        None => {
-                                            print!(" val: None");
-                                            StackValue::None
-                                        }
+                                        None => StackValue::None
                                     }
                                 },
                                 _ => todo!("{:?}", val)
@@ -364,6 +945,49 @@ impl Vm {
                         }
                         ret
                     },
+                    BuildIn::List(call) => {
+                        match call.next() {
+                            Step::Call(args) => {
+                                let parent = match self.home_scopes.get(&call.blk) {
+                                    Some(s) => *s,
+                                    None => scope_id
+                                };
+                                let scope_id = self.scope.create_child_scope(parent);
+                                let values = args.into_iter()
+                                    .map(|a| self.scope.alloc(scope_id, a))
+                                    .collect();
+
+                                BuildinRes {
+                                    call: Some(Call {
+                                        blk: call.blk,
+                                        scope_id,
+                                        values,
+                                        ..Default::default()
+                                    }),
+                                    disable_buildin: false,
+                                    stack_val: None
+                                }
+                            },
+                            Step::Done(val) => {
+                                let v = match val {
+                                    Some(v) => self.scope.alloc(scope_id, v),
+                                    None => {
+                                        match self.scope.lookup(&call.ptr) {
+                                            Some(Value::List(l)) => *l = mem::take(&mut call.items),
+                                            Some(v) => panic!("{} is not a list", value_kind(v)),
+                                            None => panic!("value not found")
+                                        }
+                                        StackValue::Ptr(call.ptr.clone())
+                                    }
+                                };
+                                BuildinRes {
+                                    call: None,
+                                    disable_buildin: true,
+                                    stack_val: Some(v)
+                                }
+                            }
+                        }
+                    },
                     BuildIn::None => BuildinRes { 
                         call: None, 
                         stack_val: None, 
@@ -393,20 +1017,33 @@ impl Vm {
                     }
                 };
 
-                if stack.pc() >= self.code_blocks[curr_blk as usize].len() as u32  {
-                    break;
-                }   
-
                 let pc = stack.pc();
-                stack.increment_pc();
 
-                let c = &self.code_blocks[curr_blk as usize][pc as usize];
+                let c = if pc >= self.code_blocks[curr_blk as usize].len() as u32 {
+                    if stack.depth() < 2 {
+                        break;
+                    }
+                    // Functions without a return give none
+                    &ByteCode::Ret(0)
+                } else {
+                    if self.debugger.should_pause(&self.line_tables, curr_blk, pc, stack.depth()) {
+                        return RunResult::Paused {
+                            stack_id,
+                            blk: curr_blk,
+                            pc
+                        };
+                    }
+
+                    stack.increment_pc();
+
+                    &self.code_blocks[curr_blk as usize][pc as usize]
+                };
 
                 if self.log > 0 {
                     print!("blk: {} pc: {}, code: {:?}", curr_blk, format_num(5, pc), c);
 
                     match c {
-                        ByteCode::Load(i) => {
+                        ByteCode::Load(i) | ByteCode::LoadCallee(i) => {
                             print!(" {}", self.id_idt_map.get(&i).unwrap());
                             print_stack_top(&mut self.scope, stack);
                         },
@@ -427,6 +1064,12 @@ impl Vm {
 
                 match c {
                     ByteCode::Load(i) => {
+                        match self.scope.lookup(&Ptr{ scope_id: stack.scope_id(), id: *i}) {
+                            Some(v) => stack.push_value(StackValue::from(&*v)),
+                            None => panic!("undefined variable {}", prop_name(&self.id_idt_map, *i))
+                        };
+                    },
+                    ByteCode::LoadCallee(i) => {
                         match self.scope.lookup(&Ptr{ scope_id: stack.scope_id(), id: *i}) {
                             Some(v) => stack.push_value(StackValue::from(&*v)),
                             None => stack.push_value(StackValue::Undef(*i))
@@ -480,45 +1123,55 @@ impl Vm {
                                     _ => panic!("Invalid operation")
                                 }
                             },
+                            (StackValue::Str(s), StackValue::Int(n)) |
+                            (StackValue::Int(n), StackValue::Str(s)) if *c == ByteCode::BinMul => {
+                                StackValue::Str(string::repeat(&s, n))
+                            },
+                            (a, b) if *c == ByteCode::BinAdd => match string::concat(&a, &b) {
+                                Some(s) => StackValue::Str(s),
+                                None => panic!("Invalid operation")
+                            },
                             _ => panic!("Invalid operation")
                         };
 
                         stack.push_value(v);
                     },
+                    ByteCode::Neg => {
+                        let v = match stack.pop_value().unwrap() {
+                            StackValue::Int(i) => StackValue::Int(-i),
+                            StackValue::Float(f) => StackValue::Float(-f),
+                            v => panic!("Can not negate {}", value_kind(&Value::from(v)))
+                        };
+                        stack.push_value(v);
+                    },
                     ByteCode::Jump(indx) => stack.set_pc(*indx),
+                    ByteCode::JumpIfNone(inx) => {
+                        if matches!(stack.peek_value(), Some(StackValue::None)) {
+                            stack.set_pc(*inx);
+                        }
+                    },
+                    ByteCode::JumpIfSome(inx) => {
+                        match stack.peek_value() {
+                            Some(StackValue::None) => {
+                                stack.pop_value();
+                            },
+                            _ => stack.set_pc(*inx),
+                        }
+                    },
                     ByteCode::JumpIfFalse(inx) => {
                         let v = stack.pop_value().unwrap();
 
-                        match v {
-                            StackValue::Bool(b) => {
-                                if !b {
-                                    stack.set_pc(*inx);
-                                }
-                            },
-                            StackValue::None => {
-                                stack.set_pc(*inx);
-                            },
-                            StackValue::Int(i) => {
-                                if i < 1 {
-                                    stack.set_pc(*inx);
-                                } 
+                        let truth = match v {
+                            StackValue::Ptr(ptr) => match self.scope.lookup(&ptr) {
+                                Some(val) => truthy(val),
+                                None => panic!("value not found")
                             },
-                            StackValue::Ptr(ptr) => {
-                                let val = match self.scope.lookup(&ptr) {
-                                    Some(v) => v,
-                                    None => todo!()
-                                };
-
-                                match val {
-                                    Value::List(arr) => {
-                                        if arr.len() < 1 {
-                                            stack.set_pc(*inx);
-                                        }
-                                    },
-                                    _ => todo!("{:?}", val)
-                                };
-                            },
-                            _ => panic!("{:?}", v)
+                            v => truthy(&Value::from(v))
+                        };
+                        match truth {
+                            Ok(true) => {},
+                            Ok(false) => stack.set_pc(*inx),
+                            Err(e) => panic!("{}", e)
                         }
                     },
                     ByteCode::Call(arg_count) => {
@@ -543,7 +1196,12 @@ impl Vm {
 
                         match callee {
                             StackValue::Fn(blk) => {
-                                let scope_id = self.scope.create_child_scope(stack.scope_id());
+                                // Module functions see the module's names
+                                let parent = match self.home_scopes.get(&blk) {
+                                    Some(s) => *s,
+                                    None => stack.scope_id()
+                                };
+                                let scope_id = self.scope.create_child_scope(parent);
 
                                 stack.push(Call {
                                     blk,
@@ -554,18 +1212,52 @@ impl Vm {
 
                                 curr_blk = blk;
                             },
+                            StackValue::Native(id) => {
+                                let args = args.into_iter()
+                                    .map(|a| self.scope.copy_value(Value::from(a)))
+                                    .collect();
+                                let v = call_native(&self.natives, id, args);
+                                let v = self.scope.alloc(scope_id, v);
+                                stack.push_value(v);
+                            },
+                            StackValue::Undef(i) if !matches!(
+                                self.code_blocks[curr_blk as usize].get(stack.pc() as usize),
+                                Some(ByteCode::Await)
+                            ) => {
+                                panic!("undefined function {}", prop_name(&self.id_idt_map, i));
+                            },
                             StackValue::Undef(i) => {
-                                let mut args = vec![];
-                                for _ in 0..*arg_count {
-                                    let v = stack.pop_value().unwrap();
-                                    args.push(v);
-                                }
-                                args.reverse();
                                 stack.push_value(StackValue::UndefCall {
                                     ident: i,
                                     args
                                 });
                             },
+                            StackValue::UserDataMethod { ud, prop } => {
+                                let typ = match self.userdata_types.get(&ud.type_id()) {
+                                    Some(t) => t,
+                                    None => panic!("Userdata type {} is not registered", ud.type_name())
+                                };
+                                let args = args.into_iter()
+                                    .map(|a| self.scope.copy_value(Value::from(a)))
+                                    .collect();
+                                let name = prop_name(&self.id_idt_map, prop);
+                                let v = match typ.call(&ud, &name, args) {
+                                    Ok(v) => v,
+                                    Err(e) => panic!("{}", e)
+                                };
+                                let v = self.scope.alloc(scope_id, v);
+                                stack.push_value(v);
+                            },
+                            StackValue::StrMethod { s, prop } => {
+                                let name = prop_name(&self.id_idt_map, prop);
+                                let args = args.into_iter()
+                                    .map(|a| self.scope.copy_value(Value::from(a)))
+                                    .collect();
+                                match string::call_method(&mut self.scope, &s, &name, args, scope_id) {
+                                    Ok(v) => stack.push_value(v),
+                                    Err(e) => panic!("{}", e)
+                                }
+                            },
                             StackValue::PropAccess { ptr, prop } => {
                                 let val = match self.scope.lookup(&ptr) {
                                     Some(v) => v,
@@ -581,16 +1273,15 @@ impl Vm {
                                                 }
                                             },
                                             POP_METHOD => {
-                                                let v = l.pop().unwrap();
+                                                let v = match l.pop() {
+                                                    Some(v) => v,
+                                                    None => panic!("pop of an empty list")
+                                                };
                                                 stack.push_value(StackValue::from(&v));
                                             },
                                             MAP_METHOD => {
-                                                if args.len() < 1 {
-                                                    todo!()
-                                                }
-                                                let val = &args[0];
-                                                match val {
-                                                    StackValue::Fn(blk) => {
+                                                match args.first() {
+                                                    Some(StackValue::Fn(blk)) => {
                                                         stack.set_buildin(
                                                             BuildIn::Map {
                                                                 blk: *blk,
@@ -599,14 +1290,66 @@ impl Vm {
                                                             }
                                                         );
                                                     },
-                                                    _ => todo!("{:?}", val)
+                                                    Some(v) => panic!("map expects a function but got {}", value_kind(&Value::from(v.clone()))),
+                                                    None => panic!("map expects a function")
                                                 }
 
                                                 continue;
                                             },
-                                            _ => todo!()
+                                            _ => {
+                                                let name = prop_name(&self.id_idt_map, prop);
+                                                let args = args.into_iter().map(Value::from).collect();
+                                                match list::call_method(&mut self.scope, &ptr, &name, args, scope_id) {
+                                                    Ok(MethodResult::Value(v)) => stack.push_value(v),
+                                                    Ok(MethodResult::Callback(call)) => {
+                                                        stack.set_buildin(BuildIn::List(Box::new(call)));
+                                                        continue;
+                                                    },
+                                                    Err(e) => panic!("{}", e)
+                                                }
+                                            }
                                         }
                                     },
+                                    Value::Obj(obj) => {
+                                        let name = prop_name(&self.id_idt_map, prop);
+                                        let blk = match obj.props.get(&name) {
+                                            Some(Value::Fn(blk)) => *blk,
+                                            Some(Value::Native(id)) => {
+                                                let id = *id;
+                                                let args = args.into_iter()
+                                                    .map(|a| self.scope.copy_value(Value::from(a)))
+                                                    .collect();
+                                                let v = call_native(&self.natives, id, args);
+                                                let v = self.scope.alloc(scope_id, v);
+                                                stack.push_value(v);
+                                                continue;
+                                            },
+                                            Some(v) => panic!("{} is not a function: {:?}", name, v),
+                                            None if OBJECT_METHODS.contains(&name.as_str()) => {
+                                                let args = args.into_iter()
+                                                    .map(|a| self.scope.copy_value(Value::from(a)))
+                                                    .collect();
+                                                match object::call_method(&mut self.scope, &ptr, &name, args, scope_id) {
+                                                    Ok(v) => stack.push_value(v),
+                                                    Err(e) => panic!("{}", e)
+                                                }
+                                                continue;
+                                            },
+                                            None => panic!("{} has no function {}", obj.name.as_deref().unwrap_or("object"), name)
+                                        };
+                                        let parent = match self.home_scopes.get(&blk) {
+                                            Some(s) => *s,
+                                            None => stack.scope_id()
+                                        };
+                                        let scope_id = self.scope.create_child_scope(parent);
+
+                                        stack.push(Call {
+                                            blk,
+                                            scope_id,
+                                            values: args,
+                                            ..Default::default()
+                                        });
+                                    },
                                     _ => todo!("{:?}", val)
                                 };
                             },
@@ -624,11 +1367,46 @@ impl Vm {
                             (StackValue::Int(a), StackValue::Float(b)) => StackValue::Bool(a as f64 == b),
                             (StackValue::Bool(a), StackValue::Bool(b)) => StackValue::Bool(a == b),
                             (StackValue::Str(a), StackValue::Str(b)) => StackValue::Bool(a == b),
+                            (StackValue::None, b) => StackValue::Bool(matches!(b, StackValue::None)),
+                            (_, StackValue::None) => StackValue::Bool(false),
+                            (StackValue::Ptr(a), StackValue::Ptr(b)) => {
+                                let a = self.scope.lookup(&a).cloned();
+                                let b = self.scope.lookup(&b).cloned();
+                                match (a, b) {
+                                    // Variants are equal when their fields are
+                                    (Some(a @ Value::Enum(_)), Some(b @ Value::Enum(_))) => {
+                                        StackValue::Bool(self.scope.copy_value(a) == self.scope.copy_value(b))
+                                    },
+                                    (Some(Value::Enum(_)), _) | (_, Some(Value::Enum(_))) => StackValue::Bool(false),
+                                    _ => panic!("Invalid operation")
+                                }
+                            },
                             _ => panic!("Invalid operation")
                         };
 
                         stack.push_value(v);
                     },
+                    ByteCode::Lt |
+                    ByteCode::Gt => {
+                        let tos = stack.pop_value().unwrap();
+                        let tos1 = stack.pop_value().unwrap();
+
+                        let order = match (tos1, tos) {
+                            (StackValue::Int(a), StackValue::Int(b)) => a.partial_cmp(&b),
+                            (StackValue::Float(a), StackValue::Float(b)) => a.partial_cmp(&b),
+                            (StackValue::Float(a), StackValue::Int(b)) => a.partial_cmp(&(b as f64)),
+                            (StackValue::Int(a), StackValue::Float(b)) => (a as f64).partial_cmp(&b),
+                            // Strings order by Unicode scalar
+                            (StackValue::Str(a), StackValue::Str(b)) => a.partial_cmp(&b),
+                            _ => panic!("Invalid operation")
+                        };
+
+                        let v = match c {
+                            ByteCode::Lt => order == Some(Ordering::Less),
+                            _ => order == Some(Ordering::Greater)
+                        };
+                        stack.push_value(StackValue::Bool(v));
+                    },
                     ByteCode::BeginScope => {
                         let scope_id = self.scope.create_child_scope(stack.scope_id());
                         stack.set_scope_id(scope_id);
@@ -657,14 +1435,14 @@ impl Vm {
                         stack.push_value(StackValue::Ptr(ptr));
                     },
                     ByteCode::Assign => todo!(),
-                    ByteCode::Ret(_) => {
+                    ByteCode::Ret(a) => {
                         if self.log > 1 {
                             println!("stack: {:?}", stack);
                             println!("stack depth: {}", stack.depth());
                         }
 
                         if stack.depth() > 1 {
-                            let v = match stack.pop_value() {
+                            let v = match stack.pop_value().filter(|_| *a > 0) {
                                 Some(v) =>  {
                                     match v {
                                         StackValue::Ptr(ptr) => {
@@ -749,6 +1527,17 @@ impl Vm {
                                 };
 
                                 match val {
+                                    Value::Obj(_) => {
+                                        let pairs = object::entries(&mut self.scope, &ptr, scope_id);
+                                        let ptr = self.scope.store_unamed(scope_id, Value::List(pairs));
+                                        let ptr = self.scope.store_unamed(scope_id, Value::ListIter(
+                                            ListIter {
+                                                ptr,
+                                                inx: 0
+                                            }
+                                        ));
+                                        stack.push_value(StackValue::Ptr(ptr));
+                                    },
                                     Value::List(list) => {
                                         let ptr = self.scope.store_unamed(scope_id, Value::ListIter(
                                             ListIter {
@@ -761,9 +1550,101 @@ impl Vm {
                                     _ => todo!("{:?}", val)
                                 };
                             },
+                            StackValue::Str(s) => {
+                                // Strings are iterated by Unicode scalar
+                                let chars = s.chars().map(|c| Value::Str(c.to_string())).collect();
+                                let ptr = self.scope.store_unamed(scope_id, Value::List(chars));
+                                let ptr = self.scope.store_unamed(scope_id, Value::ListIter(
+                                    ListIter {
+                                        ptr,
+                                        inx: 0
+                                    }
+                                ));
+                                stack.push_value(StackValue::Ptr(ptr));
+                            },
                             _ => todo!("{:?}", val)
                         }
                     },
+                    ByteCode::Unpack(n) => {
+                        let val = stack.pop_value().unwrap();
+                        let items = match &val {
+                            StackValue::Ptr(p) => match self.scope.lookup(p) {
+                                Some(Value::List(items)) => items.clone(),
+                                Some(v) => panic!("Can not unpack {}", value_kind(v)),
+                                None => panic!("value not found")
+                            },
+                            _ => panic!("Can not unpack {}", value_kind(&Value::from(val)))
+                        };
+
+                        for i in 0..*n as usize {
+                            let item = items.get(i).cloned().unwrap_or_default();
+                            let v = self.scope.alloc(scope_id, item);
+                            stack.push_value(v);
+                        }
+                    },
+                    ByteCode::Index => {
+                        let inx = stack.pop_value().unwrap();
+                        let target = stack.pop_value().unwrap();
+                        let inx = self.scope.copy_value(Value::from(inx));
+                        match index::get(&mut self.scope, &target, &inx) {
+                            Ok(v) => stack.push_value(v),
+                            Err(e) => panic!("{}", e)
+                        }
+                    },
+                    ByteCode::StoreIndex => {
+                        let v = Value::from(stack.pop_value().unwrap());
+                        let inx = stack.pop_value().unwrap();
+                        let target = stack.pop_value().unwrap();
+                        let inx = self.scope.copy_value(Value::from(inx));
+                        if let Err(e) = index::set(&mut self.scope, &target, &inx, v) {
+                            panic!("{}", e);
+                        }
+                    },
+                    ByteCode::StoreProp(a) => {
+                        let v = Value::from(stack.pop_value().unwrap());
+                        let target = stack.pop_value().unwrap();
+                        let name = prop_name(&self.id_idt_map, *a);
+                        if let StackValue::UserData(ud) = &target {
+                            let typ = match self.userdata_types.get(&ud.type_id()) {
+                                Some(t) => t,
+                                None => panic!("Userdata type {} is not registered", ud.type_name())
+                            };
+                            if let Err(e) = typ.set(ud, &name, v) {
+                                panic!("{}", e);
+                            }
+                        } else {
+                            let obj = match &target {
+                                StackValue::Ptr(ptr) => self.scope.lookup(ptr),
+                                _ => None
+                            };
+                            match obj {
+                                Some(Value::Obj(obj)) => obj.props.set(name, v),
+                                Some(other) => panic!("can not set field {} on {}", name, value_kind(other)),
+                                None => panic!("can not set field {} on {}", name, value_kind(&Value::from(target)))
+                            }
+                        }
+                    },
+                    ByteCode::Lift(n) => {
+                        let mut top = vec![];
+                        for _ in 0..*n {
+                            top.push(stack.pop_value().unwrap());
+                        }
+                        let v = stack.pop_value().unwrap();
+                        for item in top.into_iter().rev() {
+                            stack.push_value(item);
+                        }
+                        stack.push_value(v);
+                    },
+                    ByteCode::Dup(n) => {
+                        let mut top = vec![];
+                        for _ in 0..*n {
+                            top.push(stack.pop_value().unwrap());
+                        }
+                        top.reverse();
+                        for v in top.iter().chain(top.iter()) {
+                            stack.push_value(v.clone());
+                        }
+                    },
                     ByteCode::Await => {
                         let val = stack.pop_value().unwrap();
                         let val = match val {
@@ -775,7 +1656,9 @@ impl Vm {
                             StackValue::UndefCall { ident, args } => {
                                 Value::UndefCall {
                                     ident,
-                                    args: vec![]
+                                    args: args.into_iter()
+                                        .map(|a| self.scope.copy_value(Value::from(a)))
+                                        .collect()
                                 }
                             },
                             _ => todo!("{:?}", val)
@@ -797,24 +1680,36 @@ impl Vm {
                         
                         let mut obj = Obj {
                             name,
-                            props: vec![]
+                            props: Props::new()
                         };
 
+                        let mut pairs = vec![];
                         for _ in 0..*arg_count {
                             let v = stack.pop_value().unwrap();
                             let k = stack.pop_value().unwrap();
+                            pairs.push((k, v));
+                        }
 
-                            let key = match k {
-                                StackValue::Str(s) => s,
+                        // Later properties win over earlier ones and spreads
+                        for (k, v) in pairs.into_iter().rev() {
+                            match k {
+                                StackValue::Str(key) => obj.props.set(key, Value::from(v)),
+                                StackValue::None => {
+                                    let base = match &v {
+                                        StackValue::Ptr(p) => self.scope.lookup(p),
+                                        _ => None
+                                    };
+                                    match base {
+                                        Some(Value::Obj(base)) => {
+                                            for prop in base.props.iter() {
+                                                obj.props.set(prop.name.clone(), prop.value.clone());
+                                            }
+                                        },
+                                        _ => panic!("Can only spread objects, got {}", value_kind(&Value::from(v)))
+                                    }
+                                },
                                 _ => todo!("{:?}", k)
                             };
-
-                            obj.props.push(
-                                ObjProp {
-                                    name: key,
-                                    value: Value::from(v)
-                                }
-                            );
                         }
 
                         if self.log > 1 {
@@ -829,13 +1724,122 @@ impl Vm {
 
                         stack.push_value(StackValue::Ptr(ptr));
                     },
+                    ByteCode::Variant(c) => {
+                        let mut variant = match &self.constants[*c as usize] {
+                            Value::Enum(v) => v.clone(),
+                            v => panic!("Invalid variant {}", v)
+                        };
+
+                        match &mut variant.fields {
+                            VariantFields::Unit => {},
+                            VariantFields::Tuple(items) => {
+                                for item in items.iter_mut().rev() {
+                                    *item = Value::from(stack.pop_value().unwrap());
+                                }
+                            },
+                            VariantFields::Struct(props) => {
+                                for _ in 0..props.len() {
+                                    let v = stack.pop_value().unwrap();
+                                    if let StackValue::Str(key) = stack.pop_value().unwrap() {
+                                        props.set(key, Value::from(v));
+                                    }
+                                }
+                            },
+                        }
+
+                        let ptr = self.scope.store_unamed(scope_id, Value::Enum(variant));
+                        stack.push_value(StackValue::Ptr(ptr));
+                    },
+                    ByteCode::Matches(c) => {
+                        let val = stack.pop_value().unwrap();
+                        let matched = match (&self.constants[*c as usize], &val) {
+                            (Value::Enum(pattern), StackValue::Ptr(ptr)) => matches!(
+                                self.scope.lookup(ptr),
+                                Some(Value::Enum(v)) if v.is(&pattern.enum_name, &pattern.name)
+                            ),
+                            (Value::Int(a), StackValue::Int(b)) => a == b,
+                            (Value::Int(a), StackValue::Float(b)) => *a as f64 == *b,
+                            (Value::Float(a), StackValue::Float(b)) => a == b,
+                            (Value::Float(a), StackValue::Int(b)) => *a == *b as f64,
+                            (Value::Str(a), StackValue::Str(b)) => a == b,
+                            (Value::Bool(a), StackValue::Bool(b)) => a == b,
+                            (Value::None, StackValue::None) => true,
+                            _ => false
+                        };
+                        stack.push_value(StackValue::Bool(matched));
+                    },
                     ByteCode::AccessProp(a) => {
+                        // Method calls are resolved by the following Call,
+                        // everything else is a field read.
+                        let is_call = matches!(
+                            self.code_blocks[curr_blk as usize].get(stack.pc() as usize),
+                            Some(ByteCode::Call(_))
+                        );
                         let val = stack.pop_value().unwrap();
                         match val {
                             StackValue::Ptr(ptr) => {
-                                stack.push_value(StackValue::PropAccess { ptr: ptr.clone(), prop: *a });
+                                if is_call {
+                                    stack.push_value(StackValue::PropAccess { ptr: ptr.clone(), prop: *a });
+                                } else {
+                                    let name = prop_name(&self.id_idt_map, *a);
+                                    let v = match self.scope.lookup(&ptr) {
+                                        Some(Value::Obj(obj)) => {
+                                            match obj.props.get(&name) {
+                                                Some(v) => v.clone(),
+                                                None if name == NAME_PROP => match &obj.name {
+                                                    Some(n) => Value::Str(n.clone()),
+                                                    None => Value::None
+                                                },
+                                                None => Value::None
+                                            }
+                                        },
+                                        Some(Value::Enum(v)) => match v.field(&name) {
+                                            Some(f) => f.clone(),
+                                            None => panic!("{}::{} has no field {}", v.enum_name, v.name, name)
+                                        },
+                                        Some(v) => panic!("{} has no field {}", value_kind(v), name),
+                                        None => panic!("value not found")
+                                    };
+                                    let (v, slot) = index::member(&mut self.scope, &ptr, &v);
+                                    match (slot, self.scope.lookup(&ptr)) {
+                                        (Some(slot), Some(Value::Obj(obj))) => obj.props.set(name, slot),
+                                        (Some(slot), Some(Value::Enum(Variant { fields: VariantFields::Struct(props), .. }))) => {
+                                            props.set(name, slot);
+                                        },
+                                        _ => {}
+                                    }
+                                    stack.push_value(v);
+                                }
                             },
-                            _ => todo!("{:?}", val)
+                            StackValue::UserData(ud) => {
+                                if is_call {
+                                    stack.push_value(StackValue::UserDataMethod { ud, prop: *a });
+                                } else {
+                                    let typ = match self.userdata_types.get(&ud.type_id()) {
+                                        Some(t) => t,
+                                        None => panic!("Userdata type {} is not registered", ud.type_name())
+                                    };
+                                    let name = prop_name(&self.id_idt_map, *a);
+                                    let v = match typ.get(&ud, &name) {
+                                        Ok(v) => v,
+                                        Err(e) => panic!("{}", e)
+                                    };
+                                    let v = self.scope.alloc(scope_id, v);
+                                    stack.push_value(v);
+                                }
+                            },
+                            StackValue::Str(s) => {
+                                if is_call {
+                                    stack.push_value(StackValue::StrMethod { s, prop: *a });
+                                } else {
+                                    panic!("string has no field {}", prop_name(&self.id_idt_map, *a));
+                                }
+                            },
+                            // `?.` reads through none, a plain `.` does not
+                            StackValue::None => {
+                                panic!("can not read {} of none, use ?. for optional values", prop_name(&self.id_idt_map, *a));
+                            },
+                            v => panic!("{} has no field {}", value_kind(&Value::from(v)), prop_name(&self.id_idt_map, *a)),
                         };
                     },
                     _ => todo!("{:?}", c)
@@ -858,12 +1862,25 @@ impl Vm {
                         };
 
                         match val {
-                            Value::List(list) => list[*inx as usize] = Value::from(tos),
+                            // The callback may have shortened the list
+                            Value::List(list) => if let Some(item) = list.get_mut(*inx as usize) {
+                                *item = Value::from(tos);
+                            },
                             _ => todo!("{:?}", val)
                         };
 
                         *inx += 1;
                     },
+                    BuildIn::List(call) => {
+                        let result = Value::from(tos);
+                        let resolved = match &result {
+                            Value::Ptr(p) => self.scope.lookup(p).cloned().unwrap_or_default(),
+                            v => v.clone()
+                        };
+                        if let Err(e) = call.feed(result, resolved) {
+                            panic!("{}", e);
+                        }
+                    },
                     BuildIn::None => {}
                 }
             }
@@ -880,8 +1897,7 @@ impl Vm {
     }
 
     pub fn run_code(&mut self, code: &str) -> RunResult {
-        let ast = Parser::new(code).parse();
-        let blk = self.compile_ast(&ast);
+        let blk = self.compile_code(code);
 
         if self.log > 0 {
             println!("compiled ast to blk: {}", blk);
@@ -895,8 +1911,36 @@ impl Vm {
             println!("run_blk blk: {} args: {:?}", blk, args);
         }
 
-        let scope_id = self.scope.create_scope();
+        let scope_id = self.scope.create_child_scope(self.globals);
+        self.run_blk_in(blk, scope_id)
+    }
+
+    /// Runs code in a scope which persists between calls, so variables
+    /// defined by one snippet are visible to the next.
+    pub fn eval(&mut self, code: &str) -> RunResult {
+        let blk = self.compile_code(code);
+        self.eval_blk(blk)
+    }
+
+    pub fn eval_blk(&mut self, blk: u32) -> RunResult {
+        let scope_id = match self.session {
+            Some(s) => s,
+            None => {
+                let s = self.scope.create_child_scope(self.globals);
+                self.session = Some(s);
+                s
+            }
+        };
+
+        self.run_blk_in(blk, scope_id)
+    }
+
+    /// Forgets the variables defined through `eval`.
+    pub fn reset_session(&mut self) {
+        self.session = None;
+    }
 
+    pub(crate) fn run_blk_in(&mut self, blk: u32, scope_id: u32) -> RunResult {
         let mut stack = Callstack::new();
         stack.log = self.log;
         stack.push(Call {
@@ -938,6 +1982,19 @@ impl Vm {
         }
     }
 
+    /// Names and code blocks of the `test` blocks compiled so far.
+    pub fn tests(&self) -> &[(String, u32)] {
+        &self.tests
+    }
+
+    pub fn idt_name(&self, id: u32) -> Option<&str> {
+        self.id_idt_map.get(&id).map(|s| s.as_str())
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.code_blocks.len()
+    }
+
     pub fn get_val(&mut self, ptr: &Ptr) -> Option<&mut Value> {
         self.scope.lookup(&ptr)
     }
@@ -1,5 +1,7 @@
 #[cfg(test)]
 mod tests {
+    use crate::test_util::run;
+    use crate::test_util::run_err;
     use crate::Obj;
     use crate::ObjProp;
     use crate::Property;
@@ -56,6 +58,31 @@ mod tests {
         assert_eq!(res, RunResult::None);
     }
 
+    #[test]
+    fn if_else_branches() {
+        let code = r#"
+        pick = (n) => {
+            if n == 1 {
+                res = "one"
+            } else if n == 2 {
+                res = "two"
+            } else {
+                res = "many"
+            }
+            return res
+        }
+        return [pick(1), pick(2), pick(3)]
+        "#;
+
+        let mut vm = Vm::new();
+        let res = vm.run_code(code);
+        assert_eq!(res, RunResult::Value(Value::List(vec![
+            Value::Str("one".to_string()),
+            Value::Str("two".to_string()),
+            Value::Str("many".to_string()),
+        ])));
+    }
+
     #[test]
     fn assign_to_var() {
         let mut vm = Vm::new();
@@ -129,7 +156,7 @@ mod tests {
                             name: "name".to_string(),
                             value: Value::Str("Seppo".to_string())
                         }
-                    ]
+                    ].into()
                 }
             )
         );
@@ -163,6 +190,23 @@ mod tests {
         });
     }
 
+    #[test]
+    fn await_fun_with_args() {
+        let mut vm = Vm::new();
+        let res = vm.run_code(r#"await(fetch(1, "a"))"#);
+
+        assert_eq!(res, RunResult::Await {
+            stack_id: 0,
+            value: Value::UndefCall {
+                ident: 30,
+                args: vec![
+                    Value::Int(1),
+                    Value::Str("a".to_string())
+                ]
+            }
+        });
+    }
+
     #[test]
     fn await_fun_return_result() {
         let mut vm = Vm::new();
@@ -192,7 +236,7 @@ mod tests {
                                 name: "text".to_string(),
                                 value: Value::Str("lol".to_string())
                             }
-                        ]
+                        ].into()
                     }
                 ));
             },
@@ -283,10 +327,6 @@ mod tests {
                     Obj {
                         name: Some("Html".to_string()),
                         props: vec![
-                            ObjProp {
-                                name: "body".to_string(),
-                                value: Value::List(vec![])
-                            },
                             ObjProp {
                                 name: "head".to_string(),
                                 value: Value::Obj(
@@ -297,11 +337,15 @@ mod tests {
                                                 name: "title".to_string(),
                                                 value: Value::Str("hello".to_string())
                                             }
-                                        ]
+                                        ].into()
                                     }
                                 )
+                            },
+                            ObjProp {
+                                name: "body".to_string(),
+                                value: Value::List(vec![])
                             }
-                        ]
+                        ].into()
                     }
                 );
 
@@ -335,7 +379,7 @@ mod tests {
                                     Value::Int(6),
                                 ])
                             }
-                        ]
+                        ].into()
                     }
                 );
 
@@ -371,12 +415,12 @@ mod tests {
                                                     name: "name".to_string(),
                                                     value: Value::Str("test".to_string())
                                                 }
-                                            ]
+                                            ].into()
                                         }
                                     )
                                 ])
                             }
-                        ]
+                        ].into()
                     }
                 );
 
@@ -432,7 +476,7 @@ mod tests {
                                 name: "name".to_string(),
                                 value: Value::Str("test".to_string())
                             }
-                        ]
+                        ].into()
                     }
                 );
 
@@ -441,4 +485,242 @@ mod tests {
             _ => panic!("Invalid result")
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn minus_and_negation() {
+        assert_eq!(run("return 5-3"), Value::Int(2));
+        assert_eq!(run("a = 4\nreturn a -1"), Value::Int(3));
+        assert_eq!(run("a = 4\nreturn -a"), Value::Int(-4));
+        assert_eq!(run("a = 1\nb = 2\nreturn -(a + b) * 2"), Value::Int(-6));
+        assert_eq!(run("o = { x: 1.5 }\nreturn 1 - -o.x"), Value::Float(2.5));
+        assert_eq!(run("return [-1, - 2, 3 - -4]"), Value::List(vec![
+            Value::Int(-1),
+            Value::Int(-2),
+            Value::Int(7),
+        ]));
+    }
+
+    #[test]
+    fn number_literals() {
+        assert_eq!(run("return [0xff, 0o17, 0b1010, 1_000_000, -0x10]"), Value::List(vec![
+            Value::Int(255),
+            Value::Int(15),
+            Value::Int(10),
+            Value::Int(1_000_000),
+            Value::Int(-16),
+        ]));
+        assert_eq!(run("return [2.5e3, 1e-2, 1_000.25, .5]"), Value::List(vec![
+            Value::Float(2500.0),
+            Value::Float(0.01),
+            Value::Float(1000.25),
+            Value::Float(0.5),
+        ]));
+        assert_eq!(run("return [-9223372036854775808, -0x8000_0000_0000_0000]"), Value::List(vec![
+            Value::Int(i64::MIN),
+            Value::Int(i64::MIN),
+        ]));
+        let code = "x = -9223372036854775808
+match x {
+    -9223372036854775808 => return 1
+    _ => return 2
+}";
+        assert_eq!(run(code), Value::Int(1));
+    }
+
+    #[test]
+    fn let_and_const() {
+        assert_eq!(run("let a = 1\nconst b = 2\na = a + b\nreturn a"), Value::Int(3));
+
+        // Blocks end their names and leave the outer ones alone
+        let code = "let x = 1
+seen = []
+if x == 1 {
+    let x = 2
+    seen.push(x)
+    for i in [1] {
+        let x = x + 10
+        seen.push(x)
+    }
+    seen.push(x)
+}
+seen.push(x)
+return seen";
+        assert_eq!(run(code), Value::List(vec![
+            Value::Int(2),
+            Value::Int(12),
+            Value::Int(2),
+            Value::Int(1),
+        ]));
+
+        // Shadowing in the same block, closures see the name they were made with
+        let code = "let n = 1
+let n = n + 1
+f = () => {
+    let n = n * 10
+    return n
+}
+if true {
+    let y = 5
+    g = () => {
+        return y
+    }
+}
+return [n, f(), g()]";
+        assert_eq!(run(code), Value::List(vec![Value::Int(2), Value::Int(20), Value::Int(5)]));
+
+        // Assignments update the declared name
+        let code = "let total = 0
+for i in [1, 2, 3] {
+    if i > 1 {
+        let step = i
+        total += step
+    }
+}
+return total";
+        assert_eq!(run(code), Value::Int(5));
+
+        // Parameters shadow names declared around the function
+        assert_eq!(run("if true {\n    let p = 1\n    f = (p) => {\n        return p * 2\n    }\n}\nreturn f(3)"), Value::Int(6));
+    }
+
+    #[test]
+    fn const_and_undefined_errors() {
+        assert_eq!(run_err("const a = 1\na = 2"), "Can not assign to constant a");
+        assert_eq!(run_err("const a = 1\na += 2"), "Can not assign to constant a");
+        assert_eq!(run_err("const a = 1\nf = () => {\n    a = 2\n}"), "Can not assign to constant a");
+        assert_eq!(run_err("return missing"), "undefined variable missing");
+        assert_eq!(run_err("n += 1"), "undefined variable n");
+        assert_eq!(run_err("if true {\n    let y = 1\n}\nreturn y"), "undefined variable y");
+        assert_eq!(run_err("f = () => {\n    return g()\n}\nf()"), "undefined function g");
+        assert_eq!(run_err("return await(fetch(g(1)))"), "undefined function g");
+        // Rejected at compile time, before anything runs
+        assert_eq!(run_err("print(1)\nconst a = 1\na = 2"), "Can not assign to constant a");
+
+        // Shadowing a constant is allowed, as is a new constant in a block
+        let mut vm = Vm::new();
+        let res = vm.run_code("const a = 1\nlet a = 2\na = 3\nif true {\n    const a = 4\n}\nreturn a");
+        assert_eq!(res, RunResult::Value(Value::Int(3)));
+
+        // Unknown names in call position still call the host
+        let mut vm = Vm::new();
+        match vm.run_code("return await(fetch(1))") {
+            RunResult::Await { stack_id, .. } => {
+                assert_eq!(vm.cont(stack_id, Value::Int(2)), RunResult::Value(Value::Int(2)));
+            },
+            r => panic!("Expected await but got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn enums_and_match() {
+        let code = r#"
+describe = (s) => {
+    match s {
+        Status::Ok(x) => {
+            return "ok " + x
+        }
+        Status::Error { code: 404, message } => return "missing " + message
+        Status::Error { code } => return "error " + code
+        Status::Pending => return "pending"
+    }
+}
+enum Status {
+    Ok(string)
+    Error { code: int, message: string }
+    Pending
+}
+return [
+    describe(Status::Ok("a")),
+    describe(Status::Error { message: "gone", code: 404 }),
+    describe(Status::Error { code: 500, message: "x" }),
+    describe(Status::Pending),
+]"#;
+        assert_eq!(run(code), Value::List(vec![
+            Value::Str("ok a".to_string()),
+            Value::Str("missing gone".to_string()),
+            Value::Str("error 500".to_string()),
+            Value::Str("pending".to_string()),
+        ]));
+
+        // Fields are read by position or name, and values print as written
+        let code = "enum E { P(int, int), R { w: int } }\ne = E::R { w: 3 }\nreturn [E::P(1, 2)[1], e.w, e[\"w\"], e]";
+        assert_eq!(run(code).to_string(), "[2, 3, 3, E::R { w: 3 }]");
+
+        // Values of an enum are equal when their variants and fields are
+        let code = "enum E { U, P(int, int), R { w: int } }\nreturn [E::P(1, 2) == E::P(1, 2), E::P(1, 2) == E::P(1, 3), E::U == E::U, E::U == E::R { w: 1 }, E::R { w: 1 } == E::R { w: 1 }]";
+        assert_eq!(run(code).to_string(), "[true, false, true, false, true]");
+
+        // Literal patterns, and nothing runs when no arm matches
+        let code = "r = []\nf = (n) => {\n    match n {\n        1 => r.push(\"one\")\n        2 => r.push(\"two\")\n        -3 => r.push(\"minus three\")\n    }\n}\nf(1)\nf(2.0)\nf(-3)\nf(4)\nmatch \"x\" {\n    \"y\" => r.push(1)\n}\nreturn r";
+        assert_eq!(run(code).to_string(), r#"["one", "two", "minus three"]"#);
+
+        // Bound names end with their arm
+        let code = "enum O { Some(int), None }\nx = 1\nmatch O::Some(5) {\n    O::Some(x) => {\n        y = x\n    }\n    _ => {}\n}\nreturn [x, y]";
+        assert_eq!(run(code).to_string(), "[1, 5]");
+    }
+
+    #[test]
+    fn enum_errors() {
+        const E: &str = "enum E { U, T(int), S { a: int } }\n";
+        assert_eq!(run_err("x = E::U"), "Unknown enum E");
+        assert_eq!(run_err(&format!("{}x = E::V", E)), "E has no variant V");
+        assert_eq!(run_err(&format!("{}x = E::T(1, 2)", E)), "E::T takes 1 field");
+        assert_eq!(run_err(&format!("{}x = E::U(1)", E)), "E::U takes no fields");
+        assert_eq!(run_err(&format!("{}x = E::S(1)", E)), "E::S takes named fields");
+        assert_eq!(run_err(&format!("{}x = E::S {{ b: 1 }}", E)), "E::S has no field b");
+        assert_eq!(run_err(&format!("{}x = E::S {{}}", E)), "E::S takes named fields");
+        assert_eq!(run_err(&format!("{}match 1 {{\n    E::S {{ b }} => print(b)\n}}", E)), "E::S has no field b");
+        assert_eq!(run_err(&format!("{}return E::S {{ a: 1 }}.b", E)), "E::S has no field b");
+        assert_eq!(run_err(&format!("{}return E::U[0]", E)), "E::U has no fields");
+        assert_eq!(run_err(&format!("{}x = E::T(1)\nx[0] = 2", E)), "fields of an enum value can not be changed");
+    }
+
+    #[test]
+    fn optional_values() {
+        let code = r#"
+p = { name: "a", address: { city: "Turku" }, items: [1] }
+q = none
+r = []
+r.push(p?.address?.city)
+r.push(q?.address?.city)
+r.push(q?.name ?? "anon")
+r.push(p.name ?? "anon")
+r.push(p.missing ?? 0)
+p?.items.push(2)
+q?.items?.push(3)
+r.push(p.items)
+r.push(q?.f(r.push(99)))
+r.push(none ?? none ?? 5)
+r.push(p.items[0] + 1)
+match q {
+    none => r.push("none")
+    _ => r.push("some")
+}
+return r"#;
+        assert_eq!(run(code).to_string(), r#"["Turku", none, "anon", "a", 0, [1, 2], none, 5, 2, "none"]"#);
+
+        let code = "p = none\nreturn [p?.items ?? [], p?.meta ?? { a: 1 }, p ?? [1, 2][1]]";
+        assert_eq!(run(code).to_string(), "[[], { a: 1 }, 2]");
+
+        let code = "q = none\np = { a: { b: [1, 2] } }\nreturn [q?.a.b, q?.a.b[0], q?.a.f(1).g, p?.a.b[1], p?.a.b.len(), q?.a.b ?? 3]";
+        assert_eq!(run(code).to_string(), "[none, none, none, 2, 2, 3]");
+
+        let code = "x = none\ny = 1\nreturn [x == none, none == x, y == none, none == y]";
+        assert_eq!(run(code).to_string(), "[true, true, false, false]");
+    }
+
+    #[test]
+    fn none_access_errors() {
+        assert_eq!(run_err("q = none\nreturn q.name"), "can not read name of none, use ?. for optional values");
+        assert_eq!(run_err("q = { a: none }\nreturn q?.a.b"), "can not read b of none, use ?. for optional values");
+        assert_eq!(run_err("return 1.a"), "int has no field a");
+    }
+
+    #[test]
+    fn condition_errors() {
+        assert_eq!(run_err("if 1.5 { return 1 }"), "float can not be used as a condition");
+        assert_eq!(run_err("o = { a: 1 }\nif o { return 1 }"), "object can not be used as a condition");
+        assert_eq!(run_err("a = [1.5]\nreturn a.filter(x => return x)"), "float can not be used as a condition");
+        assert_eq!(run_err("a = [{ a: 1 }]\nreturn a.any(x => return x)"), "object can not be used as a condition");
+    }
+}
@@ -14,6 +14,8 @@ pub enum ByteCode {
     JumpIfFalse(u32),
     Call(u32),
     Cmp,
+    Lt,
+    Gt,
     BeginScope,
     EndScope,
     Fun(u32),
@@ -27,6 +29,55 @@ pub enum ByteCode {
     MakeIter,
     Await,
     AccessProp(u32),
+    /// Replaces a list with its first n items, for `for [a, b] in`.
+    Unpack(u32),
+    /// Replaces a container and an index with the item at the index.
+    Index,
+    /// Pops a value and an object and sets the property of the object.
+    StoreProp(u32),
+    /// Pops a value, an index and a container and sets the item.
+    StoreIndex,
+    /// Pushes copies of the top n values, for compound assignments.
+    Dup(u32),
+    /// Moves the value below the top n values to the top, for the object
+    /// of a `?.` call, which is kept under its args.
+    Lift(u32),
+    Neg,
+    /// Pushes a named value like `Load`, for the callee of a call. A name
+    /// nothing defines gives an undefined value, which calls the host.
+    LoadCallee(u32),
+    /// Builds a value of an enum from the variant constant, filling its
+    /// fields with values popped off the stack.
+    Variant(u32),
+    /// Replaces a value with whether it matches the constant: the same
+    /// variant for an enum value, an equal value for a literal.
+    Matches(u32),
+    /// Jumps when the value on top is none, which stays as the result of
+    /// a `?.` read.
+    JumpIfNone(u32),
+    /// Jumps when the value on top is not none, keeping it, and pops it
+    /// otherwise, for `??`.
+    JumpIfSome(u32),
+}
+
+/// Maps instructions of a code block back to source lines. Each entry
+/// is the pc of the first instruction of a statement and its line.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineTable {
+    pub source: Option<u32>,
+    pub entries: Vec<(u32, u32)>,
+    /// Names declared in the blocks of ifs, loops and match arms, with the
+    /// pc where their block starts and the one where it ends.
+    pub lets: Vec<(u32, u32, u32)>,
+}
+
+impl LineTable {
+    pub fn line_at(&self, pc: u32) -> Option<u32> {
+        self.entries.iter()
+            .take_while(|(start, _)| *start <= pc)
+            .last()
+            .map(|(_, line)| *line)
+    }
 }
 
 pub struct Ins {